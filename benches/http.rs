@@ -0,0 +1,210 @@
+// run with `cargo bench`, or `cargo bench -- router` for the benchmarks whose
+// name contains "router"; criterion is not a dependency, so this keeps to a
+// small timing loop of its own and reports the mean of the fastest sample
+
+use codecrafters_http_server::middleware::{Compression, Middleware, Pipeline};
+use codecrafters_http_server::request::RequestLimits;
+use codecrafters_http_server::{Config, HttpRequest, HttpResponse, Router, Server};
+use std::hint::black_box;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+use std::{env, fs, process, thread};
+
+const SAMPLES: usize = 10;
+const SAMPLE_TIME: Duration = Duration::from_millis(200);
+
+const REQUEST: &[u8] = b"GET /api/users/42/posts?page=2 HTTP/1.1\r\n\
+Host: localhost:4221\r\n\
+User-Agent: bench/1.0\r\n\
+Accept: application/json, text/plain;q=0.9\r\n\
+Accept-Encoding: gzip, deflate, br\r\n\
+Accept-Language: en-GB,en;q=0.8\r\n\
+Cookie: session=0123456789abcdef; theme=dark\r\n\
+Connection: keep-alive\r\n\r\n";
+
+struct Bench {
+    filter: Option<String>,
+}
+
+impl Bench {
+    // times `f` in samples of as many iterations as fit in SAMPLE_TIME
+    fn run(&self, name: &str, mut f: impl FnMut()) {
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !name.contains(filter))
+        {
+            return;
+        }
+
+        let start = Instant::now();
+        let mut iterations = 0u32;
+        while start.elapsed() < SAMPLE_TIME {
+            f();
+            iterations += 1;
+        }
+
+        let fastest = (0..SAMPLES)
+            .map(|_| {
+                let start = Instant::now();
+                for _ in 0..iterations {
+                    f();
+                }
+                start.elapsed() / iterations
+            })
+            .min()
+            .unwrap_or_default();
+
+        println!(
+            "{name:<32} {:>12} ns/iter {:>12.0} iter/s",
+            fastest.as_nanos(),
+            1.0 / fastest.as_secs_f64().max(f64::MIN_POSITIVE)
+        );
+    }
+}
+
+fn parse(request: &[u8]) -> HttpRequest {
+    HttpRequest::read_head(&mut &request[..], &RequestLimits::default()).unwrap()
+}
+
+fn request_parsing(bench: &Bench) {
+    bench.run("parse/request_head", || {
+        black_box(parse(black_box(REQUEST)));
+    });
+}
+
+fn router_dispatch(bench: &Bench) {
+    let mut router = Router::new();
+    for resource in ["users", "posts", "comments", "tags", "teams", "orgs"] {
+        router
+            .get(&format!("/api/{resource}"), |_, _| HttpResponse::ok())
+            .get(&format!("/api/{resource}/:id"), |_, _| HttpResponse::ok())
+            .post(&format!("/api/{resource}"), |_, _| HttpResponse::created());
+    }
+    router.get("/api/users/:id/posts", |_, params| {
+        HttpResponse::ok().body(params.get("id").unwrap_or_default().to_owned())
+    });
+
+    let first = parse(b"GET /api/users HTTP/1.1\r\nHost: x\r\n\r\n");
+    let last = parse(REQUEST);
+    let missing = parse(b"GET /nowhere HTTP/1.1\r\nHost: x\r\n\r\n");
+
+    bench.run("router/first_route", || {
+        black_box(router.handle(black_box(&first)));
+    });
+    bench.run("router/last_route", || {
+        black_box(router.handle(black_box(&last)));
+    });
+    bench.run("router/not_found", || {
+        black_box(router.handle(black_box(&missing)));
+    });
+}
+
+fn compression(bench: &Bench) {
+    let text = "the quick brown fox jumps over the lazy dog\n".repeat(100);
+    let mut router = Router::new();
+    router.get("/text", move |_, _| {
+        HttpResponse::ok()
+            .header("Content-Type", "text/plain")
+            .body(text.clone())
+    });
+    let middleware: Vec<Box<dyn Middleware>> = vec![Box::new(Compression::default())];
+    let pipeline = Pipeline::new(middleware, router);
+
+    let test_cases = [("response/identity", "identity"), ("response/gzip", "gzip")];
+    for (name, encoding) in test_cases {
+        let raw = format!("GET /text HTTP/1.1\r\nHost: x\r\nAccept-Encoding: {encoding}\r\n\r\n");
+        bench.run(name, || {
+            let mut req = parse(raw.as_bytes());
+            let mut out = Vec::with_capacity(8 * 1024);
+            pipeline
+                .handle(&mut req)
+                .unwrap()
+                .write_to(&mut out)
+                .unwrap();
+            black_box(out);
+        });
+    }
+}
+
+// reads one response off a keep-alive connection, returning its body length
+fn read_response(reader: &mut BufReader<TcpStream>) -> usize {
+    let mut length = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    length
+}
+
+fn loopback(bench: &Bench) {
+    let dir = env::temp_dir().join(format!("http-server-bench-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("small.txt"), "x".repeat(1024)).unwrap();
+
+    let server = Server::new(
+        "127.0.0.1:0",
+        Config {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 0,
+            directory: Some(dir.clone()),
+            ..Config::default()
+        },
+    );
+    let shutdown = server.shutdown_handle();
+    let thread = thread::spawn(move || server.listen());
+    let addr: SocketAddr = loop {
+        if let Some(addr) = shutdown.local_addr() {
+            break addr;
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+
+    let test_cases = [
+        ("loopback/echo", "/echo/abc"),
+        ("loopback/small_file", "/files/small.txt"),
+    ];
+    for (name, path) in test_cases {
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        bench.run(name, || {
+            writer.write_all(request.as_bytes()).unwrap();
+            black_box(read_response(&mut reader));
+        });
+    }
+
+    drop(reader);
+    drop(writer);
+    shutdown.shutdown();
+    let _ = thread.join();
+    let _ = fs::remove_dir_all(dir);
+}
+
+fn main() {
+    // cargo passes --bench along, anything else is taken as a filter
+    let bench = Bench {
+        filter: env::args().skip(1).find(|arg| !arg.starts_with("--")),
+    };
+
+    request_parsing(&bench);
+    router_dispatch(&bench);
+    compression(&bench);
+    loopback(&bench);
+}
@@ -0,0 +1,25 @@
+// cargo +nightly fuzz run chunked -- -rss_limit_mb=256
+#![no_main]
+
+use codecrafters_http_server::request::RequestLimits;
+use codecrafters_http_server::HttpRequest;
+use libfuzzer_sys::fuzz_target;
+
+const LIMITS: RequestLimits = RequestLimits {
+    max_body_size: 64 * 1024,
+    max_part_size: 64 * 1024,
+    max_header_line: 1024,
+    max_header_bytes: 8 * 1024,
+    max_header_count: 32,
+};
+
+const HEAD: &[u8] = b"POST / HTTP/1.1\r\nHost: fuzz\r\nTransfer-Encoding: chunked\r\n\r\n";
+
+// the input is the body alone, so every run reaches the chunked decoder
+fuzz_target!(|data: &[u8]| {
+    let mut req = HttpRequest::read_head(&mut &HEAD[..], &LIMITS).expect("a valid head");
+    let mut reader = data;
+    if req.read_body(&mut reader, &LIMITS).is_ok() {
+        assert!(req.body().map_or(0, <[u8]>::len) <= LIMITS.max_body_size);
+    }
+});
@@ -0,0 +1,26 @@
+// cargo +nightly fuzz run request -- -rss_limit_mb=256
+#![no_main]
+
+use codecrafters_http_server::request::RequestLimits;
+use codecrafters_http_server::HttpRequest;
+use libfuzzer_sys::fuzz_target;
+
+// small limits so a runaway allocation shows up against the rss limit
+const LIMITS: RequestLimits = RequestLimits {
+    max_body_size: 64 * 1024,
+    max_part_size: 64 * 1024,
+    max_header_line: 1024,
+    max_header_bytes: 8 * 1024,
+    max_header_count: 32,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+    // one connection may carry several requests, read them as the server would
+    while let Ok(mut req) = HttpRequest::read_head(&mut reader, &LIMITS) {
+        if req.read_body(&mut reader, &LIMITS).is_err() {
+            break;
+        }
+        assert!(req.body().map_or(0, <[u8]>::len) <= LIMITS.max_body_size);
+    }
+});
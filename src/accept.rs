@@ -0,0 +1,130 @@
+use crate::encoding::parse_quality;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaType {
+    essence: &'static str,
+}
+
+impl MediaType {
+    pub const HTML: MediaType = MediaType::new("text/html");
+    pub const JSON: MediaType = MediaType::new("application/json");
+    pub const PLAIN: MediaType = MediaType::new("text/plain");
+
+    // `essence` is a lowercase `type/subtype` without parameters
+    pub const fn new(essence: &'static str) -> Self {
+        MediaType { essence }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.essence
+    }
+
+    // 3 for an exact match, 2 for `type/*`, 1 for `*/*`, 0 for none
+    fn specificity(&self, range: &str) -> u8 {
+        let (kind, subtype) = self.essence.split_once('/').unwrap_or((self.essence, ""));
+
+        match range.split_once('/') {
+            Some(("*", "*")) => 1,
+            Some((range_kind, "*")) if range_kind.eq_ignore_ascii_case(kind) => 2,
+            Some((range_kind, range_subtype))
+                if range_kind.eq_ignore_ascii_case(kind)
+                    && range_subtype.eq_ignore_ascii_case(subtype) =>
+            {
+                3
+            }
+            _ => 0,
+        }
+    }
+}
+
+// media ranges with their q-values in thousandths, other parameters are
+// ignored
+pub fn parse_accept(header: &str) -> Vec<(String, u16)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim();
+
+            if !range.split_once('/').is_some_and(|(kind, subtype)| {
+                !kind.is_empty() && !subtype.is_empty() && (kind != "*" || subtype == "*")
+            }) {
+                return None;
+            }
+
+            let quality = parts
+                .filter_map(|param| param.trim().split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map_or(Some(1000), |(_, value)| parse_quality(value.trim()))?;
+
+            Some((range.to_lowercase(), quality))
+        })
+        .collect()
+}
+
+// each available type takes the q-value of the most specific range matching
+// it, ties go to the order of `available`, so it doubles as the server's
+// preference
+pub fn negotiate(accept: Option<&str>, available: &[MediaType]) -> Option<MediaType> {
+    let Some(header) = accept.filter(|header| !header.trim().is_empty()) else {
+        return available.first().copied();
+    };
+
+    let preferences = parse_accept(header);
+
+    let quality_of = |media_type: &MediaType| {
+        preferences
+            .iter()
+            .map(|(range, quality)| (media_type.specificity(range), *quality))
+            .filter(|(specificity, _)| *specificity > 0)
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0, |(_, quality)| quality)
+    };
+
+    let mut best: Option<(MediaType, u16)> = None;
+    for media_type in available {
+        let quality = quality_of(media_type);
+        if quality > best.map_or(0, |(_, best_quality)| best_quality) {
+            best = Some((*media_type, quality));
+        }
+    }
+
+    best.map(|(media_type, _)| media_type)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn negotiate_should_pick_the_most_preferred_available_type() {
+        let available = [MediaType::HTML, MediaType::JSON, MediaType::PLAIN];
+
+        let test_cases = vec![
+            (None, Some(MediaType::HTML)),
+            (Some(""), Some(MediaType::HTML)),
+            (Some("application/json"), Some(MediaType::JSON)),
+            (Some("Application/JSON"), Some(MediaType::JSON)),
+            (Some("*/*"), Some(MediaType::HTML)),
+            (Some("text/*"), Some(MediaType::HTML)),
+            (Some("text/*;q=0.5, text/plain"), Some(MediaType::PLAIN)),
+            (
+                Some("text/html;q=0.9, application/json;charset=utf-8"),
+                Some(MediaType::JSON),
+            ),
+            (Some("text/*, text/html;q=0"), Some(MediaType::PLAIN)),
+            (
+                Some("*/*;q=0.1, application/json;q=0.2"),
+                Some(MediaType::JSON),
+            ),
+            (Some("image/png"), None),
+            (Some("*/*;q=0"), None),
+            (Some("application/json;q=2"), None),
+            (Some("*/json, text"), None),
+        ];
+
+        for (accept, expected) in test_cases {
+            assert_eq!(negotiate(accept, &available), expected, "{accept:?}");
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use crate::chunked;
+use crate::errors::{Error, Result};
+#[cfg(unix)]
+use crate::listener::SocketFile;
+use crate::listener::{self, Acceptors, Listener};
+use crate::log;
+use crate::middleware::Pipeline;
+use crate::record::Recorded;
+use crate::request::{BodyFraming, HttpRequest, RequestLimits};
+use crate::response::{SendFile, StatusCode, Upgrade};
+use crate::server::{self, AfterResponse, ConnectionGuard};
+use crate::shutdown::ShutdownHandle;
+use crate::Config;
+use bytes::Bytes;
+use socket2::SockRef;
+use std::future::Future;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::BufReader;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+
+// handlers and response bodies are blocking, so they run on the blocking pool
+// and hand the serialized response back to the connection task in chunks
+struct ChannelWriter(mpsc::Sender<Bytes>);
+
+impl SendFile for ChannelWriter {}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Bytes::copy_from_slice(buf))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    // one message for the lot, not one per slice
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let buf: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+        self.write(&buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// the reading side of a hijacked connection, fed by the connection task
+struct ChannelReader {
+    receiver: mpsc::Receiver<Bytes>,
+    pending: Bytes,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.pending = chunk,
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending.split_to(n));
+        Ok(n)
+    }
+}
+
+struct ChannelStream(ChannelReader, ChannelWriter);
+
+impl Read for ChannelStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for ChannelStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.1.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.1.flush()
+    }
+}
+
+async fn with_timeout<T, E: Into<Error>>(
+    duration: Duration,
+    future: impl Future<Output = std::result::Result<T, E>>,
+) -> Result<T> {
+    match tokio::time::timeout(duration, future).await {
+        Ok(result) => result.map_err(Into::into),
+        Err(_) => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+    }
+}
+
+// buffers the head with the same per-line and line-count bounds the parser
+// enforces, so a client cannot grow it without limit
+async fn read_head<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limits: &RequestLimits,
+) -> Result<HttpRequest> {
+    let mut head = Vec::new();
+
+    for line_number in 0..limits.max_header_count + 2 {
+        let start = head.len();
+
+        let read = (&mut *reader)
+            .take(limits.max_header_line as u64)
+            .read_until(b'\n', &mut head)
+            .await?;
+
+        if read == 0 {
+            break;
+        }
+
+        if !head.ends_with(b"\n") && read >= limits.max_header_line {
+            return Err(if line_number == 0 {
+                Error::UriTooLong
+            } else {
+                Error::HeadersTooLarge
+            });
+        }
+
+        if matches!(&head[start..], b"\r\n" | b"\n") {
+            return HttpRequest::read_head(&mut &head[..], limits);
+        }
+    }
+
+    if head.is_empty() {
+        return Err(Error::ConnectionClosed);
+    }
+
+    HttpRequest::read_head(&mut &head[..], limits)
+}
+
+async fn read_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    req: &mut HttpRequest,
+    limits: &RequestLimits,
+) -> Result<()> {
+    let buffer = match req.body_framing(limits)? {
+        BodyFraming::Chunked => chunked::read_chunked_body_async(reader, limits).await?,
+        BodyFraming::Length(content_length) => {
+            // grows with what arrives instead of allocating whatever the
+            // header claims up front
+            let mut buffer = Vec::new();
+            let mut body = reader.take(content_length as u64);
+            body.read_to_end(&mut buffer).await?;
+            if buffer.len() < content_length {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            buffer
+        }
+        BodyFraming::Empty => Vec::new(),
+    };
+
+    req.set_body(buffer);
+
+    Ok(())
+}
+
+async fn write_chunks<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    mut chunks: mpsc::Receiver<Bytes>,
+    write_timeout: Duration,
+) -> Result<()> {
+    while let Some(chunk) = chunks.recv().await {
+        with_timeout(write_timeout, writer.write_all(&chunk)).await?;
+    }
+
+    with_timeout(write_timeout, writer.flush()).await
+}
+
+// runs the upgrade handler on the blocking pool and shuttles bytes between it
+// and the socket until it returns
+async fn hijack<S>(
+    reader: BufReader<S>,
+    upgrade: Upgrade,
+    permit: OwnedSemaphorePermit,
+    conf: &Config,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut read_half, mut write_half) = tokio::io::split(reader);
+    let (inbound, receiver) = mpsc::channel(8);
+    let (sender, outbound) = mpsc::channel(8);
+
+    let pump = tokio::spawn(async move {
+        let mut buffer = vec![0; 8192];
+        loop {
+            match read_half.read(&mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if inbound
+                        .send(Bytes::copy_from_slice(&buffer[..n]))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let handler = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let mut stream = ChannelStream(
+            ChannelReader {
+                receiver,
+                pending: Bytes::new(),
+            },
+            ChannelWriter(sender),
+        );
+        upgrade.run(&mut stream)
+    });
+
+    let written = write_chunks(&mut write_half, outbound, conf.write_timeout).await;
+    pump.abort();
+    handler.await.map_err(io::Error::other)??;
+    written
+}
+
+async fn serve<S>(
+    stream: S,
+    peer_addr: Option<SocketAddr>,
+    pipeline: Arc<Pipeline>,
+    conf: Arc<Config>,
+    jobs: Arc<Semaphore>,
+    queue_depth: Arc<AtomicUsize>,
+    shutdown: ShutdownHandle,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut reader = BufReader::new(Recorded::new(stream, conf.record.as_deref()));
+    #[cfg(feature = "tracing")]
+    let span = server::connection_span(peer_addr);
+
+    loop {
+        // the parser consumes exactly one request, so pipelined ones stay
+        // buffered and are answered in order without waiting on the socket
+        match tokio::time::timeout(conf.keep_alive_timeout, reader.fill_buf()).await {
+            Ok(Ok([])) | Err(_) => return Ok(()),
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) => return Err(e.into()),
+        }
+
+        let result: Result<HttpRequest> = async {
+            let mut req =
+                with_timeout(conf.header_timeout, read_head(&mut reader, &conf.limits)).await?;
+            req.check_host()?;
+            with_timeout(
+                conf.read_timeout,
+                read_body(&mut reader, &mut req, &conf.limits),
+            )
+            .await?;
+            Ok(req)
+        }
+        .await;
+
+        let mut req = match result {
+            Ok(req) => req,
+            Err(Error::ConnectionClosed) => return Ok(()),
+            Err(e) => {
+                let response = pipeline.render_error(server::error_response(&e), None, Some(&e));
+                let unexpected = response.status() == StatusCode::InternalServerError;
+
+                let mut out = Vec::new();
+                response.write_to(&mut out)?;
+                with_timeout(conf.write_timeout, reader.get_mut().write_all(&out)).await?;
+
+                return if unexpected { Err(e) } else { Ok(()) };
+            }
+        };
+
+        server::prepare(&mut req, peer_addr, &conf);
+
+        let Ok(permit) = Arc::clone(&jobs).try_acquire_owned() else {
+            let mut out = Vec::new();
+            pipeline
+                .render_error(server::overloaded(), Some(&req), None)
+                .write_to(&mut out)?;
+            with_timeout(conf.write_timeout, reader.get_mut().write_all(&out)).await?;
+            return Ok(());
+        };
+
+        let keep_alive = req.keep_alive() && !shutdown.is_requested();
+
+        let (sender, chunks) = mpsc::channel(8);
+        let pipeline = Arc::clone(&pipeline);
+        let queued = Arc::clone(&queue_depth);
+        queued.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "tracing")]
+        let parent = span.id();
+        let handler = tokio::task::spawn_blocking(move || {
+            queued.fetch_sub(1, Ordering::SeqCst);
+            let respond =
+                || server::respond(&pipeline, req, keep_alive, &mut ChannelWriter(sender));
+            #[cfg(feature = "tracing")]
+            let result = log::enter(parent, respond);
+            #[cfg(not(feature = "tracing"))]
+            let result = respond();
+            result.map(|upgrade| (upgrade, permit))
+        });
+
+        write_chunks(reader.get_mut(), chunks, conf.write_timeout).await?;
+        match handler.await.map_err(io::Error::other)?? {
+            (AfterResponse::Upgrade(upgrade), permit) => {
+                return hijack(reader, upgrade, permit, &conf).await
+            }
+            (AfterResponse::Close, _) => return Ok(()),
+            (AfterResponse::KeepAlive, _) => {}
+        }
+    }
+}
+
+enum AsyncListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        // removes the socket once the listener is dropped
+        _file: SocketFile,
+    },
+}
+
+impl AsyncListener {
+    // has to be called on the runtime the listener is to be driven by
+    fn from_std(listener: Listener) -> io::Result<Self> {
+        match listener {
+            Listener::Tcp(listener) => {
+                listener.set_nonblocking(true)?;
+                Ok(AsyncListener::Tcp(TcpListener::from_std(listener)?))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, file) => {
+                listener.set_nonblocking(true)?;
+                Ok(AsyncListener::Unix {
+                    listener: UnixListener::from_std(listener)?,
+                    _file: file,
+                })
+            }
+        }
+    }
+}
+
+enum Accepted {
+    Tcp(TcpStream, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+// what the accept loops of all listeners share
+#[derive(Clone)]
+struct Acceptor {
+    tls: Option<TlsAcceptor>,
+    conf: Arc<Config>,
+    pipeline: Arc<Pipeline>,
+    jobs: Arc<Semaphore>,
+    queue_depth: Arc<AtomicUsize>,
+    open: Arc<AtomicUsize>,
+    shutdown: ShutdownHandle,
+}
+
+impl Acceptor {
+    async fn run(self, listener: Arc<AsyncListener>, accepted: Arc<AtomicU64>) -> Result<()> {
+        let mut connections = JoinSet::new();
+
+        while !self.shutdown.is_requested() {
+            let connection = match &*listener {
+                AsyncListener::Tcp(listener) => listener
+                    .accept()
+                    .await
+                    .map(|(stream, peer_addr)| Accepted::Tcp(stream, peer_addr)),
+                #[cfg(unix)]
+                AsyncListener::Unix { listener, .. } => listener
+                    .accept()
+                    .await
+                    .map(|(stream, _)| Accepted::Unix(stream)),
+            };
+            let connection = match connection {
+                Ok(connection) => connection,
+                Err(e) => {
+                    log::warning!("Failed to accept connection, error {}", e);
+                    continue;
+                }
+            };
+
+            if self.shutdown.is_requested() {
+                break;
+            }
+
+            accepted.fetch_add(1, Ordering::Relaxed);
+            while connections.try_join_next().is_some() {}
+
+            match connection {
+                Accepted::Tcp(stream, peer_addr) => {
+                    if let Err(e) = listener::tune(SockRef::from(&stream), &self.conf.tcp) {
+                        log::warning!("Failed to set socket options, error {}", e);
+                    }
+                    self.spawn(&mut connections, stream, Some(peer_addr))?
+                }
+                #[cfg(unix)]
+                Accepted::Unix(stream) => self.spawn(&mut connections, stream, None)?,
+            }
+        }
+
+        while connections.join_next().await.is_some() {}
+
+        Ok(())
+    }
+
+    fn spawn<S>(
+        &self,
+        connections: &mut JoinSet<()>,
+        stream: S,
+        peer_addr: Option<SocketAddr>,
+    ) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        // tls connections are just closed, the handshake is not worth it
+        let Some(guard) = ConnectionGuard::acquire(&self.open, self.conf.max_connections) else {
+            if self.tls.is_none() {
+                let mut out = Vec::new();
+                self.pipeline
+                    .render_error(server::overloaded(), None, None)
+                    .write_to(&mut out)?;
+                let write_timeout = self.conf.write_timeout;
+                let mut stream = stream;
+                connections.spawn(async move {
+                    let _ = with_timeout(write_timeout, stream.write_all(&out)).await;
+                });
+            }
+            return Ok(());
+        };
+
+        let acceptor = self.tls.clone();
+        let conf = Arc::clone(&self.conf);
+        let pipeline = Arc::clone(&self.pipeline);
+        let jobs = Arc::clone(&self.jobs);
+        let queue_depth = Arc::clone(&self.queue_depth);
+        let shutdown = self.shutdown.clone();
+        connections.spawn(async move {
+            let _guard = guard;
+            let result = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        serve(
+                            stream,
+                            peer_addr,
+                            pipeline,
+                            conf,
+                            jobs,
+                            queue_depth,
+                            shutdown,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e.into()),
+                },
+                None => {
+                    serve(
+                        stream,
+                        peer_addr,
+                        pipeline,
+                        conf,
+                        jobs,
+                        queue_depth,
+                        shutdown,
+                    )
+                    .await
+                }
+            };
+
+            if let Err(e) = result {
+                log::warning!("Failed to handle connection, error {}", e);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+pub fn listen(
+    listeners: Vec<Acceptors>,
+    conf: Config,
+    pipeline: Pipeline,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    shutdown: ShutdownHandle,
+    queue_depth: Arc<AtomicUsize>,
+    open: Arc<AtomicUsize>,
+) -> Result<()> {
+    // handlers run on at most `workers` blocking threads with up to `backlog`
+    // more waiting for one, anything beyond that is turned away with a 503
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .max_blocking_threads(conf.workers)
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(async move {
+        let acceptor = Acceptor {
+            tls: tls_config.map(TlsAcceptor::from),
+            jobs: Arc::new(Semaphore::new(conf.workers + conf.backlog)),
+            conf: Arc::new(conf),
+            pipeline: Arc::new(pipeline),
+            queue_depth,
+            open,
+            shutdown,
+        };
+
+        // accept loops on tasks of their own, all sharing the blocking pool
+        let mut acceptors = JoinSet::new();
+        for Acceptors { listener, accepted } in listeners {
+            let listener = Arc::new(AsyncListener::from_std(listener)?);
+            for accepted in accepted {
+                acceptors.spawn(acceptor.clone().run(Arc::clone(&listener), accepted));
+            }
+        }
+
+        while let Some(result) = acceptors.join_next().await {
+            result.map_err(io::Error::other)??;
+        }
+
+        Ok(())
+    })
+}
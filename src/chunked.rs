@@ -0,0 +1,367 @@
+use crate::errors::{Error, Result};
+use crate::headers::HeaderMap;
+use crate::request::RequestLimits;
+use crate::response::write_all_vectored;
+use std::io::{self, BufRead, Read, Write};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChunkedWriter { inner }
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        self.finish_with_trailers(&HeaderMap::new())
+    }
+
+    // trailer values are only known once the body is written, the names
+    // should have been announced in a Trailer header
+    pub fn finish_with_trailers(mut self, trailers: &HeaderMap) -> io::Result<W> {
+        let mut last = String::from("0\r\n");
+        for (name, value) in trailers.iter() {
+            last.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        last.push_str("\r\n");
+        self.inner.write_all(last.as_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // size, data and the line end in one go rather than three writes
+        let size = format!("{:x}\r\n", buf.len());
+        write_all_vectored(&mut self.inner, &[size.as_bytes(), buf, b"\r\n"])?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn strip_crlf(mut line: Vec<u8>) -> Result<String> {
+    if !line.ends_with(b"\r\n") {
+        return Err(Error::InvalidRequest);
+    }
+    line.truncate(line.len() - 2);
+
+    Ok(String::from_utf8(line)?)
+}
+
+pub(crate) fn parse_chunk_size(size_line: &str) -> Result<usize> {
+    let size_str = size_line
+        .split_once(';')
+        .map_or(size_line, |(size, _)| size)
+        .trim();
+
+    // 1*HEXDIG, from_str_radix would take a sign as well
+    if !size_str.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::InvalidRequest);
+    }
+    usize::from_str_radix(size_str, 16).map_err(|_| Error::InvalidRequest)
+}
+
+// a line cut off at `max_len` has no CRLF and is rejected like a bare one
+fn read_line<R: BufRead>(reader: &mut R, max_len: usize) -> Result<String> {
+    let mut line = Vec::new();
+    reader.take(max_len as u64).read_until(b'\n', &mut line)?;
+    strip_crlf(line)
+}
+
+// counts the trailer fields after the last chunk, they are dropped but may
+// not add up to more than a head could
+#[derive(Default)]
+pub(crate) struct TrailerSection {
+    fields: usize,
+    bytes: usize,
+}
+
+impl TrailerSection {
+    // whether `line` ended the section
+    pub(crate) fn add(&mut self, line: &str, limits: &RequestLimits) -> Result<bool> {
+        if line.is_empty() {
+            return Ok(true);
+        }
+
+        self.fields += 1;
+        self.bytes += line.len() + 2;
+        if self.fields > limits.max_header_count || self.bytes > limits.max_header_bytes {
+            return Err(Error::InvalidRequest);
+        }
+        Ok(false)
+    }
+}
+
+pub fn read_chunked_body<R: BufRead>(reader: &mut R, limits: &RequestLimits) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+
+    loop {
+        let size = parse_chunk_size(&read_line(reader, limits.max_header_line)?)?;
+
+        if size == 0 {
+            break;
+        }
+
+        if body.len().saturating_add(size) > limits.max_body_size {
+            return Err(Error::PayloadTooLarge);
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+
+        if !read_line(reader, limits.max_header_line)?.is_empty() {
+            return Err(Error::InvalidRequest);
+        }
+    }
+
+    let mut trailers = TrailerSection::default();
+    while !trailers.add(&read_line(reader, limits.max_header_line)?, limits)? {}
+
+    Ok(body)
+}
+
+// decodes a chunked body as it is read, used for upstream responses that
+// are streamed on to the client
+pub struct ChunkedReader<R: BufRead> {
+    inner: R,
+    remaining: usize,
+    done: bool,
+    limits: RequestLimits,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> Self {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            done: false,
+            limits: RequestLimits::default(),
+        }
+    }
+}
+
+fn invalid_data(e: Error) -> io::Error {
+    match e {
+        Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let max_line = self.limits.max_header_line;
+        if self.remaining == 0 {
+            let size = read_line(&mut self.inner, max_line)
+                .and_then(|line| parse_chunk_size(&line))
+                .map_err(invalid_data)?;
+
+            if size == 0 {
+                let mut trailers = TrailerSection::default();
+                while !read_line(&mut self.inner, max_line)
+                    .and_then(|line| trailers.add(&line, &self.limits))
+                    .map_err(invalid_data)?
+                {}
+                self.done = true;
+                return Ok(0);
+            }
+
+            self.remaining = size;
+        }
+
+        let max = buf.len().min(self.remaining);
+        let n = self.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.remaining -= n;
+        if self.remaining == 0
+            && !read_line(&mut self.inner, max_line)
+                .map_err(invalid_data)?
+                .is_empty()
+        {
+            return Err(invalid_data(Error::InvalidRequest));
+        }
+
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "tokio")]
+async fn read_line_async<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<String> {
+    let mut line = Vec::new();
+    reader
+        .take(max_len as u64)
+        .read_until(b'\n', &mut line)
+        .await?;
+    strip_crlf(line)
+}
+
+#[cfg(feature = "tokio")]
+pub async fn read_chunked_body_async<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    limits: &RequestLimits,
+) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+
+    loop {
+        let size = parse_chunk_size(&read_line_async(reader, limits.max_header_line).await?)?;
+
+        if size == 0 {
+            break;
+        }
+
+        if body.len().saturating_add(size) > limits.max_body_size {
+            return Err(Error::PayloadTooLarge);
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await?;
+
+        if !read_line_async(reader, limits.max_header_line)
+            .await?
+            .is_empty()
+        {
+            return Err(Error::InvalidRequest);
+        }
+    }
+
+    let mut trailers = TrailerSection::default();
+    while !trailers.add(
+        &read_line_async(reader, limits.max_header_line).await?,
+        limits,
+    )? {}
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chunked_writer_should_frame_chunks_and_terminate() {
+        let mut writer = ChunkedWriter::new(Vec::new());
+
+        writer.write_all(b"hello").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.write_all(&[b'x'; 26]).unwrap();
+
+        let out = writer.finish().unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("5\r\nhello\r\n1a\r\n{}\r\n0\r\n\r\n", "x".repeat(26))
+        );
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("Content-Digest", "sha-256=:abc=:");
+        let mut writer = ChunkedWriter::new(Vec::new());
+        writer.write_all(b"hi").unwrap();
+
+        let out = writer.finish_with_trailers(&trailers).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2\r\nhi\r\n0\r\nContent-Digest: sha-256=:abc=:\r\n\r\n"
+        );
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    #[test]
+    fn read_chunked_body_should_reassemble_chunks() {
+        let test_cases = vec![
+            (
+                "5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\n",
+                64,
+                Some("hello world"),
+            ),
+            ("0\r\nTrailer: value\r\n\r\n", 64, Some("")),
+            ("5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n", 8, None),
+            ("5\r\nhelloXX0\r\n\r\n", 64, None),
+            ("zz\r\n", 64, None),
+            ("5\nhello\r\n0\r\n\r\n", 64, None),
+            ("+5\r\nhello\r\n0\r\n\r\n", 64, None),
+            ("+a\r\nhello worl\r\n0\r\n\r\n", 64, None),
+        ];
+
+        for (input, max_body_size, expected) in test_cases {
+            let limits = RequestLimits {
+                max_body_size,
+                ..RequestLimits::default()
+            };
+            let result = read_chunked_body(&mut input.as_bytes(), &limits).ok();
+            assert_eq!(result.as_deref(), expected.map(str::as_bytes));
+        }
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    #[test]
+    fn read_chunked_body_should_bound_lines_and_trailers() {
+        let limits = RequestLimits::default();
+        let long = "a".repeat(limits.max_header_line * 4);
+        let many = "X-Trailer: value\r\n".repeat(limits.max_header_count + 1);
+
+        let test_cases = vec![
+            format!("{long}\r\n"),
+            long.clone(),
+            format!("1;{long}\r\nx\r\n0\r\n\r\n"),
+            format!("0\r\nX-Trailer: {long}\r\n\r\n"),
+            format!("0\r\n{many}\r\n"),
+        ];
+
+        for input in test_cases {
+            let result = read_chunked_body(&mut input.as_bytes(), &limits);
+            assert!(
+                matches!(result, Err(Error::InvalidRequest)),
+                "{:?} for {} bytes",
+                result,
+                input.len()
+            );
+        }
+    }
+
+    #[test]
+    fn chunked_reader_should_decode_while_reading() {
+        let test_cases = vec![
+            (
+                "5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\nrest",
+                Some("hello world"),
+            ),
+            ("0\r\nTrailer: value\r\n\r\n", Some("")),
+            ("5\r\nhelloXX0\r\n\r\n", None),
+            ("5\r\nhel", None),
+            ("+5\r\nhello\r\n0\r\n\r\n", None),
+        ];
+
+        for (input, expected) in test_cases {
+            let mut out = String::new();
+            let result = ChunkedReader::new(input.as_bytes())
+                .read_to_string(&mut out)
+                .ok()
+                .map(|_| out);
+            assert_eq!(result.as_deref(), expected, "{input:?}");
+        }
+    }
+}
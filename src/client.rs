@@ -0,0 +1,202 @@
+use crate::chunked::ChunkedReader;
+use crate::errors::{Error, Result};
+use crate::request::{self, HttpMethod};
+use crate::response::{HttpResponse, StatusCode};
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+const MAX_HEADER_LINE: usize = 8 * 1024;
+const MAX_HEADER_COUNT: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    host: String,
+    port: u16,
+    base_path: String,
+}
+
+impl Upstream {
+    pub fn authority(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    // prefixes the base path of the upstream, if it has one
+    pub fn target(&self, target: &str) -> String {
+        format!("{}{}", self.base_path, target)
+    }
+}
+
+impl FromStr for Upstream {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let rest = value.strip_prefix("http://").ok_or(Error::InvalidConfig)?;
+
+        let (authority, base_path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| Error::InvalidConfig)?)
+            }
+            _ => (authority, 80),
+        };
+
+        if host.is_empty() {
+            return Err(Error::InvalidConfig);
+        }
+
+        Ok(Upstream {
+            host: host.to_owned(),
+            port,
+            base_path: base_path.trim_end_matches('/').to_owned(),
+        })
+    }
+}
+
+fn connect(upstream: &Upstream, timeout: Duration) -> Result<TcpStream> {
+    let host = upstream.host.trim_matches(['[', ']']);
+    let mut last_error = None;
+
+    for addr in (host, upstream.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error
+        .unwrap_or_else(|| std::io::ErrorKind::NotFound.into())
+        .into())
+}
+
+// sends one request over a fresh connection and returns the response with
+// its body still unread, so it can be streamed on
+pub fn send(
+    upstream: &Upstream,
+    method: HttpMethod,
+    target: &str,
+    headers: &[(String, String)],
+    body: &[u8],
+    timeout: Duration,
+) -> Result<HttpResponse> {
+    let mut stream = connect(upstream, timeout)?;
+
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\n",
+        method.as_str(),
+        upstream.target(target),
+        upstream.authority()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() || matches!(method, HttpMethod::POST | HttpMethod::PUT) {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let status_line = request::read_line(&mut reader, MAX_HEADER_LINE, || Error::InvalidProtocol)?
+        .ok_or(Error::ConnectionClosed)?;
+
+    let mut parts = status_line.splitn(3, ' ');
+    if !parts
+        .next()
+        .is_some_and(|version| version.starts_with("HTTP/1."))
+    {
+        return Err(Error::InvalidProtocol);
+    }
+    let status = parts
+        .next()
+        .and_then(|code| code.parse::<u16>().ok())
+        .filter(|code| (100..=999).contains(code))
+        .map(StatusCode::from_code)
+        .ok_or(Error::InvalidProtocol)?;
+
+    let mut response = HttpResponse::new(status);
+    let mut content_length = None;
+    let mut chunked = false;
+
+    for _ in 0..=MAX_HEADER_COUNT {
+        let line = request::read_line(&mut reader, MAX_HEADER_LINE, || Error::InvalidProtocol)?
+            .ok_or(Error::ConnectionClosed)?;
+
+        if line.is_empty() {
+            let response = if method == HttpMethod::HEAD || !status.allows_body() {
+                response
+            } else if chunked {
+                response.chunked_body(ChunkedReader::new(reader))
+            } else if let Some(length) = content_length {
+                response.sized_body(reader.take(length), length)
+            } else {
+                // the body runs until the upstream closes the connection
+                response.chunked_body(reader)
+            };
+            return Ok(response);
+        }
+
+        let (name, value) = line.split_once(':').ok_or(Error::InvalidProtocol)?;
+        let (name, value) = (name.trim(), value.trim());
+
+        if name.eq_ignore_ascii_case("content-length") {
+            if !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(Error::InvalidProtocol);
+            }
+            content_length = Some(value.parse::<u64>().map_err(|_| Error::InvalidProtocol)?);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value
+                .rsplit(',')
+                .next()
+                .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+        } else {
+            response = response.add_header(name, value);
+        }
+    }
+
+    Err(Error::HeadersTooLarge)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn upstream_should_parse_http_urls() {
+        let test_cases = vec![
+            ("http://localhost:8080", Some(("localhost", 8080, ""))),
+            ("http://example.com/api/", Some(("example.com", 80, "/api"))),
+            ("http://[::1]:9000", Some(("[::1]", 9000, ""))),
+            ("https://example.com", None),
+            ("http://:80", None),
+            ("http://host:port", None),
+        ];
+
+        for (input, expected) in test_cases {
+            let result = input.parse::<Upstream>().ok();
+            assert_eq!(
+                result
+                    .as_ref()
+                    .map(|u| (u.host.as_str(), u.port, u.base_path.as_str())),
+                expected,
+                "{input}"
+            );
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use crate::date;
+use crate::etag;
+use crate::request::{HttpMethod, HttpRequest};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// the validators of the representation a request is about
+#[derive(Debug, Clone, Copy)]
+pub struct Validators<'a> {
+    pub etag: &'a str,
+    pub last_modified: Option<SystemTime>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Proceed,
+    // only for GET and HEAD
+    NotModified,
+    PreconditionFailed,
+}
+
+fn seconds(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
+// whether the representation changed after `header`, None when either date
+// is missing or the header is not one
+fn modified_since(header: Option<&str>, last_modified: Option<SystemTime>) -> Option<bool> {
+    let since = seconds(date::parse_http(header?)?)?;
+    Some(seconds(last_modified?)? > since)
+}
+
+// the preconditions of a request in the order of RFC 9110 section 13.2.2,
+// `current` is None when there is no representation yet
+pub fn evaluate(req: &HttpRequest, current: Option<Validators<'_>>) -> Outcome {
+    let etag = current.map(|current| current.etag);
+    let last_modified = current.and_then(|current| current.last_modified);
+
+    // an If-Match takes the place of an If-Unmodified-Since
+    let failed = match req.header("if-match") {
+        Some(if_match) => !etag.is_some_and(|etag| etag::matches_any(if_match, etag, false)),
+        None => modified_since(req.header("if-unmodified-since"), last_modified) == Some(true),
+    };
+    if failed {
+        return Outcome::PreconditionFailed;
+    }
+
+    let safe = matches!(req.method(), HttpMethod::GET | HttpMethod::HEAD);
+    // as does an If-None-Match for an If-Modified-Since
+    let unchanged = match req.header("if-none-match") {
+        Some(if_none_match) => {
+            etag.is_some_and(|etag| etag::matches_any(if_none_match, etag, true))
+        }
+        None => {
+            safe && modified_since(req.header("if-modified-since"), last_modified) == Some(false)
+        }
+    };
+    match unchanged {
+        true if safe => Outcome::NotModified,
+        true => Outcome::PreconditionFailed,
+        false => Outcome::Proceed,
+    }
+}
+
+// a Range only applies while the validator in If-Range still matches, a
+// changed representation is sent whole instead, see RFC 9110 section 13.1.5
+pub fn range_applies(req: &HttpRequest, current: Validators<'_>) -> bool {
+    match req.header("if-range").map(str::trim) {
+        None => true,
+        Some(tag) if tag.starts_with('"') || tag.starts_with("W/") => {
+            etag::matches(tag, current.etag, false)
+        }
+        Some(value) => date::parse_http(value)
+            .is_some_and(|date| current.last_modified.and_then(seconds) == seconds(date)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::RequestLimits;
+    use std::time::Duration;
+
+    #[test]
+    fn evaluate_should_follow_the_rfc_precedence() {
+        let etag = "\"abc\"";
+        let modified = UNIX_EPOCH + Duration::from_secs(784111777);
+        let before = "Sun, 06 Nov 1994 08:49:36 GMT";
+        let at = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let current = Some(Validators {
+            etag,
+            last_modified: Some(modified),
+        });
+
+        let test_cases = vec![
+            ("GET", vec![], current, Outcome::Proceed),
+            (
+                "GET",
+                vec![("If-Match", "\"abc\"")],
+                current,
+                Outcome::Proceed,
+            ),
+            (
+                "GET",
+                vec![("If-Match", "\"xyz\"")],
+                current,
+                Outcome::PreconditionFailed,
+            ),
+            (
+                "PUT",
+                vec![("If-Match", "*")],
+                None,
+                Outcome::PreconditionFailed,
+            ),
+            (
+                "PUT",
+                vec![("If-Unmodified-Since", before)],
+                current,
+                Outcome::PreconditionFailed,
+            ),
+            // If-Match wins over If-Unmodified-Since
+            (
+                "PUT",
+                vec![("If-Match", "\"abc\""), ("If-Unmodified-Since", before)],
+                current,
+                Outcome::Proceed,
+            ),
+            (
+                "PUT",
+                vec![("If-Unmodified-Since", "yesterday")],
+                current,
+                Outcome::Proceed,
+            ),
+            (
+                "GET",
+                vec![("If-None-Match", "W/\"abc\"")],
+                current,
+                Outcome::NotModified,
+            ),
+            (
+                "PUT",
+                vec![("If-None-Match", "*")],
+                current,
+                Outcome::PreconditionFailed,
+            ),
+            ("PUT", vec![("If-None-Match", "*")], None, Outcome::Proceed),
+            (
+                "HEAD",
+                vec![("If-Modified-Since", at)],
+                current,
+                Outcome::NotModified,
+            ),
+            (
+                "GET",
+                vec![("If-Modified-Since", before)],
+                current,
+                Outcome::Proceed,
+            ),
+            // If-None-Match wins over If-Modified-Since
+            (
+                "GET",
+                vec![("If-None-Match", "\"xyz\""), ("If-Modified-Since", at)],
+                current,
+                Outcome::Proceed,
+            ),
+            (
+                "POST",
+                vec![("If-Modified-Since", at)],
+                current,
+                Outcome::Proceed,
+            ),
+            // a failed If-Match is reported before a matching If-None-Match
+            (
+                "GET",
+                vec![("If-Match", "\"xyz\""), ("If-None-Match", "\"abc\"")],
+                current,
+                Outcome::PreconditionFailed,
+            ),
+        ];
+
+        for (method, headers, current, expected) in test_cases {
+            let mut raw = format!("{method} /files/a.txt HTTP/1.1\r\n");
+            for (name, value) in &headers {
+                raw.push_str(&format!("{name}: {value}\r\n"));
+            }
+            raw.push_str("\r\n");
+            let req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+
+            assert_eq!(evaluate(&req, current), expected, "{method} {headers:?}");
+        }
+    }
+}
@@ -0,0 +1,851 @@
+use crate::cidr::Cidr;
+use crate::client::Upstream;
+use crate::errors::{Error, Result};
+use crate::log;
+use crate::middleware::{compression, security_headers};
+use crate::mime::MimeTypes;
+use crate::request::{normalize_host, RequestLimits};
+use crate::response;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+// how the blocking server drives its connections, a thread each or one
+// event loop handing complete requests to the workers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IoModel {
+    #[default]
+    Threads,
+    Evented,
+}
+
+impl FromStr for IoModel {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "threads" => Ok(IoModel::Threads),
+            "evented" => Ok(IoModel::Evented),
+            _ => Err(Error::InvalidConfig),
+        }
+    }
+}
+
+// where the file endpoints keep their files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageKind {
+    #[default]
+    Filesystem,
+    Memory,
+}
+
+impl FromStr for StorageKind {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "fs" | "filesystem" => Ok(StorageKind::Filesystem),
+            "memory" => Ok(StorageKind::Memory),
+            _ => Err(Error::InvalidConfig),
+        }
+    }
+}
+
+// an address to accept connections on, a path after `unix:` names a unix
+// domain socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl From<&str> for ListenAddr {
+    fn from(value: &str) -> Self {
+        match value.strip_prefix("unix:") {
+            Some(path) => ListenAddr::Unix(PathBuf::from(path)),
+            None => ListenAddr::Tcp(value.to_owned()),
+        }
+    }
+}
+
+impl From<String> for ListenAddr {
+    fn from(value: String) -> Self {
+        ListenAddr::from(value.as_str())
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(fmt, "{addr}"),
+            ListenAddr::Unix(path) => write!(fmt, "unix:{}", path.display()),
+        }
+    }
+}
+
+// a rewrite of paths matching `from` to `to`, sent back to the client as
+// a redirect when there is a status
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewriteRule {
+    pub from: String,
+    pub to: String,
+    pub status: Option<u16>,
+}
+
+// the Cache-Control `value` for responses to paths matching the router
+// pattern `path` or ending in `extension`, one of which is set
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheControlRule {
+    pub path: Option<String>,
+    pub extension: Option<String>,
+    pub value: String,
+}
+
+// socket options of the TCP listeners and the connections they accept
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpOptions {
+    // small responses go out at once instead of waiting on Nagle's algorithm
+    pub nodelay: bool,
+    // idle time before the first keepalive probe, also the time between them
+    pub keepalive: Option<Duration>,
+    // pending connections the kernel queues, the platform default when None
+    pub backlog: Option<u32>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        TcpOptions {
+            nodelay: true,
+            keepalive: None,
+            backlog: None,
+        }
+    }
+}
+
+// the headers added to every response to keep browsers careful, an empty
+// value leaves one out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaderOptions {
+    pub enabled: bool,
+    pub frame_options: String,
+    pub referrer_policy: String,
+    // Strict-Transport-Security, sent over https only
+    pub hsts_max_age: Option<Duration>,
+    pub content_security_policy: String,
+}
+
+impl Default for SecurityHeaderOptions {
+    fn default() -> Self {
+        SecurityHeaderOptions {
+            enabled: true,
+            frame_options: security_headers::DEFAULT_FRAME_OPTIONS.to_string(),
+            referrer_policy: security_headers::DEFAULT_REFERRER_POLICY.to_string(),
+            hsts_max_age: Some(security_headers::DEFAULT_HSTS_MAX_AGE),
+            content_security_policy: String::new(),
+        }
+    }
+}
+
+// what the file endpoints accept for storing, an empty list allows anything
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UploadRules {
+    // without the leading dot
+    pub extensions: Vec<String>,
+    // a `type/*` entry allows every subtype
+    pub content_types: Vec<String>,
+    pub max_files_per_dir: Option<usize>,
+}
+
+impl UploadRules {
+    pub fn allows_name(&self, name: &str) -> bool {
+        self.extensions.is_empty()
+            || Path::new(name)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| {
+                    self.extensions
+                        .iter()
+                        .any(|allowed| allowed.trim_start_matches('.').eq_ignore_ascii_case(ext))
+                })
+    }
+
+    // parameters such as the charset are not compared
+    pub fn allows_content_type(&self, content_type: Option<&str>) -> bool {
+        if self.content_types.is_empty() {
+            return true;
+        }
+        let Some(essence) = content_type.and_then(|value| value.split(';').next()) else {
+            return false;
+        };
+        let essence = essence.trim().to_ascii_lowercase();
+
+        self.content_types.iter().any(|allowed| {
+            let allowed = allowed.trim().to_ascii_lowercase();
+            match allowed.strip_suffix("/*") {
+                Some(main) => essence.split_once('/').is_some_and(|(ty, _)| ty == main),
+                None => essence == allowed,
+            }
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Config {
+    pub address: IpAddr,
+    pub port: u16,
+    // every address here is listened on instead of `address` and `port`
+    pub listen: Vec<ListenAddr>,
+    pub directory: Option<PathBuf>,
+    // the in-memory storage ignores `directory` and `vhosts`
+    pub storage: StorageKind,
+    pub vhosts: Vec<(String, PathBuf)>,
+    pub enable_dir_listing: bool,
+    pub enable_metrics: bool,
+    pub enable_trace: bool,
+    // unknown GET paths get the root index.html for client-side routing
+    pub spa: bool,
+    // every request that could change something is answered 403
+    pub read_only: bool,
+    pub compression: bool,
+    pub compression_min_size: usize,
+    pub compression_skip_types: Vec<String>,
+    pub precompress: bool,
+    pub cache_size: Option<usize>,
+    pub cache_ttl: Duration,
+    pub mime_types: MimeTypes,
+    pub keep_alive_timeout: Duration,
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+    pub header_timeout: Duration,
+    // how long a handler has for the response, from the request's arrival
+    pub request_timeout: Option<Duration>,
+    // route patterns with a request timeout of their own
+    pub route_timeouts: Vec<(String, Duration)>,
+    pub rewrites: Vec<RewriteRule>,
+    pub cache_control: Vec<CacheControlRule>,
+    pub limits: RequestLimits,
+    pub uploads: UploadRules,
+    pub tcp: TcpOptions,
+    pub security_headers: SecurityHeaderOptions,
+    // the Server header of every response, empty leaves it out
+    pub server_header: String,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub access_log: Option<PathBuf>,
+    // where the bytes each connection read and wrote go, for debugging
+    pub record: Option<PathBuf>,
+    pub error_pages: Option<PathBuf>,
+    pub problem_json: bool,
+    pub workers: usize,
+    pub backlog: usize,
+    // accept loops per listener, each on a thread or task of its own
+    pub acceptors: usize,
+    pub max_connections: Option<usize>,
+    pub io_model: IoModel,
+    pub cors_allow_origins: Vec<String>,
+    pub auth_basic: Option<(String, String)>,
+    pub auth_bearer: Option<String>,
+    pub session_secret: Option<String>,
+    pub session_ttl: Duration,
+    pub rate_limit: Option<u32>,
+    pub rate_limit_burst: Option<u32>,
+    // clients refused with a 403, and when given, the only ones let in
+    pub deny_ips: Vec<Cidr>,
+    pub allow_ips: Vec<Cidr>,
+    // peers whose X-Forwarded-* and Forwarded headers are believed
+    pub trusted_proxies: Vec<Cidr>,
+    pub log_level: log::Level,
+    pub log_format: log::Format,
+    pub proxy: Option<Upstream>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 4221,
+            listen: Vec::new(),
+            directory: None,
+            storage: StorageKind::Filesystem,
+            vhosts: Vec::new(),
+            enable_dir_listing: false,
+            enable_metrics: false,
+            enable_trace: false,
+            spa: false,
+            read_only: false,
+            compression: true,
+            compression_min_size: compression::DEFAULT_MIN_SIZE,
+            compression_skip_types: compression::DEFAULT_SKIP_TYPES
+                .iter()
+                .map(|t| t.to_string())
+                .collect(),
+            precompress: false,
+            cache_size: None,
+            cache_ttl: Duration::from_secs(60),
+            mime_types: MimeTypes::default(),
+            keep_alive_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            header_timeout: Duration::from_secs(10),
+            request_timeout: None,
+            route_timeouts: Vec::new(),
+            rewrites: Vec::new(),
+            cache_control: Vec::new(),
+            limits: RequestLimits::default(),
+            uploads: UploadRules::default(),
+            tcp: TcpOptions::default(),
+            security_headers: SecurityHeaderOptions::default(),
+            server_header: response::DEFAULT_SERVER_HEADER.to_string(),
+            tls_cert: None,
+            tls_key: None,
+            access_log: None,
+            record: None,
+            error_pages: None,
+            problem_json: false,
+            workers: 8,
+            backlog: 64,
+            acceptors: 1,
+            max_connections: None,
+            io_model: IoModel::Threads,
+            cors_allow_origins: Vec::new(),
+            auth_basic: None,
+            auth_bearer: None,
+            session_secret: None,
+            session_ttl: Duration::from_secs(30 * 60),
+            rate_limit: None,
+            rate_limit_burst: None,
+            deny_ips: Vec::new(),
+            allow_ips: Vec::new(),
+            trusted_proxies: Vec::new(),
+            log_level: log::Level::Info,
+            log_format: log::Format::Text,
+            proxy: None,
+        }
+    }
+}
+
+impl Config {
+    // the directory serving `host`, falling back to the default one
+    pub fn directory_for(&self, host: Option<&str>) -> Option<&PathBuf> {
+        host.and_then(|host| {
+            self.vhosts
+                .iter()
+                .find(|(name, _)| name == host)
+                .map(|(_, directory)| directory)
+        })
+        .or(self.directory.as_ref())
+    }
+}
+
+// the live configuration, replaced as a whole on reload so a request
+// always sees one consistent version
+#[derive(Clone, Default)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        SharedConfig(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn load(&self) -> Arc<Config> {
+        Arc::clone(&self.0.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    pub fn store(&self, config: Config) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    }
+}
+
+// the on-disk layout, every key is optional and falls back to the defaults
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    #[serde(default)]
+    listen: Vec<String>,
+    directory: Option<PathBuf>,
+    storage: Option<String>,
+    enable_dir_listing: Option<bool>,
+    enable_trace: Option<bool>,
+    spa: Option<bool>,
+    read_only: Option<bool>,
+    workers: Option<usize>,
+    backlog: Option<usize>,
+    acceptors: Option<usize>,
+    max_connections: Option<usize>,
+    io_model: Option<String>,
+    keep_alive_timeout: Option<u64>,
+    read_timeout: Option<u64>,
+    write_timeout: Option<u64>,
+    header_timeout: Option<u64>,
+    request_timeout_ms: Option<u64>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    access_log: Option<PathBuf>,
+    record: Option<PathBuf>,
+    error_pages: Option<PathBuf>,
+    problem_json: Option<bool>,
+    server_header: Option<String>,
+    log_level: Option<String>,
+    log_format: Option<String>,
+    rate_limit: Option<u32>,
+    rate_limit_burst: Option<u32>,
+    #[serde(default)]
+    deny_ips: Vec<String>,
+    #[serde(default)]
+    allow_ips: Vec<String>,
+    #[serde(default)]
+    trusted_proxies: Vec<String>,
+    auth_basic: Option<String>,
+    auth_bearer: Option<String>,
+    session_secret: Option<String>,
+    session_ttl: Option<u64>,
+    #[serde(default)]
+    limits: LimitsFile,
+    #[serde(default)]
+    uploads: UploadsFile,
+    #[serde(default)]
+    tcp: TcpFile,
+    #[serde(default)]
+    security_headers: SecurityHeadersFile,
+    #[serde(default)]
+    compression: CompressionFile,
+    #[serde(default)]
+    cache: CacheFile,
+    #[serde(default)]
+    mime_types: BTreeMap<String, String>,
+    #[serde(default)]
+    vhosts: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    route_timeouts_ms: BTreeMap<String, u64>,
+    #[serde(default)]
+    rewrites: Vec<RewriteRule>,
+    #[serde(default)]
+    cache_control: Vec<CacheControlRule>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitsFile {
+    max_body_size: Option<usize>,
+    max_part_size: Option<usize>,
+    max_header_line: Option<usize>,
+    max_header_bytes: Option<usize>,
+    max_header_count: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UploadsFile {
+    #[serde(default)]
+    extensions: Vec<String>,
+    #[serde(default)]
+    content_types: Vec<String>,
+    max_files_per_dir: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SecurityHeadersFile {
+    enabled: Option<bool>,
+    frame_options: Option<String>,
+    referrer_policy: Option<String>,
+    hsts_max_age: Option<u64>,
+    content_security_policy: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TcpFile {
+    nodelay: Option<bool>,
+    keepalive: Option<u64>,
+    backlog: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CompressionFile {
+    enabled: Option<bool>,
+    precompress: Option<bool>,
+    min_size: Option<usize>,
+    skip_types: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CacheFile {
+    max_size: Option<usize>,
+    ttl: Option<u64>,
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Config::default().with_file(path)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        Config::default().with_toml(contents)
+    }
+
+    pub fn with_file(self, path: &Path) -> Result<Self> {
+        self.with_toml(&fs::read_to_string(path)?)
+    }
+
+    // what the file sets takes the place of the values in `self`, which
+    // are kept for everything it leaves out
+    pub fn with_toml(self, contents: &str) -> Result<Self> {
+        let file: ConfigFile = toml::from_str(contents)?;
+        let mut config = self;
+
+        let secs =
+            |value: Option<u64>, default: Duration| value.map_or(default, Duration::from_secs);
+
+        config.address = file.address.unwrap_or(config.address);
+        config.port = file.port.unwrap_or(config.port);
+        if !file.listen.is_empty() {
+            config.listen = file.listen.into_iter().map(ListenAddr::from).collect();
+        }
+        config.directory = file.directory.or(config.directory);
+        config.enable_dir_listing = file.enable_dir_listing.unwrap_or(config.enable_dir_listing);
+        config.enable_trace = file.enable_trace.unwrap_or(config.enable_trace);
+        config.spa = file.spa.unwrap_or(config.spa);
+        config.read_only = file.read_only.unwrap_or(config.read_only);
+        config.workers = file.workers.unwrap_or(config.workers);
+        config.backlog = file.backlog.unwrap_or(config.backlog);
+        config.acceptors = file
+            .acceptors
+            .filter(|acceptors| *acceptors > 0)
+            .unwrap_or(config.acceptors);
+        // zero turns off what the file may switch on
+        if let Some(max) = file.max_connections {
+            config.max_connections = (max > 0).then_some(max);
+        }
+        config.keep_alive_timeout = secs(file.keep_alive_timeout, config.keep_alive_timeout);
+        config.read_timeout = secs(file.read_timeout, config.read_timeout);
+        config.write_timeout = secs(file.write_timeout, config.write_timeout);
+        config.header_timeout = secs(file.header_timeout, config.header_timeout);
+        config.session_ttl = secs(file.session_ttl, config.session_ttl);
+        if let Some(millis) = file.request_timeout_ms {
+            config.request_timeout = (millis > 0).then(|| Duration::from_millis(millis));
+        }
+        config.tls_cert = file.tls_cert.or(config.tls_cert);
+        config.tls_key = file.tls_key.or(config.tls_key);
+        config.access_log = file.access_log.or(config.access_log);
+        config.record = file.record.or(config.record);
+        config.error_pages = file.error_pages.or(config.error_pages);
+        config.problem_json = file.problem_json.unwrap_or(config.problem_json);
+        config.server_header = file.server_header.unwrap_or(config.server_header);
+        if let Some(basic) = file.auth_basic {
+            let (username, password) = basic.split_once(':').ok_or(Error::InvalidConfig)?;
+            config.auth_basic = Some((username.to_owned(), password.to_owned()));
+        }
+        config.auth_bearer = file.auth_bearer.or(config.auth_bearer);
+        config.session_secret = file.session_secret.or(config.session_secret);
+        if let Some(rate) = file.rate_limit {
+            config.rate_limit = (rate > 0).then_some(rate);
+        }
+        if let Some(burst) = file.rate_limit_burst {
+            config.rate_limit_burst = (burst > 0).then_some(burst);
+        }
+
+        if !file.deny_ips.is_empty() {
+            config.deny_ips = file
+                .deny_ips
+                .iter()
+                .map(|range| range.parse())
+                .collect::<Result<_>>()?;
+        }
+        if !file.allow_ips.is_empty() {
+            config.allow_ips = file
+                .allow_ips
+                .iter()
+                .map(|range| range.parse())
+                .collect::<Result<_>>()?;
+        }
+        if !file.trusted_proxies.is_empty() {
+            config.trusted_proxies = file
+                .trusted_proxies
+                .iter()
+                .map(|range| range.parse())
+                .collect::<Result<_>>()?;
+        }
+
+        if let Some(level) = file.log_level {
+            config.log_level = level.parse()?;
+        }
+        if let Some(format) = file.log_format {
+            config.log_format = format.parse()?;
+        }
+        if let Some(io_model) = file.io_model {
+            config.io_model = io_model.parse()?;
+        }
+        if let Some(storage) = file.storage {
+            config.storage = storage.parse()?;
+        }
+
+        let limits = &mut config.limits;
+        limits.max_body_size = file.limits.max_body_size.unwrap_or(limits.max_body_size);
+        limits.max_part_size = file.limits.max_part_size.unwrap_or(limits.max_part_size);
+        limits.max_header_line = file
+            .limits
+            .max_header_line
+            .unwrap_or(limits.max_header_line);
+        limits.max_header_bytes = file
+            .limits
+            .max_header_bytes
+            .unwrap_or(limits.max_header_bytes);
+        limits.max_header_count = file
+            .limits
+            .max_header_count
+            .unwrap_or(limits.max_header_count);
+
+        config.tcp.nodelay = file.tcp.nodelay.unwrap_or(config.tcp.nodelay);
+        if let Some(keepalive) = file.tcp.keepalive {
+            config.tcp.keepalive = (keepalive > 0).then(|| Duration::from_secs(keepalive));
+        }
+        if let Some(backlog) = file.tcp.backlog {
+            config.tcp.backlog = (backlog > 0).then_some(backlog);
+        }
+
+        let headers = &mut config.security_headers;
+        headers.enabled = file.security_headers.enabled.unwrap_or(headers.enabled);
+        if let Some(frame_options) = file.security_headers.frame_options {
+            headers.frame_options = frame_options;
+        }
+        if let Some(referrer_policy) = file.security_headers.referrer_policy {
+            headers.referrer_policy = referrer_policy;
+        }
+        if let Some(max_age) = file.security_headers.hsts_max_age {
+            headers.hsts_max_age = (max_age > 0).then(|| Duration::from_secs(max_age));
+        }
+        if let Some(policy) = file.security_headers.content_security_policy {
+            headers.content_security_policy = policy;
+        }
+        if !file.uploads.extensions.is_empty() {
+            config.uploads.extensions = file.uploads.extensions;
+        }
+        if !file.uploads.content_types.is_empty() {
+            config.uploads.content_types = file.uploads.content_types;
+        }
+        config.uploads.max_files_per_dir = file
+            .uploads
+            .max_files_per_dir
+            .or(config.uploads.max_files_per_dir);
+
+        config.compression = file.compression.enabled.unwrap_or(config.compression);
+        config.precompress = file.compression.precompress.unwrap_or(config.precompress);
+        config.compression_min_size = file
+            .compression
+            .min_size
+            .unwrap_or(config.compression_min_size);
+        if let Some(skip_types) = file.compression.skip_types {
+            config.compression_skip_types = skip_types;
+        }
+        if let Some(size) = file.cache.max_size {
+            config.cache_size = (size > 0).then_some(size);
+        }
+        config.cache_ttl = secs(file.cache.ttl, config.cache_ttl);
+
+        for (extension, mime_type) in &file.mime_types {
+            config.mime_types.insert(extension, mime_type);
+        }
+
+        if !file.vhosts.is_empty() {
+            config.vhosts = file
+                .vhosts
+                .into_iter()
+                .map(|(host, directory)| (normalize_host(&host), directory))
+                .collect();
+        }
+        if !file.route_timeouts_ms.is_empty() {
+            config.route_timeouts = file
+                .route_timeouts_ms
+                .into_iter()
+                .filter(|(_, millis)| *millis > 0)
+                .map(|(pattern, millis)| (pattern, Duration::from_millis(millis)))
+                .collect();
+        }
+
+        if !file.rewrites.is_empty() {
+            config.rewrites = file.rewrites;
+        }
+        if !file.cache_control.is_empty() {
+            config.cache_control = file.cache_control;
+        }
+
+        if config.workers == 0
+            || config.backlog == 0
+            || config.rewrites.iter().any(|rule| {
+                rule.status
+                    .is_some_and(|status| !matches!(status, 301 | 302 | 307 | 308))
+            })
+            || config
+                .cache_control
+                .iter()
+                .any(|rule| rule.path.is_some() == rule.extension.is_some())
+        {
+            return Err(Error::InvalidConfig);
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_toml_should_fill_in_defaults_for_missing_keys() {
+        let config = Config::from_toml(
+            r#"
+            address = "0.0.0.0"
+            port = 8080
+            listen = ["[::1]:8080", "unix:/run/http.sock"]
+            directory = "/srv/www"
+            workers = 4
+            acceptors = 2
+            keep_alive_timeout = 15
+            request_timeout_ms = 1500
+            rate_limit = 5
+            deny_ips = ["10.0.0.13", "fd00::/8"]
+            trusted_proxies = ["127.0.0.1"]
+            problem_json = true
+            server_header = ""
+            record = "/tmp/recorded"
+            enable_trace = true
+            spa = true
+            read_only = true
+            io_model = "evented"
+            storage = "memory"
+            auth_basic = "admin:s3:cret"
+            session_secret = "hunter2"
+
+            [limits]
+            max_body_size = 1024
+
+            [uploads]
+            extensions = ["png", "jpg"]
+            max_files_per_dir = 100
+
+            [tcp]
+            nodelay = false
+            keepalive = 60
+
+            [compression]
+            enabled = false
+            min_size = 1024
+
+            [security_headers]
+            frame_options = ""
+            hsts_max_age = 0
+            content_security_policy = "default-src 'self'"
+
+            [vhosts]
+            "Example.com" = "/srv/example"
+
+            [route_timeouts_ms]
+            "/files/*path" = 30000
+
+            [[rewrites]]
+            from = "/old/*rest"
+            to = "/files/{rest}"
+            status = 301
+
+            [[cache_control]]
+            extension = "html"
+            value = "no-cache"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                port: 8080,
+                listen: vec![
+                    ListenAddr::Tcp("[::1]:8080".to_string()),
+                    ListenAddr::Unix(PathBuf::from("/run/http.sock")),
+                ],
+                directory: Some(PathBuf::from("/srv/www")),
+                workers: 4,
+                acceptors: 2,
+                keep_alive_timeout: Duration::from_secs(15),
+                request_timeout: Some(Duration::from_millis(1500)),
+                rate_limit: Some(5),
+                deny_ips: vec!["10.0.0.13".parse().unwrap(), "fd00::/8".parse().unwrap()],
+                trusted_proxies: vec!["127.0.0.1".parse().unwrap()],
+                problem_json: true,
+                server_header: String::new(),
+                record: Some(PathBuf::from("/tmp/recorded")),
+                enable_trace: true,
+                spa: true,
+                read_only: true,
+                io_model: IoModel::Evented,
+                storage: StorageKind::Memory,
+                auth_basic: Some(("admin".to_string(), "s3:cret".to_string())),
+                session_secret: Some("hunter2".to_string()),
+                limits: RequestLimits {
+                    max_body_size: 1024,
+                    ..RequestLimits::default()
+                },
+                uploads: UploadRules {
+                    extensions: vec!["png".to_string(), "jpg".to_string()],
+                    max_files_per_dir: Some(100),
+                    ..UploadRules::default()
+                },
+                tcp: TcpOptions {
+                    nodelay: false,
+                    keepalive: Some(Duration::from_secs(60)),
+                    backlog: None,
+                },
+                compression: false,
+                compression_min_size: 1024,
+                security_headers: SecurityHeaderOptions {
+                    frame_options: String::new(),
+                    hsts_max_age: None,
+                    content_security_policy: "default-src 'self'".to_string(),
+                    ..SecurityHeaderOptions::default()
+                },
+                vhosts: vec![("example.com".to_string(), PathBuf::from("/srv/example"))],
+                route_timeouts: vec![("/files/*path".to_string(), Duration::from_secs(30))],
+                rewrites: vec![RewriteRule {
+                    from: "/old/*rest".to_string(),
+                    to: "/files/{rest}".to_string(),
+                    status: Some(301),
+                }],
+                cache_control: vec![CacheControlRule {
+                    path: None,
+                    extension: Some("html".to_string()),
+                    value: "no-cache".to_string(),
+                }],
+                ..Config::default()
+            }
+        );
+
+        let test_cases = vec![
+            "prot = 1",
+            "workers = 0",
+            "log_level = \"loud\"",
+            "io_model = \"fibers\"",
+            "storage = \"tape\"",
+            "auth_basic = \"admin\"",
+            "[[rewrites]]\nfrom = \"/a\"\nto = \"/b\"\nstatus = 200",
+            "[[cache_control]]\nvalue = \"no-cache\"",
+        ];
+        for contents in test_cases {
+            assert!(Config::from_toml(contents).is_err(), "{contents}");
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// set once the client is gone, clones share the flag so work running on
+// behalf of the request elsewhere can stop early
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Cancellation::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// values keyed by their type, a middleware stores one and the handlers
+// after it look it up
+#[derive(Default)]
+pub struct Extensions(HashMap<TypeId, Box<dyn Any + Send + Sync>>);
+
+impl Extensions {
+    pub fn new() -> Self {
+        Extensions::default()
+    }
+
+    // replaces a value of the same type, which is returned
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.0
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.0
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.0
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.0
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Extensions")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
+
+// the id the server assigned the request, as logged and sent back in
+// X-Request-Id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+// what the server knows about a request beyond its message
+#[derive(Debug, Default)]
+pub struct RequestContext {
+    peer_addr: Option<SocketAddr>,
+    // what a trusted proxy in front says about the client
+    client_ip: Option<IpAddr>,
+    scheme: Option<String>,
+    forwarded_host: Option<String>,
+    deadline: Option<Instant>,
+    cancellation: Cancellation,
+    extensions: Extensions,
+}
+
+impl RequestContext {
+    pub fn new() -> Self {
+        RequestContext::default()
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    pub fn set_peer_addr(&mut self, addr: Option<SocketAddr>) {
+        self.peer_addr = addr;
+    }
+
+    // the client behind the proxies, the peer itself without any
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+            .or_else(|| self.peer_addr.map(|addr| addr.ip()))
+    }
+
+    pub fn set_client_ip(&mut self, ip: Option<IpAddr>) {
+        self.client_ip = ip;
+    }
+
+    // http or https as the client sent the request
+    pub fn scheme(&self) -> &str {
+        self.scheme.as_deref().unwrap_or("http")
+    }
+
+    pub fn set_scheme(&mut self, scheme: impl Into<String>) {
+        self.scheme = Some(scheme.into());
+    }
+
+    pub fn forwarded_host(&self) -> Option<&str> {
+        self.forwarded_host.as_deref()
+    }
+
+    pub fn set_forwarded_host(&mut self, host: Option<String>) {
+        self.forwarded_host = host;
+    }
+
+    // when the response is due, None without a request timeout
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    // the time left until the deadline, zero once it passed
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining()
+            .is_some_and(|remaining| remaining.is_zero())
+    }
+
+    pub fn cancellation(&self) -> &Cancellation {
+        &self.cancellation
+    }
+
+    pub fn set_cancellation(&mut self, cancellation: Cancellation) {
+        self.cancellation = cancellation;
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn extensions_should_keep_one_value_per_type() {
+        #[derive(Debug, PartialEq)]
+        struct User(&'static str);
+
+        let mut extensions = Extensions::new();
+        assert_eq!(extensions.insert(User("a")), None);
+        assert_eq!(extensions.insert(RequestId("1".to_string())), None);
+        assert_eq!(extensions.insert(User("b")), Some(User("a")));
+
+        assert_eq!(extensions.len(), 2);
+        assert_eq!(extensions.get::<User>(), Some(&User("b")));
+        assert_eq!(
+            extensions.remove::<RequestId>(),
+            Some(RequestId("1".to_string()))
+        );
+        assert_eq!(extensions.get::<RequestId>(), None);
+        assert_eq!(extensions.get::<String>(), None);
+    }
+}
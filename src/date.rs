@@ -0,0 +1,248 @@
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// the second and the IMF-fixdate of it that responses were last dated with,
+// shared by every worker so the date is formatted at most once a second
+static CLOCK: RwLock<(u64, String)> = RwLock::new((u64::MAX, String::new()));
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DateTime {
+    // days since 1970-01-01, which was a Thursday
+    pub days: i64,
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    // days-to-civil conversion from http://howardhinnant.github.io/date_algorithms.html
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / 86_400) as i64;
+        let secs_of_day = (secs % 86_400) as u32;
+
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            days,
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3_600,
+            minute: secs_of_day % 3_600 / 60,
+            second: secs_of_day % 60,
+        }
+    }
+
+    pub fn from_system_time(time: SystemTime) -> Self {
+        Self::from_unix(
+            time.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        )
+    }
+
+    pub fn to_rfc3339(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+
+    pub fn month_name(&self) -> &'static str {
+        MONTHS[(self.month - 1) as usize]
+    }
+
+    pub fn weekday_name(&self) -> &'static str {
+        WEEKDAYS[self.days.rem_euclid(7) as usize]
+    }
+
+    // the civil-to-days direction of the same algorithms
+    pub fn to_unix(&self) -> Option<u64> {
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = i64::from((self.month + 9) % 12);
+        let doy = (153 * mp + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        let secs = days * 86_400
+            + i64::from(self.hour) * 3_600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second);
+        u64::try_from(secs).ok()
+    }
+}
+
+// the IMF-fixdate of RFC 9110 section 5.6.7, e.g. in Expires
+pub fn format_http(time: SystemTime) -> String {
+    let dt = DateTime::from_system_time(time);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        dt.weekday_name(),
+        dt.day,
+        dt.month_name(),
+        dt.year,
+        dt.hour,
+        dt.minute,
+        dt.second
+    )
+}
+
+// the current time for a Date header
+pub fn http_now() -> String {
+    cached_format_http(&CLOCK, SystemTime::now())
+}
+
+fn cached_format_http(clock: &RwLock<(u64, String)>, time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    if let Ok(cached) = clock.read() {
+        if cached.0 == secs {
+            return cached.1.clone();
+        }
+    }
+
+    let formatted = format_http(time);
+    if let Ok(mut cached) = clock.write() {
+        *cached = (secs, formatted.clone());
+    }
+    formatted
+}
+
+pub fn format_clf(time: SystemTime) -> String {
+    let dt = DateTime::from_system_time(time);
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        dt.day,
+        dt.month_name(),
+        dt.year,
+        dt.hour,
+        dt.minute,
+        dt.second
+    )
+}
+
+// reads an IMF-fixdate, the obsolete formats are not accepted
+pub fn parse_http(value: &str) -> Option<SystemTime> {
+    let (weekday, rest) = value.trim().split_once(", ")?;
+    let mut parts = rest.split(' ');
+    let (day, month, year, time, zone) = (
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+    );
+    if parts.next().is_some() || zone != "GMT" || day.len() != 2 || year.len() != 4 {
+        return None;
+    }
+
+    let mut clock = time.split(':').map(|part| match part.len() {
+        2 => part.parse::<u32>().ok(),
+        _ => None,
+    });
+    let dt = DateTime {
+        days: 0,
+        year: year.parse().ok()?,
+        month: MONTHS.iter().position(|name| *name == month)? as u32 + 1,
+        day: day.parse().ok().filter(|day| (1..=31).contains(day))?,
+        hour: clock.next()?.filter(|hour| *hour < 24)?,
+        minute: clock.next()?.filter(|minute| *minute < 60)?,
+        second: clock.next()?.filter(|second| *second < 61)?,
+    };
+    if clock.next().is_some() {
+        return None;
+    }
+
+    let secs = dt.to_unix()?;
+    (DateTime::from_unix(secs).weekday_name() == weekday)
+        .then(|| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_clf_should_render_utc_timestamps() {
+        let test_cases = vec![
+            (0, "01/Jan/1970:00:00:00 +0000"),
+            (971_186_136, "10/Oct/2000:13:55:36 +0000"),
+            (1_709_210_096, "29/Feb/2024:12:34:56 +0000"),
+        ];
+
+        for (secs, expected) in test_cases {
+            assert_eq!(format_clf(UNIX_EPOCH + Duration::from_secs(secs)), expected);
+        }
+    }
+
+    #[test]
+    fn cached_format_http_should_format_once_a_second() {
+        let clock = RwLock::new((u64::MAX, String::new()));
+        let at = |secs: u64, millis: u64| {
+            UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis)
+        };
+
+        let test_cases = vec![
+            (at(784_111_777, 0), "Sun, 06 Nov 1994 08:49:37 GMT"),
+            (at(784_111_777, 999), "Sun, 06 Nov 1994 08:49:37 GMT"),
+            (at(784_111_778, 1), "Sun, 06 Nov 1994 08:49:38 GMT"),
+            (at(784_111_777, 500), "Sun, 06 Nov 1994 08:49:37 GMT"),
+        ];
+        for (time, expected) in test_cases {
+            assert_eq!(cached_format_http(&clock, time), expected);
+        }
+
+        // within the cached second the stored value is handed out as is
+        *clock.write().unwrap() = (784_111_777, "cached".to_string());
+        assert_eq!(cached_format_http(&clock, at(784_111_777, 250)), "cached");
+    }
+
+    #[test]
+    fn http_dates_should_round_trip_imf_fixdates() {
+        let test_cases = vec![
+            (0, "Thu, 01 Jan 1970 00:00:00 GMT"),
+            (784_111_777, "Sun, 06 Nov 1994 08:49:37 GMT"),
+            (1_709_210_096, "Thu, 29 Feb 2024 12:34:56 GMT"),
+        ];
+
+        for (secs, expected) in test_cases {
+            let time = UNIX_EPOCH + Duration::from_secs(secs);
+            assert_eq!(format_http(time), expected);
+            assert_eq!(parse_http(expected), Some(time), "{expected}");
+        }
+
+        let malformed = vec![
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Mon, 06 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Nov 1994 25:49:37 GMT",
+        ];
+        for value in malformed {
+            assert_eq!(parse_http(value), None, "{value}");
+        }
+    }
+}
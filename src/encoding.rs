@@ -0,0 +1,336 @@
+use crate::errors::{Error, Result};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+    Gzip,
+    Brotli,
+    Deflate,
+    Identity,
+}
+
+impl ContentCoding {
+    pub const SUPPORTED: [ContentCoding; 3] = [
+        ContentCoding::Gzip,
+        ContentCoding::Brotli,
+        ContentCoding::Deflate,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Brotli => "br",
+            ContentCoding::Deflate => "deflate",
+            ContentCoding::Identity => "identity",
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case(self.as_str())
+            || (*self == ContentCoding::Gzip && name.eq_ignore_ascii_case("x-gzip"))
+    }
+
+    pub fn from_name(name: &str) -> Option<ContentCoding> {
+        [
+            ContentCoding::Gzip,
+            ContentCoding::Brotli,
+            ContentCoding::Deflate,
+            ContentCoding::Identity,
+        ]
+        .into_iter()
+        .find(|coding| coding.matches(name.trim()))
+    }
+
+    // the content as it was before encoding, stopping with PayloadTooLarge
+    // once it grows past `limit` so a small bomb cannot fill the memory
+    pub fn decode(&self, content: &[u8], limit: usize) -> Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            ContentCoding::Gzip => Box::new(flate2::read::MultiGzDecoder::new(content)),
+            ContentCoding::Deflate => Box::new(flate2::read::ZlibDecoder::new(content)),
+            ContentCoding::Brotli => Box::new(brotli::Decompressor::new(content, 4096)),
+            ContentCoding::Identity => Box::new(content),
+        };
+
+        let mut decoded = Vec::new();
+        decoder
+            .take(limit as u64 + 1)
+            .read_to_end(&mut decoded)
+            .map_err(|_| Error::InvalidRequest)?;
+        if decoded.len() > limit {
+            return Err(Error::PayloadTooLarge);
+        }
+        Ok(decoded)
+    }
+
+    pub fn encode(&self, content: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            ContentCoding::Gzip => {
+                let mut e = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                e.write_all(content)?;
+                e.finish()
+            }
+            ContentCoding::Deflate => {
+                let mut e = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                e.write_all(content)?;
+                e.finish()
+            }
+            ContentCoding::Brotli => {
+                let mut out = Vec::new();
+                {
+                    let mut e = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                    e.write_all(content)?;
+                }
+                Ok(out)
+            }
+            ContentCoding::Identity => Ok(content.to_vec()),
+        }
+    }
+
+    // compresses as the result is read, so a large body never has to be
+    // held in memory as a whole
+    pub fn encode_reader(&self, reader: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+        match self {
+            ContentCoding::Gzip => Box::new(flate2::read::GzEncoder::new(
+                reader,
+                flate2::Compression::default(),
+            )),
+            ContentCoding::Deflate => Box::new(flate2::read::ZlibEncoder::new(
+                reader,
+                flate2::Compression::default(),
+            )),
+            ContentCoding::Brotli => Box::new(brotli::CompressorReader::new(reader, 4096, 5, 22)),
+            ContentCoding::Identity => reader,
+        }
+    }
+}
+
+// q-values are kept as thousandths so they can be compared exactly
+pub fn parse_accept_encoding(header: &str) -> Vec<(String, u16)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let coding = parts.next()?.trim();
+
+            if coding.is_empty() {
+                return None;
+            }
+
+            let quality = parts
+                .filter_map(|param| param.trim().split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map_or(Some(1000), |(_, value)| parse_quality(value.trim()))?;
+
+            Some((coding.to_lowercase(), quality))
+        })
+        .collect()
+}
+
+pub(crate) fn parse_quality(value: &str) -> Option<u16> {
+    let (int_part, frac_part) = value.split_once('.').unwrap_or((value, ""));
+
+    if frac_part.len() > 3 || !frac_part.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let int_part: u16 = match int_part {
+        "0" => 0,
+        "1" => 1,
+        _ => return None,
+    };
+
+    let frac: u16 = format!("{:0<3}", frac_part).parse().ok()?;
+    let quality = int_part * 1000 + frac;
+
+    (quality <= 1000).then_some(quality)
+}
+
+pub fn negotiate(
+    accept_encoding: Option<&str>,
+    supported: &[ContentCoding],
+) -> Option<ContentCoding> {
+    let Some(header) = accept_encoding else {
+        return Some(ContentCoding::Identity);
+    };
+
+    let preferences = parse_accept_encoding(header);
+
+    let wildcard = preferences
+        .iter()
+        .find(|(name, _)| name == "*")
+        .map(|(_, quality)| *quality);
+
+    let quality_of = |coding: &ContentCoding| {
+        preferences
+            .iter()
+            .find(|(name, _)| coding.matches(name))
+            .map(|(_, quality)| *quality)
+            .or(wildcard)
+    };
+
+    // identity is always acceptable unless explicitly refused, but loses ties
+    let identity = match quality_of(&ContentCoding::Identity) {
+        Some(0) => None,
+        Some(quality) => Some((ContentCoding::Identity, quality)),
+        None => Some((ContentCoding::Identity, 1)),
+    };
+
+    supported
+        .iter()
+        .filter_map(|coding| quality_of(coding).map(|quality| (*coding, quality)))
+        .filter(|(_, quality)| *quality > 0)
+        .chain(identity)
+        .fold(
+            None,
+            |best: Option<(ContentCoding, u16)>, candidate| match best {
+                Some((_, best_quality)) if best_quality >= candidate.1 => best,
+                _ => Some(candidate),
+            },
+        )
+        .map(|(coding, _)| coding)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn negotiate_should_pick_highest_weighted_supported_coding() {
+        let test_cases = vec![
+            (None, Some(ContentCoding::Identity)),
+            (Some(""), Some(ContentCoding::Identity)),
+            (Some("gzip"), Some(ContentCoding::Gzip)),
+            (
+                Some("invalid-encoding-1, gzip, invalid-encoding-2"),
+                Some(ContentCoding::Gzip),
+            ),
+            (Some("invalid-encoding"), Some(ContentCoding::Identity)),
+            (Some("gzip;q=0.5, br;q=1.0"), Some(ContentCoding::Brotli)),
+            (Some("deflate, gzip;q=0.8"), Some(ContentCoding::Deflate)),
+            (Some("br, gzip"), Some(ContentCoding::Gzip)),
+            (
+                Some("gzip;q=0, deflate;q=0.1"),
+                Some(ContentCoding::Deflate),
+            ),
+            (Some("x-gzip"), Some(ContentCoding::Gzip)),
+            (Some("gzip;q=2"), Some(ContentCoding::Identity)),
+            (Some("*"), Some(ContentCoding::Gzip)),
+            (Some("*;q=0.5, br"), Some(ContentCoding::Brotli)),
+            (
+                Some("identity;q=1, gzip;q=0.5"),
+                Some(ContentCoding::Identity),
+            ),
+            (Some("gzip;q=0, identity;q=0"), None),
+            (Some("foo, identity;q=0"), None),
+            (Some("*;q=0"), None),
+            (Some("*;q=0, identity"), Some(ContentCoding::Identity)),
+            (Some("*;q=0, deflate;q=0.3"), Some(ContentCoding::Deflate)),
+        ];
+
+        for (header, expected) in test_cases {
+            assert_eq!(
+                negotiate(header, &ContentCoding::SUPPORTED),
+                expected,
+                "{header:?}"
+            );
+        }
+    }
+
+    // generated Accept-Encoding headers never get a refused coding and
+    // always get one of the highest weighted acceptable ones
+    #[test]
+    fn negotiate_should_respect_q_values_for_any_header() {
+        let names = ["gzip", "x-gzip", "br", "deflate", "identity", "*", "zstd"];
+        // xorshift, so a failure reproduces from the same seed
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+
+        for _ in 0..2000 {
+            let items: Vec<String> = (0..next(5))
+                .map(|_| {
+                    let name = names[next(names.len())];
+                    match next(4) {
+                        0 => name.to_string(),
+                        1 => format!("{name};q=0"),
+                        2 => format!("{name};q=1"),
+                        _ => format!("{name};q=0.{:03}", next(1000)),
+                    }
+                })
+                .collect();
+            let header = items.join(", ");
+
+            // the weight of a coding as the header gives it, an exact name
+            // before the wildcard and identity acceptable by default
+            let preferences = parse_accept_encoding(&header);
+            let weight = |coding: ContentCoding| {
+                let named = preferences
+                    .iter()
+                    .find(|(name, _)| coding.matches(name))
+                    .or_else(|| preferences.iter().find(|(name, _)| name == "*"))
+                    .map(|(_, quality)| *quality);
+                match (coding, named) {
+                    (ContentCoding::Identity, None) => 1,
+                    (_, named) => named.unwrap_or(0),
+                }
+            };
+            let best = ContentCoding::SUPPORTED
+                .into_iter()
+                .chain([ContentCoding::Identity])
+                .map(weight)
+                .max()
+                .unwrap_or(0);
+
+            match negotiate(Some(&header), &ContentCoding::SUPPORTED) {
+                Some(coding) => {
+                    assert!(weight(coding) > 0, "{header:?} picked refused {coding:?}");
+                    assert_eq!(weight(coding), best, "{header:?} picked {coding:?}");
+                }
+                None => assert_eq!(best, 0, "{header:?} refused everything"),
+            }
+        }
+    }
+
+    fn decode(coding: ContentCoding, mut data: &[u8]) -> Vec<u8> {
+        let mut decoded = Vec::new();
+        match coding {
+            ContentCoding::Gzip => flate2::read::GzDecoder::new(data).read_to_end(&mut decoded),
+            ContentCoding::Deflate => {
+                flate2::read::ZlibDecoder::new(data).read_to_end(&mut decoded)
+            }
+            ContentCoding::Brotli => {
+                brotli::Decompressor::new(data, 4096).read_to_end(&mut decoded)
+            }
+            ContentCoding::Identity => data.read_to_end(&mut decoded),
+        }
+        .unwrap();
+        decoded
+    }
+
+    #[test]
+    fn encode_reader_should_round_trip() {
+        let content = "streamed body ".repeat(10_000).into_bytes();
+
+        let test_cases = vec![
+            ContentCoding::Gzip,
+            ContentCoding::Deflate,
+            ContentCoding::Brotli,
+            ContentCoding::Identity,
+        ];
+
+        for coding in test_cases {
+            let mut encoded = Vec::new();
+            coding
+                .encode_reader(Box::new(io::Cursor::new(content.clone())))
+                .read_to_end(&mut encoded)
+                .unwrap();
+
+            assert_eq!(decode(coding, &encoded), content, "{coding:?}");
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use crate::errors::{Error, Result};
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, StatusCode};
+use crate::Config;
+use bytes::Bytes;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// what went wrong, `request` is missing when it could not be read and
+// `error` when a handler answered with an error status itself
+pub struct ErrorContext<'a> {
+    pub status: StatusCode,
+    pub request: Option<&'a HttpRequest>,
+    pub error: Option<&'a Error>,
+}
+
+pub struct ErrorPage {
+    pub content_type: String,
+    pub body: Bytes,
+}
+
+impl ErrorPage {
+    pub fn new(content_type: impl Into<String>, body: impl Into<Bytes>) -> Self {
+        ErrorPage {
+            content_type: content_type.into(),
+            body: body.into(),
+        }
+    }
+}
+
+// returning None keeps the bare status line
+pub type ErrorHandler = dyn Fn(&ErrorContext<'_>) -> Option<ErrorPage> + Send + Sync;
+
+// the status a failure to read or handle a request maps onto
+pub(crate) fn status_of(error: &Error) -> StatusCode {
+    match error {
+        Error::Io(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            StatusCode::RequestTimeout
+        }
+        Error::InvalidRequest | Error::InvalidEncoding(_) | Error::InvalidProtocol => {
+            StatusCode::BadRequest
+        }
+        Error::InvalidMethod | Error::UnsupportedTransferCoding => StatusCode::NotImplemented,
+        Error::UnsupportedVersion => StatusCode::HttpVersionNotSupported,
+        Error::PayloadTooLarge => StatusCode::PayloadTooLarge,
+        Error::UriTooLong => StatusCode::UriTooLong,
+        Error::HeadersTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+        _ => StatusCode::InternalServerError,
+    }
+}
+
+// a description fit for the client, internal errors are only described by
+// their status so nothing about the server leaks
+fn detail_of(status: StatusCode, error: Option<&Error>) -> &'static str {
+    match (status.code(), error) {
+        (400, Some(Error::InvalidEncoding(_))) => "The request is not valid UTF-8.",
+        (400, _) => "The request is malformed.",
+        (401, _) => "Authentication is required to access this resource.",
+        (403, _) => "Access to this resource is forbidden.",
+        (404, _) => "The requested resource does not exist.",
+        (405, _) => "The resource does not support the request method.",
+        (406, _) => "None of the acceptable representations is available.",
+        (408, _) => "The request was not received in time.",
+        (412, _) => "A precondition of the request does not hold.",
+        (413, _) => "The request body exceeds the configured limit.",
+        (414, _) => "The request target exceeds the configured limit.",
+        (429, _) => "Too many requests, retry later.",
+        (431, _) => "The request headers exceed the configured limit.",
+        (501, Some(Error::UnsupportedTransferCoding)) => {
+            "The transfer coding of the request is not implemented."
+        }
+        (501, _) => "The request method is not implemented.",
+        (502, _) => "The upstream server sent an invalid response.",
+        (503, _) => "The server is overloaded, retry later.",
+        (504, _) => "The upstream server did not respond in time.",
+        (505, _) => "The HTTP version is not supported.",
+        _ => "The server failed to handle the request.",
+    }
+}
+
+#[derive(Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<&'a str>,
+}
+
+// an RFC 7807 problem details body, the status alone identifies the problem
+// so the type is `about:blank`
+pub fn problem_json(context: &ErrorContext<'_>) -> Option<ErrorPage> {
+    let problem = Problem {
+        kind: "about:blank",
+        title: context.status.reason(),
+        status: context.status.code(),
+        detail: detail_of(context.status, context.error),
+        request_id: context.request.and_then(HttpRequest::id),
+    };
+
+    serde_json::to_vec(&problem)
+        .ok()
+        .map(|body| ErrorPage::new("application/problem+json", body))
+}
+
+// the handler the configuration asks for, a configured page wins over a
+// problem body
+pub fn from_config(conf: &Config) -> Result<Option<Box<ErrorHandler>>> {
+    let pages = conf.error_pages.as_deref().map(from_dir).transpose()?;
+
+    Ok(match (pages, conf.problem_json) {
+        (Some(pages), true) => Some(Box::new(move |context: &ErrorContext<'_>| {
+            pages(context).or_else(|| problem_json(context))
+        })),
+        (Some(pages), false) => Some(pages),
+        (None, true) => Some(Box::new(problem_json)),
+        (None, false) => None,
+    })
+}
+
+// serves `<code>.html` from `dir` for every 4xx and 5xx status there is a
+// file for, the pages are read once so a failing disk cannot fail them
+pub fn from_dir(dir: &Path) -> Result<Box<ErrorHandler>> {
+    let mut pages = HashMap::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(code) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".html"))
+            .and_then(|code| code.parse::<u16>().ok())
+            .filter(|code| (400..600).contains(code))
+        else {
+            continue;
+        };
+
+        pages.insert(code, Bytes::from(fs::read(&path)?));
+    }
+
+    Ok(Box::new(move |context: &ErrorContext<'_>| {
+        pages
+            .get(&context.status.code())
+            .map(|page| ErrorPage::new("text/html; charset=utf-8", page.clone()))
+    }))
+}
+
+// only error responses without a body of their own get a page, anything a
+// handler wrote on purpose is left alone
+pub(crate) fn render(
+    handler: &ErrorHandler,
+    response: HttpResponse,
+    request: Option<&HttpRequest>,
+    error: Option<&Error>,
+) -> HttpResponse {
+    let status = response.status();
+    if status.code() < 400
+        || response.content_length() != Some(0)
+        || response.get_header("content-type").is_some()
+    {
+        return response;
+    }
+
+    match handler(&ErrorContext {
+        status,
+        request,
+        error,
+    }) {
+        Some(page) => response
+            .header("Content-Type", page.content_type)
+            .body(page.body),
+        None => response,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::response::Body;
+
+    #[test]
+    fn render_should_only_fill_in_empty_error_responses() {
+        let handler = |context: &ErrorContext<'_>| {
+            (context.status != StatusCode::NotFound).then(|| {
+                let body = match context.error {
+                    Some(error) => format!("{} {:?}", context.status.code(), error),
+                    None => context.status.code().to_string(),
+                };
+                ErrorPage::new("text/plain", body)
+            })
+        };
+
+        let test_cases = vec![
+            (HttpResponse::ok(), None, ""),
+            (HttpResponse::bad_request(), None, "400"),
+            (
+                HttpResponse::internal_server_error(),
+                Some(Error::InvalidRequest),
+                "500 InvalidRequest",
+            ),
+            (HttpResponse::not_found(), None, ""),
+            (HttpResponse::forbidden().body("custom"), None, "custom"),
+        ];
+
+        for (response, error, expected) in test_cases {
+            let status = response.status();
+            let mut rendered = render(&handler, response, None, error.as_ref());
+            assert_eq!(rendered.status(), status);
+
+            let body = match rendered.take_body() {
+                Body::Bytes(bytes) => bytes,
+                _ => unreachable!(),
+            };
+            assert_eq!(body, expected, "{status:?}");
+        }
+
+        let page = problem_json(&ErrorContext {
+            status: StatusCode::PayloadTooLarge,
+            request: None,
+            error: Some(&Error::PayloadTooLarge),
+        })
+        .unwrap();
+        assert_eq!(page.content_type, "application/problem+json");
+        assert_eq!(
+            page.body,
+            r#"{"type":"about:blank","title":"Payload Too Large","status":413,"detail":"The request body exceeds the configured limit."}"#
+        );
+    }
+}
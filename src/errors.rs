@@ -4,7 +4,6 @@ use std::string::FromUtf8Error;
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Debug, From)]
-#[allow(dead_code)]
 pub enum Error {
     InvalidRequest,
 
@@ -13,9 +12,38 @@ pub enum Error {
 
     InvalidProtocol,
     InvalidMethod,
+    UnsupportedVersion,
+    UnsupportedTransferCoding,
+
+    ConnectionClosed,
+
+    // an HTTP/2 connection error with its error code, see RFC 9113 section 7
+    Http2(u32),
+
+    PayloadTooLarge,
+    UriTooLong,
+    HeadersTooLarge,
 
     #[from]
     Io(std::io::Error),
+
+    InvalidTlsConfig,
+    InvalidConfig,
+
+    #[cfg(feature = "tracing")]
+    SubscriberAlreadySet,
+
+    #[from]
+    Tls(rustls::Error),
+
+    #[from]
+    Pem(rustls::pki_types::pem::Error),
+
+    #[from]
+    ConfigFile(toml::de::Error),
+
+    #[from]
+    Crypto(ring::error::Unspecified),
 }
 
 impl core::fmt::Display for Error {
@@ -0,0 +1,60 @@
+use crate::storage::Metadata;
+use std::time::UNIX_EPOCH;
+
+pub fn from_metadata(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos());
+
+    format!("\"{:x}-{:x}\"", metadata.len, modified)
+}
+
+fn opaque_tag(etag: &str) -> (&str, bool) {
+    match etag.strip_prefix("W/") {
+        Some(tag) => (tag, true),
+        None => (etag, false),
+    }
+}
+
+pub fn matches(a: &str, b: &str, weak: bool) -> bool {
+    let (a_tag, a_weak) = opaque_tag(a);
+    let (b_tag, b_weak) = opaque_tag(b);
+
+    a_tag == b_tag && (weak || (!a_weak && !b_weak))
+}
+
+// If-Match uses strong comparison, If-None-Match uses weak comparison
+pub fn matches_any(header: &str, etag: &str, weak: bool) -> bool {
+    header.trim() == "*"
+        || header
+            .split(',')
+            .map(|candidate| candidate.trim())
+            .any(|candidate| matches(candidate, etag, weak))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches_any_should_compare_strong_and_weak_tags() {
+        let test_cases = vec![
+            ("\"abc\"", "\"abc\"", false, true),
+            ("\"xyz\", \"abc\"", "\"abc\"", false, true),
+            ("*", "\"abc\"", false, true),
+            ("W/\"abc\"", "\"abc\"", true, true),
+            ("W/\"abc\"", "\"abc\"", false, false),
+            ("\"abc\"", "W/\"abc\"", false, false),
+            ("\"abd\"", "\"abc\"", true, false),
+        ];
+
+        for (header, etag, weak, expected) in test_cases {
+            assert_eq!(
+                matches_any(header, etag, weak),
+                expected,
+                "{header} vs {etag}"
+            );
+        }
+    }
+}
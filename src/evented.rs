@@ -0,0 +1,1005 @@
+use crate::chunked::{self, TrailerSection};
+use crate::context::Cancellation;
+use crate::errors::{Error, Result};
+#[cfg(feature = "h2")]
+use crate::h2;
+use crate::listener::{self, Acceptors, Listener};
+use crate::log;
+use crate::middleware::Pipeline;
+use crate::record::Recording;
+use crate::request::{BodyFraming, HttpRequest, RequestLimits};
+use crate::response::{SendFile, Upgrade};
+use crate::server::{self, AfterResponse, ConnectionGuard};
+use crate::shutdown::ShutdownHandle;
+use crate::thread_pool::ThreadPool;
+use crate::Config;
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token, Waker};
+use rustls::{ServerConnection, StreamOwned};
+use socket2::SockRef;
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, IoSlice, Read, Write};
+use std::net::SocketAddr;
+use std::os::fd::OwnedFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const WAKER: Token = Token(0);
+// listeners take the tokens from here on, connections the ones after them
+const FIRST_LISTENER: usize = 1;
+
+const READ_CHUNK: usize = 16 * 1024;
+// reads per wakeup before what arrived is parsed and the other connections
+// get their turn, a fast client cannot keep the loop to itself
+const READS_PER_TURN: usize = 16;
+// output pulled from a handler but not yet written, beyond it the handler
+// blocks until the client catches up
+const HIGH_WATER: usize = 64 * 1024;
+
+enum Output {
+    Data(Vec<u8>),
+    Done(io::Result<AfterResponse>),
+}
+
+// hands what a handler writes to the event loop, the bounded channel makes a
+// slow client hold up its own handler only
+struct ChannelWriter {
+    sender: SyncSender<Output>,
+    waker: Arc<Waker>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sender
+            .send(Output::Data(buf.to_vec()))
+            .map_err(|_| io::Error::from(ErrorKind::BrokenPipe))?;
+        self.waker.wake()?;
+        Ok(buf.len())
+    }
+
+    // one message for the lot, not one per slice
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let buf: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+        self.write(&buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SendFile for ChannelWriter {}
+
+// finds where a chunked body ends without decoding it, resuming where the
+// previous call stopped so a large body is scanned once
+#[derive(Default)]
+struct ChunkedScan {
+    pos: usize,
+    size: usize,
+    in_trailers: bool,
+    trailers: TrailerSection,
+}
+
+impl ChunkedScan {
+    fn advance(&mut self, body: &[u8], limits: &RequestLimits) -> Result<Option<usize>> {
+        loop {
+            let rest = &body[self.pos..];
+            let Some(line_end) = find(rest, b"\r\n") else {
+                if rest.len() > limits.max_header_line {
+                    return Err(Error::InvalidRequest);
+                }
+                return Ok(None);
+            };
+
+            let line = std::str::from_utf8(&rest[..line_end]).map_err(|_| Error::InvalidRequest)?;
+            if self.in_trailers {
+                self.pos += line_end + 2;
+                if self.trailers.add(line, limits)? {
+                    return Ok(Some(self.pos));
+                }
+                continue;
+            }
+
+            let size = chunked::parse_chunk_size(line)?;
+
+            if size == 0 {
+                self.pos += line_end + 2;
+                self.in_trailers = true;
+                continue;
+            }
+
+            if self.size.saturating_add(size) > limits.max_body_size {
+                return Err(Error::PayloadTooLarge);
+            }
+
+            let chunk_end = line_end + 2 + size;
+            if rest.len() < chunk_end + 2 {
+                return Ok(None);
+            }
+            if &rest[chunk_end..chunk_end + 2] != b"\r\n" {
+                return Err(Error::InvalidRequest);
+            }
+
+            self.size += size;
+            self.pos += chunk_end + 2;
+        }
+    }
+}
+
+// a request whose head has been parsed while its body is still arriving
+struct Pending {
+    req: HttpRequest,
+    head_len: usize,
+    framing: BodyFraming,
+    chunks: ChunkedScan,
+}
+
+// buffers what the client sent until a whole request is there
+#[derive(Default)]
+struct RequestParser {
+    input: Vec<u8>,
+    pending: Option<Pending>,
+}
+
+impl RequestParser {
+    fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    fn waits_for_body(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // the next request once all of it has arrived, its bytes are consumed
+    fn parse(&mut self, limits: &RequestLimits) -> Result<Option<HttpRequest>> {
+        if self.pending.is_none() {
+            let Some(head_len) = head_len(&self.input, limits)? else {
+                return Ok(None);
+            };
+
+            let req = HttpRequest::read_head(&mut &self.input[..head_len], limits)?;
+            req.check_host()?;
+            let framing = req.body_framing(limits)?;
+
+            self.pending = Some(Pending {
+                req,
+                head_len,
+                framing,
+                chunks: ChunkedScan::default(),
+            });
+        }
+
+        let Some(pending) = &mut self.pending else {
+            return Ok(None);
+        };
+        let body = &self.input[pending.head_len..];
+
+        let (body, consumed) = match pending.framing {
+            BodyFraming::Empty => (Vec::new(), 0),
+            BodyFraming::Length(length) if body.len() >= length => {
+                (body[..length].to_vec(), length)
+            }
+            BodyFraming::Length(_) => return Ok(None),
+            BodyFraming::Chunked => match pending.chunks.advance(body, limits)? {
+                Some(length) => (
+                    chunked::read_chunked_body(&mut &body[..length], limits)?,
+                    length,
+                ),
+                None => return Ok(None),
+            },
+        };
+
+        let Some(Pending {
+            mut req, head_len, ..
+        }) = self.pending.take()
+        else {
+            return Ok(None);
+        };
+        req.set_body(body);
+        self.input.drain(..head_len + consumed);
+
+        Ok(Some(req))
+    }
+}
+
+enum State {
+    Reading,
+    Responding(Receiver<Output>),
+    // closes once the output is written
+    Closing,
+    // hands the connection to the upgrade once the 101 is written
+    Upgrading(Upgrade),
+    // switches to HTTP/2 once the 101 is written, with the request and the
+    // settings that came with it
+    #[cfg(feature = "h2")]
+    Switching(Box<HttpRequest>, Vec<u8>),
+}
+
+enum Outcome {
+    Keep,
+    Close,
+    Upgrade(Upgrade),
+    // hands the connection to the HTTP/2 server once the handshake is out
+    #[cfg(feature = "h2")]
+    Http2(Option<(Box<HttpRequest>, Vec<u8>)>),
+}
+
+struct Connection {
+    socket: TcpStream,
+    tls: Option<Box<ServerConnection>>,
+    peer_addr: SocketAddr,
+    parser: RequestParser,
+    output: Vec<u8>,
+    written: usize,
+    state: State,
+    keep_alive: bool,
+    eof: bool,
+    // the read budget ran out with the socket maybe still readable, edge
+    // triggered readiness will not say so again
+    unread: bool,
+    request_started: Option<Instant>,
+    last_progress: Instant,
+    // of the request being answered
+    cancellation: Cancellation,
+    #[cfg(feature = "tracing")]
+    span: log::Span,
+    recording: Option<Recording>,
+    _guard: ConnectionGuard,
+}
+
+impl Connection {
+    fn new(
+        socket: TcpStream,
+        tls: Option<Box<ServerConnection>>,
+        peer_addr: SocketAddr,
+        guard: ConnectionGuard,
+    ) -> Self {
+        Connection {
+            socket,
+            tls,
+            peer_addr,
+            parser: RequestParser::default(),
+            output: Vec::new(),
+            written: 0,
+            state: State::Reading,
+            keep_alive: true,
+            eof: false,
+            unread: false,
+            request_started: None,
+            last_progress: Instant::now(),
+            cancellation: Cancellation::new(),
+            #[cfg(feature = "tracing")]
+            span: server::connection_span(Some(peer_addr)),
+            recording: None,
+            _guard: guard,
+        }
+    }
+
+    fn is_flushed(&self) -> bool {
+        self.written == self.output.len() && !self.tls.as_ref().is_some_and(|tls| tls.wants_write())
+    }
+
+    #[cfg(feature = "h2")]
+    fn is_h2(&self) -> bool {
+        self.tls
+            .as_ref()
+            .is_some_and(|tls| !tls.is_handshaking() && tls.alpn_protocol() == Some(h2::ALPN))
+    }
+
+    fn is_idle(&self) -> bool {
+        matches!(self.state, State::Reading) && self.parser.is_empty() && self.is_flushed()
+    }
+
+    // when the connection gives up waiting, and whether the client is owed
+    // a 408 at that point
+    fn deadline(&self, conf: &Config) -> Option<(Instant, bool)> {
+        if !self.is_flushed() {
+            return Some((self.last_progress + conf.write_timeout, false));
+        }
+
+        match (&self.state, self.request_started) {
+            (State::Reading, None) => Some((self.last_progress + conf.keep_alive_timeout, false)),
+            (State::Reading, Some(started)) if !self.parser.waits_for_body() => {
+                Some((started + conf.header_timeout, true))
+            }
+            (State::Reading, Some(_)) => Some((self.last_progress + conf.read_timeout, true)),
+            _ => None,
+        }
+    }
+
+    fn received(&mut self, bytes: &[u8]) {
+        self.parser.input.extend_from_slice(bytes);
+        self.last_progress = Instant::now();
+        if let Some(recording) = &mut self.recording {
+            recording.read(bytes);
+        }
+    }
+
+    fn read_available(&mut self) -> io::Result<()> {
+        let mut buf = [0; READ_CHUNK];
+        self.unread = false;
+
+        for _ in 0..READS_PER_TURN {
+            let read = match &mut self.tls {
+                None => self.socket.read(&mut buf),
+                Some(tls) => match tls.read_tls(&mut self.socket) {
+                    Ok(0) => Ok(0),
+                    Ok(_) => {
+                        tls.process_new_packets().map_err(io::Error::other)?;
+                        let mut plaintext = Vec::new();
+                        loop {
+                            match tls.reader().read(&mut buf) {
+                                Ok(0) => {
+                                    self.eof = true;
+                                    break;
+                                }
+                                Ok(n) => plaintext.extend_from_slice(&buf[..n]),
+                                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                                Err(e) => return Err(e),
+                            }
+                        }
+                        if !plaintext.is_empty() {
+                            self.received(&plaintext);
+                        }
+                        continue;
+                    }
+                    Err(e) => Err(e),
+                },
+            };
+
+            match read {
+                Ok(0) => {
+                    self.eof = true;
+                    return Ok(());
+                }
+                Ok(n) => self.received(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.unread = true;
+        Ok(())
+    }
+
+    // driven again without waiting for readiness, the socket may still hold
+    // what the read budget left
+    fn wants_turn(&self) -> bool {
+        self.unread && matches!(self.state, State::Reading)
+    }
+
+    fn write_available(&mut self) -> io::Result<()> {
+        loop {
+            let wrote = match &mut self.tls {
+                None => {
+                    if self.written == self.output.len() {
+                        break;
+                    }
+                    let wrote = self.socket.write(&self.output[self.written..]);
+                    if let Ok(n) = wrote {
+                        sent(&mut self.recording, &self.output, &mut self.written, n);
+                    }
+                    wrote
+                }
+                Some(tls) => {
+                    if self.written < self.output.len() {
+                        let n = tls.writer().write(&self.output[self.written..])?;
+                        sent(&mut self.recording, &self.output, &mut self.written, n);
+                    }
+                    if !tls.wants_write() {
+                        break;
+                    }
+                    tls.write_tls(&mut self.socket)
+                }
+            };
+
+            match wrote {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(_) => self.last_progress = Instant::now(),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if self.written == self.output.len() {
+            self.output.clear();
+            self.written = 0;
+        }
+
+        Ok(())
+    }
+
+    fn respond_with_error(&mut self, pipeline: &Pipeline, error: &Error) {
+        let response = pipeline.render_error(server::error_response(error), None, Some(error));
+        let _ = response.write_to(&mut self.output);
+        self.state = State::Closing;
+    }
+
+    // reads, dispatches and writes as far as the socket allows without
+    // blocking
+    fn drive(&mut self, server: &EventLoop) -> Outcome {
+        if let Err(e) = self.advance(server) {
+            log::warning!("Failed to handle connection, error {}", e);
+            return Outcome::Close;
+        }
+
+        #[cfg(feature = "h2")]
+        if self.is_h2() && self.is_flushed() {
+            return Outcome::Http2(None);
+        }
+
+        match std::mem::replace(&mut self.state, State::Closing) {
+            State::Closing if self.is_flushed() => Outcome::Close,
+            State::Upgrading(upgrade) if self.is_flushed() => Outcome::Upgrade(upgrade),
+            #[cfg(feature = "h2")]
+            State::Switching(req, settings) if self.is_flushed() => {
+                Outcome::Http2(Some((req, settings)))
+            }
+            State::Reading if self.eof && self.is_flushed() => Outcome::Close,
+            state => {
+                self.state = state;
+                Outcome::Keep
+            }
+        }
+    }
+
+    fn advance(&mut self, server: &EventLoop) -> io::Result<()> {
+        // readiness is edge triggered, so the socket is read again whenever
+        // the connection goes back to reading
+        loop {
+            let reading = matches!(self.state, State::Reading);
+            if reading {
+                self.read_available()?;
+            }
+
+            self.pull_output()?;
+
+            let dispatched = matches!(self.state, State::Reading) && self.dispatch(server)?;
+
+            self.write_available()?;
+
+            if !dispatched && (reading || !matches!(self.state, State::Reading)) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn pull_output(&mut self) -> io::Result<()> {
+        let State::Responding(receiver) = &self.state else {
+            return Ok(());
+        };
+
+        while self.output.len() - self.written < HIGH_WATER {
+            match receiver.try_recv() {
+                Ok(Output::Data(data)) => self.output.extend_from_slice(&data),
+                Ok(Output::Done(Ok(after))) => {
+                    self.state = match after {
+                        AfterResponse::Upgrade(upgrade) => State::Upgrading(upgrade),
+                        AfterResponse::KeepAlive => State::Reading,
+                        AfterResponse::Close => State::Closing,
+                    };
+                    return Ok(());
+                }
+                Ok(Output::Done(Err(e))) => return Err(e),
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => return Err(ErrorKind::BrokenPipe.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    // starts handling the next buffered request, pipelined ones wait until
+    // the response before them is complete
+    fn dispatch(&mut self, server: &EventLoop) -> io::Result<bool> {
+        #[cfg(feature = "h2")]
+        if self.is_h2() {
+            return Ok(false);
+        }
+
+        if !self.parser.is_empty() && self.request_started.is_none() {
+            self.request_started = Some(Instant::now());
+        }
+
+        let mut req = match self.parser.parse(&server.conf.limits) {
+            Ok(Some(req)) => req,
+            Ok(None) => return Ok(false),
+            Err(e) => {
+                self.respond_with_error(&server.pipeline, &e);
+                return Ok(false);
+            }
+        };
+
+        self.request_started = None;
+
+        if server.pool.is_saturated() {
+            let response = server
+                .pipeline
+                .render_error(server::overloaded(), Some(&req), None);
+            response.write_to(&mut self.output)?;
+            self.state = State::Closing;
+            return Ok(false);
+        }
+
+        self.cancellation = server::prepare(&mut req, Some(self.peer_addr), &server.conf);
+        self.keep_alive = req.keep_alive() && !server.shutdown.is_requested();
+
+        #[cfg(feature = "h2")]
+        if let Some(settings) = h2::h2c_settings(&req).filter(|_| self.keep_alive) {
+            if self.tls.is_none() {
+                h2::switching_protocols().write_to(&mut self.output)?;
+                self.state = State::Switching(Box::new(req), settings);
+                return Ok(false);
+            }
+        }
+
+        let (sender, receiver) = mpsc::sync_channel(8);
+        let mut writer = ChannelWriter {
+            sender,
+            waker: Arc::clone(&server.waker),
+        };
+        let pipeline = Arc::clone(&server.pipeline);
+        let keep_alive = self.keep_alive;
+        #[cfg(feature = "tracing")]
+        let parent = self.span.id();
+        server.pool.execute(move || {
+            let respond = || server::respond(&pipeline, req, keep_alive, &mut writer);
+            #[cfg(feature = "tracing")]
+            let result = log::enter(parent, respond);
+            #[cfg(not(feature = "tracing"))]
+            let result = respond();
+            let _ = writer.sender.send(Output::Done(result));
+            let _ = writer.waker.wake();
+        });
+
+        self.state = State::Responding(receiver);
+        Ok(true)
+    }
+}
+
+// moves past the `n` bytes of output the socket, or tls, took
+fn sent(recording: &mut Option<Recording>, output: &[u8], written: &mut usize, n: usize) {
+    if let Some(recording) = recording {
+        recording.wrote(&output[*written..*written + n]);
+    }
+    *written += n;
+}
+
+// the length of the request head once its blank line arrived
+fn head_len(input: &[u8], limits: &RequestLimits) -> Result<Option<usize>> {
+    if let Some(end) = find(input, b"\r\n\r\n") {
+        return Ok(Some(end + 4));
+    }
+
+    // a bare LF never ends the head, leave rejecting it to the parser
+    if input
+        .iter()
+        .enumerate()
+        .any(|(i, &b)| b == b'\n' && (i == 0 || input[i - 1] != b'\r'))
+    {
+        return Ok(Some(input.len()));
+    }
+
+    if !input.contains(&b'\n') && input.len() >= limits.max_header_line {
+        return Err(Error::UriTooLong);
+    }
+    if input.len() > limits.max_header_bytes + limits.max_header_line {
+        return Err(Error::HeadersTooLarge);
+    }
+
+    Ok(None)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// an upgraded connection back in blocking mode, reads see the bytes that
+// arrived behind the handshake first
+struct Resumed<S: Read + Write> {
+    buffered: io::Cursor<Vec<u8>>,
+    inner: S,
+}
+
+impl<S: Read + Write> Read for Resumed<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.buffered.read(buf)? {
+            0 => self.inner.read(buf),
+            n => Ok(n),
+        }
+    }
+}
+
+impl<S: Read + Write> Write for Resumed<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct EventLoop {
+    conf: Arc<Config>,
+    pipeline: Arc<Pipeline>,
+    pool: ThreadPool,
+    waker: Arc<Waker>,
+    shutdown: ShutdownHandle,
+}
+
+impl EventLoop {
+    // runs the upgrade on a worker, the way the threaded model does after
+    // a 101
+    fn upgrade(&self, connection: Connection, upgrade: Upgrade) -> io::Result<()> {
+        let socket = std::net::TcpStream::from(OwnedFd::from(connection.socket));
+        socket.set_nonblocking(false)?;
+        socket.set_read_timeout(Some(self.conf.read_timeout))?;
+        socket.set_write_timeout(Some(self.conf.write_timeout))?;
+
+        let buffered = io::Cursor::new(connection.parser.input);
+        // still counted against the connection limit while upgraded
+        let guard = connection._guard;
+        let run = move || {
+            let _guard = guard;
+            let result = match connection.tls {
+                Some(tls) => upgrade.run(&mut Resumed {
+                    buffered,
+                    inner: StreamOwned::new(*tls, socket),
+                }),
+                None => upgrade.run(&mut Resumed {
+                    buffered,
+                    inner: socket,
+                }),
+            };
+            if let Err(e) = result {
+                log::warning!("Failed to handle connection, error {}", e);
+            }
+        };
+
+        self.pool.execute(run);
+        Ok(())
+    }
+
+    // serves an `h2` or upgraded h2c connection on a worker, HTTP/2
+    // multiplexes its streams on threads of its own rather than through the
+    // event loop
+    #[cfg(feature = "h2")]
+    fn http2(
+        &self,
+        connection: Connection,
+        upgraded: Option<(Box<HttpRequest>, Vec<u8>)>,
+    ) -> io::Result<()> {
+        let socket = std::net::TcpStream::from(OwnedFd::from(connection.socket));
+        socket.set_nonblocking(false)?;
+
+        let (conf, pipeline) = (Arc::clone(&self.conf), Arc::clone(&self.pipeline));
+        let (shutdown, buffered) = (self.shutdown.clone(), connection.parser.input);
+        let (tls, guard) = (connection.tls, connection._guard);
+        self.pool.execute(move || {
+            let _guard = guard;
+            let result = match (tls, upgraded) {
+                (Some(tls), _) => {
+                    h2::serve_tls_session(*tls, socket, buffered, &pipeline, &conf, &shutdown)
+                }
+                (None, Some((req, settings))) => h2::serve_h2c(
+                    socket, buffered, *req, settings, &pipeline, &conf, &shutdown,
+                ),
+                (None, None) => Ok(()),
+            };
+            if let Err(e) = result {
+                log::warning!("Failed to handle connection, error {}", e);
+            }
+        });
+        Ok(())
+    }
+}
+
+// turns a connection away without reading from it, tls ones are just closed
+fn reject(mut socket: TcpStream, tls: bool, pipeline: &Pipeline) {
+    if tls {
+        return;
+    }
+
+    let mut out = Vec::new();
+    if pipeline
+        .render_error(server::overloaded(), None, None)
+        .write_to(&mut out)
+        .is_ok()
+    {
+        let _ = socket.write(&out);
+    }
+}
+
+pub(crate) fn listen(
+    listeners: Vec<Acceptors>,
+    conf: Config,
+    pipeline: Pipeline,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    shutdown: ShutdownHandle,
+    queue_depth: Arc<AtomicUsize>,
+    open: Arc<AtomicUsize>,
+) -> Result<()> {
+    if shutdown.is_requested() {
+        return Ok(());
+    }
+
+    let mut poll = Poll::new()?;
+    let (mut listeners, accepted): (Vec<_>, Vec<_>) = listeners
+        .into_iter()
+        .enumerate()
+        .map(|(index, Acceptors { listener, accepted })| {
+            // connections here are TCP all the way to the upgrade and
+            // HTTP/2 hand-offs
+            let Listener::Tcp(listener) = listener else {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "unix sockets need the threads io model",
+                ));
+            };
+            listener.set_nonblocking(true)?;
+            let mut listener = TcpListener::from_std(listener);
+            poll.registry().register(
+                &mut listener,
+                Token(FIRST_LISTENER + index),
+                Interest::READABLE,
+            )?;
+            // the loop here is the only one accepting
+            let accepted = accepted.into_iter().next().unwrap_or_default();
+            Ok((listener, accepted))
+        })
+        .collect::<io::Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+
+    let server = EventLoop {
+        pool: ThreadPool::new(conf.workers, conf.backlog, queue_depth),
+        conf: Arc::new(conf),
+        pipeline: Arc::new(pipeline),
+        waker: Arc::new(Waker::new(poll.registry(), WAKER)?),
+        shutdown,
+    };
+
+    let mut connections: HashMap<Token, Connection> = HashMap::new();
+    let first_connection = FIRST_LISTENER + listeners.len();
+    let mut next_token = first_connection;
+    let mut accepting = true;
+    let mut events = Events::with_capacity(1024);
+    // connections whose read budget ran out, served again next time round
+    let mut unread: Vec<Token> = Vec::new();
+
+    while accepting || !connections.is_empty() {
+        let now = Instant::now();
+        let timeout = if unread.is_empty() {
+            connections
+                .values()
+                .filter_map(|connection| connection.deadline(&server.conf))
+                .map(|(deadline, _)| deadline.saturating_duration_since(now))
+                .min()
+        } else {
+            Some(Duration::ZERO)
+        };
+
+        match poll.poll(&mut events, timeout) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut ready = std::mem::take(&mut unread);
+
+        for event in events.iter() {
+            match event.token() {
+                Token(index) if (FIRST_LISTENER..first_connection).contains(&index) => loop {
+                    let (socket, peer_addr) = match listeners[index - FIRST_LISTENER].accept() {
+                        Ok(socket) => {
+                            accepted[index - FIRST_LISTENER].fetch_add(1, Ordering::Relaxed);
+                            socket
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => {
+                            log::warning!("Failed to accept connection, error {}", e);
+                            break;
+                        }
+                    };
+
+                    if server.shutdown.is_requested() {
+                        break;
+                    }
+
+                    if let Err(e) = listener::tune(SockRef::from(&socket), &server.conf.tcp) {
+                        log::warning!("Failed to set socket options, error {}", e);
+                    }
+
+                    let Some(guard) = ConnectionGuard::acquire(&open, server.conf.max_connections)
+                    else {
+                        reject(socket, tls_config.is_some(), &server.pipeline);
+                        continue;
+                    };
+
+                    let tls = match &tls_config {
+                        Some(tls_config) => match ServerConnection::new(Arc::clone(tls_config)) {
+                            Ok(tls) => Some(Box::new(tls)),
+                            Err(e) => {
+                                log::warning!("Failed to handle connection, error {}", e);
+                                continue;
+                            }
+                        },
+                        None => None,
+                    };
+
+                    let token = Token(next_token);
+                    next_token += 1;
+
+                    let mut connection = Connection::new(socket, tls, peer_addr, guard);
+                    connection.recording = server.conf.record.as_deref().map(Recording::start);
+                    if let Err(e) = poll.registry().register(
+                        &mut connection.socket,
+                        token,
+                        Interest::READABLE | Interest::WRITABLE,
+                    ) {
+                        log::warning!("Failed to handle connection, error {}", e);
+                        continue;
+                    }
+                    connections.insert(token, connection);
+                },
+                WAKER => ready.extend(
+                    connections
+                        .iter()
+                        .filter(|(_, connection)| matches!(connection.state, State::Responding(_)))
+                        .map(|(token, _)| *token),
+                ),
+                token => {
+                    // the client hung up on a request still being answered
+                    if event.is_read_closed() || event.is_error() {
+                        if let Some(connection) = connections.get(&token) {
+                            if matches!(connection.state, State::Responding(_)) {
+                                connection.cancellation.cancel();
+                            }
+                        }
+                    }
+                    ready.push(token);
+                }
+            }
+        }
+
+        if accepting && server.shutdown.is_requested() {
+            accepting = false;
+            for listener in &mut listeners {
+                let _ = poll.registry().deregister(listener);
+            }
+        }
+
+        for token in ready {
+            let Some(connection) = connections.get_mut(&token) else {
+                continue;
+            };
+
+            match connection.drive(&server) {
+                Outcome::Keep if connection.wants_turn() && !unread.contains(&token) => {
+                    unread.push(token)
+                }
+                Outcome::Keep => (),
+                Outcome::Close => {
+                    connections.remove(&token);
+                }
+                Outcome::Upgrade(upgrade) => {
+                    if let Some(mut connection) = connections.remove(&token) {
+                        let _ = poll.registry().deregister(&mut connection.socket);
+                        if let Err(e) = server.upgrade(connection, upgrade) {
+                            log::warning!("Failed to handle connection, error {}", e);
+                        }
+                    }
+                }
+                #[cfg(feature = "h2")]
+                Outcome::Http2(upgraded) => {
+                    if let Some(mut connection) = connections.remove(&token) {
+                        let _ = poll.registry().deregister(&mut connection.socket);
+                        if let Err(e) = server.http2(connection, upgraded) {
+                            log::warning!("Failed to handle connection, error {}", e);
+                        }
+                    }
+                }
+            }
+        }
+
+        let now = Instant::now();
+        connections.retain(|_, connection| {
+            if !accepting && connection.is_idle() {
+                return false;
+            }
+
+            match connection.deadline(&server.conf) {
+                Some((deadline, _)) if deadline > now => true,
+                Some((_, true)) => {
+                    let timed_out = Error::Io(ErrorKind::TimedOut.into());
+                    connection.respond_with_error(&server.pipeline, &timed_out);
+                    // one attempt, the client had its chance
+                    let _ = connection.write_available();
+                    false
+                }
+                Some((_, false)) => false,
+                None => true,
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_should_wait_for_complete_requests() {
+        let limits = RequestLimits {
+            max_body_size: 16,
+            max_header_line: 64,
+            ..RequestLimits::default()
+        };
+
+        let test_cases = vec![
+            ("GET / HTTP/1.1\r\nHost: x\r\n", Ok(None)),
+            ("GET / HTTP/1.1\r\nHost: x\r\n\r\nGET", Ok(Some(""))),
+            (
+                "POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhel",
+                Ok(None),
+            ),
+            (
+                "POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello",
+                Ok(Some("hello")),
+            ),
+            (
+                "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n",
+                Ok(None),
+            ),
+            (
+                "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n5;x=y\r\nhello\r\n0\r\nA: b\r\n\r\n",
+                Ok(Some("hello")),
+            ),
+            (
+                "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n11\r\n",
+                Err("PayloadTooLarge".to_string()),
+            ),
+            (
+                "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhello",
+                Err("InvalidRequest".to_string()),
+            ),
+            ("GET / HTTP/1.1\nHost: x\n", Err("InvalidRequest".to_string())),
+            ("GET / HTTP/1.1\r\n\r\n", Err("InvalidRequest".to_string())),
+            (
+                "GET /0123456789012345678901234567890123456789012345678901234567890123",
+                Err("UriTooLong".to_string()),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            // fed one byte at a time, like the slowest client would send it
+            let mut parser = RequestParser::default();
+            let mut result = Ok(None);
+            for &byte in input.as_bytes() {
+                parser.input.push(byte);
+                result = parser.parse(&limits).map_err(|e| format!("{e:?}"));
+                if !matches!(result, Ok(None)) {
+                    break;
+                }
+            }
+
+            let body = result.map(|req| {
+                req.map(|req| String::from_utf8_lossy(req.body().unwrap_or_default()).into_owned())
+            });
+            assert_eq!(
+                body,
+                expected.map(|body| body.map(str::to_owned)),
+                "{input:?}"
+            );
+        }
+    }
+}
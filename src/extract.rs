@@ -0,0 +1,267 @@
+use crate::headers::HeaderMap;
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, StatusCode};
+use crate::router::PathParams;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// a value a handler takes as an argument, built from the request, the
+// response it fails with is sent instead of calling the handler
+pub trait FromRequest: Sized {
+    fn from_request(req: &HttpRequest, params: &PathParams) -> Result<Self, HttpResponse>;
+}
+
+impl FromRequest for PathParams {
+    fn from_request(_: &HttpRequest, params: &PathParams) -> Result<Self, HttpResponse> {
+        Ok(params.clone())
+    }
+}
+
+// the decoded query string, a name given more than once keeps its last value
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Query(HashMap<String, String>);
+
+impl Query {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|v| v.as_str())
+    }
+
+    fn parse(query: &str) -> Self {
+        Query(
+            query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (form_decode(name), form_decode(value))
+                })
+                .collect(),
+        )
+    }
+}
+
+impl FromRequest for Query {
+    fn from_request(req: &HttpRequest, _: &PathParams) -> Result<Self, HttpResponse> {
+        Ok(req
+            .target()
+            .split_once('?')
+            .map(|(_, query)| Query::parse(query))
+            .unwrap_or_default())
+    }
+}
+
+// application/x-www-form-urlencoded, '+' is a space and malformed escapes
+// are kept as they are
+fn form_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let escaped = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match escaped {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Headers(pub HeaderMap);
+
+impl FromRequest for Headers {
+    fn from_request(req: &HttpRequest, _: &PathParams) -> Result<Self, HttpResponse> {
+        Ok(Headers(req.headers().clone()))
+    }
+}
+
+// the request body, empty when there was none
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Body(pub Vec<u8>);
+
+impl FromRequest for Body {
+    fn from_request(req: &HttpRequest, _: &PathParams) -> Result<Self, HttpResponse> {
+        Ok(Body(req.body().unwrap_or_default().to_vec()))
+    }
+}
+
+// a JSON body as an argument, or a JSON response when returned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(req: &HttpRequest, _: &PathParams) -> Result<Self, HttpResponse> {
+        let is_json = req.header("content-type").is_some_and(|content_type| {
+            let essence = content_type
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            essence == "application/json" || essence.ends_with("+json")
+        });
+        if !is_json {
+            return Err(HttpResponse::new(StatusCode::UnsupportedMediaType)
+                .header("Accept", "application/json"));
+        }
+
+        serde_json::from_slice(req.body().unwrap_or_default())
+            .map(Json)
+            .map_err(|_| HttpResponse::bad_request())
+    }
+}
+
+impl<T: Serialize> From<Json<T>> for HttpResponse {
+    fn from(Json(value): Json<T>) -> Self {
+        match serde_json::to_vec(&value) {
+            Ok(body) => HttpResponse::ok()
+                .header("Content-Type", "application/json")
+                .body(body),
+            Err(_) => HttpResponse::internal_server_error(),
+        }
+    }
+}
+
+// a route function taking extractors, Args are their types
+pub trait Handler<Args>: Send + Sync + 'static {
+    fn call(&self, req: &HttpRequest, params: &PathParams) -> HttpResponse;
+}
+
+macro_rules! impl_handler {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg,)*> Handler<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + Send + Sync + 'static,
+            R: Into<HttpResponse>,
+            $($arg: FromRequest,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn call(&self, req: &HttpRequest, params: &PathParams) -> HttpResponse {
+                $(
+                    let $arg = match $arg::from_request(req, params) {
+                        Ok(value) => value,
+                        Err(rejection) => return rejection,
+                    };
+                )*
+                self($($arg),*).into()
+            }
+        }
+    };
+}
+
+impl_handler!();
+impl_handler!(A);
+impl_handler!(A, B);
+impl_handler!(A, B, C);
+impl_handler!(A, B, C, D);
+impl_handler!(A, B, C, D, E);
+
+// turns a handler into the function the router takes
+pub fn handler<Args: 'static, H: Handler<Args>>(
+    handler: H,
+) -> impl Fn(&HttpRequest, &PathParams) -> HttpResponse + Send + Sync + 'static {
+    move |req: &HttpRequest, params: &PathParams| handler.call(req, params)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::{HttpMethod, RequestLimits};
+    use crate::response;
+    use crate::router::Router;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Serialize)]
+    struct Greeting {
+        name: String,
+    }
+
+    fn greet(params: PathParams, query: Query, Headers(headers): Headers) -> HttpResponse {
+        HttpResponse::ok().body(format!(
+            "{} {} {}",
+            params.get("id").unwrap_or_default(),
+            query.get("q").unwrap_or("-"),
+            headers.get("user-agent").unwrap_or("-")
+        ))
+    }
+
+    fn echo(Json(greeting): Json<Greeting>) -> Json<Greeting> {
+        Json(Greeting {
+            name: greeting.name.to_uppercase(),
+        })
+    }
+
+    #[test]
+    fn handler_should_call_with_extracted_arguments() {
+        let mut router = Router::new();
+        router
+            .get("/greet/:id", handler(greet))
+            .post("/json", handler(echo))
+            .post(
+                "/len",
+                handler(|Body(body): Body| HttpResponse::ok().body(body.len().to_string())),
+            )
+            .get("/none", handler(HttpResponse::created));
+
+        let test_cases = vec![
+            (
+                "GET /greet/7?q=a+b%21&x HTTP/1.1\r\nUser-Agent: t\r\n\r\n",
+                200,
+                "7 a b! t",
+            ),
+            ("GET /greet/7 HTTP/1.1\r\n\r\n", 200, "7 - -"),
+            (
+                "POST /json HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 13\r\n\r\n{\"name\":\"ab\"}",
+                200,
+                "{\"name\":\"AB\"}",
+            ),
+            (
+                "POST /json HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}",
+                400,
+                "",
+            ),
+            (
+                "POST /json HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\n{}",
+                415,
+                "",
+            ),
+            (
+                "POST /len HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc",
+                200,
+                "3",
+            ),
+            ("GET /none HTTP/1.1\r\n\r\n", 201, ""),
+        ];
+
+        for (raw, status, body) in test_cases {
+            let mut reader = raw.as_bytes();
+            let mut req = HttpRequest::read_head(&mut reader, &RequestLimits::default()).unwrap();
+            req.set_body(reader.to_vec());
+            let mut response = router.handle(&req);
+
+            assert_eq!(response.status().code(), status, "{raw:?}");
+            let response::Body::Bytes(bytes) = response.take_body() else {
+                panic!("{raw:?}");
+            };
+            assert_eq!(bytes, body.as_bytes(), "{raw:?}");
+        }
+        assert_eq!(
+            router.allowed_methods("/json"),
+            vec![HttpMethod::POST, HttpMethod::OPTIONS]
+        );
+    }
+}
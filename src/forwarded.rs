@@ -0,0 +1,205 @@
+use crate::cidr::Cidr;
+use crate::request::HttpRequest;
+use std::net::{IpAddr, SocketAddr};
+
+const HEADERS: [&str; 4] = [
+    "forwarded",
+    "x-forwarded-for",
+    "x-forwarded-proto",
+    "x-forwarded-host",
+];
+
+// what one proxy on the way says about the client it got the request from
+#[derive(Debug, Default, PartialEq)]
+struct Hop {
+    // None for an obfuscated or unknown address
+    client: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+// takes the client, scheme and host from the forwarding headers when the
+// peer is a trusted proxy, anyone else could make them up so they are
+// removed before a handler sees them
+pub(crate) fn resolve(req: &mut HttpRequest, peer_addr: Option<SocketAddr>, trusted: &[Cidr]) {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|range| range.contains(ip));
+    let Some(peer) = peer_addr.map(|addr| addr.ip()).filter(|ip| is_trusted(*ip)) else {
+        for name in HEADERS {
+            req.headers_mut().remove(name);
+        }
+        return;
+    };
+
+    // the nearest proxies come last, going back from the peer the first
+    // address that is not a trusted proxy is the client
+    let hops = hops(req);
+    let mut client = (peer, None);
+    for hop in hops.iter().rev() {
+        let Some(ip) = hop.client else {
+            break;
+        };
+        client = (ip, Some(hop));
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+
+    let (ip, hop) = client;
+    let context = req.context_mut();
+    context.set_client_ip(Some(ip));
+    if let Some(hop) = hop {
+        if let Some(proto) = hop.proto.as_deref() {
+            if proto.eq_ignore_ascii_case("http") || proto.eq_ignore_ascii_case("https") {
+                context.set_scheme(proto.to_ascii_lowercase());
+            }
+        }
+        context.set_forwarded_host(hop.host.clone());
+    }
+}
+
+// from Forwarded when the proxies sent it, otherwise from X-Forwarded-For,
+// with the scheme and host the proxy next to the server passed on
+fn hops(req: &HttpRequest) -> Vec<Hop> {
+    if let Some(forwarded) = req.headers().get_joined("forwarded") {
+        return forwarded.split(',').map(parse_element).collect();
+    }
+
+    let last = |name: &str| {
+        req.headers()
+            .get_joined(name)
+            .and_then(|value| value.rsplit(',').next().map(|v| v.trim().to_owned()))
+            .filter(|value| !value.is_empty())
+    };
+    let mut hops: Vec<Hop> = req
+        .headers()
+        .get_joined("x-forwarded-for")
+        .map(|forwarded_for| {
+            forwarded_for
+                .split(',')
+                .map(|client| Hop {
+                    client: parse_node(client),
+                    ..Hop::default()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(hop) = hops.last_mut() {
+        hop.proto = last("x-forwarded-proto");
+        hop.host = last("x-forwarded-host");
+    }
+    hops
+}
+
+// one element of a Forwarded header, `for=192.0.2.60;proto=https;host=a`
+// as in RFC 7239
+fn parse_element(element: &str) -> Hop {
+    let mut hop = Hop::default();
+    for pair in element.split(';') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match name.trim().to_ascii_lowercase().as_str() {
+            "for" => hop.client = parse_node(value),
+            "proto" => hop.proto = Some(value.to_owned()),
+            "host" => hop.host = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    hop
+}
+
+// an address that may come with a port, IPv6 ones then in brackets
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| node.trim_matches(['[', ']']).parse().ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::RequestLimits;
+
+    #[test]
+    fn resolve_should_trust_forwarding_headers_from_trusted_proxies_only() {
+        let trusted: Vec<Cidr> = vec!["10.0.0.0/8".parse().unwrap()];
+        let proxy: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let stranger: SocketAddr = "198.51.100.7:40000".parse().unwrap();
+
+        let test_cases = vec![
+            (
+                stranger,
+                "X-Forwarded-For: 203.0.113.9\r\nX-Forwarded-Proto: https\r\n",
+                "198.51.100.7",
+                "http",
+                "example.com",
+            ),
+            (
+                proxy,
+                "X-Forwarded-For: 203.0.113.9\r\nX-Forwarded-Proto: https\r\nX-Forwarded-Host: Public.example:443\r\n",
+                "203.0.113.9",
+                "https",
+                "public.example",
+            ),
+            (
+                proxy,
+                "X-Forwarded-For: 192.0.2.1, 203.0.113.9, 10.0.0.2\r\n",
+                "203.0.113.9",
+                "http",
+                "example.com",
+            ),
+            (
+                proxy,
+                "X-Forwarded-For: 10.0.0.3, 10.0.0.2\r\n",
+                "10.0.0.3",
+                "http",
+                "example.com",
+            ),
+            (
+                proxy,
+                "X-Forwarded-For: unknown, 10.0.0.2\r\n",
+                "10.0.0.2",
+                "http",
+                "example.com",
+            ),
+            (
+                proxy,
+                "Forwarded: for=192.0.2.1;proto=http, for=\"[2001:db8::1]:4711\";proto=https;host=public.example\r\nX-Forwarded-For: 192.0.2.99\r\n",
+                "2001:db8::1",
+                "https",
+                "public.example",
+            ),
+            (
+                proxy,
+                "X-Forwarded-Proto: gopher\r\n",
+                "10.0.0.1",
+                "http",
+                "example.com",
+            ),
+        ];
+
+        for (peer, headers, client_ip, scheme, host) in test_cases {
+            let raw = format!("GET / HTTP/1.1\r\nHost: example.com\r\n{headers}\r\n");
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            req.context_mut().set_peer_addr(Some(peer));
+            resolve(&mut req, Some(peer), &trusted);
+
+            assert_eq!(
+                req.client_ip(),
+                Some(client_ip.parse().unwrap()),
+                "{headers}"
+            );
+            assert_eq!(req.scheme(), scheme, "{headers}");
+            assert_eq!(req.host().as_deref(), Some(host), "{headers}");
+            assert_eq!(
+                HEADERS.iter().any(|name| req.headers().contains(name)),
+                peer == proxy && !headers.is_empty(),
+                "{headers}"
+            );
+        }
+    }
+}
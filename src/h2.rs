@@ -0,0 +1,1222 @@
+use crate::context::Cancellation;
+use crate::errors::{Error, Result};
+use crate::headers::HeaderMap;
+use crate::hpack::{self, Decoder};
+use crate::log;
+use crate::middleware::Pipeline;
+use crate::request::{HttpMethod, HttpRequest, HttpVersion, RequestLimits};
+use crate::response::{Body, HttpResponse, StatusCode};
+use crate::server;
+use crate::shutdown::ShutdownHandle;
+use crate::stream;
+use crate::Config;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rustls::{ServerConnection, StreamOwned};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::{self, Scope};
+use std::time::Duration;
+
+// the ALPN protocol id for HTTP/2 over TLS
+pub(crate) const ALPN: &[u8] = b"h2";
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// frame types, see RFC 9113 section 6
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+// error codes, see RFC 9113 section 7
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+const DEFAULT_FRAME_SIZE: usize = 16_384;
+const MAX_FRAME_SIZE: usize = (1 << 24) - 1;
+const HEADER_TABLE_SIZE: usize = 4096;
+// every stream is handled on a thread of its own
+const MAX_CONCURRENT_STREAMS: usize = 32;
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> Result<Frame> {
+    let mut head = [0; 9];
+    reader.read_exact(&mut head)?;
+
+    let length = usize::from(head[0]) << 16 | usize::from(head[1]) << 8 | usize::from(head[2]);
+    if length > DEFAULT_FRAME_SIZE {
+        return Err(Error::Http2(FRAME_SIZE_ERROR));
+    }
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+
+    Ok(Frame {
+        kind: head[3],
+        flags: head[4],
+        stream_id: u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff,
+        payload,
+    })
+}
+
+fn encode_frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&stream_id.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+// drops the padding of DATA and HEADERS frames
+fn unpad(frame: &Frame) -> Result<&[u8]> {
+    if frame.flags & PADDED == 0 {
+        return Ok(&frame.payload);
+    }
+
+    let (&padding, rest) = frame
+        .payload
+        .split_first()
+        .ok_or(Error::Http2(FRAME_SIZE_ERROR))?;
+    rest.len()
+        .checked_sub(usize::from(padding))
+        .map(|length| &rest[..length])
+        .ok_or(Error::Http2(PROTOCOL_ERROR))
+}
+
+fn read_u32(payload: &[u8]) -> u32 {
+    u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]])
+}
+
+// the send side of flow control, see RFC 9113 section 5.2
+struct Flow {
+    connection: i64,
+    // the window of every stream still being answered
+    streams: HashMap<u32, i64>,
+    initial: i64,
+    max_frame_size: usize,
+    closed: bool,
+}
+
+// what the reading side and the streams answering requests share
+struct Shared<W: Write> {
+    writer: Mutex<W>,
+    flow: Mutex<Flow>,
+    flow_changed: Condvar,
+    // of the requests being answered
+    cancellations: Mutex<HashMap<u32, Cancellation>>,
+    write_timeout: Duration,
+}
+
+impl<W: Write> Shared<W> {
+    fn flow(&self) -> MutexGuard<'_, Flow> {
+        self.flow.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self, frames: &[u8]) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        writer.write_all(frames)?;
+        writer.flush()
+    }
+
+    fn send(&self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        encode_frame(kind, flags, stream_id, payload, &mut frame);
+        self.write(&frame)
+    }
+
+    fn reset(&self, stream_id: u32, code: u32) -> io::Result<()> {
+        self.close_stream(stream_id);
+        self.send(RST_STREAM, 0, stream_id, &code.to_be_bytes())
+    }
+
+    fn cancellations(&self) -> MutexGuard<'_, HashMap<u32, Cancellation>> {
+        self.cancellations.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn close_stream(&self, stream_id: u32) {
+        self.flow().streams.remove(&stream_id);
+        self.cancellations().remove(&stream_id);
+        self.flow_changed.notify_all();
+    }
+
+    // the handlers still running learn the client is gone
+    fn close(&self) {
+        self.flow().closed = true;
+        for cancellation in self.cancellations().values() {
+            cancellation.cancel();
+        }
+        self.flow_changed.notify_all();
+    }
+
+    // a header block too large for one frame continues in CONTINUATION
+    // frames, which have to follow without anything in between
+    fn send_headers(
+        &self,
+        stream_id: u32,
+        fields: &[(String, String)],
+        end_stream: bool,
+    ) -> io::Result<()> {
+        let mut block = Vec::new();
+        hpack::encode(
+            fields
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+            &mut block,
+        );
+
+        let max_frame_size = self.flow().max_frame_size;
+        let mut frames = Vec::with_capacity(block.len() + 9);
+        let mut fragments = block.chunks(max_frame_size).peekable();
+        let mut kind = HEADERS;
+
+        // an empty block still needs its HEADERS frame
+        if fragments.peek().is_none() {
+            encode_frame(
+                HEADERS,
+                END_HEADERS | end_stream as u8,
+                stream_id,
+                &[],
+                &mut frames,
+            );
+        }
+
+        while let Some(fragment) = fragments.next() {
+            let mut flags = if fragments.peek().is_none() {
+                END_HEADERS
+            } else {
+                0
+            };
+            if kind == HEADERS && end_stream {
+                flags |= END_STREAM;
+            }
+            encode_frame(kind, flags, stream_id, fragment, &mut frames);
+            kind = CONTINUATION;
+        }
+
+        self.write(&frames)
+    }
+
+    // waits for the peer to open the stream and connection windows as far as
+    // needed, a stream that was reset or a closed connection ends it
+    fn send_data(&self, stream_id: u32, mut data: &[u8], end_stream: bool) -> io::Result<()> {
+        if data.is_empty() {
+            return match end_stream {
+                true => self.send(DATA, END_STREAM, stream_id, &[]),
+                false => Ok(()),
+            };
+        }
+
+        while !data.is_empty() {
+            let mut flow = self.flow();
+            let length = loop {
+                if flow.closed {
+                    return Err(ErrorKind::BrokenPipe.into());
+                }
+                let Some(&window) = flow.streams.get(&stream_id) else {
+                    return Err(ErrorKind::ConnectionReset.into());
+                };
+
+                let available = window.min(flow.connection).min(flow.max_frame_size as i64);
+                if available > 0 {
+                    break data.len().min(available as usize);
+                }
+
+                let (guard, timeout) = self
+                    .flow_changed
+                    .wait_timeout(flow, self.write_timeout)
+                    .unwrap_or_else(|e| e.into_inner());
+                if timeout.timed_out() {
+                    return Err(ErrorKind::TimedOut.into());
+                }
+                flow = guard;
+            };
+
+            flow.connection -= length as i64;
+            if let Some(window) = flow.streams.get_mut(&stream_id) {
+                *window -= length as i64;
+            }
+            drop(flow);
+
+            let (chunk, rest) = data.split_at(length);
+            let flags = if rest.is_empty() && end_stream {
+                END_STREAM
+            } else {
+                0
+            };
+            self.send(DATA, flags, stream_id, chunk)?;
+            data = rest;
+        }
+
+        Ok(())
+    }
+}
+
+// the fields HTTP/2 has no use for, they only make sense on one HTTP/1
+// connection, see RFC 9113 section 8.2.2
+fn is_connection_specific(name: &str) -> bool {
+    [
+        "connection",
+        "keep-alive",
+        "proxy-connection",
+        "transfer-encoding",
+        "upgrade",
+    ]
+    .iter()
+    .any(|field| name.eq_ignore_ascii_case(field))
+}
+
+// builds the request from a decoded header block, a malformed one is a
+// stream error, see RFC 9113 section 8.1.1
+fn request_from(fields: Vec<(String, String)>, limits: &RequestLimits) -> Result<HttpRequest> {
+    let (mut method, mut scheme, mut path, mut authority) = (None, None, None, None);
+    let mut headers = HeaderMap::new();
+    let mut cookies = Vec::new();
+    let mut header_bytes = 0;
+
+    for (name, value) in fields {
+        header_bytes += name.len() + value.len();
+
+        if let Some(pseudo) = name.strip_prefix(':') {
+            let slot = match pseudo {
+                "method" => &mut method,
+                "scheme" => &mut scheme,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                _ => return Err(Error::InvalidRequest),
+            };
+            if !headers.is_empty() || slot.replace(value).is_some() {
+                return Err(Error::InvalidRequest);
+            }
+            continue;
+        }
+
+        if name.is_empty()
+            || name.bytes().any(|b| b.is_ascii_uppercase())
+            || is_connection_specific(&name)
+            || (name == "te" && value != "trailers")
+        {
+            return Err(Error::InvalidRequest);
+        }
+
+        // cookie crumbs are joined the way HTTP/1 sends them, see RFC 9113
+        // section 8.2.3
+        if name == "cookie" {
+            cookies.push(value);
+        } else {
+            headers.append(name, value);
+        }
+    }
+
+    if !cookies.is_empty() {
+        headers.append("cookie", cookies.join("; "));
+    }
+
+    if headers.len() > limits.max_header_count || header_bytes > limits.max_header_bytes {
+        return Err(Error::HeadersTooLarge);
+    }
+
+    let (Some(method), Some(_), Some(path)) = (method, scheme, path) else {
+        return Err(Error::InvalidRequest);
+    };
+    if path.is_empty() {
+        return Err(Error::InvalidRequest);
+    }
+
+    if let Some(authority) = authority {
+        if !headers.contains("host") {
+            headers.append("host", authority);
+        }
+    }
+
+    Ok(HttpRequest::from_parts(
+        method.parse::<HttpMethod>()?,
+        path,
+        HttpVersion::Http2,
+        headers,
+    ))
+}
+
+// sends `response` on `stream_id`, the body in frames as large as the peer
+// accepts and as far as the windows allow
+fn respond<W: Write>(
+    shared: &Shared<W>,
+    stream_id: u32,
+    mut response: HttpResponse,
+    is_head: bool,
+) -> io::Result<()> {
+    // there is no switching protocols on an HTTP/2 stream
+    response.take_upgrade();
+
+    let status = response.status();
+    let length = response.content_length();
+    let body = response.take_body();
+    let trailers = response.take_trailers();
+
+    let mut fields = vec![(":status".to_string(), status.code().to_string())];
+    fields.extend(
+        response
+            .generated_headers()
+            .into_iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value)),
+    );
+    fields.extend(
+        response
+            .headers()
+            .iter()
+            .filter(|(name, _)| {
+                !is_connection_specific(name)
+                    && !name.eq_ignore_ascii_case("content-length")
+                    && !name.eq_ignore_ascii_case("trailer")
+            })
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.to_owned())),
+    );
+
+    if !status.allows_body() {
+        return shared.send_headers(stream_id, &fields, true);
+    }
+
+    if let Some(length) = length {
+        fields.push(("content-length".to_string(), length.to_string()));
+    }
+
+    if is_head || length == Some(0) {
+        return shared.send_headers(stream_id, &fields, true);
+    }
+
+    shared.send_headers(stream_id, &fields, false)?;
+
+    let (mut reader, is_unsized): (Box<dyn Read + Send>, bool) = match body {
+        Body::Bytes(bytes) => return shared.send_data(stream_id, &bytes, true),
+        Body::Stream {
+            reader,
+            length: Some(length),
+        } => (Box::new(reader.take(length)), false),
+        Body::Stream {
+            reader,
+            length: None,
+        } => (reader, true),
+        Body::File { file, length } => (Box::new(file.take(length)), false),
+    };
+
+    let mut buffer = vec![0; DEFAULT_FRAME_SIZE];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if read == 0 {
+            break;
+        }
+        shared.send_data(stream_id, &buffer[..read], false)?;
+    }
+
+    // like HTTP/1 trailers only follow a body of unknown length
+    match trailers {
+        Some(trailers) if is_unsized => {
+            let fields: Vec<_> = trailers()
+                .iter()
+                .map(|(name, value)| (name.to_ascii_lowercase(), value.to_owned()))
+                .collect();
+            shared.send_headers(stream_id, &fields, true)
+        }
+        _ => shared.send_data(stream_id, &[], true),
+    }
+}
+
+// a request whose body is still arriving
+struct Incoming {
+    req: HttpRequest,
+    body: Vec<u8>,
+}
+
+struct Connection<'a, W: Write> {
+    shared: &'a Shared<W>,
+    pipeline: &'a Pipeline,
+    conf: &'a Config,
+    peer_addr: Option<SocketAddr>,
+    decoder: Decoder,
+    incoming: HashMap<u32, Incoming>,
+    last_stream_id: u32,
+    // a header block continuing in CONTINUATION frames, with the flags of
+    // the HEADERS frame that started it
+    continuation: Option<(u32, u8, Vec<u8>)>,
+    settings_received: bool,
+    going_away: bool,
+}
+
+impl<'a, W: Write + Send> Connection<'a, W> {
+    fn is_idle(&self) -> bool {
+        self.incoming.is_empty() && self.shared.flow().streams.is_empty()
+    }
+
+    // the request that came with an upgrade is answered on stream 1, the
+    // client preface follows the 101 regardless, see RFC 7540 section 3.2
+    fn start<'scope, R: Read>(
+        &mut self,
+        upgraded: Option<(HttpRequest, Vec<u8>)>,
+        reader: &mut R,
+        scope: &'scope Scope<'scope, '_>,
+    ) -> Result<()>
+    where
+        'a: 'scope,
+    {
+        if let Some((req, settings)) = upgraded {
+            // the 101 acknowledges these settings already
+            self.on_settings(&settings)?;
+            self.last_stream_id = 1;
+            self.open_stream(1);
+            self.spawn(1, req, scope);
+        }
+
+        let mut preface = [0; PREFACE.len()];
+        reader.read_exact(&mut preface)?;
+        if preface != PREFACE {
+            return Err(Error::InvalidProtocol);
+        }
+
+        Ok(())
+    }
+
+    fn go_away(&mut self, code: u32) -> io::Result<()> {
+        self.going_away = true;
+
+        let mut payload = self.last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        self.shared.send(GOAWAY, 0, 0, &payload)
+    }
+
+    fn run<'scope, R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        scope: &'scope Scope<'scope, '_>,
+        shutdown: &ShutdownHandle,
+    ) -> Result<()>
+    where
+        'a: 'scope,
+    {
+        loop {
+            if shutdown.is_requested() && !self.going_away {
+                self.go_away(NO_ERROR)?;
+            }
+            if self.going_away && self.is_idle() {
+                return Ok(());
+            }
+
+            // waiting for the next frame is the only place a timeout is fine,
+            // anything else has to arrive in one piece
+            match reader.fill_buf() {
+                // peers tend to drop the connection rather than close it
+                Ok([]) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => return Ok(()),
+                Ok(_) => (),
+                Err(e) if stream::is_timeout(&e) && self.is_idle() => {
+                    return Ok(self.go_away(NO_ERROR)?);
+                }
+                Err(e) if stream::is_timeout(&e) => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            let frame = read_frame(reader)?;
+            self.handle(frame, scope)?;
+        }
+    }
+
+    fn handle<'scope>(&mut self, frame: Frame, scope: &'scope Scope<'scope, '_>) -> Result<()>
+    where
+        'a: 'scope,
+    {
+        if !self.settings_received && frame.kind != SETTINGS {
+            return Err(Error::Http2(PROTOCOL_ERROR));
+        }
+
+        if let Some((stream_id, flags, mut block)) = self.continuation.take() {
+            if frame.kind != CONTINUATION || frame.stream_id != stream_id {
+                return Err(Error::Http2(PROTOCOL_ERROR));
+            }
+
+            block.extend_from_slice(&frame.payload);
+            if block.len() > self.conf.limits.max_header_bytes + self.conf.limits.max_header_line {
+                return Err(Error::Http2(ENHANCE_YOUR_CALM));
+            }
+
+            if frame.flags & END_HEADERS == 0 {
+                self.continuation = Some((stream_id, flags, block));
+                return Ok(());
+            }
+            return self.on_headers(stream_id, flags, &block, scope);
+        }
+
+        let stream_id = frame.stream_id;
+        let is_idle_stream = stream_id > self.last_stream_id;
+
+        match frame.kind {
+            DATA => {
+                if stream_id == 0 || is_idle_stream {
+                    return Err(Error::Http2(PROTOCOL_ERROR));
+                }
+
+                // everything received is buffered right away, so the windows
+                // are opened again for all of it including the padding
+                let received = frame.payload.len() as u32;
+                if received > 0 {
+                    self.shared
+                        .send(WINDOW_UPDATE, 0, 0, &received.to_be_bytes())?;
+                }
+
+                let data = unpad(&frame)?;
+                let Some(incoming) = self.incoming.get_mut(&stream_id) else {
+                    return Ok(self.shared.reset(stream_id, STREAM_CLOSED)?);
+                };
+
+                if incoming.body.len() + data.len() > self.conf.limits.max_body_size {
+                    self.incoming.remove(&stream_id);
+                    let error = Error::PayloadTooLarge;
+                    let response = self.pipeline.render_error(
+                        server::error_response(&error),
+                        None,
+                        Some(&error),
+                    );
+                    respond(self.shared, stream_id, response, false)?;
+                    return Ok(self.shared.reset(stream_id, NO_ERROR)?);
+                }
+                incoming.body.extend_from_slice(data);
+
+                if frame.flags & END_STREAM != 0 {
+                    self.complete(stream_id, scope);
+                } else if received > 0 {
+                    self.shared
+                        .send(WINDOW_UPDATE, 0, stream_id, &received.to_be_bytes())?;
+                }
+                Ok(())
+            }
+            HEADERS => {
+                if stream_id == 0 {
+                    return Err(Error::Http2(PROTOCOL_ERROR));
+                }
+
+                let mut block = unpad(&frame)?;
+                if frame.flags & PRIORITY_FLAG != 0 {
+                    block = block.get(5..).ok_or(Error::Http2(FRAME_SIZE_ERROR))?;
+                }
+
+                if frame.flags & END_HEADERS == 0 {
+                    self.continuation = Some((stream_id, frame.flags, block.to_vec()));
+                    return Ok(());
+                }
+                self.on_headers(stream_id, frame.flags, block, scope)
+            }
+            PRIORITY if frame.payload.len() != 5 => Err(Error::Http2(FRAME_SIZE_ERROR)),
+            RST_STREAM => {
+                if frame.payload.len() != 4 {
+                    return Err(Error::Http2(FRAME_SIZE_ERROR));
+                }
+                if stream_id == 0 || is_idle_stream {
+                    return Err(Error::Http2(PROTOCOL_ERROR));
+                }
+                self.incoming.remove(&stream_id);
+                if let Some(cancellation) = self.shared.cancellations().get(&stream_id) {
+                    cancellation.cancel();
+                }
+                self.shared.close_stream(stream_id);
+                Ok(())
+            }
+            SETTINGS => {
+                if stream_id != 0 {
+                    return Err(Error::Http2(PROTOCOL_ERROR));
+                }
+                if frame.flags & ACK != 0 {
+                    return match frame.payload.is_empty() {
+                        true => Ok(()),
+                        false => Err(Error::Http2(FRAME_SIZE_ERROR)),
+                    };
+                }
+                self.on_settings(&frame.payload)?;
+                self.settings_received = true;
+                Ok(self.shared.send(SETTINGS, ACK, 0, &[])?)
+            }
+            PUSH_PROMISE => Err(Error::Http2(PROTOCOL_ERROR)),
+            PING => {
+                if frame.payload.len() != 8 {
+                    return Err(Error::Http2(FRAME_SIZE_ERROR));
+                }
+                if stream_id != 0 {
+                    return Err(Error::Http2(PROTOCOL_ERROR));
+                }
+                if frame.flags & ACK == 0 {
+                    self.shared.send(PING, ACK, 0, &frame.payload)?;
+                }
+                Ok(())
+            }
+            GOAWAY => {
+                // the peer opens no more streams, the open ones still finish
+                self.going_away = true;
+                Ok(())
+            }
+            WINDOW_UPDATE => {
+                if frame.payload.len() != 4 {
+                    return Err(Error::Http2(FRAME_SIZE_ERROR));
+                }
+                let increment = i64::from(read_u32(&frame.payload) & 0x7fff_ffff);
+                self.on_window_update(stream_id, increment, is_idle_stream)
+            }
+            CONTINUATION => Err(Error::Http2(PROTOCOL_ERROR)),
+            // unknown frame types are ignored, see RFC 9113 section 4.1
+            _ => Ok(()),
+        }
+    }
+
+    fn on_settings(&mut self, payload: &[u8]) -> Result<()> {
+        if payload.len() % 6 != 0 {
+            return Err(Error::Http2(FRAME_SIZE_ERROR));
+        }
+
+        let mut flow = self.shared.flow();
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = read_u32(&setting[2..]);
+
+            match id {
+                SETTINGS_ENABLE_PUSH if value > 1 => return Err(Error::Http2(PROTOCOL_ERROR)),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = i64::from(value);
+                    if value > MAX_WINDOW {
+                        return Err(Error::Http2(FLOW_CONTROL_ERROR));
+                    }
+
+                    // the change applies to the windows of open streams as well
+                    let delta = value - flow.initial;
+                    for window in flow.streams.values_mut() {
+                        *window += delta;
+                        if *window > MAX_WINDOW {
+                            return Err(Error::Http2(FLOW_CONTROL_ERROR));
+                        }
+                    }
+                    flow.initial = value;
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    let value = value as usize;
+                    if !(DEFAULT_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&value) {
+                        return Err(Error::Http2(PROTOCOL_ERROR));
+                    }
+                    flow.max_frame_size = value;
+                }
+                // the encoder never uses the dynamic table, so its size is
+                // of no concern, and anything else is advisory
+                _ => (),
+            }
+        }
+        drop(flow);
+
+        self.shared.flow_changed.notify_all();
+        Ok(())
+    }
+
+    fn on_window_update(
+        &mut self,
+        stream_id: u32,
+        increment: i64,
+        is_idle_stream: bool,
+    ) -> Result<()> {
+        if stream_id != 0 && is_idle_stream {
+            return Err(Error::Http2(PROTOCOL_ERROR));
+        }
+
+        let mut flow = self.shared.flow();
+
+        if stream_id == 0 {
+            if increment == 0 {
+                return Err(Error::Http2(PROTOCOL_ERROR));
+            }
+            flow.connection += increment;
+            if flow.connection > MAX_WINDOW {
+                return Err(Error::Http2(FLOW_CONTROL_ERROR));
+            }
+        } else if let Some(window) = flow.streams.get_mut(&stream_id) {
+            *window += increment;
+            if increment == 0 || *window > MAX_WINDOW {
+                let code = if increment == 0 {
+                    PROTOCOL_ERROR
+                } else {
+                    FLOW_CONTROL_ERROR
+                };
+                drop(flow);
+                return Ok(self.shared.reset(stream_id, code)?);
+            }
+        }
+        drop(flow);
+
+        self.shared.flow_changed.notify_all();
+        Ok(())
+    }
+
+    fn on_headers<'scope>(
+        &mut self,
+        stream_id: u32,
+        flags: u8,
+        block: &[u8],
+        scope: &'scope Scope<'scope, '_>,
+    ) -> Result<()>
+    where
+        'a: 'scope,
+    {
+        // the block has to be decoded whatever happens to the stream, or the
+        // dynamic table would go out of step with the peer's
+        let fields = self
+            .decoder
+            .decode(block)
+            .map_err(|_| Error::Http2(COMPRESSION_ERROR))?;
+
+        // trailers, which end the request and are not passed on
+        if self.incoming.contains_key(&stream_id) {
+            if flags & END_STREAM == 0 {
+                return Err(Error::Http2(PROTOCOL_ERROR));
+            }
+            self.complete(stream_id, scope);
+            return Ok(());
+        }
+
+        if stream_id <= self.last_stream_id {
+            return Ok(self.shared.reset(stream_id, STREAM_CLOSED)?);
+        }
+        if stream_id % 2 == 0 {
+            return Err(Error::Http2(PROTOCOL_ERROR));
+        }
+        self.last_stream_id = stream_id;
+
+        let open = self.incoming.len().max(self.shared.flow().streams.len());
+        if self.going_away || open >= MAX_CONCURRENT_STREAMS {
+            return Ok(self.shared.reset(stream_id, REFUSED_STREAM)?);
+        }
+
+        let req = match request_from(fields, &self.conf.limits) {
+            Ok(req) => req,
+            Err(Error::InvalidRequest) => {
+                return Ok(self.shared.reset(stream_id, PROTOCOL_ERROR)?)
+            }
+            Err(e) => {
+                let response =
+                    self.pipeline
+                        .render_error(server::error_response(&e), None, Some(&e));
+                self.open_stream(stream_id);
+                respond(self.shared, stream_id, response, false)?;
+                self.shared.close_stream(stream_id);
+                if flags & END_STREAM == 0 {
+                    self.shared.reset(stream_id, NO_ERROR)?;
+                }
+                return Ok(());
+            }
+        };
+
+        self.open_stream(stream_id);
+        self.incoming.insert(
+            stream_id,
+            Incoming {
+                req,
+                body: Vec::new(),
+            },
+        );
+
+        if flags & END_STREAM != 0 {
+            self.complete(stream_id, scope);
+        }
+        Ok(())
+    }
+
+    fn open_stream(&self, stream_id: u32) {
+        let mut flow = self.shared.flow();
+        let initial = flow.initial;
+        flow.streams.insert(stream_id, initial);
+    }
+
+    // hands a request that arrived in full to a thread of its own, so a slow
+    // handler does not hold up the other streams
+    fn complete<'scope>(&mut self, stream_id: u32, scope: &'scope Scope<'scope, '_>)
+    where
+        'a: 'scope,
+    {
+        let Some(Incoming { mut req, body }) = self.incoming.remove(&stream_id) else {
+            return;
+        };
+
+        // a declared length has to match what arrived, see RFC 9113
+        // section 8.1.1
+        if req.header("content-length").is_some_and(|length| {
+            !length.bytes().all(|b| b.is_ascii_digit())
+                || length.parse::<usize>().ok() != Some(body.len())
+        }) {
+            let _ = self.shared.reset(stream_id, PROTOCOL_ERROR);
+            return;
+        }
+
+        req.set_body(body);
+        server::prepare(&mut req, self.peer_addr, self.conf);
+        self.spawn(stream_id, req, scope);
+    }
+
+    fn spawn<'scope>(&self, stream_id: u32, mut req: HttpRequest, scope: &'scope Scope<'scope, '_>)
+    where
+        'a: 'scope,
+    {
+        let (shared, pipeline) = (self.shared, self.pipeline);
+        shared
+            .cancellations()
+            .insert(stream_id, req.context().cancellation().clone());
+        scope.spawn(move || {
+            let is_head = *req.method() == HttpMethod::HEAD;
+            let response = server::handle(pipeline, &mut req);
+
+            if let Err(e) = respond(shared, stream_id, response, is_head) {
+                log::debug!("Failed to answer stream {}, error {}", stream_id, e);
+            }
+            shared.close_stream(stream_id);
+        });
+    }
+}
+
+// serves an HTTP/2 connection until the peer goes away, `reader` and
+// `writer` are the two directions of the same connection, and `upgraded` the
+// request and settings of an HTTP/1.1 connection switching over
+pub(crate) fn serve<R: Read, W: Write + Send>(
+    reader: R,
+    writer: W,
+    peer_addr: Option<SocketAddr>,
+    upgraded: Option<(HttpRequest, Vec<u8>)>,
+    pipeline: &Pipeline,
+    conf: &Config,
+    shutdown: &ShutdownHandle,
+) -> Result<()> {
+    let mut reader = BufReader::new(reader);
+
+    let shared = Shared {
+        writer: Mutex::new(writer),
+        flow: Mutex::new(Flow {
+            connection: DEFAULT_WINDOW,
+            streams: HashMap::new(),
+            initial: DEFAULT_WINDOW,
+            max_frame_size: DEFAULT_FRAME_SIZE,
+            closed: false,
+        }),
+        flow_changed: Condvar::new(),
+        cancellations: Mutex::new(HashMap::new()),
+        write_timeout: conf.write_timeout,
+    };
+
+    let mut settings = Vec::new();
+    for (id, value) in [
+        (SETTINGS_HEADER_TABLE_SIZE, HEADER_TABLE_SIZE),
+        (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS),
+        (SETTINGS_MAX_HEADER_LIST_SIZE, conf.limits.max_header_bytes),
+    ] {
+        settings.extend_from_slice(&id.to_be_bytes());
+        settings.extend_from_slice(&(value as u32).to_be_bytes());
+    }
+    shared.send(SETTINGS, 0, 0, &settings)?;
+
+    let mut connection = Connection {
+        shared: &shared,
+        pipeline,
+        conf,
+        peer_addr,
+        decoder: Decoder::new(HEADER_TABLE_SIZE, conf.limits.max_header_bytes),
+        incoming: HashMap::new(),
+        last_stream_id: 0,
+        continuation: None,
+        settings_received: false,
+        going_away: false,
+    };
+
+    thread::scope(|scope| {
+        let result = connection
+            .start(upgraded, &mut reader, scope)
+            .and_then(|_| connection.run(&mut reader, scope, shutdown));
+
+        if let Err(Error::Http2(code)) = result {
+            let _ = connection.go_away(code);
+        }
+
+        // the peer is gone or about to be, streams still running or waiting
+        // on a window would do so in vain
+        shared.close();
+
+        result
+    })
+}
+
+// completes the handshake so the negotiated protocol is known, the
+// connection is served as HTTP/2 if it was `h2`
+pub(crate) fn serve_tls(
+    mut stream: StreamOwned<ServerConnection, TcpStream>,
+    pipeline: &Pipeline,
+    conf: &Config,
+    shutdown: &ShutdownHandle,
+) -> Result<Option<StreamOwned<ServerConnection, TcpStream>>> {
+    stream.sock.set_read_timeout(Some(conf.header_timeout))?;
+    stream.sock.set_write_timeout(Some(conf.write_timeout))?;
+
+    while stream.conn.is_handshaking() {
+        stream.conn.complete_io(&mut stream.sock)?;
+    }
+
+    if stream.conn.alpn_protocol() != Some(ALPN) {
+        return Ok(Some(stream));
+    }
+
+    let StreamOwned { conn, sock } = stream;
+    serve_tls_session(conn, sock, Vec::new(), pipeline, conf, shutdown)?;
+    Ok(None)
+}
+
+// serves a tls session negotiated as `h2`, `buffered` is what was read from
+// it already
+pub(crate) fn serve_tls_session(
+    tls: ServerConnection,
+    socket: TcpStream,
+    buffered: Vec<u8>,
+    pipeline: &Pipeline,
+    conf: &Config,
+    shutdown: &ShutdownHandle,
+) -> Result<()> {
+    socket.set_read_timeout(Some(conf.keep_alive_timeout))?;
+    socket.set_write_timeout(Some(conf.write_timeout))?;
+    let peer_addr = socket.peer_addr().ok();
+
+    let (reader, writer) = stream::split_tls(tls, socket)?;
+    serve(
+        io::Cursor::new(buffered).chain(reader),
+        writer,
+        peer_addr,
+        None,
+        pipeline,
+        conf,
+        shutdown,
+    )
+}
+
+// the settings an `Upgrade: h2c` request carries, None when it is not a
+// valid one, see RFC 7540 section 3.2
+pub(crate) fn h2c_settings(req: &HttpRequest) -> Option<Vec<u8>> {
+    let has_token = |name: &str, token: &str| {
+        req.headers().get_joined(name).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|item| item.trim().eq_ignore_ascii_case(token))
+        })
+    };
+
+    if req.version() != HttpVersion::Http11
+        || !has_token("upgrade", "h2c")
+        || !has_token("connection", "upgrade")
+        || !has_token("connection", "http2-settings")
+    {
+        return None;
+    }
+
+    let mut values = req.headers().get_all("http2-settings");
+    let (Some(value), None) = (values.next(), values.next()) else {
+        return None;
+    };
+
+    URL_SAFE_NO_PAD
+        .decode(value.trim().trim_end_matches('='))
+        .ok()
+        .filter(|settings| settings.len() % 6 == 0)
+}
+
+// accepts an upgrade to h2c, HTTP/2 starts right after it
+pub(crate) fn switching_protocols() -> HttpResponse {
+    HttpResponse::new(StatusCode::SwitchingProtocols)
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "h2c")
+}
+
+// serves a cleartext connection that switched to HTTP/2 after answering
+// `req` with a 101, `buffered` is what was read behind the request already
+pub(crate) fn serve_h2c(
+    socket: TcpStream,
+    buffered: Vec<u8>,
+    req: HttpRequest,
+    settings: Vec<u8>,
+    pipeline: &Pipeline,
+    conf: &Config,
+    shutdown: &ShutdownHandle,
+) -> Result<()> {
+    socket.set_read_timeout(Some(conf.keep_alive_timeout))?;
+    socket.set_write_timeout(Some(conf.write_timeout))?;
+    let peer_addr = socket.peer_addr().ok();
+
+    serve(
+        io::Cursor::new(buffered).chain(socket.try_clone()?),
+        socket,
+        peer_addr,
+        Some((req, settings)),
+        pipeline,
+        conf,
+        shutdown,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::router::Router;
+    use std::net::TcpListener;
+
+    fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        encode_frame(kind, flags, stream_id, payload, &mut frame);
+        frame
+    }
+
+    fn headers(fields: &[(&str, &str)]) -> Vec<u8> {
+        let mut block = Vec::new();
+        hpack::encode(fields.iter().copied(), &mut block);
+        block
+    }
+
+    #[test]
+    fn serve_should_answer_multiplexed_streams() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let conf = Config {
+                keep_alive_timeout: Duration::from_secs(2),
+                ..Config::default()
+            };
+            let mut router = Router::new();
+            router
+                .get("/echo/:msg", |_, params| {
+                    HttpResponse::ok().body(params.get("msg").unwrap_or_default().to_string())
+                })
+                .post("/echo", |req, _| {
+                    HttpResponse::ok().body(req.body().unwrap_or_default().to_vec())
+                });
+            let pipeline = Pipeline::new(Vec::new(), router);
+            serve(
+                socket.try_clone().unwrap(),
+                socket,
+                None,
+                None,
+                &pipeline,
+                &conf,
+                &ShutdownHandle::default(),
+            )
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let mut request = PREFACE.to_vec();
+        request.extend(frame(SETTINGS, 0, 0, &[]));
+        for (stream_id, path) in [(1, "/echo/one"), (3, "/echo/two")] {
+            request.extend(frame(
+                HEADERS,
+                END_HEADERS | END_STREAM,
+                stream_id,
+                &headers(&[
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", path),
+                    (":authority", "localhost"),
+                ]),
+            ));
+        }
+        request.extend(frame(
+            HEADERS,
+            END_HEADERS,
+            5,
+            &headers(&[
+                (":method", "POST"),
+                (":scheme", "https"),
+                (":path", "/echo"),
+            ]),
+        ));
+        request.extend(frame(DATA, END_STREAM | PADDED, 5, &[2, b'h', b'i', 0, 0]));
+        // uppercase names are malformed
+        request.extend(frame(
+            HEADERS,
+            END_HEADERS | END_STREAM,
+            7,
+            &headers(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/"),
+                ("Host", "x"),
+            ]),
+        ));
+        request.extend(frame(PING, 0, 0, b"12345678"));
+        client.write_all(&request).unwrap();
+
+        let mut decoder = Decoder::new(HEADER_TABLE_SIZE, usize::MAX);
+        let mut statuses = HashMap::new();
+        let mut bodies: HashMap<u32, Vec<u8>> = HashMap::new();
+        let (mut ended, mut reset, mut pong) = (0, Vec::new(), false);
+
+        while ended < 3 || reset.is_empty() || !pong {
+            let frame = read_frame(&mut client).unwrap();
+            match frame.kind {
+                HEADERS => {
+                    let fields = decoder.decode(&frame.payload).unwrap();
+                    statuses.insert(frame.stream_id, fields[0].1.clone());
+                }
+                DATA => bodies
+                    .entry(frame.stream_id)
+                    .or_default()
+                    .extend_from_slice(&frame.payload),
+                RST_STREAM => reset.push((frame.stream_id, read_u32(&frame.payload))),
+                PING => pong = frame.flags & ACK != 0 && frame.payload == b"12345678",
+                _ => (),
+            }
+            if matches!(frame.kind, HEADERS | DATA) && frame.flags & END_STREAM != 0 {
+                ended += 1;
+            }
+        }
+
+        assert_eq!(statuses[&1], "200");
+        assert_eq!(bodies[&1], b"one");
+        assert_eq!(bodies[&3], b"two");
+        assert_eq!(bodies[&5], b"hi");
+        assert_eq!(reset, vec![(7, PROTOCOL_ERROR)]);
+
+        client
+            .write_all(&frame(GOAWAY, 0, 0, &[0, 0, 0, 0, 0, 0, 0, 0]))
+            .unwrap();
+        assert!(server.join().unwrap().is_ok());
+    }
+}
@@ -0,0 +1,1005 @@
+use crate::accept::MediaType;
+use crate::conditional::{self, Outcome, Validators};
+use crate::config::{SharedConfig, StorageKind, UploadRules};
+use crate::date;
+use crate::digest::{self, Algorithm, Hasher};
+use crate::encoding::{self, ContentCoding};
+use crate::errors::{Error, Result};
+use crate::etag;
+use crate::extract::{handler, Headers};
+use crate::listing;
+use crate::log;
+use crate::multipart;
+use crate::range::{self, MultipartRanges, Ranges};
+use crate::request::{HttpMethod, HttpRequest};
+use crate::response::{HttpResponse, StatusCode};
+use crate::router::{PathParams, Router};
+use crate::session::SessionStore;
+use crate::sse::{self, Event};
+use crate::storage::{self, Content, FsStorage, MemoryStorage, Metadata, PathLocks, Storage};
+use crate::websocket;
+use crate::Config;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// looked up in order when a directory is requested
+const INDEX_FILES: [&str; 2] = ["index.html", "index.htm"];
+
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+// a resumable upload that got no part for this long is given up on
+const UPLOAD_EXPIRY: Duration = Duration::from_secs(60 * 60);
+const EVENTS_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+pub fn routes(conf: &SharedConfig) -> Router {
+    let mut router = Router::new();
+
+    let files = Files {
+        conf: conf.clone(),
+        memory: Arc::new(MemoryStorage::new()),
+        locks: Arc::new(PathLocks::new()),
+        digests: Arc::new(Digests::default()),
+        uploads: Arc::new(Uploads::default()),
+    };
+    let root_files = files.clone();
+    let get_files = files.clone();
+    let post_files = files.clone();
+    let put_files = files.clone();
+    let delete_files = files.clone();
+
+    let started = Instant::now();
+    let streams = Arc::new(AtomicUsize::new(0));
+
+    router
+        .get("/", move |req, _| get_root(req, &root_files))
+        .get("/echo/:msg", handler(echo))
+        .get("/user-agent", handler(user_agent))
+        .get("/ws", websocket_echo)
+        .get("/events", move |_, _| stats_events(started, &streams))
+        .get("/files/*path", move |req, params| {
+            get_file(req, params, &get_files)
+        })
+        .post("/files/*path", move |req, params| {
+            post_file(req, params, &post_files)
+        })
+        .put("/files/*path", move |req, params| {
+            put_file(req, params, &put_files)
+        })
+        .delete("/files/*path", move |req, params| {
+            delete_file(req, params, &delete_files)
+        });
+
+    // registered last so every other route takes precedence
+    if conf.load().spa {
+        router.get("/*path", move |req, params| get_spa(req, params, &files));
+    }
+
+    let conf = conf.load();
+    match SessionStore::new(
+        conf.session_secret.as_deref().map(str::as_bytes),
+        conf.session_ttl,
+    ) {
+        Ok(sessions) => {
+            router.get("/visits", move |req, _| visits(req, &sessions));
+        }
+        Err(e) => log::error!("Failed to set up sessions, error {}", e),
+    }
+
+    router
+}
+
+fn ok_with_body(content_type: &str, content: Vec<u8>) -> HttpResponse {
+    HttpResponse::ok()
+        .header("Content-Type", content_type)
+        .body(content)
+}
+
+fn visits(req: &HttpRequest, sessions: &SessionStore) -> HttpResponse {
+    let mut session = sessions.load(req);
+    let count = session
+        .get("visits")
+        .and_then(|visits| visits.parse::<u64>().ok())
+        .unwrap_or_default()
+        + 1;
+    session.insert("visits", count.to_string());
+
+    let response = ok_with_body("text/plain", format!("visits: {}", count).into_bytes());
+    sessions.save(session, response).unwrap_or_else(|e| {
+        log::error!("Failed to save session, error {}", e);
+        HttpResponse::internal_server_error()
+    })
+}
+
+fn echo(params: PathParams) -> HttpResponse {
+    if let Some(echo_str) = params.get("msg") {
+        ok_with_body("text/plain", echo_str.as_bytes().to_vec())
+    } else {
+        HttpResponse::bad_request()
+    }
+}
+
+fn user_agent(Headers(headers): Headers) -> HttpResponse {
+    if let Some(user_agent_header) = headers.get("user-agent") {
+        ok_with_body("text/plain", user_agent_header.as_bytes().to_vec())
+    } else {
+        HttpResponse::bad_request()
+    }
+}
+
+fn websocket_echo(req: &HttpRequest, _: &PathParams) -> HttpResponse {
+    websocket::upgrade(req, |mut ws| loop {
+        match ws.recv() {
+            Ok(Some(message)) => ws.send(message)?,
+            Ok(None) => return Ok(()),
+            // an idle read timed out, check the peer is still there
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                ws.ping()?
+            }
+            Err(e) => return Err(e),
+        }
+    })
+}
+
+fn stats_events(started: Instant, streams: &Arc<AtomicUsize>) -> HttpResponse {
+    let streams = Arc::clone(streams);
+
+    sse::event_stream(EVENTS_KEEP_ALIVE, move |sender| {
+        streams.fetch_add(1, Ordering::SeqCst);
+
+        for id in 0u64.. {
+            let stats = serde_json::json!({
+                "uptime_secs": started.elapsed().as_secs(),
+                "active_streams": streams.load(Ordering::SeqCst),
+            });
+            let event = Event::new(stats.to_string())
+                .id(id.to_string())
+                .event("stats");
+
+            if sender.send(event).is_err() {
+                break;
+            }
+            thread::sleep(STATS_INTERVAL);
+        }
+
+        streams.fetch_sub(1, Ordering::SeqCst);
+    })
+}
+
+// the storage behind the file endpoints by the current config, the
+// in-memory one lives as long as the routes do
+#[derive(Clone)]
+struct Files {
+    conf: SharedConfig,
+    memory: Arc<MemoryStorage>,
+    locks: Arc<PathLocks>,
+    digests: Arc<Digests>,
+    uploads: Arc<Uploads>,
+}
+
+impl Files {
+    // None when there is nothing to serve, the files of the request's host
+    // otherwise
+    fn storage(&self, req: &HttpRequest, conf: &Config) -> Option<io::Result<Arc<dyn Storage>>> {
+        match conf.storage {
+            StorageKind::Memory => Some(Ok(self.memory.clone())),
+            StorageKind::Filesystem => conf
+                .directory_for(req.host().as_deref())
+                .map(|dir| Ok(Arc::new(FsStorage::open(dir)?) as Arc<dyn Storage>)),
+        }
+    }
+
+    fn open(
+        &self,
+        req: &HttpRequest,
+    ) -> std::result::Result<(Arc<Config>, Arc<dyn Storage>), HttpResponse> {
+        let conf = self.conf.load();
+        match self.storage(req, &conf) {
+            Some(Ok(storage)) => Ok((conf, storage)),
+            _ => Err(HttpResponse::service_unavailable()),
+        }
+    }
+}
+
+// keeps at most this many digests before starting over
+const MAX_DIGESTS: usize = 4096;
+
+// Content-Digest values of served files by path, along with the ETag they
+// were computed for so a changed file is hashed again
+#[derive(Default)]
+struct Digests(Mutex<HashMap<String, (String, String)>>);
+
+impl Digests {
+    fn content_digest(&self, storage: &dyn Storage, path: &str, etag: &str) -> io::Result<String> {
+        if let Some((_, digest)) = self
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(path)
+            .filter(|(computed_for, _)| computed_for == etag)
+        {
+            return Ok(digest.clone());
+        }
+
+        let (mut content, _) = storage.get(path)?;
+        let mut hasher = Hasher::new(Algorithm::Sha256);
+        io::copy(&mut content, &mut hasher)?;
+        let digest = digest::content_digest(&hasher.finish());
+
+        let mut digests = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if digests.len() >= MAX_DIGESTS {
+            digests.clear();
+        }
+        digests.insert(path.to_owned(), (etag.to_owned(), digest.clone()));
+        Ok(digest)
+    }
+}
+
+fn storage_error(e: io::Error) -> HttpResponse {
+    match e.kind() {
+        io::ErrorKind::NotFound => HttpResponse::not_found(),
+        io::ErrorKind::PermissionDenied => HttpResponse::forbidden(),
+        _ => HttpResponse::internal_server_error(),
+    }
+}
+
+fn child(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+// where the parts of a resumable upload for `path` are collected
+fn staging_path(path: &str) -> String {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    child(dir, &format!(".{}.upload", name))
+}
+
+// staging files are half a file, clients neither see nor get them
+fn is_staging(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.len() > ".upload".len() + 1 && name.starts_with('.') && name.ends_with(".upload")
+}
+
+// a `.gz` next to the file that is not older than the file, an outdated one
+// would serve stale content
+fn fresh_gzip_sidecar(storage: &dyn Storage, path: &str, metadata: &Metadata) -> Option<String> {
+    let sidecar = format!("{}.gz", path);
+    let sidecar_metadata = storage
+        .metadata(&sidecar)
+        .ok()
+        .filter(|metadata| !metadata.is_dir)?;
+
+    (sidecar_metadata.modified? >= metadata.modified?).then_some(sidecar)
+}
+
+fn list_directory(
+    req: &HttpRequest,
+    title: &str,
+    storage: &dyn Storage,
+    dir: &str,
+) -> HttpResponse {
+    let Ok(entries) = storage.list(dir).map(|entries| {
+        listing::from_entries(
+            entries
+                .into_iter()
+                .filter(|entry| !is_staging(&entry.name))
+                .collect(),
+        )
+    }) else {
+        return HttpResponse::internal_server_error();
+    };
+
+    let response = match req.negotiate(&[MediaType::HTML, MediaType::JSON, MediaType::PLAIN]) {
+        Some(MediaType::JSON) => match listing::render_json(&entries) {
+            Ok(json) => ok_with_body("application/json", json.into_bytes()),
+            Err(_) => HttpResponse::internal_server_error(),
+        },
+        Some(MediaType::PLAIN) => ok_with_body(
+            "text/plain; charset=utf-8",
+            listing::render_text(&entries).into_bytes(),
+        ),
+        Some(_) => ok_with_body(
+            "text/html; charset=utf-8",
+            listing::render_html(title, &entries).into_bytes(),
+        ),
+        None => HttpResponse::new(StatusCode::NotAcceptable),
+    };
+    response.append_header("Vary", "Accept")
+}
+
+// the site root when there are files to serve, a bare 200 otherwise
+fn get_root(req: &HttpRequest, files: &Files) -> HttpResponse {
+    let conf = files.conf.load();
+    match files.storage(req, &conf) {
+        Some(Ok(storage)) => serve_path(req, &*storage, &files.digests, "", &conf),
+        Some(Err(_)) => HttpResponse::service_unavailable(),
+        None => HttpResponse::ok(),
+    }
+}
+
+fn get_file(req: &HttpRequest, params: &PathParams, files: &Files) -> HttpResponse {
+    match files.open(req) {
+        Ok((conf, storage)) => serve_path(
+            req,
+            &*storage,
+            &files.digests,
+            params.get("path").unwrap_or_default(),
+            &conf,
+        ),
+        Err(response) => response,
+    }
+}
+
+// a real file when there is one, the root index.html otherwise so a
+// client-side router can take over, API paths keep their 404
+fn get_spa(req: &HttpRequest, params: &PathParams, files: &Files) -> HttpResponse {
+    if req.path() == "/api" || req.path().starts_with("/api/") {
+        return HttpResponse::not_found();
+    }
+
+    let (conf, storage) = match files.open(req) {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+    let response = serve_path(
+        req,
+        &*storage,
+        &files.digests,
+        params.get("path").unwrap_or_default(),
+        &conf,
+    );
+    if response.status() != StatusCode::NotFound {
+        return response;
+    }
+
+    match index_file(&*storage, "") {
+        Some(index) => serve_file(req, &*storage, &files.digests, &index, &conf),
+        None => response,
+    }
+}
+
+// the first index file of `dir`
+fn index_file(storage: &dyn Storage, dir: &str) -> Option<String> {
+    INDEX_FILES
+        .iter()
+        .map(|name| child(dir, name))
+        .find(|index| {
+            storage
+                .metadata(index)
+                .is_ok_and(|metadata| !metadata.is_dir)
+        })
+}
+
+fn serve_path(
+    req: &HttpRequest,
+    storage: &dyn Storage,
+    digests: &Digests,
+    relative: &str,
+    conf: &Config,
+) -> HttpResponse {
+    let Some(path) = storage::normalize(relative) else {
+        return HttpResponse::forbidden();
+    };
+    if is_staging(&path) {
+        return HttpResponse::not_found();
+    }
+
+    match storage.metadata(&path) {
+        Ok(metadata) if metadata.is_dir => match index_file(storage, &path) {
+            Some(index) => serve_file(req, storage, digests, &index, conf),
+            None if conf.enable_dir_listing => list_directory(req, req.path(), storage, &path),
+            None => HttpResponse::forbidden(),
+        },
+        Ok(_) => serve_file(req, storage, digests, &path, conf),
+        Err(e) => storage_error(e),
+    }
+}
+
+fn serve_file(
+    req: &HttpRequest,
+    storage: &dyn Storage,
+    digests: &Digests,
+    path: &str,
+    conf: &Config,
+) -> HttpResponse {
+    let Ok((content, metadata)) = storage.get(path) else {
+        return HttpResponse::internal_server_error();
+    };
+
+    let sidecar = fresh_gzip_sidecar(storage, path, &metadata);
+    let accepts_gzip = encoding::negotiate(
+        req.headers().get_joined("accept-encoding").as_deref(),
+        &[ContentCoding::Gzip],
+    ) == Some(ContentCoding::Gzip);
+
+    let (served, content, metadata, gzipped) = match sidecar.as_deref().filter(|_| accepts_gzip) {
+        Some(sidecar) => match storage.get(sidecar) {
+            Ok((content, metadata)) => (sidecar, content, metadata, true),
+            Err(_) => return HttpResponse::internal_server_error(),
+        },
+        None => (path, content, metadata, false),
+    };
+
+    let etag = etag::from_metadata(&metadata);
+    let last_modified = metadata.modified.map(date::format_http);
+
+    let validators = Validators {
+        etag: &etag,
+        last_modified: metadata.modified,
+    };
+    let mut response = match conditional::evaluate(req, Some(validators)) {
+        Outcome::PreconditionFailed => return HttpResponse::new(StatusCode::PreconditionFailed),
+        Outcome::NotModified => HttpResponse::new(StatusCode::NotModified),
+        Outcome::Proceed => {
+            let ranges = match req.header("range") {
+                Some(range)
+                    if *req.method() == HttpMethod::GET
+                        && conditional::range_applies(req, validators) =>
+                {
+                    range::parse(range, metadata.len)
+                }
+                _ => Ranges::Ignored,
+            };
+            // the parts of an encoded representation could not say so
+            let ranges = match ranges {
+                Ranges::Satisfiable(ranges) if gzipped && ranges.len() > 1 => Ranges::Ignored,
+                ranges => ranges,
+            };
+            let content_type = conf.mime_types.lookup(Path::new(path));
+            match content_ranges(content, metadata.len, content_type, ranges) {
+                Ok(response) => response,
+                Err(_) => return HttpResponse::internal_server_error(),
+            }
+        }
+    };
+
+    response = response.header("ETag", etag.as_str());
+    if let Some(last_modified) = last_modified {
+        response = response.header("Last-Modified", last_modified);
+    }
+    if sidecar.is_some() {
+        response = response.append_header("Vary", "Accept-Encoding");
+    }
+    if gzipped && matches!(response.status().code(), 200 | 206) {
+        response = response.header("Content-Encoding", "gzip");
+    }
+    if response.status().code() == 200 {
+        match digests.content_digest(storage, served, &etag) {
+            Ok(digest) => response = response.header("Content-Digest", digest),
+            Err(e) => log::warning!("Failed to hash {}, error {}", served, e),
+        }
+    }
+
+    response
+}
+
+// `length` bytes of `content` from where it is positioned
+fn content_body(response: HttpResponse, content: Content, length: u64) -> HttpResponse {
+    match content {
+        Content::File(file) => response.file_body(file, length),
+        content => response.sized_body(content.take(length), length),
+    }
+}
+
+// the parts of `content` the ranges ask for, all of it when they do not
+// apply or would add up to more than the content, which overlapping ones can
+fn content_ranges(
+    mut content: Content,
+    length: u64,
+    content_type: &str,
+    ranges: Ranges,
+) -> io::Result<HttpResponse> {
+    match ranges {
+        Ranges::Satisfiable(ranges) if ranges.len() == 1 => {
+            let (first, last) = ranges[0];
+            content.seek(SeekFrom::Start(first))?;
+            let response = HttpResponse::new(StatusCode::PartialContent)
+                .header("Content-Type", content_type)
+                .header(
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", first, last, length),
+                );
+            Ok(content_body(response, content, last - first + 1))
+        }
+        Ranges::Satisfiable(ranges)
+            if ranges
+                .iter()
+                .map(|(first, last)| last - first + 1)
+                .sum::<u64>()
+                <= length =>
+        {
+            let boundary = range::boundary()?;
+            let (body, body_length) =
+                MultipartRanges::new(content, &ranges, length, content_type, &boundary);
+            Ok(HttpResponse::new(StatusCode::PartialContent)
+                .header(
+                    "Content-Type",
+                    format!("multipart/byteranges; boundary={}", boundary),
+                )
+                .sized_body(body, body_length))
+        }
+        Ranges::Unsatisfiable => Ok(HttpResponse::new(StatusCode::RangeNotSatisfiable)
+            .header("Content-Range", format!("bytes */{}", length))),
+        _ => {
+            let response = HttpResponse::ok()
+                .header("Content-Type", content_type)
+                .header("Accept-Ranges", "bytes");
+            Ok(content_body(response, content, length))
+        }
+    }
+}
+
+// the current file at `path` when its preconditions let a change to it go
+// ahead, see RFC 9110 section 13.1
+fn check_preconditions(
+    req: &HttpRequest,
+    storage: &dyn Storage,
+    path: &str,
+) -> std::result::Result<Option<Metadata>, HttpResponse> {
+    let current = match storage.metadata(path) {
+        Ok(metadata) if metadata.is_dir => return Err(HttpResponse::forbidden()),
+        Ok(metadata) => Some(metadata),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(storage_error(e)),
+    };
+
+    let etag = current.as_ref().map(etag::from_metadata);
+    let validators = etag.as_deref().map(|etag| Validators {
+        etag,
+        last_modified: current.as_ref().and_then(|metadata| metadata.modified),
+    });
+    match conditional::evaluate(req, validators) {
+        Outcome::Proceed => (),
+        _ => return Err(HttpResponse::new(StatusCode::PreconditionFailed)),
+    }
+
+    Ok(current)
+}
+
+enum Written {
+    Created,
+    Replaced,
+    // a resumable upload still missing bytes, with the ranges received
+    Incomplete(String),
+}
+
+struct Upload {
+    length: u64,
+    received: Vec<(u64, u64)>,
+    updated: Instant,
+}
+
+// resumable uploads still missing bytes by the path they are for
+#[derive(Default)]
+struct Uploads(Mutex<HashMap<String, Upload>>);
+
+impl Uploads {
+    // forgets the uploads no part arrived for in a while, returning their paths
+    fn expire(&self) -> Vec<String> {
+        let mut expired = Vec::new();
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|path, upload| {
+                let alive = upload.updated.elapsed() < UPLOAD_EXPIRY;
+                if !alive {
+                    expired.push(path.clone());
+                }
+                alive
+            });
+        expired
+    }
+}
+
+// adds a range to sorted ones that do not touch, merging where it does
+fn merge_range(ranges: &mut Vec<(u64, u64)>, (first, last): (u64, u64)) {
+    ranges.push((first, last));
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for &(first, last) in ranges.iter() {
+        match merged.last_mut() {
+            Some(previous) if first <= previous.1.saturating_add(1) => {
+                previous.1 = previous.1.max(last)
+            }
+            _ => merged.push((first, last)),
+        }
+    }
+    *ranges = merged;
+}
+
+// a part of a file sent with Content-Range, collected next to the file and
+// moved over it once every byte arrived so the file is never incomplete,
+// returns the ranges received while there are bytes missing
+fn write_chunk(
+    storage: &dyn Storage,
+    uploads: &Uploads,
+    path: &str,
+    content_range: &str,
+    contents: &[u8],
+    limit: usize,
+) -> std::result::Result<Option<String>, HttpResponse> {
+    let Some((first, last, total)) = range::parse_content_range(content_range)
+        .filter(|(first, last, _)| last - first + 1 == contents.len() as u64)
+    else {
+        return Err(HttpResponse::bad_request());
+    };
+    // the whole file has to fit within the body limit, not just this part
+    if total > limit as u64 {
+        return Err(HttpResponse::new(StatusCode::PayloadTooLarge));
+    }
+    let staging = staging_path(path);
+
+    for expired in uploads.expire() {
+        let _ = storage.delete(&staging_path(&expired));
+    }
+
+    let known = uploads
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(path)
+        .map(|upload| upload.length);
+    match known {
+        Some(length) if length != total => {
+            return Err(HttpResponse::new(StatusCode::Conflict));
+        }
+        // whatever an upload before a restart left behind
+        None => match storage.delete(&staging) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(storage_error(e)),
+            _ => (),
+        },
+        Some(_) => (),
+    }
+
+    storage
+        .write_at(&staging, first, contents)
+        .map_err(storage_error)?;
+
+    let mut uploads = uploads.0.lock().unwrap_or_else(|e| e.into_inner());
+    let upload = uploads.entry(path.to_owned()).or_insert_with(|| Upload {
+        length: total,
+        received: Vec::new(),
+        updated: Instant::now(),
+    });
+    upload.updated = Instant::now();
+    merge_range(&mut upload.received, (first, last));
+    if upload.received != [(0, total - 1)] {
+        let ranges: Vec<String> = upload
+            .received
+            .iter()
+            .map(|(first, last)| format!("{}-{}", first, last))
+            .collect();
+        return Ok(Some(format!("bytes={}", ranges.join(","))));
+    }
+    uploads.remove(path);
+    drop(uploads);
+
+    storage.rename(&staging, path).map_err(storage_error)?;
+    Ok(None)
+}
+
+fn write_file(
+    req: &HttpRequest,
+    params: &PathParams,
+    storage: &dyn Storage,
+    conf: &Config,
+    files: &Files,
+) -> std::result::Result<Written, HttpResponse> {
+    let Some(path) = storage::normalize(params.get("path").unwrap_or_default()) else {
+        return Err(HttpResponse::forbidden());
+    };
+
+    let Some(body) = req.body().filter(|_| !path.is_empty()) else {
+        return Err(HttpResponse::bad_request());
+    };
+    if !digest::verify(req.headers(), body) {
+        return Err(HttpResponse::bad_request());
+    }
+    let contents = &*decoded_body(req, body, conf.limits.max_body_size)?;
+
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", &path));
+    check_uploads(
+        req,
+        storage,
+        &conf.uploads,
+        dir,
+        &[(name, req.header("content-type"))],
+    )?;
+
+    let _lock = files.locks.lock(&path);
+    let current = check_preconditions(req, storage, &path)?;
+
+    match req.header("content-range") {
+        Some(content_range) if *req.method() == HttpMethod::PUT => {
+            if let Some(received) = write_chunk(
+                storage,
+                &files.uploads,
+                &path,
+                content_range,
+                contents,
+                conf.limits.max_body_size,
+            )? {
+                return Ok(Written::Incomplete(received));
+            }
+        }
+        _ => storage.put(&path, contents).map_err(storage_error)?,
+    }
+
+    Ok(match current {
+        Some(_) => Written::Replaced,
+        None => Written::Created,
+    })
+}
+
+// the body as it was before the client compressed it, the digests cover
+// what was sent so they are checked first
+fn decoded_body<'a>(
+    req: &HttpRequest,
+    body: &'a [u8],
+    limit: usize,
+) -> std::result::Result<Cow<'a, [u8]>, HttpResponse> {
+    let Some(content_encoding) = req.headers().get_joined("content-encoding") else {
+        return Ok(Cow::Borrowed(body));
+    };
+
+    let mut codings = Vec::new();
+    for name in content_encoding
+        .split(',')
+        .filter(|name| !name.trim().is_empty())
+    {
+        let Some(coding) = ContentCoding::from_name(name) else {
+            let accepted: Vec<&str> = ContentCoding::SUPPORTED
+                .iter()
+                .map(ContentCoding::as_str)
+                .collect();
+            return Err(HttpResponse::new(StatusCode::UnsupportedMediaType)
+                .header("Accept-Encoding", accepted.join(", ")));
+        };
+        codings.push(coding);
+    }
+
+    // undone in the reverse of the order they were applied
+    codings
+        .into_iter()
+        .rev()
+        .try_fold(Cow::Borrowed(body), |content, coding| match coding {
+            ContentCoding::Identity => Ok(content),
+            coding => coding.decode(&content, limit).map(Cow::Owned),
+        })
+        .map_err(|e| match e {
+            Error::PayloadTooLarge => HttpResponse::new(StatusCode::PayloadTooLarge),
+            _ => HttpResponse::bad_request(),
+        })
+}
+
+fn reject_upload(req: &HttpRequest, name: &str, status: StatusCode, reason: &str) -> HttpResponse {
+    log::warning!(
+        "Rejected upload of {} to {} from {}, {}",
+        name,
+        req.path(),
+        req.context()
+            .peer_addr()
+            .map_or_else(|| "-".to_string(), |addr| addr.to_string()),
+        reason
+    );
+    HttpResponse::new(status)
+}
+
+// the names and content types of files about to be stored in `dir` against
+// the configured rules, nothing is written when one of them breaks a rule
+fn check_uploads(
+    req: &HttpRequest,
+    storage: &dyn Storage,
+    rules: &UploadRules,
+    dir: &str,
+    uploads: &[(&str, Option<&str>)],
+) -> std::result::Result<(), HttpResponse> {
+    for (name, content_type) in uploads {
+        if !rules.allows_name(name) {
+            return Err(reject_upload(
+                req,
+                name,
+                StatusCode::Forbidden,
+                "extension not allowed",
+            ));
+        }
+        if !rules.allows_content_type(*content_type) {
+            return Err(reject_upload(
+                req,
+                name,
+                StatusCode::UnsupportedMediaType,
+                "content type not allowed",
+            ));
+        }
+    }
+
+    let Some(max) = rules.max_files_per_dir else {
+        return Ok(());
+    };
+    let existing = match storage.list(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(storage_error(e)),
+    };
+    let mut names: Vec<&str> = existing
+        .iter()
+        .filter(|entry| !entry.metadata.is_dir && !is_staging(&entry.name))
+        .map(|entry| entry.name.as_str())
+        .collect();
+    // replacing a file does not add one
+    for (name, _) in uploads {
+        if !names.contains(name) {
+            names.push(name);
+        }
+    }
+    if names.len() > max {
+        return Err(reject_upload(
+            req,
+            uploads.first().map_or("", |(name, _)| name),
+            StatusCode::Forbidden,
+            "too many files in the directory",
+        ));
+    }
+
+    Ok(())
+}
+
+// only the last component of a client supplied name is kept, browsers on
+// windows have been known to send the full local path
+fn upload_name(filename: &str) -> Option<&str> {
+    filename
+        .rsplit(['/', '\\'])
+        .next()
+        .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+}
+
+// stores every file of a multipart/form-data body in the directory named by
+// the path, the other form fields are ignored
+fn upload_files(
+    req: &HttpRequest,
+    params: &PathParams,
+    storage: &dyn Storage,
+    conf: &Config,
+    locks: &PathLocks,
+    content_type: &str,
+) -> HttpResponse {
+    let Some(dir) = storage::normalize(params.get("path").unwrap_or_default()) else {
+        return HttpResponse::forbidden();
+    };
+
+    if let Err(e) = storage.metadata(&dir) {
+        if e.kind() != io::ErrorKind::NotFound {
+            return storage_error(e);
+        }
+    }
+
+    let body = req.body().unwrap_or_default();
+    if !digest::verify(req.headers(), body) {
+        return HttpResponse::bad_request();
+    }
+    let body = match decoded_body(req, body, conf.limits.max_body_size) {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+
+    let parts = match multipart::Parser::new(content_type, &body).and_then(|parser| {
+        parser
+            .max_part_size(conf.limits.max_part_size)
+            .collect::<Result<Vec<_>>>()
+    }) {
+        Ok(parts) => parts,
+        Err(Error::PayloadTooLarge) => return HttpResponse::new(StatusCode::PayloadTooLarge),
+        Err(_) => return HttpResponse::bad_request(),
+    };
+
+    // every name is checked before anything is written
+    let mut files = Vec::new();
+    for part in &parts {
+        if let Some(filename) = &part.filename {
+            let Some(name) = upload_name(filename) else {
+                return HttpResponse::bad_request();
+            };
+            if storage
+                .metadata(&child(&dir, name))
+                .is_ok_and(|metadata| metadata.is_dir)
+            {
+                return HttpResponse::forbidden();
+            }
+            files.push((name, part.content_type(), part.data));
+        }
+    }
+
+    if files.is_empty() {
+        return HttpResponse::bad_request();
+    }
+
+    let uploads: Vec<_> = files
+        .iter()
+        .map(|(name, content_type, _)| (*name, *content_type))
+        .collect();
+    if let Err(response) = check_uploads(req, storage, &conf.uploads, &dir, &uploads) {
+        return response;
+    }
+
+    for (name, _, data) in &files {
+        let path = child(&dir, name);
+        let _lock = locks.lock(&path);
+        if let Err(e) = storage.put(&path, data) {
+            return storage_error(e);
+        }
+    }
+
+    let names: Vec<&str> = files.iter().map(|(name, _, _)| *name).collect();
+    HttpResponse::created()
+        .header("Content-Type", "text/plain")
+        .body(names.join("\n"))
+}
+
+fn post_file(req: &HttpRequest, params: &PathParams, files: &Files) -> HttpResponse {
+    let (conf, storage) = match files.open(req) {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+
+    if let Some(content_type) = req.header("content-type").filter(|content_type| {
+        content_type
+            .to_lowercase()
+            .starts_with("multipart/form-data")
+    }) {
+        return upload_files(req, params, &*storage, &conf, &files.locks, content_type);
+    }
+
+    match write_file(req, params, &*storage, &conf, files) {
+        Ok(_) => HttpResponse::created(),
+        Err(response) => response,
+    }
+}
+
+fn put_file(req: &HttpRequest, params: &PathParams, files: &Files) -> HttpResponse {
+    let (conf, storage) = match files.open(req) {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+
+    // 308 is what resumable upload clients expect for an incomplete one
+    match write_file(req, params, &*storage, &conf, files) {
+        Ok(Written::Replaced) => HttpResponse::new(StatusCode::NoContent),
+        Ok(Written::Created) => HttpResponse::created(),
+        Ok(Written::Incomplete(received)) => {
+            HttpResponse::new(StatusCode::PermanentRedirect).header("Range", received)
+        }
+        Err(response) => response,
+    }
+}
+
+fn delete_file(req: &HttpRequest, params: &PathParams, files: &Files) -> HttpResponse {
+    let storage = match files.open(req) {
+        Ok((_, storage)) => storage,
+        Err(response) => return response,
+    };
+    let Some(path) = storage::normalize(params.get("path").unwrap_or_default()) else {
+        return HttpResponse::forbidden();
+    };
+
+    let _lock = files.locks.lock(&path);
+    match check_preconditions(req, &*storage, &path) {
+        Ok(Some(_)) => match storage.delete(&path) {
+            Ok(()) => HttpResponse::new(StatusCode::NoContent),
+            Err(e) => storage_error(e),
+        },
+        Ok(None) => HttpResponse::not_found(),
+        Err(response) => response,
+    }
+}
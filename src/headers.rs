@@ -0,0 +1,104 @@
+// header fields in the order they were added, a name may appear more than
+// once and lookups ignore case
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // the first value for the name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // every value for the name as one comma separated list, which is how
+    // repeated list based fields are combined, see RFC 9110 section 5.3
+    pub fn get_joined(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self.get_all(name).collect();
+        (!values.is_empty()).then(|| values.join(", "))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // replaces every existing value for the name
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    // adds another value, keeping the existing ones
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header_map_should_keep_duplicates_in_order() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Content-Type", "text/plain");
+        headers.append("set-cookie", "b=2");
+
+        let test_cases = vec![
+            ("set-cookie", Some("a=1"), vec!["a=1", "b=2"]),
+            ("CONTENT-TYPE", Some("text/plain"), vec!["text/plain"]),
+            ("accept", None, vec![]),
+        ];
+
+        for (name, first, all) in test_cases {
+            assert_eq!(headers.get(name), first, "{name}");
+            assert_eq!(headers.get_all(name).collect::<Vec<_>>(), all, "{name}");
+        }
+
+        assert_eq!(
+            headers.get_joined("Set-Cookie").as_deref(),
+            Some("a=1, b=2")
+        );
+
+        headers.insert("SET-COOKIE", "c=3");
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            vec![("Content-Type", "text/plain"), ("SET-COOKIE", "c=3")]
+        );
+    }
+}
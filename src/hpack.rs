@@ -0,0 +1,482 @@
+use crate::errors::{Error, Result};
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+// RFC 7541 Appendix A
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// the Huffman code of RFC 7541 Appendix B is canonical, so the code lengths
+// alone define it, the last one is EOS
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+const EOS: u16 = 256;
+
+// every entry costs 32 bytes on top of its name and value, see RFC 7541
+// section 4.1
+fn entry_size(name: &str, value: &str) -> usize {
+    name.len() + value.len() + 32
+}
+
+// decodes header blocks, the dynamic table carries over from one block to
+// the next so every block of a connection has to go through one decoder
+pub(crate) struct Decoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+    // what the peer was allowed through SETTINGS_HEADER_TABLE_SIZE
+    limit: usize,
+    // SETTINGS_MAX_HEADER_LIST_SIZE, a block expanding past it is refused
+    // while it is decoded rather than after
+    max_list_size: usize,
+}
+
+impl Decoder {
+    pub(crate) fn new(limit: usize, max_list_size: usize) -> Self {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: limit,
+            limit,
+            max_list_size,
+        }
+    }
+
+    pub(crate) fn decode(&mut self, mut block: &[u8]) -> Result<Vec<(String, String)>> {
+        let mut fields = Vec::new();
+        let mut list_size = 0usize;
+
+        while let Some(&first) = block.first() {
+            let field = match first {
+                // indexed field
+                _ if first & 0x80 != 0 => self.get(decode_int(&mut block, 7)?)?.clone(),
+                // literal with incremental indexing
+                _ if first & 0x40 != 0 => {
+                    let field = self.literal(&mut block, 6)?;
+                    self.insert(field.clone());
+                    field
+                }
+                // dynamic table size update, only allowed ahead of any field
+                _ if first & 0x20 != 0 => {
+                    let size = decode_int(&mut block, 5)?;
+                    if !fields.is_empty() || size > self.limit {
+                        return Err(Error::InvalidRequest);
+                    }
+                    self.max_size = size;
+                    self.evict();
+                    continue;
+                }
+                // literal without indexing or never indexed
+                _ => self.literal(&mut block, 4)?,
+            };
+
+            list_size = list_size.saturating_add(entry_size(&field.0, &field.1));
+            if list_size > self.max_list_size {
+                return Err(Error::HeadersTooLarge);
+            }
+            fields.push(field);
+        }
+
+        Ok(fields)
+    }
+
+    fn get(&self, index: usize) -> Result<&(String, String)> {
+        static STATIC: OnceLock<Vec<(String, String)>> = OnceLock::new();
+        let static_table = STATIC.get_or_init(|| {
+            STATIC_TABLE
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        });
+
+        match index {
+            0 => None,
+            1..=61 => static_table.get(index - 1),
+            _ => self.table.get(index - 62),
+        }
+        .ok_or(Error::InvalidRequest)
+    }
+
+    fn literal(&self, block: &mut &[u8], prefix: u8) -> Result<(String, String)> {
+        let name = match decode_int(block, prefix)? {
+            0 => decode_string(block)?,
+            index => self.get(index)?.0.clone(),
+        };
+        let value = decode_string(block)?;
+
+        Ok((name, value))
+    }
+
+    fn insert(&mut self, field: (String, String)) {
+        self.size += entry_size(&field.0, &field.1);
+        self.table.push_front(field);
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+            self.size -= entry_size(&name, &value);
+        }
+    }
+}
+
+// fields are sent as literals that are never added to the dynamic table and
+// without Huffman coding, which keeps the encoder stateless so streams can
+// encode their headers concurrently
+pub(crate) fn encode<'a>(fields: impl IntoIterator<Item = (&'a str, &'a str)>, out: &mut Vec<u8>) {
+    for (name, value) in fields {
+        let exact = STATIC_TABLE
+            .iter()
+            .position(|entry| *entry == (name, value));
+        let named = STATIC_TABLE.iter().position(|(entry, _)| *entry == name);
+
+        match (exact, named) {
+            (Some(index), _) => encode_int(index + 1, 7, 0x80, out),
+            (None, Some(index)) => {
+                encode_int(index + 1, 4, 0x00, out);
+                encode_string(value, out);
+            }
+            (None, None) => {
+                encode_int(0, 4, 0x00, out);
+                encode_string(name, out);
+                encode_string(value, out);
+            }
+        }
+    }
+}
+
+fn encode_int(value: usize, prefix: u8, flags: u8, out: &mut Vec<u8>) {
+    let max = (1 << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+
+    out.push(flags | max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        out.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+fn encode_string(value: &str, out: &mut Vec<u8>) {
+    encode_int(value.len(), 7, 0x00, out);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn decode_int(block: &mut &[u8], prefix: u8) -> Result<usize> {
+    let (&first, rest) = block.split_first().ok_or(Error::InvalidRequest)?;
+    *block = rest;
+
+    let max = (1 << prefix) - 1;
+    let mut value = usize::from(first) & max;
+    if value < max {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = block.split_first().ok_or(Error::InvalidRequest)?;
+        *block = rest;
+
+        // anything larger is no sensible length or index
+        if shift > 21 {
+            return Err(Error::InvalidRequest);
+        }
+        value += usize::from(byte & 0x7f) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn decode_string(block: &mut &[u8]) -> Result<String> {
+    let huffman = block.first().is_some_and(|first| first & 0x80 != 0);
+    let len = decode_int(block, 7)?;
+    if block.len() < len {
+        return Err(Error::InvalidRequest);
+    }
+
+    let (raw, rest) = block.split_at(len);
+    *block = rest;
+
+    let bytes = if huffman {
+        huffman_decode(raw)?
+    } else {
+        raw.to_vec()
+    };
+
+    Ok(String::from_utf8(bytes)?)
+}
+
+// the first code of every length and where its symbols start in `symbols`,
+// which lists the symbols ordered by code
+struct Canonical {
+    symbols: Vec<u16>,
+    first_code: [u32; 31],
+    first_index: [usize; 31],
+    count: [usize; 31],
+}
+
+fn canonical() -> &'static Canonical {
+    static CANONICAL: OnceLock<Canonical> = OnceLock::new();
+
+    CANONICAL.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..=EOS).collect();
+        symbols.sort_by_key(|&symbol| HUFFMAN_LENGTHS[usize::from(symbol)]);
+
+        let mut canonical = Canonical {
+            symbols,
+            first_code: [0; 31],
+            first_index: [0; 31],
+            count: [0; 31],
+        };
+
+        let (mut code, mut index) = (0, 0);
+        for len in 1..31 {
+            canonical.first_code[len] = code;
+            canonical.first_index[len] = index;
+            canonical.count[len] = HUFFMAN_LENGTHS
+                .iter()
+                .filter(|&&length| usize::from(length) == len)
+                .count();
+
+            code = (code + canonical.count[len] as u32) << 1;
+            index += canonical.count[len];
+        }
+
+        canonical
+    })
+}
+
+fn huffman_decode(raw: &[u8]) -> Result<Vec<u8>> {
+    let canonical = canonical();
+    let mut decoded = Vec::with_capacity(raw.len() * 8 / 5);
+    let (mut code, mut len) = (0u32, 0usize);
+
+    for bit in raw
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |shift| u32::from(byte >> shift) & 1))
+    {
+        code = code << 1 | bit;
+        len += 1;
+
+        if len > 30 {
+            return Err(Error::InvalidRequest);
+        }
+
+        let offset = code.wrapping_sub(canonical.first_code[len]) as usize;
+        if code >= canonical.first_code[len] && offset < canonical.count[len] {
+            let symbol = canonical.symbols[canonical.first_index[len] + offset];
+            if symbol == EOS {
+                return Err(Error::InvalidRequest);
+            }
+            decoded.push(symbol as u8);
+            (code, len) = (0, 0);
+        }
+    }
+
+    // the padding is the most significant bits of EOS, all ones and shorter
+    // than a byte
+    if len > 7 || code != (1 << len) - 1 {
+        return Err(Error::InvalidRequest);
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hex(value: &str) -> Vec<u8> {
+        let digits: Vec<u8> = value.bytes().filter(u8::is_ascii_hexdigit).collect();
+        digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn decoder_should_follow_the_rfc_examples() {
+        let first = vec![
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+        ];
+        let mut second = first.clone();
+        second.push(("cache-control", "no-cache"));
+        let third = vec![
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/index.html"),
+            (":authority", "www.example.com"),
+            ("custom-key", "custom-value"),
+        ];
+
+        // RFC 7541 Appendix C.3 without and C.4 with Huffman coding
+        let test_cases = vec![
+            vec![
+                ("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d", &first),
+                ("8286 84be 5808 6e6f 2d63 6163 6865", &second),
+                (
+                    "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+                    &third,
+                ),
+            ],
+            vec![
+                ("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff", &first),
+                ("8286 84be 5886 a8eb 1064 9cbf", &second),
+                (
+                    "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+                    &third,
+                ),
+            ],
+        ];
+
+        for blocks in test_cases {
+            let mut decoder = Decoder::new(4096, usize::MAX);
+            for (block, expected) in blocks {
+                let fields = decoder.decode(&hex(block)).unwrap();
+                let fields: Vec<_> = fields
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str()))
+                    .collect();
+                assert_eq!(&fields, expected, "{block}");
+            }
+        }
+
+        let mut block = Vec::new();
+        encode(
+            [
+                (":status", "200"),
+                ("content-type", "text/plain"),
+                ("x-a", "b"),
+            ],
+            &mut block,
+        );
+        assert_eq!(
+            Decoder::new(4096, usize::MAX).decode(&block).unwrap(),
+            vec![
+                (":status".to_string(), "200".to_string()),
+                ("content-type".to_string(), "text/plain".to_string()),
+                ("x-a".to_string(), "b".to_string()),
+            ]
+        );
+
+        // an index past the tables, a truncated string, EOS in a string and
+        // padding that is not all ones
+        for block in ["be", "0003 6162", "0084 ffff ffff 00", "0081 0000"] {
+            assert!(
+                Decoder::new(4096, usize::MAX).decode(&hex(block)).is_err(),
+                "{block}"
+            );
+        }
+    }
+
+    #[test]
+    fn decoder_should_stop_a_block_expanding_past_the_list_size() {
+        // a 4033 byte entry added to the dynamic table, then referenced by
+        // one byte each time
+        let repeated = |references: usize| {
+            let mut block = vec![0x40, 0x01, b'a'];
+            encode_int(4000, 7, 0, &mut block);
+            block.extend_from_slice(&[b'x'; 4000]);
+            block.extend(std::iter::repeat(0xbe).take(references));
+            block
+        };
+
+        let test_cases = vec![(15, Some(16)), (16, None), (16 * 1024, None)];
+
+        for (references, expected) in test_cases {
+            let result = Decoder::new(4096, 64 * 1024).decode(&repeated(references));
+            match expected {
+                Some(fields) => assert_eq!(result.unwrap().len(), fields, "{references}"),
+                None => assert!(
+                    matches!(result, Err(Error::HeadersTooLarge)),
+                    "{references}"
+                ),
+            }
+        }
+    }
+}
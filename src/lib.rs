@@ -0,0 +1,59 @@
+pub mod accept;
+#[cfg(feature = "tokio")]
+mod async_server;
+mod chunked;
+pub mod cidr;
+pub mod client;
+mod conditional;
+pub mod config;
+pub mod context;
+mod date;
+mod digest;
+mod encoding;
+pub mod error_page;
+pub mod errors;
+mod etag;
+#[cfg(all(unix, not(feature = "tokio")))]
+mod evented;
+pub mod extract;
+mod forwarded;
+#[cfg(all(feature = "h2", not(feature = "tokio")))]
+mod h2;
+mod handlers;
+pub mod headers;
+#[cfg(all(feature = "h2", not(feature = "tokio")))]
+mod hpack;
+mod listener;
+mod listing;
+pub mod log;
+pub mod middleware;
+pub mod mime;
+pub mod multipart;
+pub mod precompress;
+mod range;
+mod record;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod session;
+pub mod shutdown;
+pub mod sse;
+pub mod storage;
+#[cfg(not(feature = "tokio"))]
+mod stream;
+#[cfg(unix)]
+mod systemd;
+#[cfg(not(feature = "tokio"))]
+mod thread_pool;
+mod tls;
+pub mod websocket;
+
+pub use config::Config;
+pub use context::RequestContext;
+pub use errors::{Error, Result};
+pub use headers::HeaderMap;
+pub use request::{HttpMethod, HttpRequest, HttpVersion};
+pub use response::{HttpResponse, StatusCode};
+pub use router::{PathParams, Router};
+pub use server::Server;
@@ -0,0 +1,239 @@
+use crate::config::{ListenAddr, TcpOptions};
+use socket2::{Domain, SockAddr, SockRef, Socket, TcpKeepalive, Type};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::fd::OwnedFd;
+#[cfg(unix)]
+use std::os::unix::{
+    fs::FileTypeExt,
+    net::{UnixListener, UnixStream},
+};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+#[cfg(unix)]
+use std::{fs, path::Path};
+
+// where a listener ended up, with the port picked for port 0 filled in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Bound {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Bound {
+    // connects once so an accept blocked on the listener returns
+    pub(crate) fn wake(&self) {
+        match self {
+            Bound::Tcp(addr) => {
+                let mut addr = *addr;
+                if addr.ip().is_unspecified() {
+                    addr.set_ip(match addr.ip() {
+                        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    });
+                }
+                let _ = TcpStream::connect(addr);
+            }
+            #[cfg(unix)]
+            Bound::Unix(path) => {
+                let _ = UnixStream::connect(path);
+            }
+            #[cfg(not(unix))]
+            Bound::Unix(_) => (),
+        }
+    }
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bound::Tcp(addr) => write!(fmt, "{addr}"),
+            Bound::Unix(path) => write!(fmt, "unix:{}", path.display()),
+        }
+    }
+}
+
+// the path of a unix socket, removed once the listener is gone when this
+// process created it so the next start does not find the path taken
+#[cfg(unix)]
+pub(crate) struct SocketFile {
+    pub(crate) path: PathBuf,
+    pub(crate) created: bool,
+}
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if self.created {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, SocketFile),
+}
+
+// a listener with a count of the connections taken by each of the accept
+// loops sharing it
+pub(crate) struct Acceptors {
+    pub(crate) listener: Listener,
+    pub(crate) accepted: Vec<Arc<AtomicU64>>,
+}
+
+#[cfg(not(feature = "tokio"))]
+pub(crate) enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+// a socket left behind by a server that did not get to clean up is taken
+// over, one that still accepts connections is not
+#[cfg(unix)]
+fn remove_stale(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() && UnixStream::connect(path).is_err() => {
+            fs::remove_file(path)
+        }
+        _ => Ok(()),
+    }
+}
+
+// a listening socket with a backlog of its own, std always asks for 128
+fn listen(domain: Domain, addr: &SockAddr, backlog: u32) -> io::Result<Socket> {
+    let socket = Socket::new(domain, Type::STREAM, None)?;
+    // as std does, so a restart does not wait for old connections to time out
+    #[cfg(unix)]
+    if domain != Domain::UNIX {
+        socket.set_reuse_address(true)?;
+    }
+    socket.bind(addr)?;
+    socket.listen(i32::try_from(backlog).unwrap_or(i32::MAX))?;
+    Ok(socket)
+}
+
+fn bind_tcp(addr: &str, backlog: Option<u32>) -> io::Result<TcpListener> {
+    let Some(backlog) = backlog else {
+        return TcpListener::bind(addr);
+    };
+
+    // the first address that works, like `TcpListener::bind`
+    let mut error = io::Error::new(io::ErrorKind::InvalidInput, "no address to listen on");
+    for addr in addr.to_socket_addrs()? {
+        match listen(Domain::for_address(addr), &addr.into(), backlog) {
+            Ok(socket) => return Ok(socket.into()),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+// applies the options to an accepted connection
+pub(crate) fn tune(socket: SockRef<'_>, options: &TcpOptions) -> io::Result<()> {
+    socket.set_tcp_nodelay(options.nodelay)?;
+    if let Some(keepalive) = options.keepalive {
+        let params = TcpKeepalive::new().with_time(keepalive);
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "freebsd",
+            windows
+        ))]
+        let params = params.with_interval(keepalive);
+        socket.set_tcp_keepalive(&params)?;
+    }
+    Ok(())
+}
+
+impl Listener {
+    pub(crate) fn bind(addr: &ListenAddr, options: &TcpOptions) -> io::Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => Ok(Listener::Tcp(bind_tcp(addr, options.backlog)?)),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                remove_stale(path)?;
+                let listener = match options.backlog {
+                    Some(backlog) => {
+                        let socket = listen(Domain::UNIX, &SockAddr::unix(path)?, backlog)?;
+                        UnixListener::from(OwnedFd::from(socket))
+                    }
+                    None => UnixListener::bind(path)?,
+                };
+                let file = SocketFile {
+                    path: path.clone(),
+                    created: true,
+                };
+                Ok(Listener::Unix(listener, file))
+            }
+            #[cfg(not(unix))]
+            ListenAddr::Unix(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<Bound> {
+        match self {
+            Listener::Tcp(listener) => Ok(Bound::Tcp(listener.local_addr()?)),
+            #[cfg(unix)]
+            Listener::Unix(_, file) => Ok(Bound::Unix(file.path.clone())),
+        }
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    pub(crate) fn accept(&self, options: &TcpOptions) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                tune(SockRef::from(&stream), options)?;
+                Ok(Connection::Tcp(stream))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => Ok(Connection::Unix(listener.accept()?.0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn tune_should_apply_the_tcp_options() {
+        let test_cases = vec![
+            TcpOptions::default(),
+            TcpOptions {
+                nodelay: false,
+                keepalive: Some(Duration::from_secs(60)),
+                backlog: Some(16),
+            },
+        ];
+
+        for options in test_cases {
+            let listener = Listener::bind(&ListenAddr::from("127.0.0.1:0"), &options).unwrap();
+            let Ok(Bound::Tcp(addr)) = listener.local_addr() else {
+                panic!("not a tcp listener");
+            };
+            let stream = TcpStream::connect(addr).unwrap();
+            tune(SockRef::from(&stream), &options).unwrap();
+
+            let socket = SockRef::from(&stream);
+            assert_eq!(
+                socket.tcp_nodelay().unwrap(),
+                options.nodelay,
+                "{options:?}"
+            );
+            assert_eq!(
+                socket.keepalive().unwrap(),
+                options.keepalive.is_some(),
+                "{options:?}"
+            );
+        }
+    }
+}
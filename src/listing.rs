@@ -0,0 +1,87 @@
+use crate::date::DateTime;
+use crate::storage::Entry;
+use serde::Serialize;
+use std::time::SystemTime;
+
+#[derive(Debug, Serialize)]
+pub struct DirEntry {
+    name: String,
+    size: u64,
+    modified: String,
+    is_dir: bool,
+}
+
+pub fn from_entries(entries: Vec<Entry>) -> Vec<DirEntry> {
+    let mut entries = entries
+        .into_iter()
+        .map(|entry| DirEntry {
+            name: entry.name,
+            size: entry.metadata.len,
+            modified: DateTime::from_system_time(
+                entry.metadata.modified.unwrap_or(SystemTime::UNIX_EPOCH),
+            )
+            .to_rfc3339(),
+            is_dir: entry.metadata.is_dir,
+        })
+        .collect::<Vec<_>>();
+
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    entries
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn encode_path_segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub fn render_html(title: &str, entries: &[DirEntry]) -> String {
+    let title = escape_html(title);
+
+    let rows: String = entries
+        .iter()
+        .map(|entry| {
+            let suffix = if entry.is_dir { "/" } else { "" };
+            format!(
+                "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                encode_path_segment(&entry.name),
+                suffix,
+                escape_html(&entry.name),
+                suffix,
+                entry.size,
+                entry.modified
+            )
+        })
+        .collect();
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n{rows}</table>\n</body>\n</html>\n"
+    )
+}
+
+pub fn render_text(entries: &[DirEntry]) -> String {
+    entries
+        .iter()
+        .map(|entry| format!("{}{}\n", entry.name, if entry.is_dir { "/" } else { "" }))
+        .collect()
+}
+
+pub fn render_json(entries: &[DirEntry]) -> serde_json::Result<String> {
+    serde_json::to_string(entries)
+}
@@ -0,0 +1,194 @@
+use crate::date::DateTime;
+use crate::errors::Error;
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(Error::InvalidConfig),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value.to_ascii_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(Error::InvalidConfig),
+        }
+    }
+}
+
+struct Logger {
+    level: AtomicU8,
+    format: Format,
+}
+
+#[cfg(feature = "tracing")]
+mod span;
+#[cfg(feature = "tracing")]
+pub use span::{enter, set_subscriber, Span, Subscriber};
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+thread_local! {
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn init(level: Level, format: Format) {
+    let _ = LOGGER.set(Logger {
+        level: AtomicU8::new(level as u8),
+        format,
+    });
+}
+
+fn logger() -> &'static Logger {
+    LOGGER.get_or_init(|| Logger {
+        level: AtomicU8::new(Level::Info as u8),
+        format: Format::Text,
+    })
+}
+
+pub fn set_level(level: Level) {
+    logger().level.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= logger().level.load(Ordering::Relaxed)
+}
+
+// a version 4 UUID, random as far as anyone correlating logs can tell
+pub fn new_request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    static SEED: OnceLock<RandomState> = OnceLock::new();
+
+    let mut hasher = SEED.get_or_init(RandomState::new).build_hasher();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    hasher.write_u64(count);
+    let high = hasher.finish();
+    hasher.write_u64(count);
+    let low = hasher.finish();
+
+    let mut bits = (u128::from(high) << 64) | u128::from(low);
+    bits = (bits & !(0xf << 76)) | (0x4 << 76);
+    bits = (bits & !(0x3 << 62)) | (0x2 << 62);
+    let hex = format!("{bits:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+// runs `f` with `id` attached to every log line emitted on this thread
+pub fn with_request_id<T>(id: &str, f: impl FnOnce() -> T) -> T {
+    let previous = REQUEST_ID.with(|current| current.replace(Some(id.to_owned())));
+    let result = f();
+    REQUEST_ID.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+pub fn log(level: Level, args: fmt::Arguments<'_>) {
+    let logger = logger();
+    let timestamp = DateTime::from_system_time(SystemTime::now()).to_rfc3339();
+    let message = args.to_string();
+    let request_id = REQUEST_ID.with(|current| current.borrow().clone());
+
+    let line = match logger.format {
+        Format::Text => match request_id {
+            Some(id) => format!("{} {:5} [{}] {}\n", timestamp, level.as_str(), id, message),
+            None => format!("{} {:5} {}\n", timestamp, level.as_str(), message),
+        },
+        Format::Json => {
+            let mut entry = serde_json::json!({
+                "ts": timestamp,
+                "level": level.as_str(),
+                "msg": message,
+            });
+            if let Some(id) = request_id {
+                entry["request_id"] = id.into();
+            }
+            format!("{}\n", entry)
+        }
+    };
+
+    let _ = io::stderr().lock().write_all(line.as_bytes());
+}
+
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::log::enabled($level) {
+            $crate::log::log($level, format_args!($($arg)*));
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::log_at!($crate::log::Level::Error, $($arg)*) };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::log::log_at!($crate::log::Level::Warn, $($arg)*) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::log_at!($crate::log::Level::Info, $($arg)*) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::log_at!($crate::log::Level::Debug, $($arg)*) };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::log::log_at!($crate::log::Level::Trace, $($arg)*) };
+}
+
+pub(crate) use {debug, error, info, log_at, trace, warning};
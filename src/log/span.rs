@@ -0,0 +1,167 @@
+use super::{enabled, log, Level};
+use crate::errors::Error;
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    // the spans in scope on this thread, innermost last
+    static SPANS: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+// receives every span as it closes, in place of the debug line logged by default
+pub trait Subscriber: Send + Sync {
+    fn on_close(&self, span: &Span);
+}
+
+static SUBSCRIBER: OnceLock<Box<dyn Subscriber>> = OnceLock::new();
+
+// installs `subscriber` for the rest of the process, only the first call wins
+pub fn set_subscriber(subscriber: impl Subscriber + 'static) -> Result<(), Error> {
+    SUBSCRIBER
+        .set(Box::new(subscriber))
+        .map_err(|_| Error::SubscriberAlreadySet)
+}
+
+// a timed unit of work, closed when dropped; spans opened while another is
+// in scope on the same thread become its children
+pub struct Span {
+    name: &'static str,
+    id: u64,
+    parent: Option<u64>,
+    fields: Vec<(&'static str, String)>,
+    started: Instant,
+}
+
+impl Span {
+    pub fn new(name: &'static str) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        Span {
+            name,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            parent: SPANS.with(|spans| spans.borrow().last().copied()),
+            fields: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn field(mut self, name: &'static str, value: impl fmt::Display) -> Self {
+        self.record(name, value);
+        self
+    }
+
+    pub fn record(&mut self, name: &'static str, value: impl fmt::Display) {
+        let value = value.to_string();
+        match self.fields.iter_mut().find(|(field, _)| *field == name) {
+            Some((_, current)) => *current = value,
+            None => self.fields.push((name, value)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn parent(&self) -> Option<u64> {
+        self.parent
+    }
+
+    pub fn fields(&self) -> &[(&'static str, String)] {
+        &self.fields
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        enter(self.id, f)
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        match SUBSCRIBER.get() {
+            Some(subscriber) => subscriber.on_close(self),
+            None if enabled(Level::Debug) => {
+                let mut line = format!("{} closed after {:?}", self.name, self.elapsed());
+                for (name, value) in &self.fields {
+                    line.push_str(&format!(" {}={}", name, value));
+                }
+                log(Level::Debug, format_args!("{}", line));
+            }
+            None => (),
+        }
+    }
+}
+
+// runs `f` inside the span `id`, for work handed to another thread than the
+// one holding the span
+pub fn enter<T>(id: u64, f: impl FnOnce() -> T) -> T {
+    SPANS.with(|spans| spans.borrow_mut().push(id));
+    let _entered = Entered;
+    f()
+}
+
+// leaves the innermost span however `f` returns, pool workers catch
+// panics and go on to the next request
+struct Entered;
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        SPANS.with(|spans| spans.borrow_mut().pop());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spans_should_nest_and_keep_their_fields() {
+        let connection = Span::new("connection").field("peer", "127.0.0.1:80");
+        let (request, sibling) = connection.in_scope(|| {
+            let mut request = Span::new("request").field("method", "GET");
+            request.record("status", 200);
+            request.record("status", 404);
+            (request, Span::new("request"))
+        });
+        let detached = Span::new("request");
+        let nested = enter(request.id(), || Span::new("upstream"));
+
+        let test_cases = vec![
+            (&connection, None, vec![("peer", "127.0.0.1:80")]),
+            (
+                &request,
+                Some(connection.id()),
+                vec![("method", "GET"), ("status", "404")],
+            ),
+            (&sibling, Some(connection.id()), vec![]),
+            (&detached, None, vec![]),
+            (&nested, Some(request.id()), vec![]),
+        ];
+
+        for (span, parent, fields) in test_cases {
+            assert_eq!(span.parent(), parent, "{}", span.name());
+            let actual: Vec<_> = span
+                .fields()
+                .iter()
+                .map(|(name, value)| (*name, value.as_str()))
+                .collect();
+            assert_eq!(actual, fields, "{}", span.name());
+        }
+        assert_ne!(request.id(), sibling.id());
+
+        let panicked = std::panic::catch_unwind(|| enter(request.id(), || panic!("in scope")));
+        assert!(panicked.is_err());
+        assert_eq!(Span::new("request").parent(), None);
+        assert!(request.started >= connection.started);
+    }
+}
@@ -1,46 +1,759 @@
-use std::{env, path::PathBuf};
+use std::{
+    env, io, net::SocketAddr, path::PathBuf, process, str::FromStr, sync::Arc, time::Duration,
+};
 
-use errors::Result;
-use server::Server;
+use codecrafters_http_server::config::{ListenAddr, SharedConfig};
+use codecrafters_http_server::middleware::{
+    AccessLog, Auth, Cache, CacheControl, Compression, Cors, IpFilter, Metrics, Proxy, RateLimit,
+    ReadOnly, Reload, Rewrite, SecurityHeaders, Timeout, Trace,
+};
+use codecrafters_http_server::request::normalize_host;
+use codecrafters_http_server::{
+    log, precompress, shutdown, Config, HttpMethod, Result, Server, StatusCode,
+};
 
-mod errors;
-mod server;
-mod thread_pool;
+const USAGE: &str = "\
+Usage: codecrafters-http-server [serve] [OPTIONS]
+       codecrafters-http-server check-config [OPTIONS]
+       codecrafters-http-server replay <DIR> [OPTIONS]
 
-#[derive(PartialEq, Eq, Debug, Clone)]
-pub struct Args {
-    directory: Option<PathBuf>,
+Commands:
+  serve                          Listen for connections, what runs without a command
+  check-config                   Load the config file and flags, report what is wrong and exit
+  replay <DIR>                   Run the requests recorded with --record and print the responses
+
+Options:
+  --config <FILE>                TOML config file
+  --address <IP>                 Address to listen on [default: 127.0.0.1]
+  --port <PORT>                  Port to listen on [default: 4221]
+  --listen <ADDR>                Listen on ADDR instead, host:port or unix:<path>, repeatable
+  --directory <DIR>              Serve and store /files from DIR, which has to exist
+  --vhost <HOST=DIR>             Serve /files for HOST from DIR, repeatable
+  --storage <disk|memory>        Where /files are kept
+  --enable-dir-listing           List directories under /files
+  --enable-metrics               Serve /metrics
+  --enable-trace                 Answer TRACE requests
+  --spa                          Answer unknown paths with index.html
+  --read-only                    Refuse requests that change files
+  --precompress                  Compress served files ahead of time
+  --problem-json                 Error bodies as application/problem+json
+  --error-pages <DIR>            Error bodies from DIR/<status>.html
+  --mime-type <EXT=TYPE>         Content-Type for an extension, repeatable
+  --upload-extension <EXT>       Accept uploads with this extension only, repeatable
+  --upload-content-type <TYPE>   Accept uploads of this type only, repeatable
+  --max-files-per-dir <N>        Refuse uploads to a directory holding N files
+  --max-body-size <BYTES>        Largest request body accepted
+  --max-part-size <BYTES>        Largest multipart part accepted
+  --compression-min-size <BYTES> Smallest body worth compressing
+  --compression-skip-type <TYPE> Never compress TYPE, repeatable
+  --cache-size <N>               Keep up to N responses in memory
+  --cache-ttl <SECS>             How long cached responses stay fresh
+  --cors-allow-origin <ORIGIN>   Allow cross-origin requests from ORIGIN, repeatable
+  --auth-basic <USER:PASS>       Require basic auth for changes to /files
+  --auth-bearer <TOKEN>          Require a bearer token for changes to /files
+  --session-secret <SECRET>      Sign session cookies with SECRET, random at each start otherwise
+  --session-ttl <SECS>           How long a session lasts
+  --rate-limit <N>               Requests per second per client
+  --rate-limit-burst <N>         Requests a client may make at once
+  --allow-ip <CIDR>              Let in only clients in CIDR, repeatable
+  --deny-ip <CIDR>               Refuse clients in CIDR, repeatable
+  --server-header <VALUE>        Server header of every response, empty to leave it out
+  --no-security-headers          Leave out X-Frame-Options, Referrer-Policy and the like
+  --frame-options <VALUE>        X-Frame-Options, empty to leave it out [default: DENY]
+  --referrer-policy <VALUE>      Referrer-Policy, empty to leave it out
+  --hsts-max-age <SECS>          Strict-Transport-Security max-age over https, 0 for none
+  --content-security-policy <POLICY> Content-Security-Policy sent with every response
+  --trusted-proxies <CIDR,..>    Believe X-Forwarded-* and Forwarded from these peers, repeatable
+  --proxy <URL>                  Forward unknown paths to URL
+  --tls-cert <FILE>              PEM certificate chain, with --tls-key serves HTTPS
+  --tls-key <FILE>               PEM private key
+  --access-log <FILE>            Log each request to FILE, - for stdout
+  --record <DIR>                 Write the bytes of each connection to DIR, credentials redacted
+  --log-level <LEVEL>            error, warn, info, debug or trace
+  --log-format <text|json>       How log lines look
+  --keep-alive-timeout <SECS>    How long an idle connection stays open
+  --request-timeout-ms <MS>      Time a handler has to respond
+  --route-timeout-ms <PATTERN=MS> Time handlers of a route have, repeatable
+  --workers <N>                  Threads handling requests
+  --backlog <N>                  Requests waiting for a worker before refusing more
+  --max-connections <N>          Open connections before refusing more
+  --acceptors <N>                Accept loops per listener
+  --io-model <threads|evented>   How connections are served
+  --no-tcp-nodelay               Leave Nagle's algorithm on
+  --tcp-keepalive <SECS>         Probe idle connections after SECS
+  --listen-backlog <N>           Connections the kernel queues before accepting
+  -h, --help                     Print this help
+
+Environment:
+  Every option can also be set as HTTP_SERVER_<OPTION>, HTTP_SERVER_MAX_BODY_SIZE
+  for --max-body-size. Options without a value are on for 1, true, yes or on, and
+  repeatable ones take a comma separated list. The config file takes precedence
+  over the environment and flags over both. HTTP_SERVER_AUTH_BASIC,
+  HTTP_SERVER_AUTH_BEARER and HTTP_SERVER_SESSION_SECRET keep credentials out
+  of `ps`.
+";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Serve,
+    CheckConfig,
+    Replay(PathBuf),
+    Help,
 }
 
 fn main() -> Result<()> {
-    let args = parse_args(env::args().collect());
+    let (command, cli) = command(env::args().collect()).unwrap_or_else(|e| usage_error(&e));
+    if command == Command::Help {
+        print!("{USAGE}");
+        return Ok(());
+    }
+
+    let vars: Vec<(String, String)> = env::vars().collect();
+    let (config, path) = load(&cli, &vars).unwrap_or_else(|e| usage_error(&e));
+    if let Err(e) = check(&config) {
+        eprintln!("error: {e}");
+        process::exit(1);
+    }
+    if command == Command::CheckConfig {
+        println!("configuration ok");
+        return Ok(());
+    }
+
+    log::init(config.log_level, config.log_format);
+
+    if config.precompress {
+        precompress::precompress_all(&config)?;
+    }
+
+    let addr = SocketAddr::new(config.address, config.port);
+
+    let access_log = config
+        .access_log
+        .as_deref()
+        .map(AccessLog::open)
+        .transpose()?;
+    let replay = match command {
+        Command::Replay(dir) => Some(dir),
+        _ => None,
+    };
+
+    let cors = (!config.cors_allow_origins.is_empty()).then(|| {
+        config
+            .cors_allow_origins
+            .iter()
+            .fold(Cors::new(), |cors, origin| cors.allow_origin(origin))
+    });
+
+    let auth = (config.auth_basic.is_some() || config.auth_bearer.is_some()).then(|| {
+        let mut auth = Auth::new("files")
+            .protect(
+                &[
+                    HttpMethod::POST,
+                    HttpMethod::PUT,
+                    HttpMethod::PATCH,
+                    HttpMethod::DELETE,
+                ],
+                "/files/",
+            )
+            .protect(&[HttpMethod::POST], "/admin/");
+        if let Some((username, password)) = &config.auth_basic {
+            auth = auth.basic(username, password);
+        }
+        if let Some(token) = &config.auth_bearer {
+            auth = auth.bearer(token);
+        }
+        auth
+    });
+
+    let security_headers = config.security_headers.enabled.then(|| {
+        let options = &config.security_headers;
+        SecurityHeaders::new()
+            .frame_options(&options.frame_options)
+            .referrer_policy(&options.referrer_policy)
+            .hsts(options.hsts_max_age)
+            .content_security_policy(&options.content_security_policy)
+    });
+
+    let ip_filter = (!config.allow_ips.is_empty() || !config.deny_ips.is_empty()).then(|| {
+        IpFilter::new()
+            .allow(&config.allow_ips)
+            .deny(&config.deny_ips)
+    });
+
+    // with a config file the limit can be switched on by a reload later
+    let rate_limit = (config.rate_limit.is_some() || path.is_some()).then(|| {
+        let rate_limit = Arc::new(RateLimit::disabled());
+        rate_limit.set_limit(rate_limit_of(&config));
+        rate_limit
+    });
+
+    let proxy = config.proxy.clone().map(|upstream| {
+        let proto = if config.tls_cert.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        Proxy::new(upstream)
+            .forwarded_proto(proto)
+            .timeout(config.read_timeout)
+    });
 
-    let server = Server::new("127.0.0.1:4221".to_string(), args);
+    let trace = config
+        .enable_trace
+        .then(|| Trace::new().forwarding(proxy.is_some()));
+
+    let timeout =
+        (config.request_timeout.is_some() || !config.route_timeouts.is_empty()).then(|| {
+            let timeout = config
+                .route_timeouts
+                .iter()
+                .fold(Timeout::new(), |timeout, (pattern, limit)| {
+                    timeout.route(pattern, *limit)
+                });
+            match config.request_timeout {
+                Some(limit) => timeout.limit(limit),
+                None => timeout,
+            }
+        });
+
+    let rewrite = (!config.rewrites.is_empty()).then(|| {
+        config
+            .rewrites
+            .iter()
+            .fold(Rewrite::new(), |rewrite, rule| match rule.status {
+                Some(status) => {
+                    rewrite.redirect(&rule.from, &rule.to, StatusCode::from_code(status))
+                }
+                None => rewrite.rewrite(&rule.from, &rule.to),
+            })
+    });
+
+    let cache_control = (!config.cache_control.is_empty()).then(|| {
+        config
+            .cache_control
+            .iter()
+            .fold(CacheControl::new(), |cache_control, rule| {
+                match (&rule.path, &rule.extension) {
+                    (Some(path), _) => cache_control.path(path, &rule.value),
+                    (None, Some(extension)) => cache_control.extension(extension, &rule.value),
+                    (None, None) => cache_control,
+                }
+            })
+    });
+
+    let enable_metrics = config.enable_metrics;
+    let read_only = config.read_only;
+    let cache = config
+        .cache_size
+        .map(|size| Cache::new(size).ttl(config.cache_ttl));
+    let compression = config.compression.then(|| {
+        Compression::new()
+            .min_size(config.compression_min_size)
+            .skip_types(&config.compression_skip_types)
+    });
+    let mut listen = config.listen.clone().into_iter();
+    let first = listen.next().unwrap_or(ListenAddr::Tcp(addr.to_string()));
+    let mut server = listen.fold(Server::new(first, config), Server::also_listen);
+
+    let reload = path.map(|_| {
+        let shared = server.config();
+        let rate_limit = rate_limit.clone();
+        Arc::new(move || reload(&cli, &vars, &shared, rate_limit.as_deref()))
+    });
+    // ahead of any route logic, `/metrics` included
+    if let Some(ip_filter) = ip_filter {
+        server = server.with(ip_filter);
+    }
+    if let Some(rate_limit) = rate_limit {
+        server = server.with(Arc::clone(&rate_limit));
+    }
+    // on everything the middleware after it answers with as well
+    if let Some(security_headers) = security_headers {
+        server = server.with(security_headers);
+    }
+    if enable_metrics {
+        let metrics = Metrics::new(server.queue_depth())
+            .connections(server.connections())
+            .acceptors(server.accept_counters());
+        server = server.with(metrics);
+    }
+    if let Some(access_log) = access_log {
+        server = server.with(access_log);
+    }
+    if read_only {
+        server = server.with(ReadOnly::new());
+    }
+    // ahead of auth, which then guards the rewritten path
+    if let Some(rewrite) = rewrite {
+        server = server.with(rewrite);
+    }
+    if let Some(cors) = cors {
+        server = server.with(cors);
+    }
+    // without credentials to check, only this machine may reload
+    let guarded = auth.is_some();
+    if let Some(auth) = auth {
+        server = server.with(auth);
+    }
+    if let Some(reload) = &reload {
+        let reload = Arc::clone(reload);
+        server = server.with(Reload::new(move || reload()).local_only(!guarded));
+    }
+    if let Some(trace) = trace {
+        server = server.with(trace);
+    }
+    if let Some(timeout) = timeout {
+        server = server.with(timeout);
+    }
+    if let Some(proxy) = proxy {
+        server = server.with(proxy);
+    }
+    if let Some(cache_control) = cache_control {
+        server = server.with(cache_control);
+    }
+    if let Some(cache) = cache {
+        server = server.with(cache);
+    }
+    if let Some(compression) = compression {
+        server = server.with(compression);
+    }
+    if let Some(dir) = replay {
+        return server.replay(&dir, &mut io::stdout().lock());
+    }
+    shutdown::shutdown_on_signals(server.shutdown_handle())?;
+    if let Some(reload) = reload {
+        shutdown::reload_on_sighup(move || reload())?;
+    }
     server.listen()
 }
 
-fn parse_args(args: Vec<String>) -> Args {
-    let mut args_iter = args.iter().peekable();
+fn rate_limit_of(config: &Config) -> Option<(f64, u32)> {
+    config
+        .rate_limit
+        .map(|rate| (f64::from(rate), config.rate_limit_burst.unwrap_or(rate)))
+}
 
-    let mut maybe_directory: Option<PathBuf> = None;
+// loads the config again the way it was at startup, settings that belong
+// to the listener only change on restart
+fn reload(
+    cli: &[String],
+    vars: &[(String, String)],
+    shared: &SharedConfig,
+    rate_limit: Option<&RateLimit>,
+) -> Result<()> {
+    let (config, _) =
+        load(cli, vars).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
 
-    while let Some(arg) = args_iter.next() {
-        if arg.starts_with("--directory") {
-            if let Some(next_arg) = args_iter.peek() {
-                maybe_directory = Some(PathBuf::from(next_arg));
+    log::set_level(config.log_level);
+    if let Some(rate_limit) = rate_limit {
+        rate_limit.set_limit(rate_limit_of(&config));
+    }
+    shared.store(config);
+    Ok(())
+}
+
+// the command and the arguments left for `parse_args`, without a command
+// the server is started as it always was
+fn command(mut args: Vec<String>) -> std::result::Result<(Command, Vec<String>), String> {
+    if args
+        .iter()
+        .skip(1)
+        .any(|arg| arg == "--help" || arg == "-h")
+    {
+        return Ok((Command::Help, args));
+    }
+
+    let command = match args.get(1).map(String::as_str) {
+        Some("serve") => Command::Serve,
+        Some("check-config") => Command::CheckConfig,
+        Some("help") => Command::Help,
+        Some("replay") if args.get(2).is_some_and(|dir| !dir.starts_with('-')) => {
+            Command::Replay(PathBuf::from(args.remove(2)))
+        }
+        Some("replay") => return Err("replay needs the directory to replay".to_string()),
+        Some(other) if !other.starts_with('-') => {
+            return Err(format!("unknown command `{other}`"));
+        }
+        _ => return Ok((Command::Serve, args)),
+    };
+    args.remove(1);
+    Ok((command, args))
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("error: {message}\n\nFor the commands and options, see --help");
+    process::exit(2)
+}
+
+// what cannot be caught while parsing, a typo in a path turning into a
+// server that answers 404 to everything
+fn check(config: &Config) -> std::result::Result<(), String> {
+    if let Some(directory) = &config.directory {
+        if !directory.is_dir() {
+            return Err(format!(
+                "--directory {} is not a directory",
+                directory.display()
+            ));
+        }
+    }
+    if let Some(error_pages) = &config.error_pages {
+        if !error_pages.is_dir() {
+            return Err(format!(
+                "--error-pages {} is not a directory",
+                error_pages.display()
+            ));
+        }
+    }
+    for file in [&config.tls_cert, &config.tls_key].into_iter().flatten() {
+        if !file.is_file() {
+            return Err(format!("{} is not a file", file.display()));
+        }
+    }
+    if config.tls_cert.is_some() != config.tls_key.is_some() {
+        return Err("--tls-cert and --tls-key go together".to_string());
+    }
+    Ok(())
+}
+
+// the closest option to a mistyped one, if any is close enough
+fn suggest(flag: &str) -> Option<&'static str> {
+    // edit distance between the two
+    let distance = |a: &str, b: &str| {
+        let b: Vec<char> = b.chars().collect();
+        let mut previous: Vec<usize> = (0..=b.len()).collect();
+        for (i, a) in a.chars().enumerate() {
+            let mut current = vec![i + 1];
+            for (j, b) in b.iter().enumerate() {
+                let substitution = previous[j] + usize::from(a != *b);
+                current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
             }
-            break;
+            previous = current;
         }
+        previous[b.len()]
+    };
+
+    USAGE
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|option| option.trim_end_matches(','))
+        .filter(|option| option.starts_with("--"))
+        .map(|option| (distance(flag, option), option))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, option)| option)
+}
+
+// the environment first, then the config file and the command line last,
+// each taking precedence over what came before
+fn load(
+    cli: &[String],
+    vars: &[(String, String)],
+) -> std::result::Result<(Config, Option<PathBuf>), String> {
+    let program = cli.first().cloned().unwrap_or_default();
+    let env = env_args(program, vars)?;
+    let mut config = parse_args(env.clone(), Config::default())?;
+
+    let path = config_path(cli).or_else(|| config_path(&env));
+    if let Some(path) = &path {
+        config = config
+            .with_file(path)
+            .map_err(|e| format!("failed to load {}, {}", path.display(), e))?;
     }
+    Ok((parse_args(cli.to_vec(), config)?, path))
+}
+
+// HTTP_SERVER_<OPTION> variables as the flags they stand for
+fn env_args(
+    program: String,
+    vars: &[(String, String)],
+) -> std::result::Result<Vec<String>, String> {
+    let mut args = vec![program];
+    for (name, value) in vars {
+        let Some(option) = name.strip_prefix("HTTP_SERVER_") else {
+            continue;
+        };
+        let flag = format!("--{}", option.to_lowercase().replace('_', "-"));
+        let Some(usage) = USAGE
+            .lines()
+            .map(str::trim_start)
+            .find(|line| line.split_whitespace().next() == Some(flag.as_str()))
+        else {
+            return Err(format!("unknown environment variable `{name}`"));
+        };
+
+        let takes_value = usage
+            .split_whitespace()
+            .nth(1)
+            .is_some_and(|word| word.starts_with('<'));
+        if !takes_value {
+            match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => args.push(flag),
+                "" | "0" | "false" | "no" | "off" => {}
+                _ => return Err(format!("`{name}` is to be true or false, not `{value}`")),
+            }
+        } else if usage.ends_with("repeatable") {
+            for value in value.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+                args.extend([flag.clone(), value.to_owned()]);
+            }
+        } else if !value.is_empty() {
+            args.extend([flag, value.to_owned()]);
+        }
+    }
+    Ok(args)
+}
+
+fn config_path(args: &[String]) -> Option<PathBuf> {
+    args.iter()
+        .position(|arg| arg == "--config")
+        .and_then(|index| args.get(index + 1))
+        .map(PathBuf::from)
+}
+
+// the value following `arg`, which a flag at the end of the line lacks
+fn value<'a>(
+    arg: &str,
+    args_iter: &mut impl Iterator<Item = &'a String>,
+) -> std::result::Result<&'a str, String> {
+    args_iter
+        .next()
+        .map(String::as_str)
+        .ok_or_else(|| format!("{arg} needs a value"))
+}
+
+fn parse<T: FromStr>(arg: &str, value: &str, what: &str) -> std::result::Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{arg} {value} is not {what}"))
+}
+
+// counts and sizes for which 0 would leave nothing to work with
+fn positive<T: FromStr + Default + PartialEq>(
+    arg: &str,
+    value: &str,
+) -> std::result::Result<T, String> {
+    parse(arg, value, "a number")
+        .ok()
+        .filter(|n| *n != T::default())
+        .ok_or_else(|| format!("{arg} {value} is not a positive number"))
+}
+
+fn split<'a>(
+    arg: &str,
+    value: &'a str,
+    separator: char,
+    what: &str,
+) -> std::result::Result<(&'a str, &'a str), String> {
+    value
+        .split_once(separator)
+        .ok_or_else(|| format!("{arg} {value} is not {what}"))
+}
+
+fn parse_args(args: Vec<String>, mut parsed: Config) -> std::result::Result<Config, String> {
+    let mut args_iter = args.iter().skip(1);
 
-    Args {
-        directory: maybe_directory,
+    while let Some(arg) = args_iter.next() {
+        let arg = arg.as_str();
+        match arg {
+            "--config" => {
+                value(arg, &mut args_iter)?;
+            }
+            "--address" => {
+                let address = value(arg, &mut args_iter)?;
+                parsed.address = address
+                    .trim_matches(['[', ']'])
+                    .parse()
+                    .map_err(|_| format!("{arg} {address} is not an IP address"))?;
+            }
+            "--port" => parsed.port = parse(arg, value(arg, &mut args_iter)?, "a port")?,
+            "--listen" => {
+                let addr = value(arg, &mut args_iter)?;
+                parsed.listen.push(ListenAddr::from(addr));
+            }
+            "--directory" => {
+                parsed.directory = Some(PathBuf::from(value(arg, &mut args_iter)?));
+            }
+            "--vhost" => {
+                let (host, directory) = split(arg, value(arg, &mut args_iter)?, '=', "HOST=DIR")?;
+                parsed
+                    .vhosts
+                    .push((normalize_host(host), PathBuf::from(directory)));
+            }
+            "--enable-dir-listing" => parsed.enable_dir_listing = true,
+            "--enable-metrics" => parsed.enable_metrics = true,
+            "--enable-trace" => parsed.enable_trace = true,
+            "--spa" => parsed.spa = true,
+            "--read-only" => parsed.read_only = true,
+            "--precompress" => parsed.precompress = true,
+            "--problem-json" => parsed.problem_json = true,
+            "--mime-type" => {
+                let (extension, mime_type) =
+                    split(arg, value(arg, &mut args_iter)?, '=', "EXT=TYPE")?;
+                parsed.mime_types.insert(extension, mime_type);
+            }
+            "--upload-extension" => {
+                let extension = value(arg, &mut args_iter)?;
+                parsed.uploads.extensions.push(extension.to_owned());
+            }
+            "--upload-content-type" => {
+                let content_type = value(arg, &mut args_iter)?;
+                parsed.uploads.content_types.push(content_type.to_owned());
+            }
+            "--max-files-per-dir" => {
+                parsed.uploads.max_files_per_dir =
+                    Some(positive(arg, value(arg, &mut args_iter)?)?);
+            }
+            "--no-tcp-nodelay" => parsed.tcp.nodelay = false,
+            "--server-header" => parsed.server_header = value(arg, &mut args_iter)?.to_owned(),
+            "--no-security-headers" => parsed.security_headers.enabled = false,
+            "--frame-options" => {
+                parsed.security_headers.frame_options = value(arg, &mut args_iter)?.to_owned();
+            }
+            "--referrer-policy" => {
+                parsed.security_headers.referrer_policy = value(arg, &mut args_iter)?.to_owned();
+            }
+            "--hsts-max-age" => {
+                let secs = parse(arg, value(arg, &mut args_iter)?, "a number of seconds")?;
+                parsed.security_headers.hsts_max_age =
+                    (secs > 0).then(|| Duration::from_secs(secs));
+            }
+            "--content-security-policy" => {
+                parsed.security_headers.content_security_policy =
+                    value(arg, &mut args_iter)?.to_owned();
+            }
+            "--tcp-keepalive" => {
+                let secs = positive(arg, value(arg, &mut args_iter)?)?;
+                parsed.tcp.keepalive = Some(Duration::from_secs(secs));
+            }
+            "--listen-backlog" => {
+                parsed.tcp.backlog = Some(positive(arg, value(arg, &mut args_iter)?)?);
+            }
+            "--keep-alive-timeout" => {
+                let secs = parse(arg, value(arg, &mut args_iter)?, "a number of seconds")?;
+                parsed.keep_alive_timeout = Duration::from_secs(secs);
+            }
+            "--request-timeout-ms" => {
+                let millis = parse(arg, value(arg, &mut args_iter)?, "a number of milliseconds")?;
+                parsed.request_timeout = (millis > 0).then(|| Duration::from_millis(millis));
+            }
+            "--route-timeout-ms" => {
+                let timeout = value(arg, &mut args_iter)?;
+                let (pattern, millis) = timeout
+                    .rsplit_once('=')
+                    .and_then(|(pattern, millis)| Some((pattern, millis.parse::<u64>().ok()?)))
+                    .filter(|(_, millis)| *millis > 0)
+                    .ok_or_else(|| format!("{arg} {timeout} is not PATTERN=MS"))?;
+                parsed
+                    .route_timeouts
+                    .push((pattern.to_owned(), Duration::from_millis(millis)));
+            }
+            "--cache-size" => parsed.cache_size = Some(positive(arg, value(arg, &mut args_iter)?)?),
+            "--cache-ttl" => {
+                let secs = parse(arg, value(arg, &mut args_iter)?, "a number of seconds")?;
+                parsed.cache_ttl = Duration::from_secs(secs);
+            }
+            "--session-ttl" => {
+                let secs = parse(arg, value(arg, &mut args_iter)?, "a number of seconds")?;
+                parsed.session_ttl = Duration::from_secs(secs);
+            }
+            "--tls-cert" => parsed.tls_cert = Some(PathBuf::from(value(arg, &mut args_iter)?)),
+            "--tls-key" => parsed.tls_key = Some(PathBuf::from(value(arg, &mut args_iter)?)),
+            "--access-log" => {
+                parsed.access_log = Some(PathBuf::from(value(arg, &mut args_iter)?));
+            }
+            "--record" => parsed.record = Some(PathBuf::from(value(arg, &mut args_iter)?)),
+            "--error-pages" => {
+                parsed.error_pages = Some(PathBuf::from(value(arg, &mut args_iter)?));
+            }
+            "--max-body-size" => {
+                parsed.limits.max_body_size =
+                    parse(arg, value(arg, &mut args_iter)?, "a number of bytes")?;
+            }
+            "--max-part-size" => {
+                parsed.limits.max_part_size =
+                    parse(arg, value(arg, &mut args_iter)?, "a number of bytes")?;
+            }
+            "--compression-min-size" => {
+                parsed.compression_min_size =
+                    parse(arg, value(arg, &mut args_iter)?, "a number of bytes")?;
+            }
+            "--compression-skip-type" => {
+                let content_type = value(arg, &mut args_iter)?;
+                parsed.compression_skip_types.push(content_type.to_owned());
+            }
+            "--cors-allow-origin" => {
+                let origin = value(arg, &mut args_iter)?;
+                parsed.cors_allow_origins.push(origin.to_owned());
+            }
+            "--auth-basic" => {
+                let (username, password) =
+                    split(arg, value(arg, &mut args_iter)?, ':', "USER:PASS")?;
+                parsed.auth_basic = Some((username.to_owned(), password.to_owned()));
+            }
+            "--auth-bearer" => parsed.auth_bearer = Some(value(arg, &mut args_iter)?.to_owned()),
+            "--session-secret" => {
+                parsed.session_secret = Some(value(arg, &mut args_iter)?.to_owned());
+            }
+            "--rate-limit" => parsed.rate_limit = Some(positive(arg, value(arg, &mut args_iter)?)?),
+            "--rate-limit-burst" => {
+                parsed.rate_limit_burst = Some(positive(arg, value(arg, &mut args_iter)?)?);
+            }
+            "--allow-ip" | "--deny-ip" => {
+                let range = parse(arg, value(arg, &mut args_iter)?, "an address or CIDR range")?;
+                match arg {
+                    "--allow-ip" => parsed.allow_ips.push(range),
+                    _ => parsed.deny_ips.push(range),
+                }
+            }
+            "--trusted-proxies" => {
+                for range in value(arg, &mut args_iter)?.split(',') {
+                    let range = parse(arg, range.trim(), "an address or CIDR range")?;
+                    parsed.trusted_proxies.push(range);
+                }
+            }
+            "--log-level" => {
+                parsed.log_level = parse(arg, value(arg, &mut args_iter)?, "a log level")?;
+            }
+            "--log-format" => {
+                parsed.log_format = parse(arg, value(arg, &mut args_iter)?, "text or json")?;
+            }
+            "--proxy" => {
+                parsed.proxy = Some(parse(arg, value(arg, &mut args_iter)?, "an http:// URL")?)
+            }
+            "--workers" => parsed.workers = positive(arg, value(arg, &mut args_iter)?)?,
+            "--max-connections" => {
+                parsed.max_connections = Some(positive(arg, value(arg, &mut args_iter)?)?);
+            }
+            "--io-model" => {
+                parsed.io_model = parse(arg, value(arg, &mut args_iter)?, "threads or evented")?;
+            }
+            "--storage" => {
+                parsed.storage = parse(arg, value(arg, &mut args_iter)?, "disk or memory")?;
+            }
+            "--acceptors" => parsed.acceptors = positive(arg, value(arg, &mut args_iter)?)?,
+            "--backlog" => parsed.backlog = positive(arg, value(arg, &mut args_iter)?)?,
+            unknown => {
+                return Err(match suggest(unknown) {
+                    Some(option) => format!("unknown option `{unknown}`, did you mean `{option}`?"),
+                    None if unknown.starts_with('-') => format!("unknown option `{unknown}`"),
+                    None => format!("unexpected argument `{unknown}`"),
+                });
+            }
+        }
     }
+
+    Ok(parsed)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use codecrafters_http_server::config::{
+        IoModel, SecurityHeaderOptions, StorageKind, TcpOptions, UploadRules,
+    };
+    use codecrafters_http_server::request::RequestLimits;
+    use std::fs;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::path::Path;
 
     #[test]
     fn parse_args_should_match_requested_params() {
@@ -51,18 +764,546 @@ mod test {
                     "--directory".to_string(),
                     "/tmp/path".to_string(),
                 ],
-                Args {
+                Config {
+                    directory: Some(PathBuf::from("/tmp/path")),
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--vhost".to_string(),
+                    "Example.com:8080=/srv/example".to_string(),
+                ],
+                Config {
+                    vhosts: vec![("example.com".to_string(), PathBuf::from("/srv/example"))],
+                    ..Config::default()
+                },
+            ),
+            (
+                vec!["foo".to_string(), "--enable-metrics".to_string()],
+                Config {
+                    enable_metrics: true,
+                    ..Config::default()
+                },
+            ),
+            (
+                vec!["foo".to_string(), "--enable-trace".to_string()],
+                Config {
+                    enable_trace: true,
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--spa".to_string(),
+                    "--read-only".to_string(),
+                ],
+                Config {
+                    spa: true,
+                    read_only: true,
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--keep-alive-timeout".to_string(),
+                    "30".to_string(),
+                    "--directory".to_string(),
+                    "/tmp/path".to_string(),
+                    "--session-ttl".to_string(),
+                    "60".to_string(),
+                    "--request-timeout-ms".to_string(),
+                    "250".to_string(),
+                    "--route-timeout-ms".to_string(),
+                    "/files/*path=5000".to_string(),
+                ],
+                Config {
                     directory: Some(PathBuf::from("/tmp/path")),
+                    keep_alive_timeout: Duration::from_secs(30),
+                    session_ttl: Duration::from_secs(60),
+                    request_timeout: Some(Duration::from_millis(250)),
+                    route_timeouts: vec![("/files/*path".to_string(), Duration::from_secs(5))],
+                    ..Config::default()
                 },
             ),
             (
-                vec!["foo".to_string(), "--directory".to_string()],
-                Args { directory: None },
+                vec![
+                    "foo".to_string(),
+                    "--tls-cert".to_string(),
+                    "cert.pem".to_string(),
+                    "--tls-key".to_string(),
+                    "key.pem".to_string(),
+                ],
+                Config {
+                    tls_cert: Some(PathBuf::from("cert.pem")),
+                    tls_key: Some(PathBuf::from("key.pem")),
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--address".to_string(),
+                    "[::]".to_string(),
+                    "--port".to_string(),
+                    "8080".to_string(),
+                ],
+                Config {
+                    address: IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED),
+                    port: 8080,
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--address".to_string(),
+                    "0.0.0.0".to_string(),
+                ],
+                Config {
+                    address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--listen".to_string(),
+                    "0.0.0.0:80".to_string(),
+                    "--listen".to_string(),
+                    "unix:/run/http.sock".to_string(),
+                ],
+                Config {
+                    listen: vec![
+                        ListenAddr::Tcp("0.0.0.0:80".to_string()),
+                        ListenAddr::Unix(PathBuf::from("/run/http.sock")),
+                    ],
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--workers".to_string(),
+                    "16".to_string(),
+                    "--acceptors".to_string(),
+                    "4".to_string(),
+                ],
+                Config {
+                    workers: 16,
+                    acceptors: 4,
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--io-model".to_string(),
+                    "Evented".to_string(),
+                ],
+                Config {
+                    io_model: IoModel::Evented,
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--upload-extension".to_string(),
+                    "png".to_string(),
+                    "--upload-content-type".to_string(),
+                    "image/*".to_string(),
+                    "--max-files-per-dir".to_string(),
+                    "10".to_string(),
+                ],
+                Config {
+                    uploads: UploadRules {
+                        extensions: vec!["png".to_string()],
+                        content_types: vec!["image/*".to_string()],
+                        max_files_per_dir: Some(10),
+                    },
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--no-tcp-nodelay".to_string(),
+                    "--tcp-keepalive".to_string(),
+                    "30".to_string(),
+                    "--listen-backlog".to_string(),
+                    "4096".to_string(),
+                ],
+                Config {
+                    tcp: TcpOptions {
+                        nodelay: false,
+                        keepalive: Some(Duration::from_secs(30)),
+                        backlog: Some(4096),
+                    },
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--no-security-headers".to_string(),
+                    "--server-header".to_string(),
+                    "edge".to_string(),
+                    "--frame-options".to_string(),
+                    "SAMEORIGIN".to_string(),
+                    "--hsts-max-age".to_string(),
+                    "0".to_string(),
+                    "--content-security-policy".to_string(),
+                    "default-src 'none'".to_string(),
+                ],
+                Config {
+                    server_header: "edge".to_string(),
+                    security_headers: SecurityHeaderOptions {
+                        enabled: false,
+                        frame_options: "SAMEORIGIN".to_string(),
+                        hsts_max_age: None,
+                        content_security_policy: "default-src 'none'".to_string(),
+                        ..SecurityHeaderOptions::default()
+                    },
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--record".to_string(),
+                    "/tmp/recorded".to_string(),
+                ],
+                Config {
+                    record: Some(PathBuf::from("/tmp/recorded")),
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--storage".to_string(),
+                    "memory".to_string(),
+                ],
+                Config {
+                    storage: StorageKind::Memory,
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--cors-allow-origin".to_string(),
+                    "https://a.example".to_string(),
+                    "--cors-allow-origin".to_string(),
+                    "https://b.example".to_string(),
+                ],
+                Config {
+                    cors_allow_origins: vec![
+                        "https://a.example".to_string(),
+                        "https://b.example".to_string(),
+                    ],
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--auth-basic".to_string(),
+                    "admin:s3:cret".to_string(),
+                    "--auth-bearer".to_string(),
+                    "t0ken".to_string(),
+                ],
+                Config {
+                    auth_basic: Some(("admin".to_string(), "s3:cret".to_string())),
+                    auth_bearer: Some("t0ken".to_string()),
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--rate-limit".to_string(),
+                    "10".to_string(),
+                ],
+                Config {
+                    rate_limit: Some(10),
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--allow-ip".to_string(),
+                    "10.0.0.0/8".to_string(),
+                    "--deny-ip".to_string(),
+                    "10.0.0.13".to_string(),
+                    "--allow-ip".to_string(),
+                    "::1".to_string(),
+                    "--trusted-proxies".to_string(),
+                    "127.0.0.1, fd00::/8".to_string(),
+                ],
+                Config {
+                    allow_ips: vec!["10.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()],
+                    trusted_proxies: vec![
+                        "127.0.0.1".parse().unwrap(),
+                        "fd00::/8".parse().unwrap(),
+                    ],
+                    deny_ips: vec!["10.0.0.13".parse().unwrap()],
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--log-level".to_string(),
+                    "DEBUG".to_string(),
+                    "--log-format".to_string(),
+                    "json".to_string(),
+                ],
+                Config {
+                    log_level: log::Level::Debug,
+                    log_format: log::Format::Json,
+                    ..Config::default()
+                },
+            ),
+            (
+                vec![
+                    "foo".to_string(),
+                    "--max-body-size".to_string(),
+                    "1024".to_string(),
+                ],
+                Config {
+                    limits: RequestLimits {
+                        max_body_size: 1024,
+                        ..RequestLimits::default()
+                    },
+                    ..Config::default()
+                },
             ),
         ];
 
         for (test_case, expected) in test_cases {
-            assert_eq!(parse_args(test_case), expected)
+            assert_eq!(parse_args(test_case, Config::default()), Ok(expected))
+        }
+
+        // flags override values loaded from the config file
+        let from_file = Config {
+            port: 9000,
+            directory: Some(PathBuf::from("/srv/www")),
+            ..Config::default()
+        };
+        let cli = ["foo", "--config", "server.toml", "--port", "8080"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            parse_args(cli, from_file.clone()),
+            Ok(Config {
+                port: 8080,
+                ..from_file
+            })
+        );
+    }
+
+    #[test]
+    fn command_should_pick_the_subcommand_and_reject_unknown_options() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        let test_cases = vec![
+            (
+                args(&["foo", "--port", "80"]),
+                Ok((Command::Serve, args(&["foo", "--port", "80"]))),
+            ),
+            (
+                args(&["foo", "serve", "--port", "80"]),
+                Ok((Command::Serve, args(&["foo", "--port", "80"]))),
+            ),
+            (
+                args(&["foo", "check-config", "--config", "a.toml"]),
+                Ok((Command::CheckConfig, args(&["foo", "--config", "a.toml"]))),
+            ),
+            (
+                args(&["foo", "replay", "/tmp/rec", "--directory", "/srv"]),
+                Ok((
+                    Command::Replay(PathBuf::from("/tmp/rec")),
+                    args(&["foo", "--directory", "/srv"]),
+                )),
+            ),
+            (
+                args(&["foo", "serve", "--help"]),
+                Ok((Command::Help, args(&["foo", "serve", "--help"]))),
+            ),
+            (
+                args(&["foo", "replay", "--port", "80"]),
+                Err("replay needs the directory to replay".to_string()),
+            ),
+            (
+                args(&["foo", "start"]),
+                Err("unknown command `start`".to_string()),
+            ),
+        ];
+
+        for (cli, expected) in test_cases {
+            assert_eq!(command(cli.clone()), expected, "{cli:?}");
         }
+
+        let test_cases = vec![
+            (
+                args(&["foo", "--directroy", "/srv"]),
+                "unknown option `--directroy`, did you mean `--directory`?",
+            ),
+            (
+                args(&["foo", "--frobnicate"]),
+                "unknown option `--frobnicate`",
+            ),
+            (
+                args(&["foo", "--port", "80", "90"]),
+                "unexpected argument `90`",
+            ),
+            (
+                args(&["foo", "--deny-ip", "10.0.0.0/40"]),
+                "--deny-ip 10.0.0.0/40 is not an address or CIDR range",
+            ),
+            (args(&["foo", "--directory"]), "--directory needs a value"),
+            (args(&["foo", "--port", "abc"]), "--port abc is not a port"),
+            (
+                args(&["foo", "--workers", "0"]),
+                "--workers 0 is not a positive number",
+            ),
+            (
+                args(&["foo", "--rate-limit-burst", "0"]),
+                "--rate-limit-burst 0 is not a positive number",
+            ),
+            (
+                args(&["foo", "--address", "nope"]),
+                "--address nope is not an IP address",
+            ),
+            (
+                args(&["foo", "--log-level", "bogus"]),
+                "--log-level bogus is not a log level",
+            ),
+            (
+                args(&["foo", "--io-model", "fibers"]),
+                "--io-model fibers is not threads or evented",
+            ),
+            (
+                args(&["foo", "--storage", "tape"]),
+                "--storage tape is not disk or memory",
+            ),
+            (
+                args(&["foo", "--max-body-size", "x"]),
+                "--max-body-size x is not a number of bytes",
+            ),
+            (
+                args(&["foo", "--proxy", "https://example.com"]),
+                "--proxy https://example.com is not an http:// URL",
+            ),
+            (
+                args(&["foo", "--vhost", "example.com"]),
+                "--vhost example.com is not HOST=DIR",
+            ),
+        ];
+
+        for (cli, expected) in test_cases {
+            assert_eq!(
+                parse_args(cli.clone(), Config::default()),
+                Err(expected.to_string()),
+                "{cli:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn load_should_take_flags_over_the_file_over_the_environment() {
+        let file = env::temp_dir().join(format!("http-server-load-{}.toml", process::id()));
+        fs::write(&file, "port = 2000\n").unwrap();
+        let file = file.to_string_lossy().into_owned();
+
+        let strings = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let vars = |vars: &[(&str, &str)]| {
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let test_cases = vec![
+            (
+                strings(&["foo"]),
+                vars(&[
+                    ("HTTP_SERVER_PORT", "1000"),
+                    ("HTTP_SERVER_ENABLE_DIR_LISTING", "true"),
+                    ("HTTP_SERVER_MIME_TYPE", "md=text/markdown, xyz=text/x-xyz"),
+                    ("PATH", "/bin"),
+                ]),
+                Ok((1000, true, "text/x-xyz")),
+            ),
+            (
+                strings(&["foo"]),
+                vars(&[
+                    ("HTTP_SERVER_PORT", "1000"),
+                    ("HTTP_SERVER_ENABLE_DIR_LISTING", "0"),
+                    ("HTTP_SERVER_CONFIG", &file),
+                ]),
+                Ok((2000, false, "application/octet-stream")),
+            ),
+            (
+                strings(&["foo", "--config", &file]),
+                vars(&[
+                    ("HTTP_SERVER_PORT", "1000"),
+                    ("HTTP_SERVER_ENABLE_DIR_LISTING", "yes"),
+                ]),
+                Ok((2000, true, "application/octet-stream")),
+            ),
+            (
+                strings(&["foo", "--port", "3000"]),
+                vars(&[("HTTP_SERVER_PORT", "1000"), ("HTTP_SERVER_CONFIG", &file)]),
+                Ok((3000, false, "application/octet-stream")),
+            ),
+            (
+                strings(&["foo"]),
+                vars(&[("HTTP_SERVER_PROT", "1000")]),
+                Err("unknown environment variable `HTTP_SERVER_PROT`".to_string()),
+            ),
+            (
+                strings(&["foo"]),
+                vars(&[("HTTP_SERVER_SPA", "maybe")]),
+                Err("`HTTP_SERVER_SPA` is to be true or false, not `maybe`".to_string()),
+            ),
+        ];
+
+        for (cli, vars, expected) in test_cases {
+            let loaded = load(&cli, &vars).map(|(config, _)| {
+                (
+                    config.port,
+                    config.enable_dir_listing,
+                    config.mime_types.lookup(Path::new("a.xyz")).to_owned(),
+                )
+            });
+            let expected =
+                expected.map(|(port, listing, mime_type)| (port, listing, mime_type.to_owned()));
+            assert_eq!(loaded, expected, "{cli:?} {vars:?}");
+        }
+
+        // credentials go the same way as everything else
+        fs::write(&file, "session_secret = \"from-file\"\n").unwrap();
+        let (config, _) = load(
+            &strings(&["foo", "--config", &file, "--auth-bearer", "from-cli"]),
+            &vars(&[
+                ("HTTP_SERVER_AUTH_BASIC", "admin:from-env"),
+                ("HTTP_SERVER_AUTH_BEARER", "from-env"),
+                ("HTTP_SERVER_SESSION_SECRET", "from-env"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            config.auth_basic,
+            Some(("admin".to_string(), "from-env".to_string()))
+        );
+        assert_eq!(config.auth_bearer.as_deref(), Some("from-cli"));
+        assert_eq!(config.session_secret.as_deref(), Some("from-file"));
+
+        fs::remove_file(file).unwrap();
     }
 }
@@ -0,0 +1,137 @@
+use crate::config::SharedConfig;
+use crate::error_page::{self, ErrorHandler};
+use crate::errors::{Error, Result};
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, DEFAULT_SERVER_HEADER};
+use crate::router::Router;
+use std::sync::Arc;
+
+pub mod access_log;
+pub mod auth;
+pub mod cache;
+pub mod cache_control;
+pub mod compression;
+pub mod cors;
+pub mod ip_filter;
+pub mod metrics;
+pub mod proxy;
+pub mod rate_limit;
+pub mod read_only;
+pub mod reload;
+pub mod rewrite;
+pub mod security_headers;
+pub mod timeout;
+pub mod trace;
+
+pub use access_log::AccessLog;
+pub use auth::{Auth, Identity};
+pub use cache::Cache;
+pub use cache_control::CacheControl;
+pub use compression::Compression;
+pub use cors::Cors;
+pub use ip_filter::IpFilter;
+pub use metrics::Metrics;
+pub use proxy::Proxy;
+pub use rate_limit::RateLimit;
+pub use read_only::ReadOnly;
+pub use reload::Reload;
+pub use rewrite::Rewrite;
+pub use security_headers::SecurityHeaders;
+pub use timeout::Timeout;
+pub use trace::Trace;
+
+pub trait Middleware: Send + Sync {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse>;
+}
+
+// lets the caller keep a handle to a middleware it wants to reconfigure
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        (**self).handle(req, next)
+    }
+}
+
+pub struct Next<'a> {
+    middleware: &'a [Box<dyn Middleware>],
+    router: &'a Router,
+}
+
+impl<'a> Next<'a> {
+    pub fn matched_route(&self, req: &HttpRequest) -> Option<&'a str> {
+        self.router.matched_route(req)
+    }
+
+    pub fn run(self, req: &mut HttpRequest) -> Result<HttpResponse> {
+        match self.middleware.split_first() {
+            Some((current, rest)) => current.handle(
+                req,
+                Next {
+                    middleware: rest,
+                    router: self.router,
+                },
+            ),
+            None => Ok(self.router.handle(req)),
+        }
+    }
+}
+
+pub struct Pipeline {
+    middleware: Vec<Box<dyn Middleware>>,
+    router: Router,
+    error_handler: Option<Box<ErrorHandler>>,
+    // where the Server header comes from, read per response so a reload
+    // takes effect
+    config: Option<SharedConfig>,
+}
+
+impl Pipeline {
+    pub fn new(middleware: Vec<Box<dyn Middleware>>, router: Router) -> Self {
+        Pipeline {
+            middleware,
+            router,
+            error_handler: None,
+            config: None,
+        }
+    }
+
+    pub fn on_error(mut self, handler: Box<ErrorHandler>) -> Self {
+        self.error_handler = Some(handler);
+        self
+    }
+
+    pub fn config(mut self, config: SharedConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    // every response leaves through here, which also names the server on
+    // it unless a handler did
+    pub fn render_error(
+        &self,
+        response: HttpResponse,
+        req: Option<&HttpRequest>,
+        error: Option<&Error>,
+    ) -> HttpResponse {
+        let response = match &self.error_handler {
+            Some(handler) => error_page::render(handler, response, req, error),
+            None => response,
+        };
+
+        let server = match &self.config {
+            Some(config) => config.load().server_header.clone(),
+            None => DEFAULT_SERVER_HEADER.to_string(),
+        };
+        if server.is_empty() || response.get_header("server").is_some() {
+            return response;
+        }
+        response.header("Server", server)
+    }
+
+    pub fn handle(&self, req: &mut HttpRequest) -> Result<HttpResponse> {
+        Next {
+            middleware: &self.middleware,
+            router: &self.router,
+        }
+        .run(req)
+    }
+}
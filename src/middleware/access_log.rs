@@ -0,0 +1,70 @@
+use super::{Middleware, Next};
+use crate::date;
+use crate::errors::Result;
+use crate::log;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime};
+
+pub struct AccessLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        AccessLog {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        if path == Path::new("-") {
+            return Ok(Self::new(io::stdout()));
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(file))
+    }
+}
+
+impl Middleware for AccessLog {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        let received_at = SystemTime::now();
+        let started = Instant::now();
+
+        let remote_addr = req.client_ip().map_or("-".to_string(), |ip| ip.to_string());
+        let request_line = format!("{} {} HTTP/1.1", req.method().as_str(), req.target());
+
+        let response = next.run(req)?;
+
+        let bytes_sent = response
+            .content_length()
+            .map_or("-".to_string(), |len| len.to_string());
+
+        let line = format!(
+            "{} - - [{}] \"{}\" {} {} {} {}\n",
+            remote_addr,
+            date::format_clf(received_at),
+            request_line,
+            response.status().code(),
+            bytes_sent,
+            started.elapsed().as_micros(),
+            req.id().unwrap_or("-")
+        );
+
+        if let Ok(mut writer) = self.writer.lock() {
+            if let Err(e) = writer
+                .write_all(line.as_bytes())
+                .and_then(|_| writer.flush())
+            {
+                log::error!("Failed to write access log, error {}", e);
+            }
+        }
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,209 @@
+use super::{Middleware, Next};
+use crate::errors::Result;
+use crate::request::{HttpMethod, HttpRequest};
+use crate::response::{HttpResponse, StatusCode};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+// who a request authenticated as, stored in its context for the handlers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identity {
+    Basic(String),
+    Bearer,
+}
+
+struct Rule {
+    methods: Vec<HttpMethod>,
+    path_prefix: String,
+}
+
+pub struct Auth {
+    realm: String,
+    basic: Option<(String, String)>,
+    bearer: Option<String>,
+    rules: Vec<Rule>,
+}
+
+impl Auth {
+    pub fn new(realm: impl Into<String>) -> Self {
+        Auth {
+            realm: realm.into(),
+            basic: None,
+            bearer: None,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn basic(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.basic = Some((username.into(), password.into()));
+        self
+    }
+
+    pub fn bearer(mut self, token: impl Into<String>) -> Self {
+        self.bearer = Some(token.into());
+        self
+    }
+
+    pub fn protect(mut self, methods: &[HttpMethod], path_prefix: impl Into<String>) -> Self {
+        self.rules.push(Rule {
+            methods: methods.to_vec(),
+            path_prefix: path_prefix.into(),
+        });
+        self
+    }
+
+    fn is_protected(&self, req: &HttpRequest) -> bool {
+        self.rules.iter().any(|rule| {
+            rule.methods.contains(req.method()) && req.path().starts_with(&rule.path_prefix)
+        })
+    }
+
+    fn authenticate(&self, authorization: &str) -> Option<Identity> {
+        let (scheme, credentials) = authorization.trim().split_once(' ')?;
+        let credentials = credentials.trim();
+
+        if scheme.eq_ignore_ascii_case("basic") {
+            let (username, password) = self.basic.as_ref()?;
+
+            let authorized = STANDARD
+                .decode(credentials)
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .and_then(|decoded| {
+                    decoded
+                        .split_once(':')
+                        .map(|(u, p)| (u.to_owned(), p.to_owned()))
+                })
+                .is_some_and(|(u, p)| {
+                    // evaluate both so timing does not reveal which one was wrong
+                    constant_time_eq(u.as_bytes(), username.as_bytes())
+                        & constant_time_eq(p.as_bytes(), password.as_bytes())
+                });
+            authorized.then(|| Identity::Basic(username.clone()))
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            self.bearer
+                .as_ref()
+                .is_some_and(|token| constant_time_eq(credentials.as_bytes(), token.as_bytes()))
+                .then_some(Identity::Bearer)
+        } else {
+            None
+        }
+    }
+
+    fn challenge(&self) -> String {
+        let mut challenges = Vec::new();
+        if self.basic.is_some() {
+            challenges.push(format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm));
+        }
+        if self.bearer.is_some() {
+            challenges.push(format!("Bearer realm=\"{}\"", self.realm));
+        }
+        challenges.join(", ")
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl Middleware for Auth {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        let identity = req
+            .header("authorization")
+            .and_then(|authorization| self.authenticate(authorization));
+
+        if let Some(identity) = identity {
+            req.context_mut().extensions_mut().insert(identity);
+            return next.run(req);
+        }
+        if !self.is_protected(req) {
+            return next.run(req);
+        }
+
+        Ok(
+            HttpResponse::new(StatusCode::Unauthorized)
+                .header("WWW-Authenticate", self.challenge()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middleware::Pipeline;
+    use crate::request::RequestLimits;
+    use crate::response::Body;
+    use crate::router::Router;
+
+    #[test]
+    fn auth_should_guard_protected_routes() {
+        let mut router = Router::new();
+        router
+            .get("/files/*path", |_, _| HttpResponse::ok())
+            .post("/files/*path", |req, _| {
+                let identity = match req.context().extensions().get::<Identity>() {
+                    Some(Identity::Basic(username)) => username.as_str(),
+                    Some(Identity::Bearer) => "bearer",
+                    None => "none",
+                };
+                HttpResponse::created().body(identity.to_string())
+            });
+
+        let auth = Auth::new("files")
+            .basic("admin", "secret")
+            .bearer("t0ken")
+            .protect(&[HttpMethod::POST], "/files/");
+        let pipeline = Pipeline::new(vec![Box::new(auth)], router);
+
+        let test_cases = vec![
+            ("GET /files/a HTTP/1.1\r\n\r\n", 200, ""),
+            ("POST /files/a HTTP/1.1\r\n\r\n", 401, ""),
+            // admin:secret
+            (
+                "POST /files/a HTTP/1.1\r\nAuthorization: Basic YWRtaW46c2VjcmV0\r\n\r\n",
+                201,
+                "admin",
+            ),
+            // admin:wrong
+            (
+                "POST /files/a HTTP/1.1\r\nAuthorization: Basic YWRtaW46d3Jvbmc=\r\n\r\n",
+                401,
+                "",
+            ),
+            (
+                "POST /files/a HTTP/1.1\r\nAuthorization: bearer t0ken\r\n\r\n",
+                201,
+                "bearer",
+            ),
+            (
+                "POST /files/a HTTP/1.1\r\nAuthorization: Bearer nope\r\n\r\n",
+                401,
+                "",
+            ),
+        ];
+
+        for (raw, status, identity) in test_cases {
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            let mut response = pipeline.handle(&mut req).unwrap();
+
+            assert_eq!(response.status().code(), status, "{raw:?}");
+            if status == 201 {
+                let Body::Bytes(bytes) = response.take_body() else {
+                    panic!("{raw:?}");
+                };
+                assert_eq!(bytes, identity.as_bytes(), "{raw:?}");
+            }
+            if status == 401 {
+                assert_eq!(
+                    response.get_header("www-authenticate"),
+                    Some("Basic realm=\"files\", charset=\"UTF-8\", Bearer realm=\"files\"")
+                );
+            }
+        }
+    }
+}
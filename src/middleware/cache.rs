@@ -0,0 +1,305 @@
+use super::{Middleware, Next};
+use crate::encoding::{self, ContentCoding};
+use crate::errors::Result;
+use crate::headers::HeaderMap;
+use crate::request::{HttpMethod, HttpRequest};
+use crate::response::{Body, HttpResponse, StatusCode};
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+const CACHED_PREFIX: &str = "/files/";
+
+// a representation is cached per virtual host, path and content coding
+type Key = (Option<String>, String, &'static str);
+
+struct Entry {
+    headers: HeaderMap,
+    body: Bytes,
+    stored: Instant,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<Key, Entry>,
+    // least recently used first
+    order: BTreeMap<u64, Key>,
+    size: usize,
+    tick: u64,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.map.remove(key) {
+            self.order.remove(&entry.tick);
+            self.size -= entry.body.len();
+        }
+    }
+
+    fn touch(&mut self, key: &Key) {
+        self.tick += 1;
+        if let Some(entry) = self.map.get_mut(key) {
+            self.order.remove(&entry.tick);
+            entry.tick = self.tick;
+            self.order.insert(self.tick, key.clone());
+        }
+    }
+}
+
+// keeps small GET /files responses in memory after compression, so repeated
+// requests skip both the disk read and the encoding
+pub struct Cache {
+    max_size: usize,
+    max_entry_size: usize,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+impl Cache {
+    pub fn new(max_size: usize) -> Self {
+        Cache {
+            max_size,
+            max_entry_size: 256 * 1024,
+            ttl: Duration::from_secs(60),
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn max_entry_size(mut self, max_entry_size: usize) -> Self {
+        self.max_entry_size = max_entry_size;
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn get(&self, key: &Key) -> Option<HttpResponse> {
+        let mut entries = self.lock();
+
+        let entry = entries.map.get(key)?;
+        if entry.stored.elapsed() >= self.ttl {
+            entries.remove(key);
+            return None;
+        }
+
+        let mut response = HttpResponse::ok();
+        for (name, value) in entry.headers.iter() {
+            response = response.add_header(name, value);
+        }
+        let response = response.body(entry.body.clone());
+
+        entries.touch(key);
+        Some(response)
+    }
+
+    fn insert(&self, key: Key, headers: HeaderMap, body: Bytes) {
+        if body.len() > self.max_entry_size || body.len() > self.max_size {
+            return;
+        }
+
+        let mut entries = self.lock();
+        entries.remove(&key);
+
+        while entries.size + body.len() > self.max_size {
+            let Some((_, oldest)) = entries.order.pop_first() else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+
+        entries.tick += 1;
+        let tick = entries.tick;
+        entries.size += body.len();
+        entries.order.insert(tick, key.clone());
+        entries.map.insert(
+            key,
+            Entry {
+                headers,
+                body,
+                stored: Instant::now(),
+                tick,
+            },
+        );
+    }
+
+    // drops every coding of the path, whichever host it was served for,
+    // along with the listings of the directories above it and, for a
+    // directory, everything a multipart upload may have written below it
+    fn invalidate(&self, path: &str) {
+        let mut entries = self.lock();
+        let stale: Vec<Key> = entries
+            .map
+            .keys()
+            .filter(|(_, cached, _)| {
+                cached == path
+                    || (cached.ends_with('/') && path.starts_with(cached.as_str()))
+                    || (path.ends_with('/') && cached.starts_with(path))
+            })
+            .cloned()
+            .collect();
+        for key in stale {
+            entries.remove(&key);
+        }
+    }
+}
+
+fn varies_beyond_coding(headers: &HeaderMap) -> bool {
+    headers
+        .get_all("vary")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|name| !name.is_empty() && !name.eq_ignore_ascii_case("accept-encoding"))
+}
+
+impl Middleware for Cache {
+    // the path is the one requests are normalized to before the pipeline
+    // runs, which is also what the files handler resolves in storage
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        if !req.path().starts_with(CACHED_PREFIX) {
+            return next.run(req);
+        }
+
+        if *req.method() != HttpMethod::GET {
+            let response = next.run(req)?;
+            if matches!(
+                req.method(),
+                HttpMethod::POST | HttpMethod::PUT | HttpMethod::PATCH | HttpMethod::DELETE
+            ) && response.status().code() < 400
+            {
+                self.invalidate(req.path());
+            }
+            return Ok(response);
+        }
+
+        // partial and conditional requests are answered from disk
+        let Some(coding) = encoding::negotiate(
+            req.headers().get_joined("accept-encoding").as_deref(),
+            &ContentCoding::SUPPORTED,
+        )
+        .filter(|_| {
+            ![
+                "range",
+                "if-range",
+                "if-match",
+                "if-none-match",
+                "if-modified-since",
+                "if-unmodified-since",
+            ]
+            .iter()
+            .any(|name| req.headers().contains(name))
+        }) else {
+            return next.run(req);
+        };
+
+        let key = (req.host(), req.path().to_owned(), coding.as_str());
+        if let Some(response) = self.get(&key) {
+            return Ok(response);
+        }
+
+        // the key only tells codings apart, a response chosen by anything
+        // else would reach clients that asked for something different
+        let mut response = next.run(req)?;
+        if response.status() != StatusCode::Ok || varies_beyond_coding(response.headers()) {
+            return Ok(response);
+        }
+
+        let body = match response.take_body() {
+            Body::Bytes(bytes) => bytes,
+            Body::File { mut file, length } if length <= self.max_entry_size as u64 => {
+                let mut buffer = Vec::with_capacity(length as usize);
+                file.read_to_end(&mut buffer)?;
+                Bytes::from(buffer)
+            }
+            Body::File { file, length } => return Ok(response.file_body(file, length)),
+            Body::Stream {
+                mut reader,
+                length: Some(length),
+            } if length <= self.max_entry_size as u64 => {
+                let mut buffer = Vec::with_capacity(length as usize);
+                reader.read_to_end(&mut buffer)?;
+                Bytes::from(buffer)
+            }
+            Body::Stream { reader, length } => {
+                let response = match length {
+                    Some(length) => response.sized_body(reader, length),
+                    None => response.chunked_body(reader),
+                };
+                return Ok(response);
+            }
+        };
+
+        self.insert(key, response.headers().clone(), body.clone());
+        Ok(response.body(body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_should_evict_least_recently_used_entries() {
+        let cache = Cache::new(10);
+        let key = |path: &str| (None, path.to_string(), "identity");
+
+        cache.insert(key("/a"), HeaderMap::new(), Bytes::from("aaaa"));
+        cache.insert(key("/b"), HeaderMap::new(), Bytes::from("bbbb"));
+        assert!(cache.get(&key("/a")).is_some());
+        cache.insert(key("/c"), HeaderMap::new(), Bytes::from("cccc"));
+        cache.insert(key("/big"), HeaderMap::new(), Bytes::from("x".repeat(11)));
+
+        let test_cases = vec![("/a", true), ("/b", false), ("/c", true), ("/big", false)];
+        for (path, cached) in test_cases {
+            assert_eq!(cache.get(&key(path)).is_some(), cached, "{path}");
+        }
+
+        cache.invalidate("/a");
+        assert!(cache.get(&key("/a")).is_none());
+        assert_eq!(cache.lock().size, 4);
+    }
+
+    #[test]
+    fn invalidate_should_drop_the_path_its_listings_and_what_is_below_it() {
+        let key = |path: &str| (None, path.to_string(), "identity");
+        let cached = [
+            "/files/",
+            "/files/d/",
+            "/files/d/a",
+            "/files/d/e/b",
+            "/files/x",
+        ];
+
+        let test_cases = vec![
+            ("/files/d/a", vec!["/files/d/e/b", "/files/x"]),
+            ("/files/d/", vec!["/files/x"]),
+            ("/files/x", vec!["/files/d/", "/files/d/a", "/files/d/e/b"]),
+            (
+                "/files/y",
+                vec!["/files/d/", "/files/d/a", "/files/d/e/b", "/files/x"],
+            ),
+        ];
+
+        for (path, kept) in test_cases {
+            let cache = Cache::new(1024);
+            for cached in cached {
+                cache.insert(key(cached), HeaderMap::new(), Bytes::from("x"));
+            }
+            cache.invalidate(path);
+
+            let remaining: Vec<&str> = cached
+                .into_iter()
+                .filter(|cached| cache.get(&key(cached)).is_some())
+                .collect();
+            assert_eq!(remaining, kept, "{path}");
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use super::{Middleware, Next};
+use crate::date;
+use crate::errors::Result;
+use crate::request::{HttpMethod, HttpRequest};
+use crate::response::HttpResponse;
+use crate::router::Pattern;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+enum Matcher {
+    Path(Pattern),
+    Extension(String),
+}
+
+impl Matcher {
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            Matcher::Path(pattern) => pattern.match_path(path).is_some(),
+            Matcher::Extension(extension) => Path::new(path)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(extension)),
+        }
+    }
+}
+
+// sets Cache-Control on successful GET and HEAD responses by the first rule
+// matching the path, along with an Expires for HTTP/1.0 caches when the
+// policy has a max-age
+#[derive(Default)]
+pub struct CacheControl {
+    rules: Vec<(Matcher, String)>,
+}
+
+impl CacheControl {
+    pub fn new() -> Self {
+        CacheControl::default()
+    }
+
+    // `pattern` as understood by the router
+    pub fn path(mut self, pattern: &str, value: impl Into<String>) -> Self {
+        self.rules
+            .push((Matcher::Path(Pattern::parse(pattern)), value.into()));
+        self
+    }
+
+    pub fn extension(mut self, extension: &str, value: impl Into<String>) -> Self {
+        let extension = extension.trim_start_matches('.').to_owned();
+        self.rules
+            .push((Matcher::Extension(extension), value.into()));
+        self
+    }
+}
+
+fn max_age(value: &str) -> Option<u64> {
+    value.split(',').find_map(|directive| {
+        let (name, seconds) = directive.trim().split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("max-age")
+            .then(|| seconds.trim().trim_matches('"').parse().ok())?
+    })
+}
+
+impl Middleware for CacheControl {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        let cacheable = matches!(req.method(), HttpMethod::GET | HttpMethod::HEAD);
+        let policy = self
+            .rules
+            .iter()
+            .find(|(matcher, _)| matcher.matches(req.path()))
+            .map(|(_, value)| value);
+
+        let response = next.run(req)?;
+
+        let Some(value) = policy.filter(|_| cacheable) else {
+            return Ok(response);
+        };
+        // a handler that decided on its own policy keeps it
+        if response.get_header("cache-control").is_some()
+            || !matches!(response.status().code(), 200 | 203 | 206 | 304)
+        {
+            return Ok(response);
+        }
+
+        let response = response.header("Cache-Control", value.as_str());
+        Ok(match max_age(value) {
+            Some(seconds) => response.header(
+                "Expires",
+                date::format_http(SystemTime::now() + Duration::from_secs(seconds)),
+            ),
+            None => response,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middleware::Pipeline;
+    use crate::request::RequestLimits;
+    use crate::router::Router;
+
+    #[test]
+    fn cache_control_should_apply_the_first_matching_rule() {
+        let mut router = Router::new();
+        router
+            .get("/files/*path", |req, _| match req.path() {
+                "/files/missing.js" => HttpResponse::not_found(),
+                _ => HttpResponse::ok(),
+            })
+            .get("/events", |_, _| {
+                HttpResponse::ok().header("Cache-Control", "no-store")
+            })
+            .post("/files/*path", |_, _| HttpResponse::created());
+
+        let cache_control = CacheControl::new()
+            .path("/files/assets/*rest", "public, max-age=31536000, immutable")
+            .extension("html", "no-cache")
+            .extension(".js", "max-age=60")
+            .path("/events", "no-cache");
+        let pipeline = Pipeline::new(vec![Box::new(cache_control)], router);
+
+        let test_cases = vec![
+            (
+                "GET /files/assets/app.1a2b.js",
+                Some("public, max-age=31536000, immutable"),
+                true,
+            ),
+            ("GET /files/index.HTML", Some("no-cache"), false),
+            ("HEAD /files/app.js", Some("max-age=60"), true),
+            ("GET /files/missing.js", None, false),
+            ("GET /files/a.txt", None, false),
+            ("GET /events", Some("no-store"), false),
+            ("POST /files/app.js", None, false),
+        ];
+
+        for (line, cache_control, expires) in test_cases {
+            let raw = format!("{line} HTTP/1.1\r\n\r\n");
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            let response = pipeline.handle(&mut req).unwrap();
+
+            assert_eq!(
+                response.get_header("cache-control"),
+                cache_control,
+                "{line}"
+            );
+            assert_eq!(response.get_header("expires").is_some(), expires, "{line}");
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use super::{Middleware, Next};
+use crate::encoding::{self, ContentCoding};
+use crate::errors::Result;
+use crate::request::HttpRequest;
+use crate::response::{Body, HttpResponse, StatusCode};
+use std::io::Read;
+
+pub const DEFAULT_MIN_SIZE: usize = 256;
+
+// streamed bodies above this, or of unknown length, are encoded while they
+// are sent instead of being buffered first
+const STREAMING_THRESHOLD: u64 = 1024 * 1024;
+
+// formats that are compressed already and would only grow, an entry ending
+// in `/` covers the whole top level type
+pub const DEFAULT_SKIP_TYPES: [&str; 13] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "video/",
+    "audio/",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/x-7z-compressed",
+    "application/zstd",
+];
+
+pub struct Compression {
+    min_size: usize,
+    skip_types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: DEFAULT_MIN_SIZE,
+            skip_types: DEFAULT_SKIP_TYPES.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Compression::default()
+    }
+
+    // bodies smaller than this are sent as they are
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    pub fn skip_types(mut self, skip_types: &[String]) -> Self {
+        self.skip_types = skip_types.iter().map(|t| t.to_lowercase()).collect();
+        self
+    }
+
+    fn skips(&self, content_type: &str) -> bool {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+
+        // event streams never end, so they cannot be buffered for encoding
+        essence == "text/event-stream"
+            || self.skip_types.iter().any(|skipped| {
+                if skipped.ends_with('/') {
+                    essence.starts_with(skipped.as_str())
+                } else {
+                    essence == *skipped
+                }
+            })
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        let Some(coding) = encoding::negotiate(
+            req.headers().get_joined("accept-encoding").as_deref(),
+            &ContentCoding::SUPPORTED,
+        ) else {
+            return Ok(HttpResponse::new(StatusCode::NotAcceptable));
+        };
+
+        let mut response = next.run(req)?;
+
+        // a part of the representation cannot be encoded on its own
+        if coding == ContentCoding::Identity
+            || response.status() == StatusCode::PartialContent
+            || response.get_header("content-encoding").is_some()
+            || response
+                .get_header("content-type")
+                .is_some_and(|content_type| self.skips(content_type))
+            || response
+                .content_length()
+                .is_some_and(|length| length < self.min_size as u64)
+        {
+            return Ok(response);
+        }
+
+        let content = match response.take_body() {
+            Body::Bytes(bytes) => bytes.to_vec(),
+            Body::Stream { reader, length }
+                if length.map_or(true, |length| length > STREAMING_THRESHOLD) =>
+            {
+                return Ok(encoded(response, coding).chunked_body(coding.encode_reader(reader)));
+            }
+            Body::Stream { mut reader, length } => {
+                let mut buffer = Vec::with_capacity(length.unwrap_or(0) as usize);
+                reader.read_to_end(&mut buffer)?;
+                buffer
+            }
+            Body::File { file, length } if length > STREAMING_THRESHOLD => {
+                return Ok(
+                    encoded(response, coding).chunked_body(coding.encode_reader(Box::new(file)))
+                );
+            }
+            Body::File { mut file, length } => {
+                let mut buffer = Vec::with_capacity(length as usize);
+                file.read_to_end(&mut buffer)?;
+                buffer
+            }
+        };
+
+        if content.is_empty() {
+            return Ok(response);
+        }
+
+        let body = coding.encode(&content)?;
+        Ok(encoded(response, coding).body(body))
+    }
+}
+
+fn encoded(mut response: HttpResponse, coding: ContentCoding) -> HttpResponse {
+    // the encoded bytes differ from the identity representation, so the
+    // validator can at most be weak
+    if let Some(etag) = response
+        .get_header("etag")
+        .filter(|etag| !etag.starts_with("W/"))
+    {
+        let weak_etag = format!("W/{}", etag);
+        response = response.header("ETag", weak_etag);
+    }
+
+    // a digest of the content before encoding no longer matches it
+    response
+        .remove_header("Content-Digest")
+        .header("Content-Encoding", coding.as_str())
+        .append_header("Vary", "Accept-Encoding")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn skips_should_match_exact_types_and_top_level_prefixes() {
+        let compression = Compression::new();
+
+        let test_cases = vec![
+            ("image/png", true),
+            ("IMAGE/JPEG; q=1", true),
+            ("image/svg+xml", false),
+            ("video/mp4", true),
+            ("text/event-stream", true),
+            ("text/html; charset=utf-8", false),
+            ("application/zip", true),
+            ("application/json", false),
+        ];
+
+        for (content_type, expected) in test_cases {
+            assert_eq!(compression.skips(content_type), expected, "{content_type}");
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use super::{Middleware, Next};
+use crate::errors::Result;
+use crate::request::{HttpMethod, HttpRequest};
+use crate::response::{HttpResponse, StatusCode};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AllowedOrigins {
+    Any,
+    List(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: AllowedOrigins,
+    methods: Vec<HttpMethod>,
+    headers: Vec<String>,
+    max_age: Option<Duration>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors {
+            origins: AllowedOrigins::List(Vec::new()),
+            methods: vec![HttpMethod::GET, HttpMethod::HEAD, HttpMethod::POST],
+            headers: Vec::new(),
+            max_age: None,
+        }
+    }
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Cors::default()
+    }
+
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Self {
+        let origin = origin.into();
+
+        if origin == "*" {
+            self.origins = AllowedOrigins::Any;
+        } else if let AllowedOrigins::List(origins) = &mut self.origins {
+            origins.push(origin);
+        }
+        self
+    }
+
+    pub fn allow_methods(mut self, methods: &[HttpMethod]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = headers.iter().map(|h| h.to_lowercase()).collect();
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn allowed_origin(&self, origin: &str) -> Option<String> {
+        match &self.origins {
+            AllowedOrigins::Any => Some("*".to_string()),
+            AllowedOrigins::List(origins) => origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+                .then(|| origin.to_string()),
+        }
+    }
+
+    fn preflight(&self, req: &HttpRequest, allow_origin: String) -> Option<HttpResponse> {
+        let method = req
+            .header("access-control-request-method")
+            .and_then(|method| HttpMethod::from_str(method).ok())?;
+
+        if !self.methods.contains(&method) {
+            return None;
+        }
+
+        let requested_headers: Vec<String> = req
+            .header("access-control-request-headers")
+            .map(|headers| {
+                headers
+                    .split(',')
+                    .map(|h| h.trim().to_lowercase())
+                    .filter(|h| !h.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if !requested_headers.iter().all(|h| self.headers.contains(h)) {
+            return None;
+        }
+
+        let methods = self
+            .methods
+            .iter()
+            .map(|method| method.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut response = HttpResponse::new(StatusCode::NoContent)
+            .header("Access-Control-Allow-Origin", allow_origin)
+            .header("Access-Control-Allow-Methods", methods)
+            .append_header("Vary", "Origin");
+
+        if !requested_headers.is_empty() {
+            response =
+                response.header("Access-Control-Allow-Headers", requested_headers.join(", "));
+        }
+
+        if let Some(max_age) = self.max_age {
+            response = response.header("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+
+        Some(response)
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        let Some(allow_origin) = req
+            .header("origin")
+            .and_then(|origin| self.allowed_origin(origin))
+        else {
+            return next.run(req);
+        };
+
+        if *req.method() == HttpMethod::OPTIONS
+            && req.header("access-control-request-method").is_some()
+        {
+            if let Some(response) = self.preflight(req, allow_origin) {
+                return Ok(response);
+            }
+            return next.run(req);
+        }
+
+        Ok(next
+            .run(req)?
+            .header("Access-Control-Allow-Origin", allow_origin)
+            .append_header("Vary", "Origin"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middleware::Pipeline;
+    use crate::request::RequestLimits;
+    use crate::router::Router;
+
+    #[test]
+    fn cors_should_answer_preflight_and_tag_responses() {
+        let mut router = Router::new();
+        router.get("/echo/:msg", |_, _| HttpResponse::ok());
+
+        let cors = Cors::new()
+            .allow_origin("https://a.example")
+            .allow_methods(&[HttpMethod::GET, HttpMethod::PUT])
+            .allow_headers(&["X-Token"])
+            .max_age(Duration::from_secs(600));
+        let pipeline = Pipeline::new(vec![Box::new(cors)], router);
+
+        let test_cases = vec![
+            (
+                "GET /echo/x HTTP/1.1\r\nOrigin: https://a.example\r\n\r\n",
+                200,
+                Some("https://a.example"),
+                None,
+            ),
+            ("GET /echo/x HTTP/1.1\r\nOrigin: https://b.example\r\n\r\n", 200, None, None),
+            (
+                "OPTIONS /echo/x HTTP/1.1\r\nOrigin: https://a.example\r\nAccess-Control-Request-Method: PUT\r\nAccess-Control-Request-Headers: x-token\r\n\r\n",
+                204,
+                Some("https://a.example"),
+                Some("600"),
+            ),
+            (
+                "OPTIONS /echo/x HTTP/1.1\r\nOrigin: https://a.example\r\nAccess-Control-Request-Method: DELETE\r\n\r\n",
+                204,
+                None,
+                None,
+            ),
+        ];
+
+        for (raw, status, allow_origin, max_age) in test_cases {
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            let response = pipeline.handle(&mut req).unwrap();
+
+            assert_eq!(response.status().code(), status, "{raw:?}");
+            assert_eq!(
+                response.get_header("access-control-allow-origin"),
+                allow_origin,
+                "{raw:?}"
+            );
+            assert_eq!(
+                response.get_header("access-control-max-age"),
+                max_age,
+                "{raw:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use super::{Middleware, Next};
+use crate::cidr::Cidr;
+use crate::errors::Result;
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, StatusCode};
+use std::net::IpAddr;
+
+// refuses clients by address, a denied range always wins and with any
+// allowed range given only those get through
+#[derive(Default)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(mut self, ranges: &[Cidr]) -> Self {
+        self.allow.extend_from_slice(ranges);
+        self
+    }
+
+    pub fn deny(mut self, ranges: &[Cidr]) -> Self {
+        self.deny.extend_from_slice(ranges);
+        self
+    }
+
+    fn permits(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|range| range.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|range| range.contains(ip)))
+    }
+}
+
+impl Middleware for IpFilter {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        // clients on a unix socket are on this machine and have no address
+        match req.client_ip() {
+            Some(ip) if !self.permits(ip) => Ok(HttpResponse::new(StatusCode::Forbidden)),
+            _ => next.run(req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn permits_should_let_deny_win_and_allow_restrict() {
+        let ranges = |ranges: &[&str]| -> Vec<Cidr> {
+            ranges.iter().map(|range| range.parse().unwrap()).collect()
+        };
+
+        let test_cases = vec![
+            (vec![], vec![], "203.0.113.9", true),
+            (vec![], vec!["203.0.113.0/24"], "203.0.113.9", false),
+            (vec![], vec!["203.0.113.0/24"], "198.51.100.1", true),
+            (vec!["10.0.0.0/8"], vec![], "10.0.0.1", true),
+            (vec!["10.0.0.0/8"], vec![], "192.168.0.1", false),
+            (vec!["10.0.0.0/8"], vec!["10.0.0.13"], "10.0.0.13", false),
+            (
+                vec!["10.0.0.0/8"],
+                vec!["10.0.0.13"],
+                "::ffff:10.0.0.14",
+                true,
+            ),
+        ];
+
+        for (allow, deny, ip, expected) in test_cases {
+            let filter = IpFilter::new().allow(&ranges(&allow)).deny(&ranges(&deny));
+            let ip: IpAddr = ip.parse().unwrap();
+            assert_eq!(filter.permits(ip), expected, "{allow:?} {deny:?} {ip}");
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use super::{Middleware, Next};
+use crate::accept::MediaType;
+use crate::errors::Result;
+use crate::request::{HttpMethod, HttpRequest};
+use crate::response::{HttpResponse, StatusCode};
+use crate::server::AcceptCounters;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const METRICS_PATH: &str = "/metrics";
+
+// upper bounds in seconds, the implicit +Inf bucket is `duration_count`
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(String, u16), u64>>,
+    in_flight: AtomicUsize,
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    duration_sum_micros: AtomicU64,
+    duration_count: AtomicU64,
+    bytes_served: AtomicU64,
+    queue_depth: Arc<AtomicUsize>,
+    connections: Arc<AtomicUsize>,
+    accepted: AcceptCounters,
+}
+
+impl Metrics {
+    pub fn new(queue_depth: Arc<AtomicUsize>) -> Self {
+        Metrics {
+            requests: Mutex::new(BTreeMap::new()),
+            in_flight: AtomicUsize::new(0),
+            duration_buckets: Default::default(),
+            duration_sum_micros: AtomicU64::new(0),
+            duration_count: AtomicU64::new(0),
+            bytes_served: AtomicU64::new(0),
+            queue_depth,
+            connections: Arc::new(AtomicUsize::new(0)),
+            accepted: AcceptCounters::default(),
+        }
+    }
+
+    pub fn connections(mut self, connections: Arc<AtomicUsize>) -> Self {
+        self.connections = connections;
+        self
+    }
+
+    pub fn acceptors(mut self, accepted: AcceptCounters) -> Self {
+        self.accepted = accepted;
+        self
+    }
+
+    fn record(&self, route: &str, status: StatusCode, started: Instant, bytes: Option<u64>) {
+        let elapsed = started.elapsed();
+
+        *self
+            .requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry((route.to_owned(), status.code()))
+            .or_insert(0) += 1;
+
+        let seconds = elapsed.as_secs_f64();
+        for (bound, bucket) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.duration_sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.duration_count.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(bytes) = bytes {
+            self.bytes_served.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Requests handled, by route and status code.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((route, status), count) in self
+            .requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+        {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                escape_label(route),
+                status,
+                count
+            );
+        }
+
+        out.push_str("# HELP http_requests_in_flight Requests currently being handled.\n");
+        out.push_str("# TYPE http_requests_in_flight gauge\n");
+        let _ = writeln!(
+            out,
+            "http_requests_in_flight {}",
+            self.in_flight.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# HELP http_request_duration_seconds Time spent handling requests.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (bound, bucket) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = self.duration_count.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "http_request_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        );
+        let _ = writeln!(
+            out,
+            "http_request_duration_seconds_sum {}",
+            self.duration_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+        let _ = writeln!(out, "http_request_duration_seconds_count {}", count);
+
+        out.push_str("# HELP http_response_bytes_total Response body bytes with a known length.\n");
+        out.push_str("# TYPE http_response_bytes_total counter\n");
+        let _ = writeln!(
+            out,
+            "http_response_bytes_total {}",
+            self.bytes_served.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# HELP thread_pool_queue_depth Requests waiting for a worker.\n");
+        out.push_str("# TYPE thread_pool_queue_depth gauge\n");
+        let _ = writeln!(
+            out,
+            "thread_pool_queue_depth {}",
+            self.queue_depth.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# HELP http_connections_open Client connections currently open.\n");
+        out.push_str("# TYPE http_connections_open gauge\n");
+        let _ = writeln!(
+            out,
+            "http_connections_open {}",
+            self.connections.load(Ordering::Relaxed)
+        );
+
+        out.push_str(
+            "# HELP http_connections_accepted_total Connections taken, by listener and accept loop.\n",
+        );
+        out.push_str("# TYPE http_connections_accepted_total counter\n");
+        for (listener, acceptor, accepted) in self.accepted.counts() {
+            let _ = writeln!(
+                out,
+                "http_connections_accepted_total{{listener=\"{}\",acceptor=\"{}\"}} {}",
+                escape_label(&listener),
+                acceptor,
+                accepted
+            );
+        }
+
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// counts a request as in flight until dropped, which a panicking handler
+// does on its way out too
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn enter(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        InFlight(count)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Middleware for Metrics {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        if req.path() == METRICS_PATH && matches!(req.method(), HttpMethod::GET | HttpMethod::HEAD)
+        {
+            if req.negotiate(&[MediaType::PLAIN]).is_none() {
+                return Ok(HttpResponse::new(StatusCode::NotAcceptable));
+            }
+            return Ok(HttpResponse::ok()
+                .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .body(self.render()));
+        }
+
+        // unmatched paths share one label so scanners cannot grow the map
+        let route = next.matched_route(req).unwrap_or("unmatched");
+        let started = Instant::now();
+
+        let in_flight = InFlight::enter(&self.in_flight);
+        let response = next.run(req);
+        drop(in_flight);
+
+        match &response {
+            Ok(response) => {
+                self.record(route, response.status(), started, response.content_length())
+            }
+            Err(_) => self.record(route, StatusCode::InternalServerError, started, None),
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::listener::Bound;
+    use crate::middleware::Pipeline;
+    use crate::request::RequestLimits;
+    use crate::response::Body;
+    use crate::router::Router;
+    use std::panic::AssertUnwindSafe;
+
+    #[test]
+    fn metrics_should_count_requests_by_route_and_status() {
+        let mut router = Router::new();
+        router
+            .get("/echo/:msg", |_, _| HttpResponse::ok().body("hi"))
+            .get("/panic", |_, _| -> HttpResponse {
+                panic!("handler failed")
+            });
+
+        let queue_depth = Arc::new(AtomicUsize::new(3));
+        let accepted = AcceptCounters::default();
+        let listener = Bound::Unix("/run/http.sock".into());
+        accepted.add(&listener, 0).fetch_add(5, Ordering::Relaxed);
+        accepted.add(&listener, 1);
+        let metrics = Metrics::new(queue_depth)
+            .connections(Arc::new(AtomicUsize::new(2)))
+            .acceptors(accepted);
+        let pipeline = Pipeline::new(vec![Box::new(metrics)], router);
+
+        for raw in [
+            "GET /echo/a HTTP/1.1\r\n\r\n",
+            "GET /echo/b HTTP/1.1\r\n\r\n",
+            "GET /nope HTTP/1.1\r\n\r\n",
+        ] {
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            pipeline.handle(&mut req).unwrap();
+        }
+        let mut req = HttpRequest::read_head(
+            &mut "GET /panic HTTP/1.1\r\n\r\n".as_bytes(),
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        let panicked = std::panic::catch_unwind(AssertUnwindSafe(|| pipeline.handle(&mut req)));
+        assert!(panicked.is_err());
+
+        let mut req = HttpRequest::read_head(
+            &mut "GET /metrics HTTP/1.1\r\n\r\n".as_bytes(),
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        let mut response = pipeline.handle(&mut req).unwrap();
+        let body = match response.take_body() {
+            Body::Bytes(body) => String::from_utf8(body.to_vec()).unwrap(),
+            _ => panic!("expected a full body"),
+        };
+
+        let test_cases = vec![
+            "http_requests_total{route=\"/echo/:msg\",status=\"200\"} 2",
+            "http_requests_total{route=\"unmatched\",status=\"404\"} 1",
+            "http_requests_in_flight 0",
+            "http_request_duration_seconds_count 3",
+            "http_response_bytes_total 4",
+            "thread_pool_queue_depth 3",
+            "http_connections_open 2",
+            "http_connections_accepted_total{listener=\"unix:/run/http.sock\",acceptor=\"0\"} 5",
+            "http_connections_accepted_total{listener=\"unix:/run/http.sock\",acceptor=\"1\"} 0",
+        ];
+
+        for expected in test_cases {
+            assert!(body.lines().any(|line| line == expected), "{expected}");
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use super::{Middleware, Next};
+use crate::client::{self, Upstream};
+use crate::errors::{Error, Result};
+use crate::log;
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, StatusCode};
+use std::io;
+use std::time::Duration;
+
+// headers that only apply to a single connection and are never forwarded
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+pub struct Proxy {
+    upstream: Upstream,
+    forwarded_proto: &'static str,
+    timeout: Duration,
+}
+
+impl Proxy {
+    pub fn new(upstream: Upstream) -> Self {
+        Proxy {
+            upstream,
+            forwarded_proto: "http",
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    pub fn forwarded_proto(mut self, proto: &'static str) -> Self {
+        self.forwarded_proto = proto;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn forward(&self, req: &HttpRequest) -> Result<HttpResponse> {
+        let connection = req.headers().get_joined("connection").unwrap_or_default();
+        let is_forwarded = |name: &str| {
+            !HOP_BY_HOP.contains(&name)
+                && !matches!(
+                    name,
+                    "host" | "content-length" | "x-forwarded-for" | "x-request-id"
+                )
+                && !connection
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case(name))
+        };
+
+        let mut headers: Vec<(String, String)> = req
+            .headers()
+            .iter()
+            .filter(|(name, _)| is_forwarded(name))
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+
+        if let Some(ip) = req.peer_addr().map(|addr| addr.ip()) {
+            let forwarded_for = match req.headers().get_joined("x-forwarded-for") {
+                Some(existing) => format!("{}, {}", existing, ip),
+                None => ip.to_string(),
+            };
+            headers.push(("X-Forwarded-For".to_string(), forwarded_for));
+        }
+        headers.push((
+            "X-Forwarded-Proto".to_string(),
+            self.forwarded_proto.to_string(),
+        ));
+        // the upstream logs the request under the same id
+        if let Some(id) = req.id() {
+            headers.push(("X-Request-Id".to_string(), id.to_owned()));
+        }
+
+        // the upstream gets no longer than the request has left
+        let timeout = match req.context().remaining() {
+            Some(remaining) if remaining.is_zero() => {
+                return Err(io::Error::from(io::ErrorKind::TimedOut).into())
+            }
+            Some(remaining) => remaining.min(self.timeout),
+            None => self.timeout,
+        };
+
+        let mut response = client::send(
+            &self.upstream,
+            *req.method(),
+            req.target(),
+            &headers,
+            req.body().unwrap_or_default(),
+            timeout,
+        )?;
+
+        for name in HOP_BY_HOP {
+            response = response.remove_header(name);
+        }
+
+        Ok(response)
+    }
+}
+
+impl Middleware for Proxy {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        if next.matched_route(req).is_some() {
+            return next.run(req);
+        }
+
+        match self.forward(req) {
+            Ok(response) => Ok(response),
+            Err(Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                log::warning!("Upstream timed out for {}", req.target());
+                Ok(HttpResponse::new(StatusCode::GatewayTimeout))
+            }
+            Err(e) => {
+                log::warning!("Failed to proxy {}, error {}", req.target(), e);
+                Ok(HttpResponse::new(StatusCode::BadGateway))
+            }
+        }
+    }
+}
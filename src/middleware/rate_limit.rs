@@ -0,0 +1,145 @@
+use super::{Middleware, Next};
+use crate::errors::Result;
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, StatusCode};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    by_ip: HashMap<IpAddr, Bucket>,
+    last_sweep: Instant,
+}
+
+#[derive(Clone, Copy)]
+struct Limit {
+    rate: f64,
+    burst: f64,
+}
+
+pub struct RateLimit {
+    limit: RwLock<Option<Limit>>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimit {
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        let rate_limit = Self::disabled();
+        rate_limit.set_limit(Some((requests_per_second, burst)));
+        rate_limit
+    }
+
+    // lets every request through until a limit is set
+    pub fn disabled() -> Self {
+        RateLimit {
+            limit: RwLock::new(None),
+            buckets: Mutex::new(Buckets {
+                by_ip: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    // takes effect for subsequent requests, clients start with a full bucket
+    pub fn set_limit(&self, limit: Option<(f64, u32)>) {
+        *self.limit.write().unwrap_or_else(|e| e.into_inner()) =
+            limit.map(|(requests_per_second, burst)| Limit {
+                rate: requests_per_second,
+                burst: f64::from(burst.max(1)),
+            });
+        self.buckets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .by_ip
+            .clear();
+    }
+
+    // returns how long the client has to wait when it is over the limit
+    fn acquire(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        let Limit { rate, burst } = (*self.limit.read().unwrap_or_else(|e| e.into_inner()))?;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        // a bucket that has been idle long enough to refill completely is
+        // indistinguishable from a new one, so it can be dropped
+        if now.duration_since(buckets.last_sweep) >= SWEEP_INTERVAL {
+            let full_after = Duration::from_secs_f64(burst / rate);
+            buckets
+                .by_ip
+                .retain(|_, bucket| now.duration_since(bucket.updated) < full_after);
+            buckets.last_sweep = now;
+        }
+
+        let bucket = buckets.by_ip.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        let Some(ip) = req.client_ip() else {
+            return next.run(req);
+        };
+
+        match self.acquire(ip, Instant::now()) {
+            None => next.run(req),
+            Some(wait) => {
+                let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+                Ok(HttpResponse::new(StatusCode::TooManyRequests)
+                    .header("Retry-After", retry_after.to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn acquire_should_refill_tokens_over_time() {
+        let limiter = RateLimit::new(2.0, 2);
+        let a = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let b = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let start = Instant::now();
+
+        let test_cases = vec![
+            (a, 0, None),
+            (a, 0, None),
+            (a, 0, Some(Duration::from_millis(500))),
+            (b, 0, None),
+            (a, 250, Some(Duration::from_millis(250))),
+            (a, 500, None),
+            (a, 500, Some(Duration::from_millis(500))),
+        ];
+
+        for (ip, offset_ms, expected) in test_cases {
+            let now = start + Duration::from_millis(offset_ms);
+            assert_eq!(limiter.acquire(ip, now), expected, "{ip} at {offset_ms}ms");
+        }
+
+        limiter.set_limit(None);
+        assert_eq!(limiter.acquire(a, start), None);
+    }
+}
@@ -0,0 +1,65 @@
+use super::{Middleware, Next};
+use crate::errors::Result;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+
+// turns away every request with a method that could change something, so
+// no route has to check for itself whether the server is read-only
+#[derive(Debug, Default)]
+pub struct ReadOnly;
+
+impl ReadOnly {
+    pub fn new() -> Self {
+        ReadOnly
+    }
+}
+
+impl Middleware for ReadOnly {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        if req.method().is_safe() {
+            next.run(req)
+        } else {
+            Ok(HttpResponse::forbidden())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middleware::Pipeline;
+    use crate::request::RequestLimits;
+    use crate::router::Router;
+
+    #[test]
+    fn read_only_should_forbid_unsafe_methods() {
+        let mut router = Router::new();
+        router
+            .get("/files/*path", |_, _| HttpResponse::ok())
+            .post("/files/*path", |_, _| HttpResponse::created())
+            .put("/files/*path", |_, _| HttpResponse::created())
+            .delete("/files/*path", |_, _| HttpResponse::ok())
+            .post("/admin/reload", |_, _| HttpResponse::ok());
+        let pipeline = Pipeline::new(vec![Box::new(ReadOnly::new())], router);
+
+        let test_cases = vec![
+            ("GET /files/a.txt", 200),
+            ("HEAD /files/a.txt", 200),
+            ("OPTIONS /files/a.txt", 204),
+            ("POST /files/a.txt", 403),
+            ("PUT /files/a.txt", 403),
+            ("DELETE /files/a.txt", 403),
+            ("PATCH /files/a.txt", 403),
+            ("POST /admin/reload", 403),
+        ];
+
+        for (line, status) in test_cases {
+            let raw = format!("{line} HTTP/1.1\r\n\r\n");
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            let response = pipeline.handle(&mut req).unwrap();
+
+            assert_eq!(response.status().code(), status, "{line}");
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use super::{Middleware, Next};
+use crate::errors::Result;
+use crate::log;
+use crate::request::{HttpMethod, HttpRequest};
+use crate::response::{HttpResponse, StatusCode};
+
+const RELOAD_PATH: &str = "/admin/reload";
+
+type ReloadFn = dyn Fn() -> Result<()> + Send + Sync;
+
+// answers POST /admin/reload by running the given reload function
+pub struct Reload {
+    reload: Box<ReloadFn>,
+    local_only: bool,
+}
+
+impl Reload {
+    pub fn new(reload: impl Fn() -> Result<()> + Send + Sync + 'static) -> Self {
+        Reload {
+            reload: Box::new(reload),
+            local_only: false,
+        }
+    }
+
+    // for when nothing else guards the endpoint, clients on this machine
+    // may reload and anyone else is refused
+    pub fn local_only(mut self, local_only: bool) -> Self {
+        self.local_only = local_only;
+        self
+    }
+}
+
+impl Middleware for Reload {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        if req.path() != RELOAD_PATH {
+            return next.run(req);
+        }
+
+        if *req.method() != HttpMethod::POST {
+            return Ok(HttpResponse::new(StatusCode::MethodNotAllowed).header("Allow", "POST"));
+        }
+
+        // a unix socket client has no address and is on this machine
+        if self.local_only && !req.client_ip().map_or(true, |ip| ip.is_loopback()) {
+            return Ok(HttpResponse::forbidden());
+        }
+
+        match (self.reload)() {
+            Ok(()) => {
+                log::info!("Configuration reloaded");
+                Ok(HttpResponse::new(StatusCode::NoContent))
+            }
+            Err(e) => {
+                log::error!("Failed to reload configuration, error {}", e);
+                Ok(HttpResponse::internal_server_error())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middleware::Pipeline;
+    use crate::request::RequestLimits;
+    use crate::router::Router;
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn reload_should_answer_only_local_clients_when_local_only() {
+        let test_cases = vec![
+            (false, Some("203.0.113.9"), StatusCode::NoContent),
+            (true, Some("203.0.113.9"), StatusCode::Forbidden),
+            (true, Some("127.0.0.1"), StatusCode::NoContent),
+            (true, Some("::1"), StatusCode::NoContent),
+            (true, None, StatusCode::NoContent),
+        ];
+
+        for (local_only, client_ip, expected) in test_cases {
+            let reloads = Arc::new(AtomicUsize::new(0));
+            let counter = Arc::clone(&reloads);
+            let reload = Reload::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .local_only(local_only);
+            let pipeline = Pipeline::new(vec![Box::new(reload)], Router::new());
+
+            let raw = "POST /admin/reload HTTP/1.1\r\n\r\n";
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            req.context_mut()
+                .set_client_ip(client_ip.map(|ip| ip.parse::<IpAddr>().unwrap()));
+
+            let response = pipeline.handle(&mut req).unwrap();
+            assert_eq!(response.status(), expected, "{local_only} {client_ip:?}");
+            assert_eq!(
+                reloads.load(Ordering::SeqCst),
+                usize::from(expected == StatusCode::NoContent),
+                "{local_only} {client_ip:?}"
+            );
+        }
+    }
+}
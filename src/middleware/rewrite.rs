@@ -0,0 +1,156 @@
+use super::{Middleware, Next};
+use crate::errors::Result;
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, StatusCode};
+use crate::router::{PathParams, Pattern};
+
+enum Action {
+    Rewrite,
+    Redirect(StatusCode),
+}
+
+struct Rule {
+    pattern: Pattern,
+    to: String,
+    action: Action,
+}
+
+// rules matched against the path in order, the first one that matches
+// either changes the target before routing or redirects the client
+#[derive(Default)]
+pub struct Rewrite {
+    rules: Vec<Rule>,
+}
+
+impl Rewrite {
+    pub fn new() -> Self {
+        Rewrite::default()
+    }
+
+    // `from` is a router pattern, `to` may refer to its parameters and the
+    // request's scheme and host as `{name}`, `{scheme}` and `{host}`
+    pub fn rewrite(mut self, from: &str, to: impl Into<String>) -> Self {
+        self.rules.push(Rule {
+            pattern: Pattern::parse(from),
+            to: to.into(),
+            action: Action::Rewrite,
+        });
+        self
+    }
+
+    // `status` is one of 301, 302, 307 or 308
+    pub fn redirect(mut self, from: &str, to: impl Into<String>, status: StatusCode) -> Self {
+        self.rules.push(Rule {
+            pattern: Pattern::parse(from),
+            to: to.into(),
+            action: Action::Redirect(status),
+        });
+        self
+    }
+}
+
+fn expand(template: &str, params: &PathParams, req: &HttpRequest) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        expanded.push_str(&rest[..start]);
+        match &rest[start + 1..end] {
+            "scheme" => expanded.push_str(req.scheme()),
+            "host" => expanded.push_str(&req.host().unwrap_or_default()),
+            name => expanded.push_str(params.get(name).unwrap_or_default()),
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    // the query carries over unless the rule sets its own
+    match req.target().split_once('?') {
+        Some((_, query)) if !expanded.contains('?') => format!("{}?{}", expanded, query),
+        _ => expanded,
+    }
+}
+
+impl Middleware for Rewrite {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        let matched = self.rules.iter().find_map(|rule| {
+            let params = rule.pattern.match_path(req.path())?;
+            let target = expand(&rule.to, &params, req);
+            // a redirect to where the client already is would loop
+            match rule.action {
+                Action::Redirect(_) if target == req.target() => None,
+                _ => Some((rule, target)),
+            }
+        });
+
+        match matched {
+            Some((rule, target)) => match rule.action {
+                Action::Rewrite => {
+                    req.set_target(target);
+                    next.run(req)
+                }
+                Action::Redirect(status) => {
+                    Ok(HttpResponse::new(status).header("Location", target))
+                }
+            },
+            None => next.run(req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middleware::Pipeline;
+    use crate::request::RequestLimits;
+    use crate::response::Body;
+    use crate::router::Router;
+
+    #[test]
+    fn rewrite_should_change_the_target_or_redirect() {
+        let mut router = Router::new();
+        router.get("/echo/:msg", |req, _| {
+            HttpResponse::ok().body(req.target().to_string())
+        });
+
+        let rewrite = Rewrite::new()
+            .redirect("/docs", "/docs/", StatusCode::PermanentRedirect)
+            .redirect("/old/*rest", "/new/{rest}", StatusCode::MovedPermanently)
+            .redirect("/secure/*rest", "https://{host}/{rest}", StatusCode::Found)
+            .redirect("/canonical", "{scheme}://{host}/", StatusCode::Found)
+            .rewrite("/say/:word", "/echo/{word}")
+            .redirect("/echo/:msg", "/echo/{msg}", StatusCode::Found);
+        let pipeline = Pipeline::new(vec![Box::new(rewrite)], router);
+
+        let test_cases = vec![
+            ("/docs", 308, "/docs/"),
+            ("/docs/", 404, ""),
+            ("/old/a/b?c=d", 301, "/new/a/b?c=d"),
+            ("/secure/x", 302, "https://example.com/x"),
+            ("/canonical", 302, "http://example.com/"),
+            ("/say/hi?x", 200, "/echo/hi?x"),
+            ("/echo/hi", 200, "/echo/hi"),
+        ];
+
+        for (target, status, expected) in test_cases {
+            let raw = format!("GET {target} HTTP/1.1\r\nHost: Example.com:8080\r\n\r\n");
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            let mut response = pipeline.handle(&mut req).unwrap();
+
+            assert_eq!(response.status().code(), status, "{target}");
+            match status {
+                200 => {
+                    let Body::Bytes(bytes) = response.take_body() else {
+                        panic!("{target}");
+                    };
+                    assert_eq!(bytes, expected.as_bytes(), "{target}");
+                }
+                404 => (),
+                _ => assert_eq!(response.get_header("location"), Some(expected), "{target}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use super::{Middleware, Next};
+use crate::errors::Result;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use std::time::Duration;
+
+pub const DEFAULT_FRAME_OPTIONS: &str = "DENY";
+pub const DEFAULT_REFERRER_POLICY: &str = "strict-origin-when-cross-origin";
+pub const DEFAULT_HSTS_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+// headers asking browsers to be careful with what the server sends, an
+// empty value leaves a header out and one a handler set is kept
+pub struct SecurityHeaders {
+    content_type_options: String,
+    frame_options: String,
+    referrer_policy: String,
+    hsts_max_age: Option<Duration>,
+    content_security_policy: String,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        SecurityHeaders {
+            content_type_options: "nosniff".to_string(),
+            frame_options: DEFAULT_FRAME_OPTIONS.to_string(),
+            referrer_policy: DEFAULT_REFERRER_POLICY.to_string(),
+            hsts_max_age: Some(DEFAULT_HSTS_MAX_AGE),
+            content_security_policy: String::new(),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    pub fn new() -> Self {
+        SecurityHeaders::default()
+    }
+
+    pub fn content_type_options(mut self, value: impl Into<String>) -> Self {
+        self.content_type_options = value.into();
+        self
+    }
+
+    pub fn frame_options(mut self, value: impl Into<String>) -> Self {
+        self.frame_options = value.into();
+        self
+    }
+
+    pub fn referrer_policy(mut self, value: impl Into<String>) -> Self {
+        self.referrer_policy = value.into();
+        self
+    }
+
+    // only sent over https, browsers ignore it on plain connections
+    pub fn hsts(mut self, max_age: Option<Duration>) -> Self {
+        self.hsts_max_age = max_age;
+        self
+    }
+
+    pub fn content_security_policy(mut self, value: impl Into<String>) -> Self {
+        self.content_security_policy = value.into();
+        self
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        let hsts = self
+            .hsts_max_age
+            .filter(|_| req.scheme() == "https")
+            .map(|max_age| format!("max-age={}", max_age.as_secs()))
+            .unwrap_or_default();
+
+        let mut response = next.run(req)?;
+        for (name, value) in [
+            ("X-Content-Type-Options", self.content_type_options.as_str()),
+            ("X-Frame-Options", &self.frame_options),
+            ("Referrer-Policy", &self.referrer_policy),
+            ("Strict-Transport-Security", &hsts),
+            ("Content-Security-Policy", &self.content_security_policy),
+        ] {
+            if !value.is_empty() && response.get_header(name).is_none() {
+                response = response.header(name, value);
+            }
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middleware::Pipeline;
+    use crate::request::RequestLimits;
+    use crate::router::Router;
+
+    #[test]
+    fn security_headers_should_be_added_unless_set_or_turned_off() {
+        let router = || {
+            let mut router = Router::new();
+            router.get("/", |_, _| HttpResponse::ok());
+            router.get("/embeddable", |_, _| {
+                HttpResponse::ok().header("X-Frame-Options", "SAMEORIGIN")
+            });
+            router
+        };
+
+        let test_cases = vec![
+            (
+                SecurityHeaders::new(),
+                "/",
+                "http",
+                vec![
+                    ("X-Content-Type-Options", Some("nosniff")),
+                    ("X-Frame-Options", Some("DENY")),
+                    ("Referrer-Policy", Some("strict-origin-when-cross-origin")),
+                    ("Strict-Transport-Security", None),
+                    ("Content-Security-Policy", None),
+                ],
+            ),
+            (
+                SecurityHeaders::new(),
+                "/",
+                "https",
+                vec![("Strict-Transport-Security", Some("max-age=31536000"))],
+            ),
+            (
+                SecurityHeaders::new(),
+                "/embeddable",
+                "http",
+                vec![("X-Frame-Options", Some("SAMEORIGIN"))],
+            ),
+            (
+                SecurityHeaders::new()
+                    .frame_options("")
+                    .referrer_policy("no-referrer")
+                    .hsts(Some(Duration::from_secs(60)))
+                    .content_security_policy("default-src 'self'"),
+                "/",
+                "https",
+                vec![
+                    ("X-Frame-Options", None),
+                    ("Referrer-Policy", Some("no-referrer")),
+                    ("Strict-Transport-Security", Some("max-age=60")),
+                    ("Content-Security-Policy", Some("default-src 'self'")),
+                ],
+            ),
+            (
+                SecurityHeaders::new().hsts(None),
+                "/",
+                "https",
+                vec![("Strict-Transport-Security", None)],
+            ),
+        ];
+
+        for (security_headers, path, scheme, expected) in test_cases {
+            let pipeline = Pipeline::new(vec![Box::new(security_headers)], router());
+            let raw = format!("GET {path} HTTP/1.1\r\n\r\n");
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            req.context_mut().set_scheme(scheme);
+
+            let response = pipeline.handle(&mut req).unwrap();
+            for (name, value) in expected {
+                assert_eq!(
+                    response.get_header(name),
+                    value,
+                    "{name} for {scheme}://{path}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use super::{Middleware, Next};
+use crate::errors::Result;
+use crate::log;
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, StatusCode};
+use std::time::{Duration, Instant};
+
+// answers 504 when the handlers behind it overrun their deadline, a
+// handler cannot be interrupted but the deadline is in the request context
+// for anything that waits, the proxy bounds its upstream I/O with it
+#[derive(Debug, Default)]
+pub struct Timeout {
+    limit: Option<Duration>,
+    routes: Vec<(String, Duration)>,
+}
+
+impl Timeout {
+    pub fn new() -> Self {
+        Timeout::default()
+    }
+
+    // applies to every route without one of its own
+    pub fn limit(mut self, limit: Duration) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    // `pattern` as registered with the router
+    pub fn route(mut self, pattern: impl Into<String>, limit: Duration) -> Self {
+        self.routes.push((pattern.into(), limit));
+        self
+    }
+
+    fn deadline(&self, req: &HttpRequest, route: Option<&str>) -> Option<Instant> {
+        let now = Instant::now();
+        let current = req.context().deadline();
+
+        let overridden = route.and_then(|route| {
+            self.routes
+                .iter()
+                .find(|(pattern, _)| pattern == route)
+                .map(|(_, limit)| *limit)
+        });
+        // a route's own limit replaces the global one, which may be shorter
+        match (overridden, self.limit) {
+            (Some(limit), _) => Some(now + limit),
+            (None, Some(limit)) => Some(current.map_or(now + limit, |d| d.min(now + limit))),
+            (None, None) => current,
+        }
+    }
+}
+
+impl Middleware for Timeout {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        let Some(deadline) = self.deadline(req, next.matched_route(req)) else {
+            return next.run(req);
+        };
+        req.context_mut().set_deadline(Some(deadline));
+
+        let response = next.run(req)?;
+        if Instant::now() > deadline {
+            log::warning!("Timed out handling {}", req.target());
+            return Ok(HttpResponse::new(StatusCode::GatewayTimeout));
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middleware::Pipeline;
+    use crate::request::RequestLimits;
+    use crate::router::Router;
+    use std::thread;
+
+    #[test]
+    fn timeout_should_answer_504_once_the_deadline_passed() {
+        let test_cases = vec![
+            ("/fast", vec![], 200),
+            ("/slow", vec![], 504),
+            ("/slow", vec![("/slow", 500)], 200),
+            ("/fast", vec![("/slow", 500)], 200),
+        ];
+
+        for (path, routes, status) in test_cases {
+            let mut router = Router::new();
+            router
+                .get("/fast", |_, _| HttpResponse::ok())
+                .get("/slow", |req, _| {
+                    thread::sleep(Duration::from_millis(100));
+                    match req.context().remaining() {
+                        Some(remaining) if !remaining.is_zero() => HttpResponse::ok(),
+                        _ => HttpResponse::internal_server_error(),
+                    }
+                });
+            let timeout = routes.iter().fold(
+                Timeout::new().limit(Duration::from_millis(50)),
+                |timeout, (pattern, millis)| {
+                    timeout.route(*pattern, Duration::from_millis(*millis))
+                },
+            );
+            let pipeline = Pipeline::new(vec![Box::new(timeout)], router);
+
+            let raw = format!("GET {path} HTTP/1.1\r\n\r\n");
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            let response = pipeline.handle(&mut req).unwrap();
+
+            assert_eq!(response.status().code(), status, "{path} {routes:?}");
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use super::{Middleware, Next};
+use crate::errors::Result;
+use crate::request::{HttpMethod, HttpRequest};
+use crate::response::HttpResponse;
+
+// never reflected, a script able to send TRACE could read them otherwise
+pub(crate) const SENSITIVE: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+// answers TRACE with the request as received, see RFC 9110 section 9.3.8
+#[derive(Debug, Default)]
+pub struct Trace {
+    forwarding: bool,
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Trace::default()
+    }
+
+    // a middleware further down forwards requests, so TRACE is passed on
+    // while Max-Forwards allows it
+    pub fn forwarding(mut self, forwarding: bool) -> Self {
+        self.forwarding = forwarding;
+        self
+    }
+
+    fn reflect(req: &HttpRequest) -> HttpResponse {
+        let mut message = format!(
+            "{} {} {}\r\n",
+            req.method().as_str(),
+            req.target(),
+            req.version().as_str()
+        );
+        for (name, value) in req.headers().iter() {
+            if !SENSITIVE
+                .iter()
+                .any(|field| name.eq_ignore_ascii_case(field))
+            {
+                message.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        message.push_str("\r\n");
+
+        HttpResponse::ok()
+            .header("Content-Type", "message/http")
+            .body(message)
+    }
+}
+
+impl Middleware for Trace {
+    fn handle(&self, req: &mut HttpRequest, next: Next<'_>) -> Result<HttpResponse> {
+        if *req.method() != HttpMethod::TRACE {
+            return next.run(req);
+        }
+
+        // every hop counts down, the one reaching zero answers, which ends
+        // a forwarding loop, see RFC 9110 section 7.6.2
+        let max_forwards = req
+            .header("max-forwards")
+            .and_then(|value| value.trim().parse::<u64>().ok());
+
+        match max_forwards {
+            Some(remaining) if self.forwarding && remaining > 0 => {
+                req.headers_mut()
+                    .insert("Max-Forwards", (remaining - 1).to_string());
+                next.run(req)
+            }
+            None if self.forwarding => next.run(req),
+            _ => Ok(Self::reflect(req)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middleware::Pipeline;
+    use crate::request::RequestLimits;
+    use crate::response::Body;
+    use crate::router::Router;
+
+    #[test]
+    fn trace_should_reflect_the_request_without_credentials() {
+        let test_cases = vec![
+            (
+                false,
+                "TRACE /a?b HTTP/1.1\r\nHost: x\r\nCookie: s=1\r\nAuthorization: Basic eA==\r\n\r\n",
+                "message/http",
+                "TRACE /a?b HTTP/1.1\r\nhost: x\r\n\r\n",
+            ),
+            (
+                false,
+                "TRACE / HTTP/1.1\r\nHost: x\r\nMax-Forwards: 3\r\n\r\n",
+                "message/http",
+                "TRACE / HTTP/1.1\r\nhost: x\r\nmax-forwards: 3\r\n\r\n",
+            ),
+            (
+                true,
+                "TRACE / HTTP/1.1\r\nHost: x\r\nMax-Forwards: 3\r\n\r\n",
+                "text/plain",
+                "2",
+            ),
+            (
+                true,
+                "TRACE / HTTP/1.1\r\nHost: x\r\n\r\n",
+                "text/plain",
+                "none",
+            ),
+            (
+                true,
+                "TRACE / HTTP/1.1\r\nHost: x\r\nMax-Forwards: 0\r\n\r\n",
+                "message/http",
+                "TRACE / HTTP/1.1\r\nhost: x\r\nmax-forwards: 0\r\n\r\n",
+            ),
+        ];
+
+        for (forwarding, raw, content_type, body) in test_cases {
+            let mut router = Router::new();
+            router.route(HttpMethod::TRACE, "/*path", |req, _| {
+                HttpResponse::ok()
+                    .header("Content-Type", "text/plain")
+                    .body(req.header("max-forwards").unwrap_or("none").to_string())
+            });
+            let trace = Trace::new().forwarding(forwarding);
+            let pipeline = Pipeline::new(vec![Box::new(trace)], router);
+
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            let mut response = pipeline.handle(&mut req).unwrap();
+
+            assert_eq!(
+                response.get_header("content-type"),
+                Some(content_type),
+                "{raw:?}"
+            );
+            let Body::Bytes(bytes) = response.take_body() else {
+                panic!("{raw:?}");
+            };
+            assert_eq!(bytes, body.as_bytes(), "{raw:?}");
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+fn builtin(extension: &str) -> Option<&'static str> {
+    let mime_type = match extension {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "map" => "application/json",
+        "xml" => "application/xml",
+        "txt" | "log" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+
+    Some(mime_type)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MimeTypes {
+    overrides: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    pub fn insert(&mut self, extension: &str, mime_type: &str) {
+        self.overrides.insert(
+            extension.trim_start_matches('.').to_lowercase(),
+            mime_type.to_owned(),
+        );
+    }
+
+    pub fn lookup(&self, path: &Path) -> &str {
+        let Some(extension) = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+        else {
+            return DEFAULT_MIME_TYPE;
+        };
+
+        self.overrides
+            .get(&extension)
+            .map(|mime_type| mime_type.as_str())
+            .or_else(|| builtin(&extension))
+            .unwrap_or(DEFAULT_MIME_TYPE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup_should_prefer_overrides_then_builtins() {
+        let mut mime_types = MimeTypes::default();
+        mime_types.insert(".md", "text/x-markdown");
+        mime_types.insert("dat", "application/x-custom");
+
+        let test_cases = vec![
+            ("index.html", "text/html; charset=utf-8"),
+            ("app.min.JS", "text/javascript; charset=utf-8"),
+            ("logo.svg", "image/svg+xml"),
+            ("module.wasm", "application/wasm"),
+            ("README.md", "text/x-markdown"),
+            ("blob.dat", "application/x-custom"),
+            ("no_extension", DEFAULT_MIME_TYPE),
+            ("archive.unknown", DEFAULT_MIME_TYPE),
+        ];
+
+        for (path, expected) in test_cases {
+            assert_eq!(mime_types.lookup(Path::new(path)), expected, "{path}");
+        }
+    }
+}
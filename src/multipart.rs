@@ -0,0 +1,275 @@
+use crate::errors::{Error, Result};
+use crate::headers::HeaderMap;
+
+const MAX_BOUNDARY_LEN: usize = 70;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Part<'a> {
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub headers: HeaderMap,
+    pub data: &'a [u8],
+}
+
+impl Part<'_> {
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get("content-type")
+    }
+}
+
+// iterates the parts of a multipart/form-data body, see RFC 7578
+pub struct Parser<'a> {
+    body: &'a [u8],
+    delimiter: Vec<u8>,
+    pos: usize,
+    max_part_size: usize,
+    done: bool,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(content_type: &str, body: &'a [u8]) -> Result<Self> {
+        let mut params = content_type.split(';');
+
+        if !params
+            .next()
+            .is_some_and(|essence| essence.trim().eq_ignore_ascii_case("multipart/form-data"))
+        {
+            return Err(Error::InvalidRequest);
+        }
+
+        let boundary = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+            .map(|(_, value)| unquote(value.trim()))
+            .filter(|boundary| (1..=MAX_BOUNDARY_LEN).contains(&boundary.len()))
+            .ok_or(Error::InvalidRequest)?;
+
+        Ok(Parser {
+            body,
+            delimiter: format!("--{}", boundary).into_bytes(),
+            pos: 0,
+            max_part_size: usize::MAX,
+            done: false,
+        })
+    }
+
+    pub fn max_part_size(mut self, max_part_size: usize) -> Self {
+        self.max_part_size = max_part_size;
+        self
+    }
+
+    fn next_part(&mut self) -> Result<Option<Part<'a>>> {
+        let body = self.body;
+
+        // the first delimiter may follow a preamble, later ones are found
+        // right where the previous part ended
+        if self.pos == 0 {
+            let start = if body.starts_with(&self.delimiter) {
+                0
+            } else {
+                let mut marker = b"\r\n".to_vec();
+                marker.extend_from_slice(&self.delimiter);
+                find(body, &marker).ok_or(Error::InvalidRequest)? + 2
+            };
+            self.pos = start + self.delimiter.len();
+        }
+
+        let rest = &body[self.pos..];
+        if rest.starts_with(b"--") {
+            return Ok(None);
+        }
+
+        // linear whitespace may pad the delimiter line
+        let line_end = find(rest, b"\r\n").ok_or(Error::InvalidRequest)?;
+        if !rest[..line_end].iter().all(|b| matches!(b, b' ' | b'\t')) {
+            return Err(Error::InvalidRequest);
+        }
+        let rest = &rest[line_end + 2..];
+
+        // a part without headers starts with the blank line right away
+        let (head, rest) = match rest.strip_prefix(b"\r\n") {
+            Some(rest) => (&rest[..0], rest),
+            None => {
+                let end = find(rest, b"\r\n\r\n").ok_or(Error::InvalidRequest)?;
+                (&rest[..end], &rest[end + 4..])
+            }
+        };
+
+        let mut headers = HeaderMap::new();
+        for line in head.split(|&b| b == b'\n') {
+            let line = std::str::from_utf8(line.strip_suffix(b"\r").unwrap_or(line))
+                .map_err(|_| Error::InvalidRequest)?;
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line.split_once(':').ok_or(Error::InvalidRequest)?;
+            headers.append(name.trim(), value.trim());
+        }
+
+        let mut end_marker = b"\r\n".to_vec();
+        end_marker.extend_from_slice(&self.delimiter);
+        let data_len = find(rest, &end_marker).ok_or(Error::InvalidRequest)?;
+
+        if data_len > self.max_part_size {
+            return Err(Error::PayloadTooLarge);
+        }
+
+        let data = &rest[..data_len];
+        self.pos = body.len() - rest.len() + data_len + end_marker.len();
+
+        let disposition = headers.get("content-disposition").unwrap_or_default();
+        let (name, filename) = (
+            disposition_param(disposition, "name"),
+            disposition_param(disposition, "filename"),
+        );
+
+        Ok(Some(Part {
+            name,
+            filename,
+            headers,
+            data,
+        }))
+    }
+}
+
+impl<'a> Iterator for Parser<'a> {
+    type Item = Result<Part<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let part = self.next_part().transpose();
+        if !matches!(part, Some(Ok(_))) {
+            self.done = true;
+        }
+        part
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn unquote(value: &str) -> String {
+    match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(quoted) => {
+            let mut unquoted = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                unquoted.push(if c == '\\' {
+                    chars.next().unwrap_or(c)
+                } else {
+                    c
+                });
+            }
+            unquoted
+        }
+        None => value.to_owned(),
+    }
+}
+
+// splits on semicolons outside of quoted strings
+fn disposition_param(disposition: &str, name: &str) -> Option<String> {
+    let mut params = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+
+    for (i, c) in disposition.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(&disposition[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    params.push(&disposition[start..]);
+
+    params
+        .into_iter()
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| unquote(value.trim()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parser_should_yield_each_part() {
+        let body = concat!(
+            "preamble\r\n",
+            "--XyZ\r\n",
+            "Content-Disposition: form-data; name=\"title\"\r\n",
+            "\r\n",
+            "hello\r\n",
+            "--XyZ  \r\n",
+            "Content-Disposition: form-data; name=\"file\"; filename=\"a;b \\\"c\\\".txt\"\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "line one\r\nline two\r\n",
+            "--XyZ--\r\n",
+            "epilogue",
+        );
+
+        let parts: Vec<_> = Parser::new("multipart/form-data; boundary=\"XyZ\"", body.as_bytes())
+            .unwrap()
+            .map(|part| {
+                let part = part.unwrap();
+                (
+                    part.name.clone(),
+                    part.filename.clone(),
+                    part.content_type().map(str::to_owned),
+                    part.data.to_vec(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            parts,
+            vec![
+                (Some("title".to_string()), None, None, b"hello".to_vec()),
+                (
+                    Some("file".to_string()),
+                    Some("a;b \"c\".txt".to_string()),
+                    Some("text/plain".to_string()),
+                    b"line one\r\nline two".to_vec()
+                ),
+            ]
+        );
+
+        let test_cases = vec![
+            ("text/plain; boundary=XyZ", body, "InvalidRequest"),
+            ("multipart/form-data", body, "InvalidRequest"),
+            (
+                "multipart/form-data; boundary=XyZ",
+                "--XyZ\r\n\r\nunterminated",
+                "InvalidRequest",
+            ),
+            (
+                "multipart/form-data; boundary=XyZ",
+                "--XyZ\r\n\r\n0123456789\r\n--XyZ--",
+                "PayloadTooLarge",
+            ),
+        ];
+
+        for (content_type, body, expected) in test_cases {
+            let error = Parser::new(content_type, body.as_bytes())
+                .and_then(|parser| parser.max_part_size(8).collect::<Result<Vec<_>>>())
+                .map(|_| ())
+                .unwrap_err();
+            assert_eq!(format!("{error:?}"), expected, "{content_type} {body:?}");
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::config::Config;
+use crate::errors::Result;
+use crate::log;
+use flate2::write::GzEncoder;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub(crate) fn gzip_sidecar(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".gz");
+    PathBuf::from(sidecar)
+}
+
+// writes a gzip sidecar next to every file served by the configured
+// directories, so GET /files can skip compressing them per request
+pub fn precompress_all(conf: &Config) -> Result<()> {
+    let directories = conf
+        .directory
+        .iter()
+        .chain(conf.vhosts.iter().map(|(_, directory)| directory));
+
+    for directory in directories {
+        let written = precompress(directory)?;
+        log::info!("Precompressed {} files in {}", written, directory.display());
+    }
+
+    Ok(())
+}
+
+// returns how many sidecars were (re)written, ones that are at least as new
+// as their file are left alone
+fn precompress(dir: &Path) -> Result<usize> {
+    let mut written = 0;
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            written += precompress(&path)?;
+            continue;
+        }
+
+        if !file_type.is_file() || path.extension().is_some_and(|extension| extension == "gz") {
+            continue;
+        }
+
+        let sidecar = gzip_sidecar(&path);
+        let modified = entry.metadata()?.modified()?;
+        if fs::metadata(&sidecar)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|sidecar_modified| sidecar_modified >= modified)
+        {
+            continue;
+        }
+
+        let content = fs::read(&path)?;
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&content)?;
+        let encoded = encoder.finish()?;
+
+        // already compressed formats only grow
+        if encoded.len() >= content.len() {
+            continue;
+        }
+
+        fs::write(&sidecar, encoded)?;
+        written += 1;
+    }
+
+    Ok(written)
+}
@@ -0,0 +1,238 @@
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::VecDeque;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+// ranges beyond this many are not worth the bookkeeping, the whole
+// representation is sent instead
+const MAX_RANGES: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ranges {
+    // no usable Range header, the full representation applies
+    Ignored,
+    // first and last byte of each range, both inclusive
+    Satisfiable(Vec<(u64, u64)>),
+    Unsatisfiable,
+}
+
+// a byte range set of RFC 9110 section 14.1.2 against a representation of
+// `length` bytes, a malformed one is ignored as the RFC allows
+pub fn parse(header: &str, length: u64) -> Ranges {
+    let Some((unit, set)) = header.trim().split_once('=') else {
+        return Ranges::Ignored;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Ranges::Ignored;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in set
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        let Some((first, last)) = spec.split_once('-') else {
+            return Ranges::Ignored;
+        };
+        let number = |value: &str| match value.trim() {
+            "" => Some(None),
+            value if value.bytes().all(|b| b.is_ascii_digit()) => {
+                value.parse::<u64>().ok().map(Some)
+            }
+            _ => None,
+        };
+        let (Some(first), Some(last)) = (number(first), number(last)) else {
+            return Ranges::Ignored;
+        };
+
+        let range = match (first, last) {
+            (Some(first), _) if first >= length => None,
+            (Some(first), Some(last)) if last < first => return Ranges::Ignored,
+            (Some(first), Some(last)) => Some((first, last.min(length - 1))),
+            (Some(first), None) => Some((first, length - 1)),
+            (None, Some(0)) => None,
+            (None, Some(suffix)) if length > 0 => Some((length - suffix.min(length), length - 1)),
+            (None, Some(_)) => None,
+            (None, None) => return Ranges::Ignored,
+        };
+        ranges.extend(range);
+    }
+
+    if ranges.len() > MAX_RANGES {
+        Ranges::Ignored
+    } else if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Satisfiable(ranges)
+    }
+}
+
+// the first and last byte along with the total length of a Content-Range
+// sent with a request, RFC 9110 section 14.4, the length has to be known
+pub fn parse_content_range(header: &str) -> Option<(u64, u64, u64)> {
+    let (unit, range) = header.trim().split_once(' ')?;
+    if !unit.eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let (range, total) = range.trim().split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let number = |value: &str| {
+        Some(value)
+            .filter(|value| !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()))?
+            .parse::<u64>()
+            .ok()
+    };
+    let (first, last, total) = (number(first)?, number(last)?, number(total)?);
+
+    (first <= last && last < total).then_some((first, last, total))
+}
+
+// a boundary that will not turn up in the content by chance
+pub fn boundary() -> io::Result<String> {
+    let mut random = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut random)
+        .map_err(|_| io::Error::other("no randomness available"))?;
+    Ok(random.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+enum Segment {
+    Text(Cursor<Vec<u8>>),
+    Range { first: u64, remaining: u64 },
+}
+
+// the body of a multipart/byteranges response, the parts of `content` with
+// their headers in between, see RFC 9110 section 14.6
+pub struct MultipartRanges<R> {
+    content: R,
+    segments: VecDeque<Segment>,
+    // the content is positioned at the start of the front range
+    positioned: bool,
+}
+
+impl<R: Read + Seek> MultipartRanges<R> {
+    // returns the body along with its length
+    pub fn new(
+        content: R,
+        ranges: &[(u64, u64)],
+        length: u64,
+        content_type: &str,
+        boundary: &str,
+    ) -> (Self, u64) {
+        let mut segments = VecDeque::new();
+        let mut body_length = 0;
+        for (index, (first, last)) in ranges.iter().enumerate() {
+            let head = format!(
+                "{}--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                if index == 0 { "" } else { "\r\n" },
+                boundary,
+                content_type,
+                first,
+                last,
+                length
+            );
+            body_length += head.len() as u64 + (last - first + 1);
+            segments.push_back(Segment::Text(Cursor::new(head.into_bytes())));
+            segments.push_back(Segment::Range {
+                first: *first,
+                remaining: last - first + 1,
+            });
+        }
+        let tail = format!("\r\n--{}--\r\n", boundary);
+        body_length += tail.len() as u64;
+        segments.push_back(Segment::Text(Cursor::new(tail.into_bytes())));
+
+        let body = MultipartRanges {
+            content,
+            segments,
+            positioned: false,
+        };
+        (body, body_length)
+    }
+}
+
+impl<R: Read + Seek> Read for MultipartRanges<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(segment) = self.segments.front_mut() {
+            let read = match segment {
+                Segment::Text(text) => text.read(buf)?,
+                Segment::Range { first, remaining } => {
+                    if !self.positioned {
+                        self.content.seek(SeekFrom::Start(*first))?;
+                        self.positioned = true;
+                    }
+                    let limit = buf
+                        .len()
+                        .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                    let read = self.content.read(&mut buf[..limit])?;
+                    if read == 0 && *remaining > 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    *remaining -= read as u64;
+                    read
+                }
+            };
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            self.segments.pop_front();
+            self.positioned = false;
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_should_resolve_byte_ranges_against_the_length() {
+        let test_cases = vec![
+            ("bytes=0-499", 1000, Ranges::Satisfiable(vec![(0, 499)])),
+            ("bytes=500-", 1000, Ranges::Satisfiable(vec![(500, 999)])),
+            ("bytes=-200", 1000, Ranges::Satisfiable(vec![(800, 999)])),
+            ("bytes=-2000", 1000, Ranges::Satisfiable(vec![(0, 999)])),
+            (
+                "Bytes=990-2000",
+                1000,
+                Ranges::Satisfiable(vec![(990, 999)]),
+            ),
+            (
+                "bytes=0-0, -1",
+                1000,
+                Ranges::Satisfiable(vec![(0, 0), (999, 999)]),
+            ),
+            ("bytes=1000-", 1000, Ranges::Unsatisfiable),
+            ("bytes=-0", 1000, Ranges::Unsatisfiable),
+            ("bytes=0-", 0, Ranges::Unsatisfiable),
+            ("bytes=5-1", 1000, Ranges::Ignored),
+            ("bytes=a-b", 1000, Ranges::Ignored),
+            ("bytes=-", 1000, Ranges::Ignored),
+            ("items=0-1", 1000, Ranges::Ignored),
+            ("0-1", 1000, Ranges::Ignored),
+        ];
+
+        for (header, length, expected) in test_cases {
+            assert_eq!(parse(header, length), expected, "{header} of {length}");
+        }
+    }
+
+    #[test]
+    fn parse_content_range_should_need_a_complete_range() {
+        let test_cases = vec![
+            ("bytes 0-99/200", Some((0, 99, 200))),
+            ("Bytes 100-199/200", Some((100, 199, 200))),
+            ("bytes 100-200/200", None),
+            ("bytes 5-1/200", None),
+            ("bytes 0-99/*", None),
+            ("bytes */200", None),
+            ("bytes -1-5/200", None),
+            ("items 0-1/2", None),
+        ];
+
+        for (header, expected) in test_cases {
+            assert_eq!(parse_content_range(header), expected, "{header}");
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use crate::date::DateTime;
+use crate::log;
+use crate::middleware::trace::SENSITIVE;
+#[cfg(not(feature = "tokio"))]
+use crate::response::SendFile;
+#[cfg(not(feature = "tokio"))]
+use crate::stream::Stream;
+use std::fs::File;
+use std::io::{self, IoSlice, Read, Write};
+#[cfg(not(feature = "tokio"))]
+use std::net::SocketAddr;
+#[cfg(all(feature = "h2", not(feature = "tokio")))]
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+#[cfg(not(feature = "tokio"))]
+use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// sorts in the order the connections came in, the sequence tells apart
+// connections within the same millisecond
+fn name() -> String {
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+    let now = SystemTime::now();
+    let millis = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.subsec_millis());
+    let date = DateTime::from_system_time(now);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}.{:03}Z-{:06}",
+        date.year,
+        date.month,
+        date.day,
+        date.hour,
+        date.minute,
+        date.second,
+        millis,
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+fn is_sensitive(name: &[u8]) -> bool {
+    SENSITIVE
+        .iter()
+        .any(|field| name.eq_ignore_ascii_case(field.as_bytes()))
+}
+
+// what becomes of the rest of a line once it is known whether it is one
+// with credentials
+enum Rest {
+    Pass,
+    Drop,
+}
+
+// keeps the values of credential fields out of a byte stream, a line is
+// only held back while it may still turn out to be one of them
+#[derive(Default)]
+struct Redactor {
+    line: Vec<u8>,
+    rest: Option<Rest>,
+}
+
+impl Redactor {
+    fn feed(&mut self, bytes: &[u8], out: &mut Vec<u8>) {
+        for part in bytes.split_inclusive(|&b| b == b'\n') {
+            let end = part.ends_with(b"\n");
+            match self.rest {
+                Some(Rest::Pass) => out.extend_from_slice(part),
+                Some(Rest::Drop) if end => out.extend_from_slice(b"\r\n"),
+                Some(Rest::Drop) => (),
+                None => {
+                    self.line.extend_from_slice(part);
+                    self.decide(out, false);
+                }
+            }
+            if end {
+                self.rest = None;
+            }
+        }
+    }
+
+    fn decide(&mut self, out: &mut Vec<u8>, finished: bool) {
+        let line = &self.line;
+        let end = line.ends_with(b"\n");
+
+        match line.iter().position(|&b| b == b':') {
+            Some(colon) if is_sensitive(&line[..colon]) => {
+                out.extend_from_slice(&line[..colon]);
+                out.extend_from_slice(b": [redacted]");
+                if end {
+                    out.extend_from_slice(b"\r\n");
+                } else {
+                    self.rest = Some(Rest::Drop);
+                }
+            }
+            None if !end
+                && !finished
+                && SENSITIVE.iter().any(|field| {
+                    field.len() >= line.len()
+                        && field.as_bytes()[..line.len()].eq_ignore_ascii_case(line)
+                }) =>
+            {
+                return;
+            }
+            _ => {
+                out.extend_from_slice(line);
+                if !end {
+                    self.rest = Some(Rest::Pass);
+                }
+            }
+        }
+        self.line.clear();
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        if !self.line.is_empty() {
+            self.decide(out, true);
+        }
+    }
+}
+
+// one direction of a connection, the file is created with the first byte
+struct Tap {
+    path: PathBuf,
+    file: Option<File>,
+    failed: bool,
+    redactor: Redactor,
+}
+
+impl Tap {
+    fn new(path: PathBuf) -> Self {
+        Tap {
+            path,
+            file: None,
+            failed: false,
+            redactor: Redactor::default(),
+        }
+    }
+
+    fn write(&mut self, redacted: &[u8]) {
+        if redacted.is_empty() || self.failed {
+            return;
+        }
+
+        let result = match &mut self.file {
+            Some(file) => file.write_all(redacted),
+            None => File::create(&self.path).and_then(|mut file| {
+                file.write_all(redacted)?;
+                self.file = Some(file);
+                Ok(())
+            }),
+        };
+        if let Err(e) = result {
+            log::warning!("Failed to record {}, error {}", self.path.display(), e);
+            self.failed = true;
+        }
+    }
+
+    fn record(&mut self, bytes: &[u8]) {
+        let mut redacted = Vec::with_capacity(bytes.len());
+        self.redactor.feed(bytes, &mut redacted);
+        self.write(&redacted);
+    }
+}
+
+impl Drop for Tap {
+    fn drop(&mut self) {
+        let mut redacted = Vec::new();
+        self.redactor.finish(&mut redacted);
+        self.write(&redacted);
+    }
+}
+
+// the bytes of a connection as they were read from and written to the
+// client, to a `.request` and a `.response` file in `dir`
+pub(crate) struct Recording {
+    read: Tap,
+    written: Tap,
+}
+
+impl Recording {
+    pub(crate) fn start(dir: &Path) -> Self {
+        let name = name();
+        Recording {
+            read: Tap::new(dir.join(format!("{name}.request"))),
+            written: Tap::new(dir.join(format!("{name}.response"))),
+        }
+    }
+
+    pub(crate) fn read(&mut self, bytes: &[u8]) {
+        self.read.record(bytes);
+    }
+
+    pub(crate) fn wrote(&mut self, bytes: &[u8]) {
+        self.written.record(bytes);
+    }
+
+    // the first `n` bytes of `bufs`, which is what a vectored write took
+    fn wrote_vectored(&mut self, bufs: &[IoSlice<'_>], mut n: usize) {
+        for buf in bufs {
+            if n == 0 {
+                break;
+            }
+            let len = n.min(buf.len());
+            self.wrote(&buf[..len]);
+            n -= len;
+        }
+    }
+}
+
+// a stream whose traffic is recorded when there is a directory to record to
+pub(crate) struct Recorded<S> {
+    // ahead of the stream, so everything is on disk by the time the client
+    // sees the connection close
+    recording: Option<Recording>,
+    inner: S,
+}
+
+impl<S> Recorded<S> {
+    pub(crate) fn new(inner: S, dir: Option<&Path>) -> Self {
+        Recorded {
+            recording: dir.map(Recording::start),
+            inner,
+        }
+    }
+}
+
+impl<S: Read> Read for Recorded<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(recording) = &mut self.recording {
+            recording.read(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<S: Write> Write for Recorded<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(recording) = &mut self.recording {
+            recording.wrote(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let n = self.inner.write_vectored(bufs)?;
+        if let Some(recording) = &mut self.recording {
+            recording.wrote_vectored(bufs, n);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// a recorded file has to pass through userspace to be seen
+#[cfg(not(feature = "tokio"))]
+impl<S: SendFile> SendFile for Recorded<S> {
+    fn send_file(&mut self, file: &mut File, length: u64) -> io::Result<u64> {
+        match self.recording {
+            Some(_) => io::copy(&mut file.take(length), self),
+            None => self.inner.send_file(file, length),
+        }
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+impl<S: Stream> Stream for Recorded<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    // what goes over the connection after switching to h2c is not recorded
+    #[cfg(feature = "h2")]
+    fn cleartext(&self) -> Option<&TcpStream> {
+        self.inner.cleartext()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: AsyncRead + Unpin> AsyncRead for Recorded<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let (Poll::Ready(Ok(())), Some(recording)) = (&poll, &mut this.recording) {
+            recording.read(&buf.filled()[filled..]);
+        }
+        poll
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: AsyncWrite + Unpin> AsyncWrite for Recorded<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let (Poll::Ready(Ok(n)), Some(recording)) = (&poll, &mut this.recording) {
+            recording.wrote(&buf[..*n]);
+        }
+        poll
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        if let (Poll::Ready(Ok(n)), Some(recording)) = (&poll, &mut this.recording) {
+            recording.wrote_vectored(bufs, *n);
+        }
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redactor_should_hide_credentials_however_the_bytes_arrive() {
+        let test_cases = vec![
+            (
+                vec!["GET / HTTP/1.1\r\nAuthorization: Basic dTpw\r\nHost: a\r\n\r\n"],
+                "GET / HTTP/1.1\r\nAuthorization: [redacted]\r\nHost: a\r\n\r\n",
+            ),
+            (
+                vec!["GET / HTTP/1.1\r\nCoo", "kie: a=1\r", "\nX: y\r\n\r\n"],
+                "GET / HTTP/1.1\r\nCookie: [redacted]\r\nX: y\r\n\r\n",
+            ),
+            (
+                vec!["HTTP/1.1 200 OK\r\nset-cookie:", " s=", "1\r\n\r\nbody"],
+                "HTTP/1.1 200 OK\r\nset-cookie: [redacted]\r\n\r\nbody",
+            ),
+            (vec!["cook", "ies are fine"], "cookies are fine"),
+            (vec!["co"], "co"),
+            (vec!["\0\x01Authorization\r\n"], "\0\x01Authorization\r\n"),
+        ];
+
+        for (parts, expected) in test_cases {
+            let mut redactor = Redactor::default();
+            let mut out = Vec::new();
+            for part in &parts {
+                redactor.feed(part.as_bytes(), &mut out);
+            }
+            redactor.finish(&mut out);
+            assert_eq!(String::from_utf8_lossy(&out), expected, "{parts:?}");
+        }
+    }
+}
@@ -0,0 +1,894 @@
+use crate::accept::{self, MediaType};
+use crate::chunked;
+use crate::context::{RequestContext, RequestId};
+use crate::errors::{Error, Result};
+use crate::headers::HeaderMap;
+use std::io::{BufRead, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum HttpMethod {
+    GET,
+    DELETE,
+    POST,
+    PUT,
+    HEAD,
+    CONNECT,
+    OPTIONS,
+    TRACE,
+    PATCH,
+}
+
+impl HttpMethod {
+    pub const ALL: [HttpMethod; 9] = [
+        Self::GET,
+        Self::HEAD,
+        Self::POST,
+        Self::PUT,
+        Self::DELETE,
+        Self::PATCH,
+        Self::OPTIONS,
+        Self::CONNECT,
+        Self::TRACE,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::GET => "GET",
+            Self::DELETE => "DELETE",
+            Self::POST => "POST",
+            Self::PUT => "PUT",
+            Self::HEAD => "HEAD",
+            Self::CONNECT => "CONNECT",
+            Self::OPTIONS => "OPTIONS",
+            Self::TRACE => "TRACE",
+            Self::PATCH => "PATCH",
+        }
+    }
+
+    // methods that do not change anything on the server, RFC 9110
+    // section 9.2.1
+    pub fn is_safe(&self) -> bool {
+        matches!(self, Self::GET | Self::HEAD | Self::OPTIONS | Self::TRACE)
+    }
+}
+
+impl FromStr for HttpMethod {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "GET" => Ok(Self::GET),
+            "DELETE" => Ok(Self::DELETE),
+            "POST" => Ok(Self::POST),
+            "PUT" => Ok(Self::PUT),
+            "HEAD" => Ok(Self::HEAD),
+            "CONNECT" => Ok(Self::CONNECT),
+            "OPTIONS" => Ok(Self::OPTIONS),
+            "TRACE" => Ok(Self::TRACE),
+            "PATCH" => Ok(Self::PATCH),
+            _ => Err(Error::InvalidMethod),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+    Http2,
+}
+
+impl HttpVersion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Http10 => "HTTP/1.0",
+            Self::Http11 => "HTTP/1.1",
+            Self::Http2 => "HTTP/2",
+        }
+    }
+}
+
+impl FromStr for HttpVersion {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (major, minor) = value
+            .strip_prefix("HTTP/")
+            .and_then(|version| version.split_once('.').or(Some((version, "0"))))
+            .filter(|(major, minor)| {
+                !major.is_empty()
+                    && !minor.is_empty()
+                    && major
+                        .bytes()
+                        .chain(minor.bytes())
+                        .all(|b| b.is_ascii_digit())
+            })
+            .ok_or(Error::InvalidProtocol)?;
+
+        match (major, minor) {
+            ("1", "0") => Ok(Self::Http10),
+            ("1", "1") => Ok(Self::Http11),
+            _ => Err(Error::UnsupportedVersion),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RequestLimits {
+    pub max_body_size: usize,
+    pub max_part_size: usize,
+    pub max_header_line: usize,
+    pub max_header_bytes: usize,
+    pub max_header_count: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_body_size: 10 * 1024 * 1024,
+            max_part_size: 5 * 1024 * 1024,
+            max_header_line: 8 * 1024,
+            max_header_bytes: 64 * 1024,
+            max_header_count: 100,
+        }
+    }
+}
+
+// reads a single line of at most `max_len` bytes including the line ending,
+// returns None on a clean EOF
+pub(crate) fn read_line<R: BufRead>(
+    reader: &mut R,
+    max_len: usize,
+    too_long: fn() -> Error,
+) -> Result<Option<String>> {
+    let mut line = Vec::new();
+
+    if reader.take(max_len as u64).read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+
+    if line.ends_with(b"\n") {
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+    } else if line.len() >= max_len {
+        return Err(too_long());
+    }
+
+    Ok(Some(String::from_utf8(line)?))
+}
+
+// like read_line but for the request head, the line has to end in CRLF and
+// may not contain control characters other than HTAB
+fn read_strict_line<R: BufRead>(
+    reader: &mut R,
+    max_len: usize,
+    too_long: fn() -> Error,
+) -> Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+
+    if reader.take(max_len as u64).read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+
+    if !line.ends_with(b"\n") {
+        return Err(if line.len() >= max_len {
+            too_long()
+        } else {
+            Error::InvalidRequest
+        });
+    }
+
+    line.pop();
+    if line.pop() != Some(b'\r') {
+        return Err(Error::InvalidRequest);
+    }
+
+    if line.iter().any(|&b| b.is_ascii_control() && b != b'\t') {
+        return Err(Error::InvalidRequest);
+    }
+
+    Ok(Some(line))
+}
+
+// tchar from RFC 9110 section 5.6.2
+fn is_token(value: &[u8]) -> bool {
+    !value.is_empty()
+        && value
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(b))
+}
+
+fn trim_whitespace(mut value: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = value {
+        value = rest;
+    }
+    while let [rest @ .., b' ' | b'\t'] = value {
+        value = rest;
+    }
+    value
+}
+
+// the parts were checked to be ASCII already
+fn ascii(value: &[u8]) -> &str {
+    std::str::from_utf8(value).unwrap_or_default()
+}
+
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+
+    let name = if host.starts_with('[') {
+        host.find(']').map_or(host, |end| &host[..=end])
+    } else {
+        host.split(':').next().unwrap_or_default()
+    };
+
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+// the path with empty and `.` segments dropped and `..` segments resolved,
+// see RFC 3986 section 5.2.4, a trailing slash is kept
+fn normalize_path(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+
+    for segment in path.split('/').skip(1) {
+        trailing_slash = matches!(segment, "" | "." | "..");
+        match segment {
+            "" | "." => (),
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+// lowercased and without the port the scheme implies anyway
+fn normalize_authority(authority: &str, default_port: &str) -> String {
+    let authority = authority.trim();
+    authority
+        .strip_suffix(default_port)
+        .unwrap_or(authority)
+        .to_ascii_lowercase()
+}
+
+// splits an absolute-form target into its authority, the default port of its
+// scheme and the origin-form target, None for any other form, see RFC 9112
+// section 3.2.2
+fn split_absolute_form(target: &str) -> Result<Option<(String, &'static str, String)>> {
+    let Some((scheme, rest)) = target.split_once("://") else {
+        return Ok(None);
+    };
+    if target.starts_with('/') {
+        return Ok(None);
+    }
+
+    let default_port = match scheme.to_ascii_lowercase().as_str() {
+        "http" => ":80",
+        "https" => ":443",
+        _ => return Err(Error::InvalidRequest),
+    };
+
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+
+    // userinfo is deprecated and a host is required, see RFC 9110 section 4.2
+    if authority.is_empty() || authority.contains('@') {
+        return Err(Error::InvalidRequest);
+    }
+
+    let origin = match path {
+        "" => "/".to_string(),
+        path if path.starts_with('?') => format!("/{}", path),
+        path => path.to_string(),
+    };
+
+    Ok(Some((
+        normalize_authority(authority, default_port),
+        default_port,
+        origin,
+    )))
+}
+
+pub(crate) enum BodyFraming {
+    Empty,
+    Length(usize),
+    Chunked,
+}
+
+#[derive(Debug)]
+pub struct HttpRequest {
+    target: String,
+    // the authority of an absolute-form target, which takes the place of
+    // the Host header
+    authority: Option<String>,
+    method: HttpMethod,
+    version: HttpVersion,
+    headers: HeaderMap,
+    body: Option<Vec<u8>>,
+    context: RequestContext,
+}
+
+impl HttpRequest {
+    pub fn method(&self) -> &HttpMethod {
+        &self.method
+    }
+
+    pub fn version(&self) -> HttpVersion {
+        self.version
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn path(&self) -> &str {
+        self.target
+            .split_once('?')
+            .map_or(self.target.as_str(), |(path, _)| path)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    // the host the request is for without its port, lowercased and without
+    // a trailing dot
+    pub fn host(&self) -> Option<String> {
+        self.context
+            .forwarded_host()
+            .or(self.authority.as_deref())
+            .or(self.header("host"))
+            .map(normalize_host)
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    // the available type the Accept header prefers, None calls for a 406
+    pub fn negotiate(&self, available: &[MediaType]) -> Option<MediaType> {
+        accept::negotiate(self.headers.get_joined("accept").as_deref(), available)
+    }
+
+    pub fn body(&self) -> Option<&[u8]> {
+        self.body.as_deref()
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.context.peer_addr()
+    }
+
+    // the client's address, which behind a trusted proxy is not the peer's
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.context.client_ip()
+    }
+
+    pub fn scheme(&self) -> &str {
+        self.context.scheme()
+    }
+
+    // the id the request goes by in logs and X-Request-Id, assigned once
+    // the server starts handling it
+    pub fn id(&self) -> Option<&str> {
+        self.context
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.as_str())
+    }
+
+    pub fn context(&self) -> &RequestContext {
+        &self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut RequestContext {
+        &mut self.context
+    }
+
+    fn has_connection_token(&self, token: &str) -> bool {
+        self.headers
+            .get_all("connection")
+            .flat_map(|connection| connection.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            HttpVersion::Http10 => self.has_connection_token("keep-alive"),
+            HttpVersion::Http11 => !self.has_connection_token("close"),
+            HttpVersion::Http2 => true,
+        }
+    }
+
+    // a request whose head arrived some other way than as HTTP/1 text
+    #[cfg(all(feature = "h2", not(feature = "tokio")))]
+    pub(crate) fn from_parts(
+        method: HttpMethod,
+        target: String,
+        version: HttpVersion,
+        headers: HeaderMap,
+    ) -> Self {
+        HttpRequest {
+            target,
+            authority: None,
+            method,
+            version,
+            headers,
+            body: None,
+            context: RequestContext::default(),
+        }
+    }
+
+    pub fn read_head<R: BufRead>(reader: &mut R, limits: &RequestLimits) -> Result<Self> {
+        let Some(request_line) =
+            read_strict_line(reader, limits.max_header_line, || Error::UriTooLong)?
+        else {
+            return Err(Error::ConnectionClosed);
+        };
+
+        // method SP request-target SP HTTP-version, with exactly one space
+        // between the parts, see RFC 9112 section 3
+        let mut parts = request_line.split(|&b| b == b' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(Error::InvalidRequest);
+        };
+
+        if !is_token(method) || target.is_empty() || !target.iter().all(u8::is_ascii_graphic) {
+            return Err(Error::InvalidRequest);
+        }
+
+        let method = HttpMethod::from_str(ascii(method))?;
+        let (authority, request_target) = match split_absolute_form(ascii(target))? {
+            Some((authority, default_port, target)) => (Some((authority, default_port)), target),
+            None => (None, ascii(target).to_owned()),
+        };
+        let version = HttpVersion::from_str(
+            std::str::from_utf8(version).map_err(|_| Error::InvalidProtocol)?,
+        )?;
+
+        let mut headers = HeaderMap::new();
+        let mut header_count = 0;
+        let mut header_bytes = 0;
+
+        loop {
+            let header_line =
+                read_strict_line(reader, limits.max_header_line, || Error::HeadersTooLarge)?
+                    .ok_or(Error::InvalidRequest)?;
+
+            if header_line.is_empty() {
+                break;
+            }
+
+            header_count += 1;
+            header_bytes += header_line.len();
+
+            if header_count > limits.max_header_count || header_bytes > limits.max_header_bytes {
+                return Err(Error::HeadersTooLarge);
+            }
+
+            // no whitespace is allowed before the colon, which also rules
+            // out obsolete line folding
+            let colon = header_line
+                .iter()
+                .position(|&b| b == b':')
+                .ok_or(Error::InvalidRequest)?;
+            let (name, value) = (&header_line[..colon], &header_line[colon + 1..]);
+
+            if !is_token(name) {
+                return Err(Error::InvalidRequest);
+            }
+
+            headers.append(
+                ascii(name).to_ascii_lowercase(),
+                String::from_utf8(trim_whitespace(value).to_vec())?,
+            );
+        }
+
+        // the Host header has to name the same authority as the target
+        if let (Some((authority, default_port)), Some(host)) = (&authority, headers.get("host")) {
+            if normalize_authority(host, default_port) != *authority {
+                return Err(Error::InvalidRequest);
+            }
+        }
+        let authority = authority.map(|(authority, _)| authority);
+
+        Ok(HttpRequest {
+            target: request_target,
+            authority,
+            method,
+            version,
+            headers,
+            body: None,
+            context: RequestContext::default(),
+        })
+    }
+
+    // HTTP/1.1 requires exactly one Host header, see RFC 9112 section 3.2
+    pub(crate) fn check_host(&self) -> Result<()> {
+        let hosts = self.headers.get_all("host").count();
+        if hosts > 1 || (self.version == HttpVersion::Http11 && hosts == 0) {
+            return Err(Error::InvalidRequest);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn body_framing(&self, limits: &RequestLimits) -> Result<BodyFraming> {
+        // a message framed both ways is read differently by each hop it
+        // passes, the ground for request smuggling, see RFC 9112 section 6.3
+        if self.headers.get("transfer-encoding").is_some()
+            && self.headers.get("content-length").is_some()
+        {
+            return Err(Error::InvalidRequest);
+        }
+
+        if let Some(transfer_encoding) = self.headers.get_joined("transfer-encoding") {
+            // chunked is the one coding understood, anything layered under
+            // it would be passed on still encoded, see RFC 9112 section 6.1
+            if !transfer_encoding.trim().eq_ignore_ascii_case("chunked") {
+                return Err(Error::UnsupportedTransferCoding);
+            }
+
+            Ok(BodyFraming::Chunked)
+        } else if let Some(content_length_str) = self.headers.get("content-length") {
+            // 1*DIGIT, parse would take a sign as well
+            if !content_length_str.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(Error::InvalidRequest);
+            }
+            let content_length = content_length_str
+                .parse::<usize>()
+                .map_err(|_| Error::InvalidRequest)?;
+
+            // repeated lengths are only acceptable when they all agree
+            if self
+                .headers
+                .get_all("content-length")
+                .any(|value| value != content_length_str)
+            {
+                return Err(Error::InvalidRequest);
+            }
+
+            if content_length > limits.max_body_size {
+                return Err(Error::PayloadTooLarge);
+            }
+
+            Ok(BodyFraming::Length(content_length))
+        } else {
+            Ok(BodyFraming::Empty)
+        }
+    }
+
+    // so that `//files/a` or `/x/../files/a` reach middleware as the path
+    // the router matches them to
+    pub(crate) fn normalize_target(&mut self) {
+        if !self.target.starts_with('/') {
+            return;
+        }
+        self.target = match self.target.split_once('?') {
+            Some((path, query)) => format!("{}?{}", normalize_path(path), query),
+            None => normalize_path(&self.target),
+        };
+    }
+
+    // an internal rewrite, the client still sees the target it asked for
+    pub(crate) fn set_target(&mut self, target: String) {
+        self.target = target;
+    }
+
+    pub(crate) fn set_body(&mut self, buffer: Vec<u8>) {
+        if !buffer.is_empty() {
+            self.body = Some(buffer);
+        }
+    }
+
+    pub fn read_body<R: BufRead>(&mut self, reader: &mut R, limits: &RequestLimits) -> Result<()> {
+        let buffer = match self.body_framing(limits)? {
+            BodyFraming::Chunked => chunked::read_chunked_body(reader, limits)?,
+            BodyFraming::Length(content_length) => {
+                // grows with what arrives instead of allocating whatever the
+                // header claims up front
+                let mut buffer = Vec::new();
+                reader
+                    .take(content_length as u64)
+                    .read_to_end(&mut buffer)?;
+                if buffer.len() < content_length {
+                    return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                }
+                buffer
+            }
+            BodyFraming::Empty => Vec::new(),
+        };
+
+        self.set_body(buffer);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_head_should_parse_version_and_connection_semantics() {
+        let test_cases = vec![
+            ("GET / HTTP/1.1\r\n\r\n", Some((HttpVersion::Http11, true))),
+            (
+                "GET / HTTP/1.1\r\nConnection: close\r\n\r\n",
+                Some((HttpVersion::Http11, false)),
+            ),
+            ("GET / HTTP/1.0\r\n\r\n", Some((HttpVersion::Http10, false))),
+            (
+                "GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n",
+                Some((HttpVersion::Http10, true)),
+            ),
+            ("GET / HTTP/2.0\r\n\r\n", None),
+            ("GET / HTTX/1.1\r\n\r\n", None),
+            ("GET /\r\n\r\n", None),
+        ];
+
+        for (input, expected) in test_cases {
+            let result = HttpRequest::read_head(&mut input.as_bytes(), &RequestLimits::default())
+                .ok()
+                .map(|req| (req.version(), req.keep_alive()));
+            assert_eq!(result, expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn read_head_should_reject_malformed_framing() {
+        let test_cases = vec![
+            ("GET / HTTP/1.1\r\nHost: a\r\n\r\n", Ok(())),
+            ("GET / HTTP/1.1\r\nX-Tab:\tone\ttwo \r\n\r\n", Ok(())),
+            ("GET / HTTP/1.1\nHost: a\r\n\r\n", Err("InvalidRequest")),
+            ("GET / HTTP/1.1\r\nHost: a\n\r\n", Err("InvalidRequest")),
+            ("GET / HTTP/1.1\r\nHost: a\r\n\n", Err("InvalidRequest")),
+            ("GET / HTTP/1.1\r\nHost: a\r\n", Err("InvalidRequest")),
+            ("GET  / HTTP/1.1\r\n\r\n", Err("InvalidRequest")),
+            ("GET\t/ HTTP/1.1\r\n\r\n", Err("InvalidRequest")),
+            ("GET /a\0b HTTP/1.1\r\n\r\n", Err("InvalidRequest")),
+            (
+                "GET / HTTP/1.1\r\nHost: a\rb\r\n\r\n",
+                Err("InvalidRequest"),
+            ),
+            ("GET / HTTP/1.1\r\nHost : a\r\n\r\n", Err("InvalidRequest")),
+            ("GET / HTTP/1.1\r\nX(y): a\r\n\r\n", Err("InvalidRequest")),
+            (
+                "GET / HTTP/1.1\r\nX: a\r\n folded\r\n\r\n",
+                Err("InvalidRequest"),
+            ),
+            ("G@T / HTTP/1.1\r\n\r\n", Err("InvalidRequest")),
+            ("BREW / HTTP/1.1\r\n\r\n", Err("InvalidMethod")),
+            ("GET /aaaaaaaa HTTP/1.1\r\n\r\n", Err("UriTooLong")),
+            (
+                "GET / HTTP/1.1\r\nX: aaaaaaaaaaaaaaaaaa\r\n\r\n",
+                Err("HeadersTooLarge"),
+            ),
+        ];
+
+        let limits = RequestLimits {
+            max_header_line: 20,
+            ..RequestLimits::default()
+        };
+
+        for (input, expected) in test_cases {
+            let result = HttpRequest::read_head(&mut input.as_bytes(), &limits)
+                .map(|_| ())
+                .map_err(|e| format!("{e:?}"));
+            assert_eq!(result, expected.map_err(str::to_string), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn read_head_should_reconcile_absolute_form_targets_with_host() {
+        let test_cases = vec![
+            (
+                "GET /a?b HTTP/1.1\r\nHost: Example.com:8080\r\n\r\n",
+                Ok(("/a?b", "example.com")),
+            ),
+            (
+                "GET http://example.com/a?b HTTP/1.1\r\nHost: example.com\r\n\r\n",
+                Ok(("/a?b", "example.com")),
+            ),
+            (
+                "GET HTTP://Example.com:80 HTTP/1.1\r\nHost: example.com\r\n\r\n",
+                Ok(("/", "example.com")),
+            ),
+            (
+                "GET https://example.com?q HTTP/1.1\r\nHost: example.com:443\r\n\r\n",
+                Ok(("/?q", "example.com")),
+            ),
+            (
+                "GET http://[::1]:8080/ HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n",
+                Ok(("/", "[::1]")),
+            ),
+            (
+                "GET http://example.com/ HTTP/1.0\r\n\r\n",
+                Ok(("/", "example.com")),
+            ),
+            (
+                "GET http://example.com/ HTTP/1.1\r\nHost: other.com\r\n\r\n",
+                Err("InvalidRequest"),
+            ),
+            (
+                "GET http://example.com:8080/ HTTP/1.1\r\nHost: example.com\r\n\r\n",
+                Err("InvalidRequest"),
+            ),
+            (
+                "GET http://user@example.com/ HTTP/1.1\r\nHost: example.com\r\n\r\n",
+                Err("InvalidRequest"),
+            ),
+            (
+                "GET ftp://example.com/ HTTP/1.1\r\nHost: example.com\r\n\r\n",
+                Err("InvalidRequest"),
+            ),
+            (
+                "GET http:/// HTTP/1.1\r\nHost: example.com\r\n\r\n",
+                Err("InvalidRequest"),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            let result = HttpRequest::read_head(&mut input.as_bytes(), &RequestLimits::default())
+                .and_then(|req| req.check_host().map(|_| req))
+                .map(|req| (req.path().to_string(), req.target().to_string(), req.host()))
+                .map_err(|e| format!("{e:?}"));
+            let expected = expected
+                .map(|(target, host)| {
+                    let path = target.split('?').next().unwrap_or_default().to_string();
+                    (path, target.to_string(), Some(host.to_string()))
+                })
+                .map_err(str::to_string);
+            assert_eq!(result, expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn normalize_target_should_collapse_slashes_and_dot_segments() {
+        let test_cases = vec![
+            ("/", "/"),
+            ("/files/a.txt", "/files/a.txt"),
+            ("//files/a.txt", "/files/a.txt"),
+            ("/files//dir/", "/files/dir/"),
+            ("/files/./a.txt", "/files/a.txt"),
+            ("/x/../files/a.txt", "/files/a.txt"),
+            ("/../files/a.txt", "/files/a.txt"),
+            ("/files/..", "/"),
+            ("/files/dir/.", "/files/dir/"),
+            ("//files/a.txt?q=//x/../", "/files/a.txt?q=//x/../"),
+            ("*", "*"),
+        ];
+
+        for (target, expected) in test_cases {
+            let raw = format!("OPTIONS {target} HTTP/1.1\r\nHost: a\r\n\r\n");
+            let mut req =
+                HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap();
+            req.normalize_target();
+            assert_eq!(req.target(), expected, "{target}");
+        }
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    #[test]
+    fn read_should_not_panic_on_truncated_or_mangled_requests() {
+        let seeds = [
+            "GET http://example.com/a?b HTTP/1.1\r\nHost: example.com\r\nRange: bytes=0-\r\n\r\n",
+            "POST /files/a HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;x=y\r\nhello\r\n0\r\nX-T: 1\r\n\r\n",
+        ];
+        let limits = RequestLimits {
+            max_body_size: 16,
+            max_header_line: 64,
+            max_header_bytes: 256,
+            max_header_count: 8,
+            ..RequestLimits::default()
+        };
+
+        for seed in seeds {
+            let mut inputs: Vec<Vec<u8>> = (0..seed.len())
+                .map(|end| seed.as_bytes()[..end].to_vec())
+                .collect();
+            for at in 0..seed.len() {
+                for byte in [0, b'\r', b'\n', b':', b' ', b';', b'f', 0xff] {
+                    let mut input = seed.as_bytes().to_vec();
+                    input[at] = byte;
+                    inputs.push(input);
+                }
+            }
+
+            for input in inputs {
+                let mut reader = &input[..];
+                if let Ok(mut req) = HttpRequest::read_head(&mut reader, &limits) {
+                    if req.read_body(&mut reader, &limits).is_ok() {
+                        let length = req.body().map_or(0, <[u8]>::len);
+                        assert!(length <= limits.max_body_size, "{input:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    // generated header fields written out as a request and as a response
+    // come back the same, names lowercased and values trimmed on the way in
+    #[test]
+    fn headers_should_round_trip_through_serialization() {
+        let tchar =
+            b"!#$%&'*+-.^_`|~0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        let vchar: Vec<u8> = (0x21..0x7f).collect();
+        // xorshift, so a failure reproduces from the same seed
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut next = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+
+        for _ in 0..500 {
+            let mut headers = HeaderMap::new();
+            for _ in 0..next(12) {
+                let name: String = (0..1 + next(8))
+                    .map(|_| tchar[next(tchar.len())] as char)
+                    .collect();
+                let mut value = String::new();
+                for i in 0..next(12) {
+                    // spaces and tabs only between visible characters
+                    if i > 0 && next(4) == 0 {
+                        value.push([' ', '\t'][next(2)]);
+                    }
+                    value.push(vchar[next(vchar.len())] as char);
+                }
+                headers.append(format!("X-{name}"), value);
+            }
+
+            let mut raw = String::from("GET / HTTP/1.1\r\n");
+            for (name, value) in headers.iter() {
+                let padding = [" ", "", "\t", "  "][next(4)];
+                raw.push_str(&format!("{name}:{padding}{value}{padding}\r\n"));
+            }
+            raw.push_str("\r\n");
+            let req = HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default())
+                .unwrap_or_else(|e| panic!("{raw:?}: {e:?}"));
+            let expected: Vec<(String, &str)> = headers
+                .iter()
+                .map(|(name, value)| (name.to_ascii_lowercase(), value))
+                .collect();
+            let parsed: Vec<(String, &str)> = req
+                .headers()
+                .iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect();
+            assert_eq!(parsed, expected, "{raw:?}");
+
+            let mut response = crate::response::HttpResponse::ok();
+            for (name, value) in headers.iter() {
+                response = response.add_header(name, value);
+            }
+            let mut out = Vec::new();
+            response.write_to(&mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            let written: Vec<(&str, &str)> = out
+                .lines()
+                .filter(|line| line.starts_with("X-"))
+                .filter_map(|line| line.split_once(": "))
+                .collect();
+            assert_eq!(written, headers.iter().collect::<Vec<_>>(), "{out:?}");
+        }
+    }
+}
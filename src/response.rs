@@ -0,0 +1,824 @@
+use crate::chunked::ChunkedWriter;
+use crate::date;
+use crate::headers::HeaderMap;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, IoSlice, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+pub const DEFAULT_SERVER_HEADER: &str = concat!("codecrafters-http/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusCode {
+    SwitchingProtocols,
+    Ok,
+    Created,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
+    Conflict,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported,
+    // anything passed through from elsewhere, e.g. a proxied upstream
+    Other(u16),
+}
+
+impl StatusCode {
+    pub fn code(&self) -> u16 {
+        match self {
+            StatusCode::SwitchingProtocols => 101,
+            StatusCode::Ok => 200,
+            StatusCode::Created => 201,
+            StatusCode::NoContent => 204,
+            StatusCode::PartialContent => 206,
+            StatusCode::MovedPermanently => 301,
+            StatusCode::Found => 302,
+            StatusCode::NotModified => 304,
+            StatusCode::TemporaryRedirect => 307,
+            StatusCode::PermanentRedirect => 308,
+            StatusCode::BadRequest => 400,
+            StatusCode::Unauthorized => 401,
+            StatusCode::Forbidden => 403,
+            StatusCode::NotFound => 404,
+            StatusCode::MethodNotAllowed => 405,
+            StatusCode::NotAcceptable => 406,
+            StatusCode::RequestTimeout => 408,
+            StatusCode::Conflict => 409,
+            StatusCode::PreconditionFailed => 412,
+            StatusCode::PayloadTooLarge => 413,
+            StatusCode::UriTooLong => 414,
+            StatusCode::UnsupportedMediaType => 415,
+            StatusCode::RangeNotSatisfiable => 416,
+            StatusCode::TooManyRequests => 429,
+            StatusCode::RequestHeaderFieldsTooLarge => 431,
+            StatusCode::InternalServerError => 500,
+            StatusCode::NotImplemented => 501,
+            StatusCode::BadGateway => 502,
+            StatusCode::ServiceUnavailable => 503,
+            StatusCode::GatewayTimeout => 504,
+            StatusCode::HttpVersionNotSupported => 505,
+            StatusCode::Other(code) => *code,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            StatusCode::SwitchingProtocols => "Switching Protocols",
+            StatusCode::Ok => "OK",
+            StatusCode::Created => "Created",
+            StatusCode::NoContent => "No Content",
+            StatusCode::PartialContent => "Partial Content",
+            StatusCode::MovedPermanently => "Moved Permanently",
+            StatusCode::Found => "Found",
+            StatusCode::NotModified => "Not Modified",
+            StatusCode::TemporaryRedirect => "Temporary Redirect",
+            StatusCode::PermanentRedirect => "Permanent Redirect",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::Unauthorized => "Unauthorized",
+            StatusCode::Forbidden => "Forbidden",
+            StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::NotAcceptable => "Not Acceptable",
+            StatusCode::RequestTimeout => "Request Timeout",
+            StatusCode::Conflict => "Conflict",
+            StatusCode::PreconditionFailed => "Precondition Failed",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::UriTooLong => "URI Too Long",
+            StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            StatusCode::TooManyRequests => "Too Many Requests",
+            StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::NotImplemented => "Not Implemented",
+            StatusCode::BadGateway => "Bad Gateway",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+            StatusCode::GatewayTimeout => "Gateway Timeout",
+            StatusCode::HttpVersionNotSupported => "HTTP Version Not Supported",
+            StatusCode::Other(_) => "",
+        }
+    }
+
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            101 => StatusCode::SwitchingProtocols,
+            200 => StatusCode::Ok,
+            201 => StatusCode::Created,
+            204 => StatusCode::NoContent,
+            206 => StatusCode::PartialContent,
+            301 => StatusCode::MovedPermanently,
+            302 => StatusCode::Found,
+            304 => StatusCode::NotModified,
+            307 => StatusCode::TemporaryRedirect,
+            308 => StatusCode::PermanentRedirect,
+            400 => StatusCode::BadRequest,
+            401 => StatusCode::Unauthorized,
+            403 => StatusCode::Forbidden,
+            404 => StatusCode::NotFound,
+            405 => StatusCode::MethodNotAllowed,
+            406 => StatusCode::NotAcceptable,
+            408 => StatusCode::RequestTimeout,
+            409 => StatusCode::Conflict,
+            412 => StatusCode::PreconditionFailed,
+            413 => StatusCode::PayloadTooLarge,
+            414 => StatusCode::UriTooLong,
+            415 => StatusCode::UnsupportedMediaType,
+            416 => StatusCode::RangeNotSatisfiable,
+            429 => StatusCode::TooManyRequests,
+            431 => StatusCode::RequestHeaderFieldsTooLarge,
+            500 => StatusCode::InternalServerError,
+            501 => StatusCode::NotImplemented,
+            502 => StatusCode::BadGateway,
+            503 => StatusCode::ServiceUnavailable,
+            504 => StatusCode::GatewayTimeout,
+            505 => StatusCode::HttpVersionNotSupported,
+            code => StatusCode::Other(code),
+        }
+    }
+
+    pub fn allows_body(&self) -> bool {
+        !matches!(self.code(), 100..=199 | 204 | 304)
+    }
+}
+
+pub enum Body {
+    Bytes(Bytes),
+    Stream {
+        reader: Box<dyn Read + Send>,
+        length: Option<u64>,
+    },
+    // kept apart from other streams so it can be sent without a userspace copy
+    File {
+        file: File,
+        length: u64,
+    },
+}
+
+pub trait SendFile: Write {
+    // copies `length` bytes of `file`, writers backed by a socket may do so
+    // without going through userspace
+    fn send_file(&mut self, file: &mut File, length: u64) -> io::Result<u64> {
+        io::copy(&mut file.take(length), self)
+    }
+}
+
+impl SendFile for Vec<u8> {}
+
+// with both types concrete std turns the copy into sendfile or splice on
+// Linux, other platforms get a buffered copy
+impl SendFile for TcpStream {}
+
+#[cfg(unix)]
+impl SendFile for UnixStream {}
+
+impl SendFile for io::StdoutLock<'_> {}
+
+// `Write::write_all_vectored` is not stable yet, this one resumes after a
+// partial write mid-slice
+pub(crate) fn write_all_vectored<W: Write + ?Sized>(
+    writer: &mut W,
+    mut bufs: &[&[u8]],
+) -> io::Result<()> {
+    let mut offset = 0;
+    while let Some((first, rest)) = bufs.split_first() {
+        let slices: Vec<IoSlice<'_>> = std::iter::once(&first[offset..])
+            .chain(rest.iter().copied())
+            .map(IoSlice::new)
+            .collect();
+        let mut n = match writer.write_vectored(&slices) {
+            Ok(0) if slices.iter().any(|slice| !slice.is_empty()) => {
+                return Err(io::ErrorKind::WriteZero.into())
+            }
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        n += offset;
+        offset = 0;
+        while let Some((first, rest)) = bufs.split_first() {
+            if n < first.len() {
+                offset = n;
+                break;
+            }
+            n -= first.len();
+            bufs = rest;
+        }
+    }
+    Ok(())
+}
+
+// holds the head back so it goes out together with the first bytes of the
+// body, one syscall for most responses instead of two
+struct ResponseWriter<'a, W: SendFile> {
+    inner: &'a mut W,
+    head: BytesMut,
+}
+
+impl<W: SendFile> ResponseWriter<'_, W> {
+    fn write_head(&mut self) -> io::Result<()> {
+        if !self.head.is_empty() {
+            self.inner.write_all(&self.head)?;
+            self.head.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: SendFile> Write for ResponseWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_vectored(&[IoSlice::new(buf)])
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        while !self.head.is_empty() {
+            let slices: Vec<IoSlice<'_>> = std::iter::once(IoSlice::new(&self.head))
+                .chain(bufs.iter().map(|buf| IoSlice::new(buf)))
+                .collect();
+            let n = match self.inner.write_vectored(&slices) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n >= self.head.len() {
+                let written = n - self.head.len();
+                self.head.clear();
+                if written > 0 {
+                    return Ok(written);
+                }
+            } else {
+                self.head.advance(n);
+            }
+        }
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_head()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: SendFile> SendFile for ResponseWriter<'_, W> {
+    fn send_file(&mut self, file: &mut File, length: u64) -> io::Result<u64> {
+        self.write_head()?;
+        self.inner.send_file(file, length)
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => fmt.debug_tuple("Bytes").field(bytes).finish(),
+            Body::Stream { length, .. } => {
+                fmt.debug_struct("Stream").field("length", length).finish()
+            }
+            Body::File { length, .. } => fmt.debug_struct("File").field("length", length).finish(),
+        }
+    }
+}
+
+// the connection after a 101 response, reads see any bytes the client sent
+// right behind the handshake
+pub trait Upgraded: Read + Write {}
+
+impl<T: Read + Write> Upgraded for T {}
+
+type UpgradeHandler = dyn FnOnce(&mut dyn Upgraded) -> io::Result<()> + Send;
+
+pub struct Upgrade(Box<UpgradeHandler>);
+
+impl Upgrade {
+    pub fn run(self, connection: &mut dyn Upgraded) -> io::Result<()> {
+        (self.0)(connection)
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Upgrade")
+    }
+}
+
+type TrailerFn = dyn FnOnce() -> HeaderMap + Send;
+
+// produces the trailer fields once a chunked body has been written, so they
+// can depend on what was streamed
+struct Trailers(Box<TrailerFn>);
+
+impl fmt::Debug for Trailers {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("Trailers")
+    }
+}
+
+#[derive(Debug)]
+pub struct HttpResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Body,
+    upgrade: Option<Upgrade>,
+    trailers: Option<Trailers>,
+    close_delimited: bool,
+}
+
+impl HttpResponse {
+    pub fn new(status: StatusCode) -> Self {
+        HttpResponse {
+            status,
+            headers: HeaderMap::new(),
+            body: Body::Bytes(Bytes::new()),
+            upgrade: None,
+            trailers: None,
+            close_delimited: false,
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(StatusCode::Ok)
+    }
+
+    pub fn created() -> Self {
+        Self::new(StatusCode::Created)
+    }
+
+    pub fn bad_request() -> Self {
+        Self::new(StatusCode::BadRequest)
+    }
+
+    pub fn forbidden() -> Self {
+        Self::new(StatusCode::Forbidden)
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(StatusCode::NotFound)
+    }
+
+    pub fn internal_server_error() -> Self {
+        Self::new(StatusCode::InternalServerError)
+    }
+
+    pub fn service_unavailable() -> Self {
+        Self::new(StatusCode::ServiceUnavailable)
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    // adds a separate field line, for headers like Set-Cookie that cannot
+    // be combined into a list
+    pub fn add_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn append_header(self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let name = name.into();
+        let value = value.into();
+
+        let combined = match self.get_header(&name) {
+            Some(existing)
+                if existing
+                    .split(',')
+                    .any(|item| item.trim().eq_ignore_ascii_case(&value)) =>
+            {
+                return self
+            }
+            Some(existing) => format!("{}, {}", existing, value),
+            None => value,
+        };
+
+        self.header(name, combined)
+    }
+
+    pub fn remove_header(mut self, name: &str) -> Self {
+        self.headers.remove(name);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    pub fn sized_body(mut self, reader: impl Read + Send + 'static, length: u64) -> Self {
+        self.body = Body::Stream {
+            reader: Box::new(reader),
+            length: Some(length),
+        };
+        self
+    }
+
+    pub fn file_body(mut self, file: File, length: u64) -> Self {
+        self.body = Body::File { file, length };
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        match &self.body {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Stream { length, .. } => *length,
+            Body::File { length, .. } => Some(*length),
+        }
+    }
+
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn take_body(&mut self) -> Body {
+        std::mem::replace(&mut self.body, Body::Bytes(Bytes::new()))
+    }
+
+    // takes over the connection once the 101 head has been written
+    pub fn upgrade(
+        mut self,
+        handler: impl FnOnce(&mut dyn Upgraded) -> io::Result<()> + Send + 'static,
+    ) -> Self {
+        self.status = StatusCode::SwitchingProtocols;
+        self.upgrade = Some(Upgrade(Box::new(handler)));
+        self
+    }
+
+    pub fn take_upgrade(&mut self) -> Option<Upgrade> {
+        self.upgrade.take()
+    }
+
+    // announces `names` in a Trailer header and sends the fields `trailers`
+    // returns after the last chunk, undeclared ones are dropped; trailers
+    // need a chunked body and are left out for any other
+    pub fn trailers(
+        mut self,
+        names: &[&str],
+        trailers: impl FnOnce() -> HeaderMap + Send + 'static,
+    ) -> Self {
+        self.trailers = Some(Trailers(Box::new(trailers)));
+        self.header("Trailer", names.join(", "))
+    }
+
+    // the trailer fields once the body has been sent, only the declared ones
+    #[cfg(all(feature = "h2", not(feature = "tokio")))]
+    pub(crate) fn take_trailers(&mut self) -> Option<impl FnOnce() -> HeaderMap> {
+        let Trailers(trailers) = self.trailers.take()?;
+        let headers = self.headers.clone();
+        Some(move || declared_trailers(&headers, trailers()))
+    }
+
+    pub fn chunked_body(mut self, reader: impl Read + Send + 'static) -> Self {
+        self.body = Body::Stream {
+            reader: Box::new(reader),
+            length: None,
+        };
+        self
+    }
+
+    // an unsized body goes out as it is rather than chunked, ending where the
+    // connection does, for HTTP/1.0 clients that cannot decode chunks
+    pub(crate) fn close_delimited(mut self) -> Self {
+        self.close_delimited = true;
+        self
+    }
+
+    pub fn write_to<W: SendFile>(self, writer: &mut W) -> io::Result<()> {
+        self.write(writer, true)
+    }
+
+    pub fn write_head_to<W: SendFile>(self, writer: &mut W) -> io::Result<()> {
+        self.write(writer, false)
+    }
+
+    // the Date, made up as the response goes out unless a handler set its
+    // own
+    pub(crate) fn generated_headers(&self) -> Vec<(&'static str, String)> {
+        let mut generated = Vec::with_capacity(1);
+        if !self.headers.contains("date") {
+            generated.push(("Date", date::http_now()));
+        }
+        generated
+    }
+
+    // the status line and header fields as they go out, framing included
+    fn head(&self) -> BytesMut {
+        let mut head = BytesMut::with_capacity(256);
+
+        head.put(
+            format!(
+                "HTTP/1.1 {} {}\r\n",
+                self.status.code(),
+                self.status.reason()
+            )
+            .as_bytes(),
+        );
+        for (name, value) in self.generated_headers() {
+            head.put(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+
+        let chunked =
+            matches!(self.body, Body::Stream { length: None, .. }) && !self.close_delimited;
+
+        for (name, value) in self.headers.iter().filter(|(name, _)| {
+            !name.eq_ignore_ascii_case("content-length")
+                && !name.eq_ignore_ascii_case("transfer-encoding")
+                && (chunked || !name.eq_ignore_ascii_case("trailer"))
+        }) {
+            head.put(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+
+        if !self.status.allows_body() {
+            head.put(&b"\r\n"[..]);
+        } else {
+            match &self.body {
+                Body::Bytes(body) => {
+                    head.put(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())
+                }
+                Body::Stream {
+                    length: Some(length),
+                    ..
+                }
+                | Body::File { length, .. } => {
+                    head.put(format!("Content-Length: {}\r\n\r\n", length).as_bytes())
+                }
+                Body::Stream { length: None, .. } if self.close_delimited => head.put(&b"\r\n"[..]),
+                Body::Stream { length: None, .. } => {
+                    head.put(&b"Transfer-Encoding: chunked\r\n\r\n"[..])
+                }
+            }
+        }
+
+        head
+    }
+
+    fn write<W: SendFile>(self, writer: &mut W, include_body: bool) -> io::Result<()> {
+        let head = self.head();
+
+        let mut writer = ResponseWriter {
+            inner: writer,
+            head,
+        };
+        if include_body && self.status.allows_body() {
+            match self.body {
+                Body::Bytes(body) => writer.write_all(&body[..])?,
+                Body::Stream {
+                    mut reader,
+                    length: Some(length),
+                } => {
+                    io::copy(&mut reader.by_ref().take(length), &mut writer)?;
+                }
+                Body::File { mut file, length } => {
+                    writer.send_file(&mut file, length)?;
+                }
+                Body::Stream {
+                    mut reader,
+                    length: None,
+                } if self.close_delimited => {
+                    io::copy(&mut reader, &mut writer)?;
+                }
+                Body::Stream {
+                    mut reader,
+                    length: None,
+                } => {
+                    let mut chunked_writer = ChunkedWriter::new(&mut writer);
+                    io::copy(&mut reader, &mut chunked_writer)?;
+
+                    match self.trailers {
+                        Some(Trailers(trailers)) => chunked_writer
+                            .finish_with_trailers(&declared_trailers(&self.headers, trailers()))?,
+                        None => chunked_writer.finish()?,
+                    };
+                }
+            }
+        }
+        writer.flush()
+    }
+}
+
+// the fields a Trailer header announced, a client may not expect others
+fn declared_trailers(headers: &HeaderMap, fields: HeaderMap) -> HeaderMap {
+    let declared = headers.get_joined("trailer").unwrap_or_default();
+
+    let mut trailers = HeaderMap::new();
+    for (name, value) in fields.iter().filter(|(name, _)| {
+        declared
+            .split(',')
+            .any(|declared| declared.trim().eq_ignore_ascii_case(name))
+    }) {
+        trailers.append(name, value);
+    }
+    trailers
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // the Date is left to its own test, it changes
+    fn without_generated(out: Vec<u8>) -> String {
+        String::from_utf8(out)
+            .unwrap()
+            .split_inclusive("\r\n")
+            .filter(|line| !line.starts_with("Date: "))
+            .collect()
+    }
+
+    #[test]
+    fn write_to_should_serialize_status_headers_and_body() {
+        let path = std::env::temp_dir().join(format!("response-test-{}", std::process::id()));
+        std::fs::write(&path, "file contents").unwrap();
+        let file = File::open(&path).unwrap();
+
+        let test_cases = vec![
+            (
+                HttpResponse::ok().file_body(file, 4),
+                "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nfile",
+            ),
+            (
+                HttpResponse::not_found(),
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+            ),
+            (
+                HttpResponse::ok()
+                    .header("Content-Type", "text/plain")
+                    .header("Content-Length", "999")
+                    .body("abc"),
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 3\r\n\r\nabc",
+            ),
+            (
+                HttpResponse::ok().chunked_body(&b"streamed"[..]),
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n",
+            ),
+            (
+                HttpResponse::ok()
+                    .trailers(&["X-Checksum"], || {
+                        let mut trailers = HeaderMap::new();
+                        trailers.insert("X-Checksum", "42");
+                        trailers.insert("X-Undeclared", "1");
+                        trailers
+                    })
+                    .chunked_body(&b"streamed"[..]),
+                "HTTP/1.1 200 OK\r\nTrailer: X-Checksum\r\nTransfer-Encoding: chunked\r\n\r\n8\r\nstreamed\r\n0\r\nX-Checksum: 42\r\n\r\n",
+            ),
+            (
+                HttpResponse::ok()
+                    .trailers(&["X-Checksum"], HeaderMap::new)
+                    .body("abc"),
+                "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc",
+            ),
+        ];
+
+        for (response, expected) in test_cases {
+            let mut out = Vec::new();
+            response.write_to(&mut out).unwrap();
+            assert_eq!(without_generated(out), expected);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn write_head_to_should_keep_headers_and_omit_body() {
+        let mut out = Vec::new();
+
+        HttpResponse::ok()
+            .sized_body(&b"abc"[..], 3)
+            .write_head_to(&mut out)
+            .unwrap();
+
+        assert_eq!(
+            without_generated(out),
+            "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn write_to_should_date_the_response() {
+        let test_cases = vec![
+            (HttpResponse::ok(), None),
+            (
+                HttpResponse::not_found().header("Date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+                Some("Sun, 06 Nov 1994 08:49:37 GMT"),
+            ),
+        ];
+
+        for (response, date) in test_cases {
+            let mut out = Vec::new();
+            response.write_to(&mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            let dates: Vec<_> = out
+                .lines()
+                .filter_map(|line| line.strip_prefix("Date: "))
+                .collect();
+            assert_eq!(dates.len(), 1, "{out}");
+            match date {
+                Some(date) => assert_eq!(dates[0], date),
+                None => assert!(date::parse_http(dates[0]).is_some(), "{out}"),
+            }
+        }
+    }
+
+    // takes at most `limit` bytes of each call, as a socket with a full send
+    // buffer does
+    struct Trickle {
+        out: Vec<u8>,
+        limit: usize,
+        calls: usize,
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            self.calls += 1;
+            let mut n = 0;
+            for buf in bufs {
+                let take = buf.len().min(self.limit - n);
+                self.out.extend_from_slice(&buf[..take]);
+                n += take;
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SendFile for Trickle {}
+
+    #[test]
+    fn write_to_should_send_head_and_body_together_and_survive_partial_writes() {
+        let test_cases = vec![
+            (
+                HttpResponse::ok().body("abc"),
+                usize::MAX,
+                "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc",
+                1,
+            ),
+            (
+                HttpResponse::ok().body("abc"),
+                5,
+                "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc",
+                16,
+            ),
+            (
+                HttpResponse::ok().chunked_body(&b"streamed"[..]),
+                usize::MAX,
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n",
+                2,
+            ),
+            (
+                HttpResponse::ok().chunked_body(&b"streamed"[..]),
+                7,
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n",
+                15,
+            ),
+        ];
+
+        for (response, limit, expected, calls) in test_cases {
+            let mut out = Trickle {
+                out: Vec::new(),
+                limit,
+                calls: 0,
+            };
+            response.write_to(&mut out).unwrap();
+            assert_eq!(without_generated(out.out), expected);
+            assert_eq!(out.calls, calls, "{expected:?} in writes of {limit}");
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use crate::request::{HttpMethod, HttpRequest};
+use crate::response::{HttpResponse, StatusCode};
+use std::collections::HashMap;
+
+pub type Handler = Box<dyn Fn(&HttpRequest, &PathParams) -> HttpResponse + Send + Sync>;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PathParams(HashMap<String, String>);
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|v| v.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    CatchAll(String),
+}
+
+// a path pattern, `:name` matches one segment and `*name` the rest
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Pattern(Vec<Segment>);
+
+impl Pattern {
+    pub(crate) fn parse(pattern: &str) -> Self {
+        let segments = pattern
+            .trim_start_matches('/')
+            .split('/')
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_owned())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::CatchAll(name.to_owned())
+                } else {
+                    Segment::Static(segment.to_owned())
+                }
+            })
+            .collect();
+        Pattern(segments)
+    }
+
+    pub(crate) fn match_path(&self, path: &str) -> Option<PathParams> {
+        let mut params = HashMap::new();
+        let mut path_segments = path.trim_start_matches('/').split('/');
+
+        for segment in &self.0 {
+            match segment {
+                Segment::Static(expected) => {
+                    if path_segments.next()? != expected {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let value = path_segments.next().filter(|v| !v.is_empty())?;
+                    params.insert(name.clone(), value.to_owned());
+                }
+                Segment::CatchAll(name) => {
+                    let rest: Vec<&str> = path_segments.by_ref().collect();
+                    params.insert(name.clone(), rest.join("/"));
+                }
+            }
+        }
+
+        if path_segments.next().is_some() {
+            None
+        } else {
+            Some(PathParams(params))
+        }
+    }
+}
+
+struct Route {
+    method: HttpMethod,
+    pattern: String,
+    segments: Pattern,
+    handler: Handler,
+}
+
+impl Route {
+    fn match_path(&self, path: &str) -> Option<PathParams> {
+        self.segments.match_path(path)
+    }
+}
+
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    pub fn route<F>(&mut self, method: HttpMethod, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&HttpRequest, &PathParams) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            pattern: pattern.to_owned(),
+            segments: Pattern::parse(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&HttpRequest, &PathParams) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::GET, pattern, handler)
+    }
+
+    pub fn post<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&HttpRequest, &PathParams) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::POST, pattern, handler)
+    }
+
+    pub fn put<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&HttpRequest, &PathParams) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::PUT, pattern, handler)
+    }
+
+    pub fn delete<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&HttpRequest, &PathParams) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.route(HttpMethod::DELETE, pattern, handler)
+    }
+
+    fn allow_list(implemented: &[HttpMethod]) -> Vec<HttpMethod> {
+        if implemented.is_empty() {
+            return Vec::new();
+        }
+
+        HttpMethod::ALL
+            .into_iter()
+            .filter(|method| {
+                implemented.contains(method)
+                    || *method == HttpMethod::OPTIONS
+                    || (*method == HttpMethod::HEAD && implemented.contains(&HttpMethod::GET))
+            })
+            .collect()
+    }
+
+    fn allow_header(allowed: &[HttpMethod]) -> String {
+        allowed
+            .iter()
+            .map(|method| method.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn match_path(&self, path: &str) -> Vec<(&Route, PathParams)> {
+        self.routes
+            .iter()
+            .filter_map(|route| route.match_path(path).map(|params| (route, params)))
+            .collect()
+    }
+
+    pub fn allowed_methods(&self, path: &str) -> Vec<HttpMethod> {
+        let implemented: Vec<HttpMethod> = if path == "*" {
+            self.routes.iter().map(|route| route.method).collect()
+        } else {
+            self.match_path(path)
+                .iter()
+                .map(|(route, _)| route.method)
+                .collect()
+        };
+
+        Self::allow_list(&implemented)
+    }
+
+    pub fn matched_route(&self, req: &HttpRequest) -> Option<&str> {
+        self.match_path(req.path())
+            .first()
+            .map(|(route, _)| route.pattern.as_str())
+    }
+
+    pub fn handle(&self, req: &HttpRequest) -> HttpResponse {
+        let method = *req.method();
+
+        if method == HttpMethod::OPTIONS && req.path() == "*" {
+            return HttpResponse::new(StatusCode::NoContent)
+                .header("Allow", Self::allow_header(&self.allowed_methods("*")));
+        }
+
+        let mut candidates = self.match_path(req.path());
+
+        if candidates.is_empty() {
+            return HttpResponse::not_found();
+        }
+
+        let position = |method: HttpMethod| {
+            candidates
+                .iter()
+                .position(|(route, _)| route.method == method)
+        };
+
+        let found = match method {
+            HttpMethod::HEAD => position(HttpMethod::HEAD).or_else(|| position(HttpMethod::GET)),
+            method => position(method),
+        };
+
+        if let Some(index) = found {
+            let (route, params) = candidates.swap_remove(index);
+            return (route.handler)(req, &params);
+        }
+
+        let implemented: Vec<HttpMethod> =
+            candidates.iter().map(|(route, _)| route.method).collect();
+        let allow = Self::allow_header(&Self::allow_list(&implemented));
+
+        if method == HttpMethod::OPTIONS {
+            HttpResponse::new(StatusCode::NoContent).header("Allow", allow)
+        } else {
+            HttpResponse::new(StatusCode::MethodNotAllowed).header("Allow", allow)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> PathParams {
+        PathParams(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn match_path_should_extract_params() {
+        let test_cases = vec![
+            ("/", "/", Some(params(&[]))),
+            ("/", "/echo", None),
+            ("/echo/:msg", "/echo/abc", Some(params(&[("msg", "abc")]))),
+            ("/echo/:msg", "/echo/", None),
+            ("/echo/:msg", "/echo/a/b", None),
+            ("/user-agent", "/user-agent", Some(params(&[]))),
+            ("/user-agent", "/user-agents", None),
+            (
+                "/files/*path",
+                "/files/sub/dir/a.txt",
+                Some(params(&[("path", "sub/dir/a.txt")])),
+            ),
+        ];
+
+        for (pattern, path, expected) in test_cases {
+            let route = Route {
+                method: HttpMethod::GET,
+                pattern: pattern.to_owned(),
+                segments: Pattern::parse(pattern),
+                handler: Box::new(|_, _| HttpResponse::ok()),
+            };
+
+            assert_eq!(route.match_path(path), expected, "{pattern} vs {path}");
+        }
+    }
+
+    #[test]
+    fn allowed_methods_should_list_methods_for_matching_routes() {
+        let mut router = Router::new();
+        router
+            .get("/echo/:msg", |_, _| HttpResponse::ok())
+            .get("/files/:name", |_, _| HttpResponse::ok())
+            .post("/files/:name", |_, _| HttpResponse::created());
+
+        let test_cases = vec![
+            (
+                "/echo/abc",
+                vec![HttpMethod::GET, HttpMethod::HEAD, HttpMethod::OPTIONS],
+            ),
+            (
+                "/files/a.txt",
+                vec![
+                    HttpMethod::GET,
+                    HttpMethod::HEAD,
+                    HttpMethod::POST,
+                    HttpMethod::OPTIONS,
+                ],
+            ),
+            (
+                "*",
+                vec![
+                    HttpMethod::GET,
+                    HttpMethod::HEAD,
+                    HttpMethod::POST,
+                    HttpMethod::OPTIONS,
+                ],
+            ),
+            ("/missing", vec![]),
+        ];
+
+        for (path, expected) in test_cases {
+            assert_eq!(router.allowed_methods(path), expected, "{path}");
+        }
+    }
+}
@@ -1,347 +1,876 @@
+#[cfg(feature = "tokio")]
+use crate::async_server;
+#[cfg(all(unix, not(feature = "tokio")))]
+use crate::config::IoModel;
+use crate::config::{ListenAddr, SharedConfig};
+use crate::context::{Cancellation, RequestId};
+use crate::error_page::{self, ErrorContext, ErrorHandler, ErrorPage};
 use crate::errors::{Error, Result};
+#[cfg(all(unix, not(feature = "tokio")))]
+use crate::evented;
+use crate::forwarded;
+#[cfg(all(feature = "h2", not(feature = "tokio")))]
+use crate::h2;
+use crate::handlers;
+#[cfg(not(feature = "tokio"))]
+use crate::listener::Connection;
+use crate::listener::{Acceptors, Bound, Listener};
+use crate::log;
+use crate::middleware::{Middleware, Pipeline};
+#[cfg(not(feature = "tokio"))]
+use crate::record::Recorded;
+use crate::request::{HttpMethod, HttpRequest, HttpVersion};
+#[cfg(not(feature = "tokio"))]
+use crate::response::StatusCode;
+use crate::response::{HttpResponse, SendFile, Upgrade};
+use crate::router::Router;
+use crate::shutdown::ShutdownHandle;
+#[cfg(not(feature = "tokio"))]
+use crate::stream::{self, Hijacked, Stream, TimeoutStream};
+#[cfg(unix)]
+use crate::systemd;
+#[cfg(not(feature = "tokio"))]
 use crate::thread_pool::ThreadPool;
-use crate::Args;
-use bytes::{BufMut, Bytes, BytesMut};
-use flate2::write::GzEncoder;
-use flate2::Compression;
+use crate::tls;
+use crate::Config;
+#[cfg(not(feature = "tokio"))]
+use rustls::{ServerConnection, StreamOwned};
 use std::fs;
-use std::io::Read;
-use std::str::FromStr;
-use std::sync::Arc;
+use std::io;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+#[cfg(not(feature = "tokio"))]
 use std::{
-    collections::HashMap,
-    io::{BufRead, BufReader, Write},
-    net::{TcpListener, TcpStream},
+    io::{BufRead, BufReader},
+    thread,
 };
 
-#[derive(Debug, PartialEq, Eq)]
-#[allow(clippy::upper_case_acronyms)]
-pub enum HttpMethod {
-    GET,
-    DELETE,
-    POST,
-    PUT,
-    HEAD,
-    CONNECT,
-    OPTIONS,
-    TRACE,
-    PATCH,
+// fills in what the server knows about `req` once it arrived, the returned
+// flag is for the server to set when the client goes away
+pub(crate) fn prepare(
+    req: &mut HttpRequest,
+    peer_addr: Option<SocketAddr>,
+    conf: &Config,
+) -> Cancellation {
+    if conf.tls_cert.is_some() {
+        req.context_mut().set_scheme("https");
+    }
+    req.normalize_target();
+    forwarded::resolve(req, peer_addr, &conf.trusted_proxies);
+    let context = req.context_mut();
+    context.set_peer_addr(peer_addr);
+    context.set_deadline(conf.request_timeout.map(|timeout| Instant::now() + timeout));
+    context.cancellation().clone()
 }
 
-impl FromStr for HttpMethod {
-    type Err = Error;
-
-    fn from_str(value: &str) -> Result<Self> {
-        match value {
-            "GET" => Ok(Self::GET),
-            "DELETE" => Ok(Self::DELETE),
-            "POST" => Ok(Self::POST),
-            "PUT" => Ok(Self::PUT),
-            "HEAD" => Ok(Self::HEAD),
-            "CONNECT" => Ok(Self::CONNECT),
-            "OPTIONS" => Ok(Self::OPTIONS),
-            "TRACE" => Ok(Self::TRACE),
-            "PATCH" => Ok(Self::PATCH),
-            _ => Err(Error::InvalidMethod),
-        }
+// the id a client or a proxy in front already gave the request, when it
+// is short and plain enough to go into log lines and headers as it is
+fn inbound_request_id(req: &HttpRequest) -> Option<String> {
+    req.header("x-request-id")
+        .filter(|id| {
+            (1..=128).contains(&id.len())
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+        })
+        .map(str::to_owned)
+}
+
+// runs the pipeline for `req`, errors and panics in it become a 500
+pub(crate) fn handle(pipeline: &Pipeline, req: &mut HttpRequest) -> HttpResponse {
+    let request_id = inbound_request_id(req).unwrap_or_else(log::new_request_id);
+    req.context_mut()
+        .extensions_mut()
+        .insert(RequestId(request_id.clone()));
+
+    in_request_span(req, &request_id, |req| {
+        log::with_request_id(&request_id, || {
+            log::trace!(
+                "{} {} {}",
+                req.method().as_str(),
+                req.target(),
+                req.version().as_str()
+            );
+
+            let (response, error) =
+                match panic::catch_unwind(AssertUnwindSafe(|| pipeline.handle(req))) {
+                    Ok(Ok(response)) => (response, None),
+                    Ok(Err(e)) => {
+                        log::error!("Failed to handle request, error {}", e);
+                        (HttpResponse::internal_server_error(), Some(e))
+                    }
+                    Err(_) => {
+                        log::error!("Handler panicked while handling {}", req.target());
+                        (HttpResponse::internal_server_error(), None)
+                    }
+                };
+            let response = pipeline.render_error(response, Some(req), error.as_ref());
+
+            log::debug!(
+                "{} {} -> {}",
+                req.method().as_str(),
+                req.target(),
+                response.status().code()
+            );
+
+            response.header("X-Request-Id", request_id.as_str())
+        })
+    })
+}
+
+// runs `f` inside a span of the request, closed with its status and latency
+#[cfg(feature = "tracing")]
+fn in_request_span(
+    req: &mut HttpRequest,
+    request_id: &str,
+    f: impl FnOnce(&mut HttpRequest) -> HttpResponse,
+) -> HttpResponse {
+    let mut span = log::Span::new("request")
+        .field("request_id", request_id)
+        .field("method", req.method().as_str())
+        .field("path", req.path());
+
+    let response = span.in_scope(|| f(req));
+    span.record("status", response.status().code());
+    span.record("latency_ms", span.elapsed().as_millis());
+    response
+}
+
+#[cfg(not(feature = "tracing"))]
+fn in_request_span(
+    req: &mut HttpRequest,
+    _: &str,
+    f: impl FnOnce(&mut HttpRequest) -> HttpResponse,
+) -> HttpResponse {
+    f(req)
+}
+
+// the span the requests of a connection nest in
+#[cfg(feature = "tracing")]
+pub(crate) fn connection_span(peer_addr: Option<SocketAddr>) -> log::Span {
+    let mut span = log::Span::new("connection");
+    if let Some(peer_addr) = peer_addr {
+        span.record("peer", peer_addr);
     }
+    span
 }
 
-#[derive(Debug)]
-pub struct HttpRequest {
-    target: String,
-    method: HttpMethod,
-    headers: HashMap<String, String>,
-    body: Option<Vec<u8>>,
+// what becomes of a connection once a response went out on it
+pub(crate) enum AfterResponse {
+    KeepAlive,
+    Close,
+    Upgrade(Upgrade),
 }
 
-impl TryFrom<&TcpStream> for HttpRequest {
-    type Error = Error;
+pub(crate) fn respond<W: SendFile>(
+    pipeline: &Pipeline,
+    mut req: HttpRequest,
+    mut keep_alive: bool,
+    writer: &mut W,
+) -> io::Result<AfterResponse> {
+    let is_head = *req.method() == HttpMethod::HEAD;
+    let version = req.version();
+    let cancellation = req.context().cancellation().clone();
+
+    let mut response = handle(pipeline, &mut req);
+
+    // HTTP/1.0 clients cannot decode chunked bodies, an unsized one is sent
+    // as it comes and the connection closed to mark its end
+    if version == HttpVersion::Http10 && response.content_length().is_none() {
+        response = response.close_delimited();
+        keep_alive = false;
+    }
 
-    fn try_from(stream: &TcpStream) -> Result<Self> {
-        let mut buf_reader = BufReader::new(stream);
+    let upgrade = response.take_upgrade();
 
-        let mut lines = buf_reader.by_ref().lines();
+    let response = match (keep_alive, version) {
+        _ if upgrade.is_some() => response,
+        (false, _) => response.header("Connection", "close"),
+        (true, HttpVersion::Http10) => response.header("Connection", "keep-alive"),
+        (true, HttpVersion::Http11 | HttpVersion::Http2) => response,
+    };
 
-        if let Some(line) = lines.next() {
-            let request_line = line?;
+    let written = if is_head {
+        response.write_head_to(writer)
+    } else {
+        response.write_to(writer)
+    };
 
-            let request_line_split: Vec<&str> = request_line.split_whitespace().collect();
+    // a streamed body may still be produced elsewhere for a client that left
+    if written.is_err() {
+        cancellation.cancel();
+    }
+    written?;
 
-            let method = request_line_split
-                .first()
-                .ok_or(Error::InvalidRequest)
-                .and_then(|method_str| HttpMethod::from_str(method_str))?;
+    Ok(match upgrade {
+        Some(upgrade) => AfterResponse::Upgrade(upgrade),
+        None if keep_alive => AfterResponse::KeepAlive,
+        None => AfterResponse::Close,
+    })
+}
 
-            let request_target = request_line_split
-                .get(1)
-                .ok_or(Error::InvalidRequest)
-                .map(|rt| (*rt).to_owned())?;
+// turns a connection away while the server is at capacity
+pub(crate) fn overloaded() -> HttpResponse {
+    HttpResponse::service_unavailable()
+        .header("Retry-After", "1")
+        .header("Connection", "close")
+        .header("X-Request-Id", log::new_request_id())
+}
 
-            let mut headers: HashMap<String, String> = HashMap::new();
-            for line in lines {
-                let header_line = line?;
+// counts a connection as open until it is dropped
+pub(crate) struct ConnectionGuard(Arc<AtomicUsize>);
 
-                if header_line.trim().is_empty() {
-                    break;
-                }
+impl ConnectionGuard {
+    // None when `max` connections are open already
+    pub(crate) fn acquire(open: &Arc<AtomicUsize>, max: Option<usize>) -> Option<Self> {
+        let previous = open.fetch_add(1, Ordering::SeqCst);
+        if max.is_some_and(|max| previous >= max) {
+            open.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(ConnectionGuard(Arc::clone(open)))
+    }
+}
 
-                if let Some((key, value)) = header_line.split_once(':') {
-                    headers.insert(
-                        key.trim().to_lowercase().to_owned(),
-                        value.trim().to_owned(),
-                    );
-                } else {
-                    return Err(Error::InvalidRequest);
-                }
-            }
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
-            let maybe_body = if let Some(content_length_str) = headers.get("content-length") {
-                let content_length = content_length_str
-                    .parse::<usize>()
-                    .map_err(|_| Error::InvalidRequest)?;
+// maps a failure to read a request onto the response the client gets, the
+// connection is closed afterwards since the stream position is unknown
+pub(crate) fn error_response(error: &Error) -> HttpResponse {
+    HttpResponse::new(error_page::status_of(error))
+        .header("Connection", "close")
+        .header("X-Request-Id", log::new_request_id())
+}
 
-                let mut buffer = vec![0; content_length];
-                buf_reader.read_exact(&mut buffer)?;
+struct AcceptLoop {
+    listener: String,
+    index: usize,
+    accepted: Arc<AtomicU64>,
+}
 
-                if !buffer.is_empty() {
-                    Some(buffer)
-                } else {
-                    None
-                }
-            } else {
-                None
-            };
+// the connections each accept loop took, by the address it listens on and
+// its place among the loops sharing that listener
+#[derive(Clone, Default)]
+pub struct AcceptCounters(Arc<Mutex<Vec<AcceptLoop>>>);
+
+impl AcceptCounters {
+    pub(crate) fn add(&self, listener: &Bound, index: usize) -> Arc<AtomicU64> {
+        let accepted = Arc::new(AtomicU64::new(0));
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(AcceptLoop {
+                listener: listener.to_string(),
+                index,
+                accepted: Arc::clone(&accepted),
+            });
+        accepted
+    }
 
-            Ok(HttpRequest {
-                target: request_target,
-                method,
-                headers,
-                body: maybe_body,
+    pub fn counts(&self) -> Vec<(String, usize, u64)> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|acceptor| {
+                let accepted = acceptor.accepted.load(Ordering::Relaxed);
+                (acceptor.listener.clone(), acceptor.index, accepted)
             })
-        } else {
-            Err(Error::InvalidRequest)
-        }
+            .collect()
+    }
+}
+
+fn pipeline(
+    middleware: Vec<Box<dyn Middleware>>,
+    router: Router,
+    error_handler: Option<Box<ErrorHandler>>,
+    conf: &Config,
+    shared: SharedConfig,
+) -> Result<Pipeline> {
+    let error_handler = match error_handler {
+        Some(handler) => Some(handler),
+        None => error_page::from_config(conf)?,
+    };
+
+    let mut pipeline = Pipeline::new(middleware, router).config(shared);
+    if let Some(handler) = error_handler {
+        pipeline = pipeline.on_error(handler);
     }
+    Ok(pipeline)
 }
 
 pub struct Server {
-    addr: String,
-    conf: Args,
+    addrs: Vec<ListenAddr>,
+    conf: Config,
+    shared: SharedConfig,
+    router: Router,
+    middleware: Vec<Box<dyn Middleware>>,
+    error_handler: Option<Box<ErrorHandler>>,
+    shutdown: ShutdownHandle,
+    queue_depth: Arc<AtomicUsize>,
+    connections: Arc<AtomicUsize>,
+    accepted: AcceptCounters,
 }
 
 impl Server {
-    pub fn new(addr: String, conf: Args) -> Self {
-        Server { addr, conf }
+    pub fn new(addr: impl Into<ListenAddr>, conf: Config) -> Self {
+        let shared = SharedConfig::new(conf.clone());
+        let router = handlers::routes(&shared);
+        Server {
+            addrs: vec![addr.into()],
+            conf,
+            shared,
+            router,
+            middleware: Vec::new(),
+            error_handler: None,
+            shutdown: ShutdownHandle::default(),
+            queue_depth: Arc::new(AtomicUsize::new(0)),
+            connections: Arc::new(AtomicUsize::new(0)),
+            accepted: AcceptCounters::default(),
+        }
     }
 
-    fn compress_gzip(content: &str) -> Result<Vec<u8>> {
-        let mut e = GzEncoder::new(Vec::new(), Compression::default());
-        e.write_all(content.as_bytes())?;
-        e.finish().map_err(|e| e.into())
+    // one more address to accept connections on, served by the same
+    // pipeline and workers
+    pub fn also_listen(mut self, addr: ListenAddr) -> Self {
+        self.addrs.push(addr);
+        self
     }
 
-    fn handle_request(req: &HttpRequest, stream: &mut TcpStream, conf: &Args) -> Result<()> {
-        let response = match req {
-            HttpRequest {
-                target,
-                method: HttpMethod::GET,
-                headers: _,
-                body: _,
-            } if target == "/" => Bytes::from("HTTP/1.1 200 OK\r\n\r\n"),
-            HttpRequest {
-                target,
-                method: HttpMethod::POST,
-                headers: _,
-                body,
-            } if target.starts_with("/file") => {
-                if let Some(parent_dir) = &conf.directory {
-                    if let Some((_, file_name)) = &target[1..].split_once('/') {
-                        if !file_name.contains("..") {
-                            let file_path = parent_dir.join(file_name);
-
-                            if let Some(contents) = body {
-                                if let Ok(()) = fs::write(file_path, contents) {
-                                    Bytes::from("HTTP/1.1 201 Created\r\n\r\n")
-                                } else {
-                                    Bytes::from("HTTP/1.1 500 Internal Server Error\r\n\r\n")
-                                }
-                            } else {
-                                Bytes::from("HTTP/1.1 400 Bad Request\r\n\r\n")
-                            }
-                        } else {
-                            Bytes::from("HTTP/1.1 400 Bad Request\r\n\r\n")
-                        }
-                    } else {
-                        Bytes::from("HTTP/1.1 400 Bad Request\r\n\r\n")
-                    }
-                } else {
-                    Bytes::from("HTTP/1.1 503 Service Unavailable\r\n\r\n")
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    // replaces the empty body of 4xx and 5xx responses, takes precedence
+    // over `error_pages` and `problem_json`
+    pub fn on_error(
+        mut self,
+        handler: impl Fn(&ErrorContext<'_>) -> Option<ErrorPage> + Send + Sync + 'static,
+    ) -> Self {
+        self.error_handler = Some(Box::new(handler));
+        self
+    }
+
+    // swapping in a new config affects the routes of subsequent requests,
+    // listener settings such as the address or worker count stay as they are
+    pub fn config(&self) -> SharedConfig {
+        self.shared.clone()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    // number of accepted requests waiting for a free worker
+    pub fn queue_depth(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.queue_depth)
+    }
+
+    // number of open client connections
+    pub fn connections(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.connections)
+    }
+
+    // connections taken by each accept loop, filled in once listening
+    pub fn accept_counters(&self) -> AcceptCounters {
+        self.accepted.clone()
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    fn serve<S: Stream>(
+        stream: S,
+        pipeline: &Pipeline,
+        conf: &Config,
+        shutdown: &ShutdownHandle,
+    ) -> Result<()> {
+        let stream = Recorded::new(stream, conf.record.as_deref());
+        let stream = TimeoutStream::new(stream, conf.keep_alive_timeout, conf.write_timeout)?;
+
+        let mut reader = BufReader::new(stream);
+
+        loop {
+            // the parser consumes exactly one request, so pipelined ones stay
+            // buffered and are answered in order without waiting on the socket
+            reader.get_mut().set_read_timeout(conf.keep_alive_timeout);
+
+            match reader.fill_buf() {
+                Ok([]) => return Ok(()),
+                Ok(_) => (),
+                Err(e) if stream::is_timeout(&e) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+
+            reader.get_mut().set_read_timeout(conf.read_timeout);
+            reader
+                .get_mut()
+                .set_deadline(Some(Instant::now() + conf.header_timeout));
+
+            let head = HttpRequest::read_head(&mut reader, &conf.limits);
+
+            reader.get_mut().set_deadline(None);
+
+            let mut req = match head.and_then(|mut req| {
+                req.check_host()?;
+                req.read_body(&mut reader, &conf.limits)?;
+                Ok(req)
+            }) {
+                Ok(req) => req,
+                Err(Error::ConnectionClosed) => return Ok(()),
+                Err(e) => {
+                    let response = pipeline.render_error(error_response(&e), None, Some(&e));
+                    let unexpected = response.status() == StatusCode::InternalServerError;
+
+                    response.write_to(reader.get_mut())?;
+
+                    return if unexpected { Err(e) } else { Ok(()) };
+                }
+            };
+
+            prepare(&mut req, reader.get_ref().peer_addr(), conf);
+
+            let keep_alive = req.keep_alive() && !shutdown.is_requested();
+
+            #[cfg(feature = "h2")]
+            if let Some(settings) = h2::h2c_settings(&req).filter(|_| keep_alive) {
+                if let Some(socket) = reader.get_ref().get_ref().cleartext() {
+                    let socket = socket.try_clone()?;
+                    h2::switching_protocols().write_to(reader.get_mut())?;
+                    let buffered = reader.buffer().to_vec();
+                    return h2::serve_h2c(
+                        socket, buffered, req, settings, pipeline, conf, shutdown,
+                    );
                 }
             }
-            HttpRequest {
-                target,
-                method: HttpMethod::GET,
-                headers,
-                body: _,
-            } if target.starts_with("/file") => {
-                if let Some(parent_dir) = &conf.directory {
-                    if let Some((_, file_name)) = &target[1..].split_once('/') {
-                        let file_path = parent_dir.join(file_name);
-                        if let Ok(full_file_path) = file_path.canonicalize() {
-                            if full_file_path.starts_with(parent_dir) {
-                                if let Ok(contents) = fs::read_to_string(file_path) {
-                                    println!("sending file content {}", contents);
-                                    if let Some(encoding) = headers.get("accept-encoding") {
-                                        if encoding.contains("gzip") {
-                                            if let Ok(body) = Self::compress_gzip(&contents) {
-                                                let mut response_buf =
-                                                    BytesMut::with_capacity(4096);
-
-                                                response_buf.put(format!("HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", body.len()).as_bytes());
-                                                response_buf.put(&body[..]);
-
-                                                response_buf.freeze()
-                                            } else {
-                                                Bytes::from(
-                                                    "HTTP/1.1 500 Internal Server Error\r\n\r\n",
-                                                )
-                                            }
-                                        } else {
-                                            let mut response_buf = BytesMut::with_capacity(1024);
-
-                                            response_buf.put(format!("HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n", contents.len()).as_bytes());
-                                            response_buf.put(contents.as_bytes());
-
-                                            response_buf.freeze()
-                                        }
-                                    } else {
-                                        let mut response_buf = BytesMut::with_capacity(1024);
-
-                                        response_buf.put(format!("HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n", contents.len()).as_bytes());
-                                        response_buf.put(contents.as_bytes());
-
-                                        response_buf.freeze()
-                                    }
-                                } else {
-                                    Bytes::from("HTTP/1.1 500 Internal Server Error\r\n\r\n")
-                                }
-                            } else {
-                                Bytes::from("HTTP/1.1 400 Bad Request\r\n\r\n")
-                            }
-                        } else {
-                            Bytes::from("HTTP/1.1 404 Not Found\r\n\r\n")
-                        }
-                    } else {
-                        Bytes::from("HTTP/1.1 400 Bad Request\r\n\r\n")
-                    }
-                } else {
-                    Bytes::from("HTTP/1.1 503 Service Unavailable\r\n\r\n")
+
+            match respond(pipeline, req, keep_alive, reader.get_mut())? {
+                AfterResponse::Upgrade(upgrade) => {
+                    return Ok(upgrade.run(&mut Hijacked(&mut reader))?)
                 }
+                AfterResponse::Close => return Ok(()),
+                AfterResponse::KeepAlive => {}
             }
-            HttpRequest {
-                target,
-                method: HttpMethod::GET,
-                headers,
-                body: _,
-            } if target.starts_with("/echo") => {
-                if let Some((_, echo_str)) = &target[1..].split_once('/') {
-                    if let Some(encoding) = headers.get("accept-encoding") {
-                        if encoding.contains("gzip") {
-                            if let Ok(body) = Self::compress_gzip(echo_str) {
-                                let mut response_buf = BytesMut::with_capacity(4096);
-
-                                response_buf.put(format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", body.len()).as_bytes());
-                                response_buf.put(&body[..]);
-
-                                response_buf.freeze()
-                            } else {
-                                Bytes::from("HTTP/1.1 500 Internal Server Error\r\n\r\n")
-                            }
-                        } else {
-                            let mut response_buf = BytesMut::with_capacity(1024);
+        }
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    fn handle_connection(
+        connection: Connection,
+        tls_config: Option<&Arc<rustls::ServerConfig>>,
+        pipeline: &Pipeline,
+        conf: &Config,
+        shutdown: &ShutdownHandle,
+    ) -> Result<()> {
+        let stream = match connection {
+            Connection::Tcp(stream) => stream,
+            // HTTP/2 needs a socket that can be cloned for its writer
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                return match tls_config {
+                    Some(tls_config) => {
+                        let connection = ServerConnection::new(Arc::clone(tls_config))?;
+                        let stream = StreamOwned::new(connection, stream);
+                        Self::serve(stream, pipeline, conf, shutdown)
+                    }
+                    None => Self::serve(stream, pipeline, conf, shutdown),
+                };
+            }
+        };
+
+        if let Some(tls_config) = tls_config {
+            let connection = ServerConnection::new(Arc::clone(tls_config))?;
+            let stream = StreamOwned::new(connection, stream);
+
+            // connections negotiating `h2` are done once `serve_tls` returns
+            #[cfg(feature = "h2")]
+            let Some(stream) = h2::serve_tls(stream, pipeline, conf, shutdown)?
+            else {
+                return Ok(());
+            };
 
-                            response_buf.put(format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n", echo_str.len()).as_bytes());
-                            response_buf.put(echo_str.as_bytes());
+            Self::serve(stream, pipeline, conf, shutdown)
+        } else {
+            Self::serve(stream, pipeline, conf, shutdown)
+        }
+    }
 
-                            response_buf.freeze()
+    // runs the requests recorded in `dir` through the pipeline in the order
+    // they came in, no socket involved, and writes each response as it
+    // would have been sent
+    pub fn replay<W: SendFile>(self, dir: &Path, writer: &mut W) -> Result<()> {
+        let pipeline = pipeline(
+            self.middleware,
+            self.router,
+            self.error_handler,
+            &self.conf,
+            self.shared,
+        )?;
+
+        let mut requests = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "request")
+            {
+                requests.push(path);
+            }
+        }
+        requests.sort();
+
+        // each file holds what one connection sent, requests one after the
+        // other as they came over the wire
+        for path in requests {
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            writer.write_all(format!("==> {name} <==\r\n").as_bytes())?;
+
+            let raw = fs::read(&path)?;
+            let mut reader = &raw[..];
+            while !reader.is_empty() {
+                let limits = &self.conf.limits;
+                let mut req =
+                    match HttpRequest::read_head(&mut reader, limits).and_then(|mut req| {
+                        req.check_host()?;
+                        req.read_body(&mut reader, limits)?;
+                        Ok(req)
+                    }) {
+                        Ok(req) => req,
+                        Err(e) => {
+                            writer.write_all(
+                                format!("unreadable request, error {e:?}\r\n").as_bytes(),
+                            )?;
+                            break;
                         }
-                    } else {
-                        let mut response_buf = BytesMut::with_capacity(1024);
+                    };
+                prepare(&mut req, None, &self.conf);
+                let keep_alive = req.keep_alive();
+                respond(&pipeline, req, keep_alive, writer)?;
+                writer.write_all(b"\r\n")?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
 
-                        response_buf.put(format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n", echo_str.len()).as_bytes());
-                        response_buf.put(echo_str.as_bytes());
+    pub fn listen(self) -> Result<()> {
+        let tls_config = match (&self.conf.tls_cert, &self.conf.tls_key) {
+            (Some(cert), Some(key)) => Some(tls::load_config(cert, key)?),
+            (None, None) => None,
+            _ => return Err(Error::InvalidTlsConfig),
+        };
 
-                        response_buf.freeze()
-                    }
-                } else {
-                    Bytes::from("HTTP/1.1 400 Bad Request\r\n\r\n")
-                }
+        let pipeline = pipeline(
+            self.middleware,
+            self.router,
+            self.error_handler,
+            &self.conf,
+            self.shared,
+        )?;
+        if let Some(dir) = &self.conf.record {
+            fs::create_dir_all(dir)?;
+        }
+
+        #[cfg(feature = "tokio")]
+        let listen = async_server::listen;
+        #[cfg(all(unix, not(feature = "tokio")))]
+        let listen = match self.conf.io_model {
+            IoModel::Threads => Self::listen_blocking,
+            IoModel::Evented => evented::listen,
+        };
+        #[cfg(all(not(unix), not(feature = "tokio")))]
+        let listen = Self::listen_blocking;
+
+        // the sockets of a socket-activated unit take the place of the
+        // addresses
+        #[cfg(unix)]
+        let mut listeners = systemd::listeners()?;
+        #[cfg(not(unix))]
+        let mut listeners = Vec::new();
+        if listeners.is_empty() {
+            for addr in &self.addrs {
+                listeners.push(Listener::bind(addr, &self.conf.tcp)?);
             }
-            HttpRequest {
-                target,
-                method: HttpMethod::GET,
-                headers,
-                body: _,
-            } if target.starts_with("/user-agent") => {
-                if let Some(user_agent_header) = headers.get("user-agent") {
-                    if let Some(encoding) = headers.get("accept-encoding") {
-                        if encoding.contains("gzip") {
-                            if let Ok(body) = Self::compress_gzip(user_agent_header) {
-                                let mut response_buf = BytesMut::with_capacity(4096);
-
-                                response_buf.put(format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", body.len()).as_bytes());
-                                response_buf.put(&body[..]);
-
-                                response_buf.freeze()
-                            } else {
-                                Bytes::from("HTTP/1.1 500 Internal Server Error\r\n\r\n")
-                            }
-                        } else {
-                            let mut response_buf = BytesMut::with_capacity(1024);
+        }
+        // the event loop accepts on its own thread, whatever the setting
+        #[cfg(all(unix, not(feature = "tokio")))]
+        let acceptors = match self.conf.io_model {
+            IoModel::Threads => self.conf.acceptors,
+            IoModel::Evented => 1,
+        };
+        #[cfg(any(not(unix), feature = "tokio"))]
+        let acceptors = self.conf.acceptors;
+
+        let mut listeners_with_acceptors = Vec::with_capacity(listeners.len());
+        for listener in listeners {
+            let bound = listener.local_addr()?;
+            log::info!("Listening on {}", bound);
+            let accepted = (0..acceptors)
+                .map(|acceptor| {
+                    // each loop blocked in accept needs a connection to wake up
+                    self.shutdown.add_listener(bound.clone());
+                    self.accepted.add(&bound, acceptor)
+                })
+                .collect();
+            listeners_with_acceptors.push(Acceptors { listener, accepted });
+        }
+
+        #[cfg(unix)]
+        if let Err(e) = systemd::notify("READY=1") {
+            log::warning!("Failed to notify the service manager, error {}", e);
+        }
+
+        listen(
+            listeners_with_acceptors,
+            self.conf,
+            pipeline,
+            tls_config,
+            self.shutdown,
+            self.queue_depth,
+            self.connections,
+        )
+    }
+
+    // plain connections get a 503, tls ones are just closed since the
+    // handshake would have to happen on the accept thread
+    #[cfg(not(feature = "tokio"))]
+    fn reject(connection: Connection, tls: bool, pipeline: &Pipeline, conf: &Config) {
+        fn write<S: Stream>(mut stream: S, pipeline: &Pipeline, conf: &Config) {
+            let _ = stream.set_write_timeout(Some(conf.write_timeout));
+            let _ = pipeline
+                .render_error(overloaded(), None, None)
+                .write_to(&mut stream);
+        }
 
-                            response_buf.put(format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n", user_agent_header.len()).as_bytes());
-                            response_buf.put(user_agent_header.as_bytes());
+        if tls {
+            return;
+        }
+
+        match connection {
+            Connection::Tcp(stream) => write(stream, pipeline, conf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => write(stream, pipeline, conf),
+        }
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    fn listen_blocking(
+        listeners: Vec<Acceptors>,
+        conf: Config,
+        pipeline: Pipeline,
+        tls_config: Option<Arc<rustls::ServerConfig>>,
+        shutdown: ShutdownHandle,
+        queue_depth: Arc<AtomicUsize>,
+        connections: Arc<AtomicUsize>,
+    ) -> Result<()> {
+        if shutdown.is_requested() {
+            return Ok(());
+        }
+
+        let pool = ThreadPool::new(conf.workers, conf.backlog, queue_depth);
+
+        let conf = Arc::new(conf);
+        let pipeline = Arc::new(pipeline);
+
+        // accept loops on threads of their own, all handing connections to
+        // one pool
+        thread::scope(|scope| {
+            for Acceptors { listener, accepted } in &listeners {
+                for accepted in accepted {
+                    let (pool, conf, pipeline) = (&pool, &conf, &pipeline);
+                    let (tls_config, shutdown) = (&tls_config, &shutdown);
+                    let connections = &connections;
+                    scope.spawn(move || loop {
+                        let stream = listener.accept(&conf.tcp);
 
-                            response_buf.freeze()
+                        if shutdown.is_requested() {
+                            break;
+                        }
+                        if stream.is_ok() {
+                            accepted.fetch_add(1, Ordering::Relaxed);
                         }
-                    } else {
-                        let mut response_buf = BytesMut::with_capacity(1024);
 
-                        response_buf.put(format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n", user_agent_header.len()).as_bytes());
-                        response_buf.put(user_agent_header.as_bytes());
+                        let guard = ConnectionGuard::acquire(connections, conf.max_connections);
+                        if guard.is_none() || pool.is_saturated() {
+                            if let Ok(stream) = stream {
+                                Self::reject(stream, tls_config.is_some(), pipeline, conf);
+                            }
+                            continue;
+                        }
 
-                        response_buf.freeze()
-                    }
-                } else {
-                    Bytes::from("HTTP/1.1 400 Bad Request\r\n\r\n")
+                        let conf = Arc::clone(conf);
+                        let shutdown = shutdown.clone();
+                        let pipeline = Arc::clone(pipeline);
+                        let tls_config = tls_config.clone();
+                        pool.execute(move || {
+                            let _guard = guard;
+                            match stream.map_err(|e| e.into()).and_then(|stream| {
+                                #[cfg(feature = "tracing")]
+                                let span = connection_span(match &stream {
+                                    Connection::Tcp(stream) => stream.peer_addr().ok(),
+                                    #[cfg(unix)]
+                                    Connection::Unix(_) => None,
+                                });
+                                let handle = || {
+                                    Self::handle_connection(
+                                        stream,
+                                        tls_config.as_ref(),
+                                        &pipeline,
+                                        &conf,
+                                        &shutdown,
+                                    )
+                                };
+                                #[cfg(feature = "tracing")]
+                                return span.in_scope(handle);
+                                #[cfg(not(feature = "tracing"))]
+                                handle()
+                            }) {
+                                Ok(_) => (),
+                                Err(e) => log::warning!("Failed to handle connection, error {}", e),
+                            }
+                        });
+                    });
                 }
             }
-            _ => Bytes::from("HTTP/1.1 404 Not Found\r\n\r\n"),
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn listen_should_return_after_shutdown_is_requested() {
+        let server = Server::new("127.0.0.1:0".to_string(), Config::default());
+        let handle = server.shutdown_handle();
+
+        let listener = thread::spawn(move || server.listen());
+
+        handle.shutdown();
+
+        assert!(listener.join().unwrap().is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listen_should_serve_every_address() {
+        use std::os::unix::net::UnixStream;
+        use std::time::Duration;
+
+        let socket = std::env::temp_dir().join(format!("http-server-{}.sock", std::process::id()));
+        let conf = Config {
+            acceptors: 2,
+            ..Config::default()
         };
+        let server = Server::new("127.0.0.1:0".to_string(), conf)
+            .also_listen(ListenAddr::Unix(socket.clone()));
+        let handle = server.shutdown_handle();
+        let accepted = server.accept_counters();
+        let listener = thread::spawn(move || server.listen());
+
+        let addr = loop {
+            if let (Some(addr), true) = (handle.local_addr(), socket.exists()) {
+                break addr;
+            }
+            assert!(!listener.is_finished(), "server exited before binding");
+            thread::sleep(Duration::from_millis(5));
+        };
+
+        fn get<S: io::Read + io::Write>(mut stream: S) -> String {
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        }
+
+        assert!(get(std::net::TcpStream::connect(addr).unwrap()).starts_with("HTTP/1.1 200"));
+        assert!(get(UnixStream::connect(&socket).unwrap()).starts_with("HTTP/1.1 200"));
+
+        let counts = accepted.counts();
+        assert_eq!(counts.len(), 4);
+        for listener in [addr.to_string(), format!("unix:{}", socket.display())] {
+            let total: u64 = counts
+                .iter()
+                .filter(|(bound, _, _)| *bound == listener)
+                .map(|(_, _, accepted)| accepted)
+                .sum();
+            assert_eq!(total, 1, "{listener}");
+        }
 
-        stream.write_all(&response[..]).map_err(Error::Io)
+        handle.shutdown();
+        assert!(listener.join().unwrap().is_ok());
+        assert!(!socket.exists());
     }
 
-    pub fn listen(&self) -> Result<()> {
-        let listener = TcpListener::bind(&self.addr)?;
-        let pool = ThreadPool::new(8);
-
-        let conf = Arc::new(self.conf.clone());
-
-        for stream in listener.incoming() {
-            let conf = Arc::clone(&conf);
-            pool.execute(move || {
-                match stream.map_err(|e| e.into()).and_then(|mut stream| {
-                    HttpRequest::try_from(&stream)
-                        .and_then(|req| Self::handle_request(&req, &mut stream, &conf))
-                }) {
-                    Ok(_) => (),
-                    Err(e) => eprintln!("Failed to handle request, error {}", e),
-                }
-            });
+    #[test]
+    fn replay_should_answer_recorded_requests_in_order() {
+        let dir = std::env::temp_dir().join(format!("http-server-replay-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let recorded = [
+            ("1-a.request", "GET /echo/abc HTTP/1.1\r\nhost: x\r\n\r\n"),
+            ("1-a.response", "not a request"),
+            ("2-b.request", "POST /echo/x HTTP/1.0\r\n\r\nignored"),
+            ("3-c.request", "nonsense"),
+            (
+                "4-d.request",
+                "GET /echo/one HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 1\r\nx\r\n0\r\n\r\nGET /echo/two HTTP/1.1\r\nHost: x\r\n\r\n",
+            ),
+        ];
+        for (name, contents) in recorded {
+            fs::write(dir.join(name), contents).unwrap();
         }
 
-        Ok(())
+        let mut out = Vec::new();
+        Server::new("127.0.0.1:0".to_string(), Config::default())
+            .replay(&dir, &mut out)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let sections: Vec<(&str, &str)> = out
+            .split("==> ")
+            .skip(1)
+            .filter_map(|section| section.split_once(" <==\r\n"))
+            .collect();
+        let names: Vec<&str> = sections.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["1-a", "2-b", "3-c", "4-d"]);
+        assert!(
+            sections[0].1.starts_with("HTTP/1.1 200 OK\r\n") && sections[0].1.ends_with("abc\r\n"),
+            "{out}"
+        );
+        assert!(
+            sections[1]
+                .1
+                .starts_with("HTTP/1.1 405 Method Not Allowed\r\n"),
+            "{out}"
+        );
+        assert!(sections[2].1.starts_with("unreadable request"), "{out}");
+        // a connection's requests one after the other, bodies still framed
+        assert_eq!(
+            sections[3].1.matches("HTTP/1.1 200 OK\r\n").count(),
+            2,
+            "{out}"
+        );
+        assert!(
+            sections[3].1.contains("\r\n\r\none") && sections[3].1.ends_with("two\r\n"),
+            "{out}"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,220 @@
+use crate::errors::Result;
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+const COOKIE_NAME: &str = "sid";
+const ID_LEN: usize = 16;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Default)]
+pub struct Session {
+    id: Option<String>,
+    data: HashMap<String, String>,
+}
+
+impl Session {
+    pub fn is_new(&self) -> bool {
+        self.id.is_none()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.data.get(key).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.data.insert(key.into(), value.into());
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.data.remove(key)
+    }
+}
+
+struct Entry {
+    data: HashMap<String, String>,
+    expires: Instant,
+}
+
+struct Sessions {
+    entries: HashMap<String, Entry>,
+    last_sweep: Instant,
+}
+
+impl Sessions {
+    fn sweep(&mut self, now: Instant) {
+        if now.duration_since(self.last_sweep) >= SWEEP_INTERVAL {
+            self.entries.retain(|_, entry| entry.expires > now);
+            self.last_sweep = now;
+        }
+    }
+}
+
+// keeps session data in memory and hands the client a cookie of the form
+// `<id>.<hmac of id>`, so ids cannot be guessed or forged
+pub struct SessionStore {
+    key: hmac::Key,
+    ttl: Duration,
+    rng: SystemRandom,
+    sessions: Mutex<Sessions>,
+}
+
+impl SessionStore {
+    // without a secret a random one is used and cookies stop being valid
+    // once the process exits, which the data does as well
+    pub fn new(secret: Option<&[u8]>, ttl: Duration) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let key = match secret {
+            Some(secret) => hmac::Key::new(hmac::HMAC_SHA256, secret),
+            None => hmac::Key::generate(hmac::HMAC_SHA256, &rng)?,
+        };
+
+        Ok(SessionStore {
+            key,
+            ttl,
+            rng,
+            sessions: Mutex::new(Sessions {
+                entries: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Sessions> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn sign(&self, id: &str) -> String {
+        URL_SAFE_NO_PAD.encode(hmac::sign(&self.key, id.as_bytes()))
+    }
+
+    fn verify<'a>(&self, cookie: &'a str) -> Option<&'a str> {
+        let (id, signature) = cookie.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        hmac::verify(&self.key, id.as_bytes(), &signature).ok()?;
+        Some(id)
+    }
+
+    // the session named by the request cookie, or a new empty one when the
+    // cookie is missing, forged or expired
+    pub fn load(&self, req: &HttpRequest) -> Session {
+        let now = Instant::now();
+        let mut sessions = self.lock();
+        sessions.sweep(now);
+
+        cookie(req, COOKIE_NAME)
+            .and_then(|value| self.verify(value))
+            .and_then(|id| {
+                let entry = sessions
+                    .entries
+                    .get(id)
+                    .filter(|entry| entry.expires > now)?;
+                Some(Session {
+                    id: Some(id.to_owned()),
+                    data: entry.data.clone(),
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    // stores the session and (re)sets its cookie, every save extends the ttl
+    pub fn save(&self, session: Session, response: HttpResponse) -> Result<HttpResponse> {
+        let id = match session.id {
+            Some(id) => id,
+            None => {
+                let mut bytes = [0; ID_LEN];
+                self.rng.fill(&mut bytes)?;
+                URL_SAFE_NO_PAD.encode(bytes)
+            }
+        };
+
+        let cookie = format!(
+            "{}={}.{}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+            COOKIE_NAME,
+            id,
+            self.sign(&id),
+            self.ttl.as_secs()
+        );
+
+        self.lock().entries.insert(
+            id,
+            Entry {
+                data: session.data,
+                expires: Instant::now() + self.ttl,
+            },
+        );
+
+        Ok(response.add_header("Set-Cookie", cookie))
+    }
+}
+
+fn cookie<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get_all("cookie")
+        .flat_map(|header| header.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::request::RequestLimits;
+
+    fn request(cookie: &str) -> HttpRequest {
+        let raw = format!("GET / HTTP/1.1\r\nCookie: {cookie}\r\n\r\n");
+        HttpRequest::read_head(&mut raw.as_bytes(), &RequestLimits::default()).unwrap()
+    }
+
+    #[test]
+    fn load_should_only_accept_signed_live_sessions() {
+        let store = SessionStore::new(Some(b"secret"), Duration::from_secs(60)).unwrap();
+
+        let mut session = store.load(&request(""));
+        assert!(session.is_new());
+        session.insert("user", "alice");
+
+        let response = store.save(session, HttpResponse::ok()).unwrap();
+        let set_cookie = response.get_header("set-cookie").unwrap();
+        let value = set_cookie
+            .split(';')
+            .next()
+            .and_then(|pair| pair.strip_prefix("sid="))
+            .unwrap();
+        let (id, signature) = value.split_once('.').unwrap();
+
+        let other = SessionStore::new(Some(b"other"), Duration::from_secs(60)).unwrap();
+        let expired = SessionStore::new(Some(b"secret"), Duration::ZERO).unwrap();
+        let mut old = expired.load(&request(""));
+        old.insert("user", "bob");
+        let old_cookie = expired
+            .save(old, HttpResponse::ok())
+            .unwrap()
+            .get_header("set-cookie")
+            .map(|cookie| cookie.split(';').next().unwrap().to_owned())
+            .unwrap();
+
+        let test_cases = vec![
+            (&store, format!("a=1; sid={value}"), Some("alice")),
+            (&store, format!("sid={id}.{}", &signature[1..]), None),
+            (&store, format!("sid={id}"), None),
+            (&other, format!("sid={value}"), None),
+            (&expired, old_cookie, None),
+        ];
+
+        for (store, cookie, expected) in test_cases {
+            assert_eq!(
+                store.load(&request(&cookie)).get("user"),
+                expected,
+                "{cookie}"
+            );
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::errors::Result;
+use crate::listener::Bound;
+use crate::log;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+    listeners: Arc<Mutex<Vec<Bound>>>,
+}
+
+impl ShutdownHandle {
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    // the first TCP address listened on
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.bound().iter().find_map(|bound| match bound {
+            Bound::Tcp(addr) => Some(*addr),
+            Bound::Unix(_) => None,
+        })
+    }
+
+    fn bound(&self) -> MutexGuard<'_, Vec<Bound>> {
+        self.listeners.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn add_listener(&self, bound: Bound) {
+        self.bound().push(bound);
+    }
+
+    pub fn shutdown(&self) {
+        if self.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        // wake up the accept loops so they notice the flag
+        let listeners = self.bound().clone();
+        for bound in listeners {
+            bound.wake();
+        }
+    }
+}
+
+pub fn shutdown_on_signals(handle: ShutdownHandle) -> Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            log::info!("Received signal {signal}, shutting down");
+            handle.shutdown();
+        }
+    });
+
+    Ok(())
+}
+
+pub fn reload_on_sighup(reload: impl Fn() -> Result<()> + Send + 'static) -> Result<()> {
+    let mut signals = Signals::new([SIGHUP])?;
+
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            log::info!("Received SIGHUP, reloading configuration");
+            match reload() {
+                Ok(()) => log::info!("Configuration reloaded"),
+                Err(e) => log::error!("Failed to reload configuration, error {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
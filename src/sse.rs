@@ -0,0 +1,161 @@
+use crate::log;
+use crate::response::HttpResponse;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread;
+use std::time::Duration;
+
+const KEEP_ALIVE_COMMENT: &[u8] = b": keep-alive\n\n";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    retry: Option<Duration>,
+    data: String,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Self {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    fn encode(&self) -> String {
+        let mut out = String::new();
+
+        // field values cannot span lines, only data may repeat
+        if let Some(id) = &self.id {
+            out.push_str(&format!("id: {}\n", id.replace(['\r', '\n'], "")));
+        }
+        if let Some(event) = &self.event {
+            out.push_str(&format!("event: {}\n", event.replace(['\r', '\n'], "")));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.lines() {
+            out.push_str(&format!("data: {}\n", line));
+        }
+        if self.data.is_empty() {
+            out.push_str("data: \n");
+        }
+
+        out.push('\n');
+        out
+    }
+}
+
+// handed to the producer, `send` fails once the client has gone away
+pub struct EventSender(SyncSender<Event>);
+
+impl EventSender {
+    pub fn send(&self, event: Event) -> io::Result<()> {
+        self.0
+            .send(event)
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+struct EventReader {
+    events: Receiver<Event>,
+    keep_alive: Duration,
+    pending: Vec<u8>,
+    position: usize,
+}
+
+impl Read for EventReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.pending.len() {
+            self.pending = match self.events.recv_timeout(self.keep_alive) {
+                Ok(event) => event.encode().into_bytes(),
+                Err(RecvTimeoutError::Timeout) => KEEP_ALIVE_COMMENT.to_vec(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.position = 0;
+        }
+
+        let n = buf.len().min(self.pending.len() - self.position);
+        buf[..n].copy_from_slice(&self.pending[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+// runs `producer` on its own thread and streams whatever it sends, with a
+// comment every `keep_alive` so idle streams notice a dead client. Once the
+// client disconnects the next `send` fails and the producer should return.
+pub fn event_stream(
+    keep_alive: Duration,
+    producer: impl FnOnce(EventSender) + Send + 'static,
+) -> HttpResponse {
+    let (sender, events) = mpsc::sync_channel(16);
+
+    if let Err(e) = thread::Builder::new()
+        .name("sse".to_string())
+        .spawn(move || producer(EventSender(sender)))
+    {
+        log::error!("Failed to start event stream, error {}", e);
+        return HttpResponse::internal_server_error();
+    }
+
+    HttpResponse::ok()
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .chunked_body(EventReader {
+            events,
+            keep_alive,
+            pending: Vec::new(),
+            position: 0,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn event_reader_should_frame_events_and_send_keep_alives() {
+        let (sender, events) = mpsc::sync_channel(1);
+        let mut reader = EventReader {
+            events,
+            keep_alive: Duration::from_millis(10),
+            pending: Vec::new(),
+            position: 0,
+        };
+
+        let producer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            sender
+                .send(Event::new("a\nb").id("1").event("stats"))
+                .unwrap();
+        });
+
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        producer.join().unwrap();
+
+        assert!(out.starts_with(": keep-alive\n\n"), "{out:?}");
+        assert!(
+            out.ends_with("id: 1\nevent: stats\ndata: a\ndata: b\n\n"),
+            "{out:?}"
+        );
+    }
+}
@@ -0,0 +1,552 @@
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, RwLock};
+use std::time::SystemTime;
+
+// tells apart the temporary files of writes running at the same time
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub is_dir: bool,
+}
+
+impl From<&fs::Metadata> for Metadata {
+    fn from(metadata: &fs::Metadata) -> Self {
+        Metadata {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            is_dir: metadata.is_dir(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+// what a backend hands out to read, files stay files so they can be sent
+// without a userspace copy
+pub enum Content {
+    File(File),
+    Bytes(Cursor<Bytes>),
+}
+
+impl Read for Content {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Content::File(file) => file.read(buf),
+            Content::Bytes(bytes) => bytes.read(buf),
+        }
+    }
+}
+
+impl Seek for Content {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Content::File(file) => file.seek(pos),
+            Content::Bytes(bytes) => bytes.seek(pos),
+        }
+    }
+}
+
+// where the file endpoints keep their files, paths are relative and '/'
+// separated with "" for the top, as returned by `normalize`
+//
+// a missing path fails with NotFound and one the backend will not touch,
+// such as a directory where a file is expected, with PermissionDenied
+pub trait Storage: Send + Sync {
+    fn metadata(&self, path: &str) -> io::Result<Metadata>;
+
+    fn get(&self, path: &str) -> io::Result<(Content, Metadata)>;
+
+    // replaces any file at `path`, directories above it are created
+    fn put(&self, path: &str, contents: &[u8]) -> io::Result<()>;
+
+    // writes `contents` at `offset` into the file at `path`, which is created
+    // when missing and otherwise keeps the bytes around the written ones
+    fn write_at(&self, path: &str, offset: u64, contents: &[u8]) -> io::Result<()>;
+
+    // moves a file over whatever is at `to`, readers see one or the other
+    fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+
+    fn delete(&self, path: &str) -> io::Result<()>;
+
+    // the entries of a directory in no particular order
+    fn list(&self, path: &str) -> io::Result<Vec<Entry>>;
+}
+
+// `path` without empty or `.` components, None when it tries to leave the
+// top with `..` or an absolute path
+pub fn normalize(path: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
+
+// one writer per path at a time, held across checking a precondition and
+// the write it guards
+#[derive(Default)]
+pub struct PathLocks {
+    held: Mutex<HashSet<String>>,
+    released: Condvar,
+}
+
+pub struct PathLock<'a> {
+    locks: &'a PathLocks,
+    path: String,
+}
+
+impl PathLocks {
+    pub fn new() -> Self {
+        PathLocks::default()
+    }
+
+    pub fn lock(&self, path: &str) -> PathLock<'_> {
+        let mut held = self.held.lock().unwrap_or_else(|e| e.into_inner());
+        while held.contains(path) {
+            held = self.released.wait(held).unwrap_or_else(|e| e.into_inner());
+        }
+        held.insert(path.to_owned());
+        PathLock {
+            locks: self,
+            path: path.to_owned(),
+        }
+    }
+}
+
+impl Drop for PathLock<'_> {
+    fn drop(&mut self) {
+        self.locks
+            .held
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.path);
+        self.locks.released.notify_all();
+    }
+}
+
+fn forbidden(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, reason)
+}
+
+// a directory on disk, symlinks are followed as long as they stay inside it
+pub struct FsStorage {
+    root: PathBuf,
+}
+
+impl FsStorage {
+    pub fn open(root: &Path) -> io::Result<Self> {
+        Ok(FsStorage {
+            root: root.canonicalize()?,
+        })
+    }
+
+    fn join(&self, path: &str) -> io::Result<PathBuf> {
+        let path = normalize(path).ok_or_else(|| forbidden("path leaves the root"))?;
+        Ok(self.root.join(path))
+    }
+
+    // an existing path with its symlinks resolved
+    fn existing(&self, path: &str) -> io::Result<PathBuf> {
+        let path = self
+            .join(path)?
+            .canonicalize()
+            .map_err(|_| io::Error::from(io::ErrorKind::NotFound))?;
+        if path.starts_with(&self.root) {
+            Ok(path)
+        } else {
+            Err(forbidden("path leaves the root"))
+        }
+    }
+
+    // a path a file may be written to along with its parent, which is
+    // created when missing
+    fn writable(&self, path: &str) -> io::Result<(PathBuf, PathBuf)> {
+        let path = self.join(path)?;
+        let Some(parent) = path.parent().filter(|_| path != self.root) else {
+            return Err(forbidden("is a directory"));
+        };
+        if !self.within_root(parent) {
+            return Err(forbidden("path leaves the root"));
+        }
+        if path.is_dir() {
+            return Err(forbidden("is a directory"));
+        }
+
+        let parent = parent.to_path_buf();
+        fs::create_dir_all(&parent)?;
+        Ok((path, parent))
+    }
+
+    // symlinks may only be followed when they stay inside the root, so check
+    // the deepest existing ancestor before creating anything below it
+    fn within_root(&self, dir: &Path) -> bool {
+        dir.ancestors()
+            .find(|ancestor| ancestor.exists())
+            .and_then(|ancestor| ancestor.canonicalize().ok())
+            .is_some_and(|ancestor| ancestor.starts_with(&self.root))
+    }
+}
+
+impl Storage for FsStorage {
+    fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        Ok(Metadata::from(&fs::metadata(self.existing(path)?)?))
+    }
+
+    fn get(&self, path: &str) -> io::Result<(Content, Metadata)> {
+        let file = File::open(self.existing(path)?)?;
+        let metadata = Metadata::from(&file.metadata()?);
+        if metadata.is_dir {
+            return Err(forbidden("is a directory"));
+        }
+        Ok((Content::File(file), metadata))
+    }
+
+    fn put(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        let (path, parent) = self.writable(path)?;
+
+        // written next to the file and renamed over it once complete, so
+        // readers never see part of an upload
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = parent.join(format!(
+            ".{}.{}-{}.tmp",
+            name,
+            process::id(),
+            TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = File::create_new(&temp).and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        });
+        match written.and_then(|()| fs::rename(&temp, &path)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                Err(e)
+            }
+        }
+    }
+
+    fn write_at(&self, path: &str, offset: u64, contents: &[u8]) -> io::Result<()> {
+        let (path, _) = self.writable(path)?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(contents)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        if self.existing(from)?.is_dir() {
+            return Err(forbidden("is a directory"));
+        }
+        let (to, _) = self.writable(to)?;
+        fs::rename(self.join(from)?, to)
+    }
+
+    // a symlink is removed rather than what it points to
+    fn delete(&self, path: &str) -> io::Result<()> {
+        if self.existing(path)?.is_dir() {
+            return Err(forbidden("is a directory"));
+        }
+        fs::remove_file(self.join(path)?)
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<Entry>> {
+        Ok(fs::read_dir(self.existing(path)?)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                Some(Entry {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    metadata: Metadata::from(&entry.metadata().ok()?),
+                })
+            })
+            .collect())
+    }
+}
+
+const DIRECTORY: Metadata = Metadata {
+    len: 0,
+    modified: None,
+    is_dir: true,
+};
+
+// files kept in memory for tests and throwaway servers, a directory exists
+// for as long as there are files below it
+#[derive(Default)]
+pub struct MemoryStorage {
+    files: RwLock<BTreeMap<String, (Bytes, SystemTime)>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+fn file_metadata((contents, modified): &(Bytes, SystemTime)) -> Metadata {
+    Metadata {
+        len: contents.len() as u64,
+        modified: Some(*modified),
+        is_dir: false,
+    }
+}
+
+// the files below directory `path`, by their path relative to it
+fn below<'a>(
+    files: &'a BTreeMap<String, (Bytes, SystemTime)>,
+    path: &str,
+) -> impl Iterator<Item = (&'a str, &'a (Bytes, SystemTime))> {
+    let prefix = if path.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", path)
+    };
+    files
+        .range(prefix.clone()..)
+        .map_while(move |(name, file)| Some((name.strip_prefix(&prefix)?, file)))
+}
+
+fn lookup(files: &BTreeMap<String, (Bytes, SystemTime)>, path: &str) -> io::Result<Metadata> {
+    match files.get(path) {
+        Some(file) => Ok(file_metadata(file)),
+        None if path.is_empty() || below(files, path).next().is_some() => Ok(DIRECTORY),
+        None => Err(io::ErrorKind::NotFound.into()),
+    }
+}
+
+// a file may go to `path` when there is no directory there and none of its
+// ancestors is a file
+fn writable(files: &BTreeMap<String, (Bytes, SystemTime)>, path: &str) -> io::Result<()> {
+    if lookup(files, path).is_ok_and(|metadata| metadata.is_dir) {
+        return Err(forbidden("is a directory"));
+    }
+    let mut ancestors = path.match_indices('/').map(|(end, _)| &path[..end]);
+    if ancestors.any(|ancestor| files.contains_key(ancestor)) {
+        return Err(forbidden("not a directory"));
+    }
+    Ok(())
+}
+
+impl Storage for MemoryStorage {
+    fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        let path = normalize(path).ok_or_else(|| forbidden("path leaves the root"))?;
+        lookup(&self.files.read().unwrap_or_else(|e| e.into_inner()), &path)
+    }
+
+    fn get(&self, path: &str) -> io::Result<(Content, Metadata)> {
+        let path = normalize(path).ok_or_else(|| forbidden("path leaves the root"))?;
+        let files = self.files.read().unwrap_or_else(|e| e.into_inner());
+        match files.get(&path) {
+            Some(file) => Ok((
+                Content::Bytes(Cursor::new(file.0.clone())),
+                file_metadata(file),
+            )),
+            None => lookup(&files, &path).and(Err(forbidden("is a directory"))),
+        }
+    }
+
+    fn put(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        let path = normalize(path).ok_or_else(|| forbidden("path leaves the root"))?;
+        let mut files = self.files.write().unwrap_or_else(|e| e.into_inner());
+        writable(&files, &path)?;
+
+        files.insert(path, (Bytes::copy_from_slice(contents), SystemTime::now()));
+        Ok(())
+    }
+
+    fn write_at(&self, path: &str, offset: u64, contents: &[u8]) -> io::Result<()> {
+        let path = normalize(path).ok_or_else(|| forbidden("path leaves the root"))?;
+        let offset = usize::try_from(offset).map_err(|_| io::ErrorKind::OutOfMemory)?;
+        let mut files = self.files.write().unwrap_or_else(|e| e.into_inner());
+        writable(&files, &path)?;
+
+        let mut data = files
+            .get(&path)
+            .map(|(data, _)| data.to_vec())
+            .unwrap_or_default();
+        let end = offset
+            .checked_add(contents.len())
+            .ok_or(io::ErrorKind::OutOfMemory)?;
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset..end].copy_from_slice(contents);
+        files.insert(path, (Bytes::from(data), SystemTime::now()));
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let from = normalize(from).ok_or_else(|| forbidden("path leaves the root"))?;
+        let to = normalize(to).ok_or_else(|| forbidden("path leaves the root"))?;
+        let mut files = self.files.write().unwrap_or_else(|e| e.into_inner());
+        writable(&files, &to)?;
+
+        match files.remove(&from) {
+            Some((data, _)) => {
+                files.insert(to, (data, SystemTime::now()));
+                Ok(())
+            }
+            None => lookup(&files, &from).and(Err(forbidden("is a directory"))),
+        }
+    }
+
+    fn delete(&self, path: &str) -> io::Result<()> {
+        let path = normalize(path).ok_or_else(|| forbidden("path leaves the root"))?;
+        let mut files = self.files.write().unwrap_or_else(|e| e.into_inner());
+        match files.remove(&path) {
+            Some(_) => Ok(()),
+            None => lookup(&files, &path).and(Err(forbidden("is a directory"))),
+        }
+    }
+
+    fn list(&self, path: &str) -> io::Result<Vec<Entry>> {
+        let path = normalize(path).ok_or_else(|| forbidden("path leaves the root"))?;
+        let files = self.files.read().unwrap_or_else(|e| e.into_inner());
+        if !lookup(&files, &path)?.is_dir {
+            return Err(forbidden("not a directory"));
+        }
+
+        let mut entries: Vec<Entry> = Vec::new();
+        for (name, file) in below(&files, &path) {
+            let entry = match name.split_once('/') {
+                Some((dir, _)) => Entry {
+                    name: dir.to_owned(),
+                    metadata: DIRECTORY,
+                },
+                None => Entry {
+                    name: name.to_owned(),
+                    metadata: file_metadata(file),
+                },
+            };
+            // the files of a subdirectory are next to each other
+            if entries.last() != Some(&entry) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(storage: &dyn Storage) {
+        storage.put("a/b.txt", b"hello").unwrap();
+        storage.put("./a//c.txt", b"hi").unwrap();
+        storage.put("a/d/e.txt", b"").unwrap();
+
+        let (mut content, metadata) = storage.get("a/b.txt").unwrap();
+        let mut read = String::new();
+        content.read_to_string(&mut read).unwrap();
+        assert_eq!(read, "hello");
+        assert_eq!(metadata.len, 5);
+
+        let mut names: Vec<_> = storage
+            .list("a")
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.name, entry.metadata.is_dir))
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                ("b.txt".to_string(), false),
+                ("c.txt".to_string(), false),
+                ("d".to_string(), true)
+            ]
+        );
+        assert!(storage.metadata("").unwrap().is_dir);
+
+        let test_cases = vec![
+            ("missing.txt", io::ErrorKind::NotFound),
+            ("../secret", io::ErrorKind::PermissionDenied),
+            ("/etc/passwd", io::ErrorKind::PermissionDenied),
+            ("a", io::ErrorKind::PermissionDenied),
+        ];
+        for (path, kind) in test_cases {
+            assert_eq!(
+                storage.get(path).err().map(|e| e.kind()),
+                Some(kind),
+                "{path}"
+            );
+        }
+        assert_eq!(
+            storage.put("a", b"").unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        storage.write_at("a/f.part", 3, b"def").unwrap();
+        storage.write_at("a/f.part", 0, b"abc").unwrap();
+        assert!(storage.write_at("a/f.part", u64::MAX, b"ghi").is_err());
+        storage.rename("a/f.part", "f.txt").unwrap();
+        let (mut content, _) = storage.get("f.txt").unwrap();
+        let mut read = String::new();
+        content.read_to_string(&mut read).unwrap();
+        assert_eq!(read, "abcdef");
+        assert_eq!(
+            storage.metadata("a/f.part").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        storage.delete("a/b.txt").unwrap();
+        assert_eq!(
+            storage.metadata("a/b.txt").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn path_locks_should_let_one_writer_in_at_a_time() {
+        let locks = PathLocks::new();
+        let inside = AtomicU64::new(0);
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        let _lock = locks.lock("a/b.txt");
+                        assert_eq!(inside.fetch_add(1, Ordering::SeqCst), 0);
+                        std::thread::yield_now();
+                        inside.fetch_sub(1, Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+
+        let _a = locks.lock("a/b.txt");
+        let _b = locks.lock("a/c.txt");
+    }
+
+    #[test]
+    fn storages_should_keep_files_inside_their_root() {
+        let dir = std::env::temp_dir().join(format!("storage-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        check(&FsStorage::open(&dir).unwrap());
+        check(&MemoryStorage::new());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+use crate::response::SendFile;
+use rustls::{ServerConnection, StreamOwned};
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, IoSlice, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(feature = "h2")]
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+pub trait Stream: Read + Write + SendFile + Send {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    // the socket of a connection without tls, which alone may switch to h2c
+    #[cfg(feature = "h2")]
+    fn cleartext(&self) -> Option<&TcpStream> {
+        None
+    }
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    #[cfg(feature = "h2")]
+    fn cleartext(&self) -> Option<&TcpStream> {
+        Some(self)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    // the peer of a unix socket has no network address
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Err(ErrorKind::Unsupported.into())
+    }
+}
+
+// tls has to encrypt every byte in userspace anyway
+impl<S: Read + Write> SendFile for StreamOwned<ServerConnection, S> {}
+
+impl<S: Stream> Stream for StreamOwned<ServerConnection, S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_write_timeout(timeout)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.peer_addr()
+    }
+}
+
+pub struct TimeoutStream<S: Stream> {
+    inner: S,
+    read_timeout: Duration,
+    deadline: Option<Instant>,
+    applied_timeout: Option<Duration>,
+}
+
+impl<S: Stream> TimeoutStream<S> {
+    pub fn new(inner: S, read_timeout: Duration, write_timeout: Duration) -> io::Result<Self> {
+        inner.set_write_timeout(Some(write_timeout))?;
+
+        Ok(TimeoutStream {
+            inner,
+            read_timeout,
+            deadline: None,
+            applied_timeout: None,
+        })
+    }
+
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.inner.peer_addr().ok()
+    }
+
+    #[cfg(feature = "h2")]
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: Stream> Read for TimeoutStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(io::Error::from(ErrorKind::TimedOut));
+                }
+                remaining.min(self.read_timeout)
+            }
+            None => self.read_timeout,
+        };
+
+        if self.applied_timeout != Some(timeout) {
+            self.inner.set_read_timeout(Some(timeout))?;
+            self.applied_timeout = Some(timeout);
+        }
+
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Stream> Write for TimeoutStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Stream> SendFile for TimeoutStream<S> {
+    fn send_file(&mut self, file: &mut File, length: u64) -> io::Result<u64> {
+        self.inner.send_file(file, length)
+    }
+}
+
+// a connection taken over after a 101, reading through the buffer so bytes
+// that arrived with the handshake are not lost
+pub struct Hijacked<'a, S: Stream>(pub &'a mut BufReader<TimeoutStream<S>>);
+
+impl<S: Stream> Read for Hijacked<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<S: Stream> Write for Hijacked<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.get_mut().write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.0.get_mut().write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.get_mut().flush()
+    }
+}
+
+pub fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+// a tls connection used from two threads, one reading and one writing, the
+// session state is shared while each side has its own handle on the socket
+#[cfg(feature = "h2")]
+pub struct TlsReader {
+    tls: Arc<Mutex<ServerConnection>>,
+    socket: TcpStream,
+}
+
+#[cfg(feature = "h2")]
+pub struct TlsWriter {
+    tls: Arc<Mutex<ServerConnection>>,
+    socket: TcpStream,
+}
+
+#[cfg(feature = "h2")]
+pub fn split_tls(tls: ServerConnection, socket: TcpStream) -> io::Result<(TlsReader, TlsWriter)> {
+    let tls = Arc::new(Mutex::new(tls));
+    let writer = TlsWriter {
+        tls: Arc::clone(&tls),
+        socket: socket.try_clone()?,
+    };
+    Ok((TlsReader { tls, socket }, writer))
+}
+
+#[cfg(feature = "h2")]
+fn lock(tls: &Mutex<ServerConnection>) -> MutexGuard<'_, ServerConnection> {
+    tls.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(feature = "h2")]
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut received = [0; 16 * 1024];
+
+        loop {
+            match lock(&self.tls).reader().read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+                result => return result,
+            }
+
+            // the socket is read without holding the lock, so the writer
+            // is not held up while waiting for the peer
+            let n = self.socket.read(&mut received)?;
+            if n == 0 {
+                return Ok(0);
+            }
+
+            let mut tls = lock(&self.tls);
+            let mut received = &received[..n];
+            while !received.is_empty() {
+                tls.read_tls(&mut received)?;
+                tls.process_new_packets().map_err(io::Error::other)?;
+            }
+            while tls.wants_write() {
+                tls.write_tls(&mut self.socket)?;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "h2")]
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut tls = lock(&self.tls);
+        let n = tls.writer().write(buf)?;
+        while tls.wants_write() {
+            tls.write_tls(&mut self.socket)?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use crate::listener::{Listener, SocketFile};
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::path::PathBuf;
+use std::process;
+
+// the first descriptor passed along, see sd_listen_fds(3)
+const LISTEN_FDS_START: RawFd = 3;
+
+// how many sockets were passed to this very process, a LISTEN_PID naming
+// another one means they were meant for a parent
+fn listen_fds(pid: Option<&str>, fds: Option<&str>) -> usize {
+    let pid = pid.and_then(|pid| pid.parse::<u32>().ok());
+    match fds.and_then(|fds| fds.parse().ok()) {
+        Some(fds) if pid == Some(process::id()) => fds,
+        _ => 0,
+    }
+}
+
+// takes over a listening socket whatever its family, one that is not TCP
+// has no network address
+fn adopt(fd: RawFd) -> io::Result<Listener> {
+    // SAFETY: the descriptors from LISTEN_FDS_START on are handed to this
+    // process to own and nothing else here refers to them
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    if listener.local_addr().is_ok() {
+        return Ok(Listener::Tcp(listener));
+    }
+
+    let listener = UnixListener::from(OwnedFd::from(listener));
+    let path = listener
+        .local_addr()?
+        .as_pathname()
+        .map_or_else(PathBuf::new, PathBuf::from);
+    // the socket file belongs to the unit, not to this process
+    let file = SocketFile {
+        path,
+        created: false,
+    };
+    Ok(Listener::Unix(listener, file))
+}
+
+// the sockets of a socket-activated unit, none when the server was not
+// started by one
+pub(crate) fn listeners() -> io::Result<Vec<Listener>> {
+    let fds = listen_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+    );
+
+    // so nothing started from here takes them for its own
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    (0..fds)
+        .map(|index| adopt(LISTEN_FDS_START + index as RawFd))
+        .collect()
+}
+
+fn send(socket: &OsStr, state: &str) -> io::Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            datagram.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+// tells the service manager about a change of state such as READY=1, see
+// sd_notify(3), nothing to do when it is not listening
+pub(crate) fn notify(state: &str) -> io::Result<()> {
+    match env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => send(&socket, state),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::fd::IntoRawFd;
+
+    #[test]
+    fn systemd_sockets_should_be_taken_over_by_their_process() {
+        let pid = process::id().to_string();
+        let test_cases = vec![
+            (Some(pid.as_str()), Some("2"), 2),
+            (Some("1"), Some("2"), 0),
+            (None, Some("2"), 0),
+            (Some(pid.as_str()), None, 0),
+            (Some(pid.as_str()), Some("two"), 0),
+        ];
+
+        for (pid, fds, expected) in test_cases {
+            assert_eq!(listen_fds(pid, fds), expected, "{pid:?} {fds:?}");
+        }
+
+        let dir = env::temp_dir().join(format!("http-server-systemd-{}", process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        assert!(matches!(
+            adopt(tcp.into_raw_fd()).unwrap(),
+            Listener::Tcp(listener) if listener.local_addr().unwrap() == addr
+        ));
+
+        let path = dir.join("http.sock");
+        let unix = UnixListener::bind(&path).unwrap();
+        assert!(matches!(
+            adopt(unix.into_raw_fd()).unwrap(),
+            Listener::Unix(_, file) if file.path == path && !file.created
+        ));
+        assert!(path.exists());
+
+        let notify = dir.join("notify.sock");
+        let receiver = UnixDatagram::bind(&notify).unwrap();
+        send(notify.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0; 16];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+    }
+}
@@ -1,32 +1,69 @@
+use crate::log;
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
 };
 
-#[allow(dead_code)]
 pub struct ThreadPool {
     workers: Vec<Worker>,
-    sender: mpsc::Sender<Job>,
+    sender: Option<mpsc::SyncSender<Message>>,
+    queued: Arc<AtomicUsize>,
+    backlog: usize,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+enum Message {
+    NewJob(Job),
+    Terminate,
+}
+
 impl ThreadPool {
-    pub fn new(size: usize) -> ThreadPool {
+    pub fn new(size: usize, backlog: usize, queued: Arc<AtomicUsize>) -> ThreadPool {
         // todo maybe change to return Result
         assert!(size > 0);
+        assert!(backlog > 0);
 
-        let (sender, receiver) = mpsc::channel();
+        let (sender, receiver) = mpsc::sync_channel(backlog);
 
         let receiver = Arc::new(Mutex::new(receiver));
 
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&queued)));
+        }
+
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+            queued,
+            backlog,
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            for _ in &self.workers {
+                let _ = sender.send(Message::Terminate);
+            }
+        }
+
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                if thread.join().is_err() {
+                    log::error!("Worker {} panicked while shutting down", worker.id);
+                }
+            }
         }
+    }
 
-        ThreadPool { workers, sender }
+    pub fn is_saturated(&self) -> bool {
+        self.queued.load(Ordering::SeqCst) >= self.backlog
     }
 
     pub fn execute<F>(&self, f: F)
@@ -35,28 +72,71 @@ impl ThreadPool {
     {
         let job = Box::new(f);
 
-        self.sender
-            .send(job)
-            .unwrap_or_else(|e| eprintln!("failed to add given job to queue: {}", e));
+        if let Some(sender) = &self.sender {
+            self.queued.fetch_add(1, Ordering::SeqCst);
+            sender
+                .send(Message::NewJob(job))
+                .unwrap_or_else(|e| log::error!("failed to add given job to queue: {}", e));
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
     }
 }
 
-#[allow(dead_code)]
 struct Worker {
     id: usize,
-    thread: thread::JoinHandle<()>,
+    thread: Option<thread::JoinHandle<()>>,
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+        queued: Arc<AtomicUsize>,
+    ) -> Worker {
         let thread = thread::spawn(move || loop {
-            let job = receiver.lock().unwrap().recv().unwrap();
+            let message = receiver.lock().unwrap().recv();
+
+            match message {
+                Ok(Message::NewJob(job)) => {
+                    queued.fetch_sub(1, Ordering::SeqCst);
 
-            println!("Worker {id} got a job; executing.");
+                    log::trace!("Worker {id} got a job; executing.");
 
-            job();
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        log::error!("Worker {id} recovered from a panicking job");
+                    }
+                }
+                Ok(Message::Terminate) | Err(_) => break,
+            }
         });
 
-        Worker { id, thread }
+        Worker {
+            id,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn worker_should_survive_a_panicking_job() {
+        let mut pool = ThreadPool::new(1, 4, Arc::new(AtomicUsize::new(0)));
+        let (sender, receiver) = channel();
+
+        pool.execute(|| panic!("boom"));
+        pool.execute(move || sender.send(42).unwrap());
+
+        assert_eq!(receiver.recv().unwrap(), 42);
+
+        pool.shutdown();
     }
 }
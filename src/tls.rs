@@ -0,0 +1,27 @@
+use crate::errors::Result;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use std::path::Path;
+use std::sync::Arc;
+
+pub fn load_config(cert_path: &Path, key_path: &Path) -> Result<Arc<ServerConfig>> {
+    let certs =
+        CertificateDer::pem_file_iter(cert_path)?.collect::<std::result::Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(key_path)?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    #[cfg(all(feature = "h2", not(feature = "tokio")))]
+    let config = {
+        let mut config = config;
+        config.alpn_protocols = vec![crate::h2::ALPN.to_vec(), b"http/1.1".to_vec()];
+        config
+    };
+
+    Ok(Arc::new(config))
+}
@@ -0,0 +1,384 @@
+use crate::request::{HttpMethod, HttpRequest, HttpVersion};
+use crate::response::{HttpResponse, StatusCode, Upgraded};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use std::io::{self, ErrorKind, Read, Write};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_BIG: u16 = 1009;
+
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.trim().as_bytes());
+    sha1.update(GUID.as_bytes());
+    STANDARD.encode(sha1.finalize())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    fn is_control(&self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+impl Frame {
+    fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Frame {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+
+    // reads one client frame, which has to be masked
+    pub fn read_from<R: Read + ?Sized>(reader: &mut R, max_payload: usize) -> io::Result<Frame> {
+        let mut head = [0u8; 2];
+        reader.read_exact(&mut head[..1])?;
+
+        // a timeout halfway through a frame cannot be resumed
+        let mut read_rest = |buf: &mut [u8]| {
+            reader.read_exact(buf).map_err(|e| match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                    io::Error::new(ErrorKind::UnexpectedEof, "frame truncated")
+                }
+                _ => e,
+            })
+        };
+        read_rest(&mut head[1..])?;
+
+        let fin = head[0] & 0x80 != 0;
+        if head[0] & 0x70 != 0 {
+            return Err(protocol_error("reserved bits set"));
+        }
+        let opcode =
+            Opcode::from_u8(head[0] & 0x0F).ok_or_else(|| protocol_error("unknown opcode"))?;
+
+        if head[1] & 0x80 == 0 {
+            return Err(protocol_error("client frame not masked"));
+        }
+
+        let length = match head[1] & 0x7F {
+            126 => {
+                let mut length = [0u8; 2];
+                read_rest(&mut length)?;
+                u64::from(u16::from_be_bytes(length))
+            }
+            127 => {
+                let mut length = [0u8; 8];
+                read_rest(&mut length)?;
+                u64::from_be_bytes(length)
+            }
+            length => u64::from(length),
+        };
+
+        if opcode.is_control() && (!fin || length > 125) {
+            return Err(protocol_error("invalid control frame"));
+        }
+        if length > max_payload as u64 {
+            return Err(io::Error::new(ErrorKind::OutOfMemory, "frame too large"));
+        }
+
+        let mut mask = [0u8; 4];
+        read_rest(&mut mask)?;
+
+        let mut payload = vec![0u8; length as usize];
+        read_rest(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok(Frame {
+            fin,
+            opcode,
+            payload,
+        })
+    }
+
+    // server frames are never masked
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        let mut out = Vec::with_capacity(self.payload.len() + 10);
+        out.push(if self.fin { 0x80 } else { 0 } | self.opcode.as_u8());
+
+        match self.payload.len() {
+            length if length < 126 => out.push(length as u8),
+            length if length <= usize::from(u16::MAX) => {
+                out.push(126);
+                out.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                out.push(127);
+                out.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+
+        out.extend_from_slice(&self.payload);
+        writer.write_all(&out)?;
+        writer.flush()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+pub struct WebSocket<'a> {
+    conn: &'a mut dyn Upgraded,
+    closed: bool,
+}
+
+impl WebSocket<'_> {
+    // returns the next data message, control frames are answered here and
+    // `None` means the peer closed the connection
+    pub fn recv(&mut self) -> io::Result<Option<Message>> {
+        let mut fragments: Option<(Opcode, Vec<u8>)> = None;
+
+        loop {
+            let frame = match Frame::read_from(self.conn, MAX_MESSAGE_SIZE) {
+                Ok(frame) => frame,
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    return self.fail(CLOSE_PROTOCOL_ERROR, e)
+                }
+                Err(e) if e.kind() == ErrorKind::OutOfMemory => return self.fail(CLOSE_TOO_BIG, e),
+                Err(e) => return Err(e),
+            };
+
+            match frame.opcode {
+                Opcode::Ping => Frame::new(Opcode::Pong, frame.payload).write_to(self.conn)?,
+                Opcode::Pong => (),
+                Opcode::Close => {
+                    if !self.closed {
+                        self.closed = true;
+                        let code = frame
+                            .payload
+                            .get(..2)
+                            .map_or(CLOSE_NORMAL, |code| u16::from_be_bytes([code[0], code[1]]));
+                        Frame::new(Opcode::Close, code.to_be_bytes().to_vec())
+                            .write_to(self.conn)?;
+                    }
+                    return Ok(None);
+                }
+                Opcode::Continuation => {
+                    let Some((_, buffer)) = &mut fragments else {
+                        return self.fail(
+                            CLOSE_PROTOCOL_ERROR,
+                            protocol_error("unexpected continuation"),
+                        );
+                    };
+                    if buffer.len() + frame.payload.len() > MAX_MESSAGE_SIZE {
+                        return self.fail(
+                            CLOSE_TOO_BIG,
+                            io::Error::new(ErrorKind::OutOfMemory, "message too large"),
+                        );
+                    }
+                    buffer.extend_from_slice(&frame.payload);
+                }
+                Opcode::Text | Opcode::Binary => {
+                    if fragments.is_some() {
+                        return self
+                            .fail(CLOSE_PROTOCOL_ERROR, protocol_error("interleaved message"));
+                    }
+                    fragments = Some((frame.opcode, frame.payload));
+                }
+            }
+
+            if frame.fin && !frame.opcode.is_control() {
+                if let Some((opcode, payload)) = fragments.take() {
+                    return match opcode {
+                        Opcode::Text => match String::from_utf8(payload) {
+                            Ok(text) => Ok(Some(Message::Text(text))),
+                            Err(e) => self.fail(
+                                CLOSE_INVALID_DATA,
+                                io::Error::new(ErrorKind::InvalidData, e),
+                            ),
+                        },
+                        _ => Ok(Some(Message::Binary(payload))),
+                    };
+                }
+            }
+        }
+    }
+
+    pub fn send(&mut self, message: Message) -> io::Result<()> {
+        match message {
+            Message::Text(text) => Frame::new(Opcode::Text, text.into_bytes()),
+            Message::Binary(data) => Frame::new(Opcode::Binary, data),
+        }
+        .write_to(self.conn)
+    }
+
+    pub fn ping(&mut self) -> io::Result<()> {
+        Frame::new(Opcode::Ping, Vec::new()).write_to(self.conn)
+    }
+
+    pub fn close(&mut self, code: u16) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        Frame::new(Opcode::Close, code.to_be_bytes().to_vec()).write_to(self.conn)
+    }
+
+    fn fail<T>(&mut self, code: u16, error: io::Error) -> io::Result<T> {
+        let _ = self.close(code);
+        Err(error)
+    }
+}
+
+fn has_token(value: Option<&str>, token: &str) -> bool {
+    value.is_some_and(|value| {
+        value
+            .split(',')
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    })
+}
+
+// answers the opening handshake and hands the connection to `handler`
+pub fn upgrade(
+    req: &HttpRequest,
+    handler: impl FnOnce(WebSocket<'_>) -> io::Result<()> + Send + 'static,
+) -> HttpResponse {
+    let is_handshake = *req.method() == HttpMethod::GET
+        && req.version() == HttpVersion::Http11
+        && has_token(req.header("upgrade"), "websocket")
+        && has_token(req.header("connection"), "upgrade");
+
+    let Some(key) = req.header("sec-websocket-key").filter(|_| is_handshake) else {
+        return HttpResponse::bad_request();
+    };
+
+    if req.header("sec-websocket-version").map(str::trim) != Some("13") {
+        return HttpResponse::new(StatusCode::BadRequest).header("Sec-WebSocket-Version", "13");
+    }
+
+    HttpResponse::new(StatusCode::SwitchingProtocols)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", accept_key(key))
+        .upgrade(move |conn| {
+            handler(WebSocket {
+                conn,
+                closed: false,
+            })
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut out = vec![first, 0x80 | payload.len() as u8];
+        out.extend_from_slice(&mask);
+        out.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        out
+    }
+
+    #[test]
+    fn accept_key_should_match_rfc_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn recv_should_reassemble_messages_and_answer_control_frames() {
+        let mut input = Vec::new();
+        input.extend(masked(0x01, b"hel"));
+        input.extend(masked(0x89, b"p"));
+        input.extend(masked(0x80, b"lo"));
+        input.extend(masked(0x82, &[1, 2]));
+        input.extend(masked(0x88, &1000u16.to_be_bytes()));
+
+        let mut conn = io::Cursor::new(input);
+        let mut output = Vec::new();
+        let mut duplex = Duplex(&mut conn, &mut output);
+        let mut ws = WebSocket {
+            conn: &mut duplex,
+            closed: false,
+        };
+
+        let test_cases = vec![
+            Some(Message::Text("hello".to_string())),
+            Some(Message::Binary(vec![1, 2])),
+            None,
+        ];
+
+        for expected in test_cases {
+            assert_eq!(ws.recv().unwrap(), expected);
+        }
+
+        // pong for the ping, then the close echo
+        assert_eq!(output, [0x8A, 1, b'p', 0x88, 2, 0x03, 0xE8]);
+    }
+
+    struct Duplex<'a>(&'a mut io::Cursor<Vec<u8>>, &'a mut Vec<u8>);
+
+    impl Read for Duplex<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Duplex<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,210 @@
+#![allow(dead_code)]
+
+use codecrafters_http_server::middleware::Compression;
+use codecrafters_http_server::shutdown::ShutdownHandle;
+use codecrafters_http_server::{Config, Result, Server};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use std::{env, fs, process};
+
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("http-server-test-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub struct TestServer {
+    addr: SocketAddr,
+    shutdown: ShutdownHandle,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl TestServer {
+    pub fn start(args: Config) -> Self {
+        Self::start_with(args, |server| server)
+    }
+
+    // `configure` adds to the server what Config has no say over
+    pub fn start_with(args: Config, configure: impl FnOnce(Server) -> Server) -> Self {
+        let args = Config {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 0,
+            ..args
+        };
+
+        let server = configure(
+            Server::new(SocketAddr::new(args.address, args.port).to_string(), args)
+                .with(Compression::default()),
+        );
+        let shutdown = server.shutdown_handle();
+        let thread = thread::spawn(move || server.listen());
+
+        let addr = loop {
+            if let Some(addr) = shutdown.local_addr() {
+                break addr;
+            }
+            assert!(!thread.is_finished(), "server exited before binding");
+            thread::sleep(Duration::from_millis(5));
+        };
+
+        TestServer {
+            addr,
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn get(&self, path: &str, headers: &[(&str, &str)]) -> Response {
+        send(self.addr, "GET", path, headers, b"")
+    }
+
+    pub fn post(&self, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Response {
+        send(self.addr, "POST", path, headers, body)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(|v| v.as_str())
+    }
+}
+
+pub fn send(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Response {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let mut request = format!("{method} {path} HTTP/1.1\r\nConnection: close\r\n");
+    if !headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("host"))
+    {
+        request.push_str(&format!("Host: {addr}\r\n"));
+    }
+    for (name, value) in headers {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if !body.is_empty() {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes()).unwrap();
+    stream.write_all(body).unwrap();
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+
+    parse_response(&raw)
+}
+
+fn parse_response(raw: &[u8]) -> Response {
+    let head_end = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .expect("response head is not terminated");
+
+    let head = String::from_utf8(raw[..head_end].to_vec()).unwrap();
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .expect("invalid status line");
+
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_owned()))
+        .collect();
+
+    let rest = &raw[head_end + 4..];
+    let body = if headers
+        .get("transfer-encoding")
+        .is_some_and(|te| te.eq_ignore_ascii_case("chunked"))
+    {
+        decode_chunked(rest)
+    } else {
+        rest.to_vec()
+    };
+
+    Response {
+        status,
+        headers,
+        body,
+    }
+}
+
+// splits back-to-back responses, each one needs a Content-Length
+pub fn parse_responses(mut raw: &[u8]) -> Vec<Response> {
+    let mut responses = Vec::new();
+
+    while !raw.is_empty() {
+        let head_end = raw
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("response head is not terminated")
+            + 4;
+        let head = parse_response(&raw[..head_end]);
+        let length: usize = head
+            .header("content-length")
+            .and_then(|length| length.parse().ok())
+            .expect("response has no content length");
+
+        responses.push(parse_response(&raw[..head_end + length]));
+        raw = &raw[head_end + length..];
+    }
+
+    responses
+}
+
+fn decode_chunked(mut raw: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+
+    loop {
+        let line_end = raw.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size_line = std::str::from_utf8(&raw[..line_end]).unwrap();
+        let size = usize::from_str_radix(size_line.split(';').next().unwrap().trim(), 16).unwrap();
+
+        if size == 0 {
+            return body;
+        }
+
+        let start = line_end + 2;
+        body.extend_from_slice(&raw[start..start + size]);
+        raw = &raw[start + size + 2..];
+    }
+}
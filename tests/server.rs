@@ -0,0 +1,1192 @@
+mod common;
+
+use codecrafters_http_server::config::{IoModel, StorageKind, UploadRules};
+use codecrafters_http_server::middleware::{Auth, Cache};
+use codecrafters_http_server::precompress;
+use codecrafters_http_server::request::{HttpMethod, RequestLimits};
+use codecrafters_http_server::response::DEFAULT_SERVER_HEADER;
+use codecrafters_http_server::Config;
+use common::TestServer;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn server_should_answer_basic_routes() {
+    let server = TestServer::start(Config::default());
+
+    let test_cases = vec![
+        ("/", vec![], 200, ""),
+        ("/echo/abc", vec![], 200, "abc"),
+        (
+            "/user-agent",
+            vec![("User-Agent", "test/1.0")],
+            200,
+            "test/1.0",
+        ),
+        ("/missing", vec![], 404, ""),
+    ];
+
+    for (path, headers, status, body) in test_cases {
+        let response = server.get(path, &headers);
+        assert_eq!(response.status, status, "{path}");
+        assert_eq!(response.body, body.as_bytes(), "{path}");
+    }
+}
+
+#[test]
+fn files_should_round_trip_through_post_and_get() {
+    let dir = common::temp_dir("files");
+    let server = TestServer::start(Config {
+        directory: Some(dir.clone()),
+        ..Config::default()
+    });
+
+    let created = server.post("/files/nested/a.txt", &[], b"hello");
+    assert_eq!(created.status, 201);
+    assert_eq!(fs::read(dir.join("nested/a.txt")).unwrap(), b"hello");
+
+    let fetched = server.get("/files/nested/a.txt", &[]);
+    assert_eq!(fetched.status, 200);
+    assert_eq!(
+        fetched.header("content-type"),
+        Some("text/plain; charset=utf-8")
+    );
+    assert_eq!(fetched.body, b"hello");
+
+    assert_eq!(server.get("/files/missing.txt", &[]).status, 404);
+    // dot segments are resolved before routing, so this asks for `/secret`
+    assert_eq!(server.get("/files/../secret", &[]).status, 404);
+
+    let deleted = common::send(server.addr(), "DELETE", "/files/nested/a.txt", &[], b"");
+    assert_eq!(deleted.status, 204);
+    assert!(!dir.join("nested/a.txt").exists());
+    assert_eq!(server.get("/files/nested/a.txt", &[]).status, 404);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn protected_paths_should_stay_protected_however_they_are_spelled() {
+    let dir = common::temp_dir("protected");
+    fs::write(dir.join("a.txt"), b"original").unwrap();
+    let server = TestServer::start_with(
+        Config {
+            directory: Some(dir.clone()),
+            ..Config::default()
+        },
+        |server| {
+            server.with(
+                Auth::new("files")
+                    .bearer("s3cret")
+                    .protect(&[HttpMethod::PUT, HttpMethod::DELETE], "/files/"),
+            )
+        },
+    );
+
+    let test_cases = vec![
+        ("PUT", "/files/a.txt"),
+        ("PUT", "//files/a.txt"),
+        ("PUT", "/files//a.txt"),
+        ("PUT", "/./files/a.txt"),
+        ("PUT", "/x/../files/a.txt"),
+        ("DELETE", "//files/a.txt"),
+    ];
+
+    for (method, path) in test_cases {
+        let response = common::send(server.addr(), method, path, &[], b"changed");
+        assert_eq!(response.status, 401, "{method} {path}");
+    }
+    assert_eq!(fs::read(dir.join("a.txt")).unwrap(), b"original");
+
+    let authorized = [("Authorization", "Bearer s3cret")];
+    let response = common::send(
+        server.addr(),
+        "PUT",
+        "//files/a.txt",
+        &authorized,
+        b"changed",
+    );
+    assert_eq!(response.status, 204);
+    assert_eq!(fs::read(dir.join("a.txt")).unwrap(), b"changed");
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn files_should_be_kept_in_memory_without_a_directory() {
+    let server = TestServer::start(Config {
+        storage: StorageKind::Memory,
+        enable_dir_listing: true,
+        ..Config::default()
+    });
+
+    assert_eq!(server.get("/files/notes/a.txt", &[]).status, 404);
+    assert_eq!(server.post("/files/notes/a.txt", &[], b"hello").status, 201);
+    assert_eq!(server.post("/files/notes/b.txt", &[], b"hi").status, 201);
+
+    let fetched = server.get("/files/notes/a.txt", &[("Range", "bytes=1-3")]);
+    assert_eq!(fetched.status, 206);
+    assert_eq!(fetched.body, b"ell");
+
+    let listed = server.get("/files/", &[("Accept", "text/plain")]);
+    assert_eq!(listed.status, 200);
+    assert_eq!(listed.body, b"notes/\n");
+    let listed = server.get("/files/notes", &[("Accept", "text/plain")]);
+    assert_eq!(listed.body, b"a.txt\nb.txt\n");
+
+    let deleted = common::send(server.addr(), "DELETE", "/files/notes/a.txt", &[], b"");
+    assert_eq!(deleted.status, 204);
+    assert_eq!(server.get("/files/notes/a.txt", &[]).status, 404);
+    assert_eq!(server.post("/files/notes", &[], b"x").status, 403);
+}
+
+#[test]
+fn uploads_should_follow_the_configured_rules() {
+    let server = TestServer::start(Config {
+        storage: StorageKind::Memory,
+        uploads: UploadRules {
+            extensions: vec!["txt".to_string(), ".md".to_string()],
+            content_types: vec!["text/*".to_string()],
+            max_files_per_dir: Some(2),
+        },
+        ..Config::default()
+    });
+    let text = [("Content-Type", "text/plain; charset=utf-8")];
+
+    let test_cases = vec![
+        ("/files/a.txt", &text[..], 201),
+        ("/files/b.exe", &text[..], 403),
+        (
+            "/files/b.MD",
+            &[("Content-Type", "application/json")][..],
+            415,
+        ),
+        ("/files/b.md", &[][..], 415),
+        ("/files/b.md", &[("Content-Type", "text/markdown")][..], 201),
+        ("/files/a.txt", &text[..], 201),
+        ("/files/c.txt", &text[..], 403),
+        ("/files/sub/c.txt", &text[..], 201),
+    ];
+
+    for (path, headers, status) in test_cases {
+        assert_eq!(server.post(path, headers, b"x").status, status, "{path}");
+    }
+
+    let body = b"--XX\r\nContent-Disposition: form-data; name=\"f\"; filename=\"d.txt\"\r\nContent-Type: image/png\r\n\r\nx\r\n--XX--\r\n";
+    let uploaded = server.post(
+        "/files/sub",
+        &[("Content-Type", "multipart/form-data; boundary=XX")],
+        body,
+    );
+    assert_eq!(uploaded.status, 415);
+}
+
+#[test]
+fn uploads_should_be_checked_against_their_digest() {
+    let server = TestServer::start(Config {
+        storage: StorageKind::Memory,
+        ..Config::default()
+    });
+    let sha256 = "sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:";
+
+    let test_cases = vec![
+        (vec![("Content-Digest", sha256)], 201),
+        (vec![("Content-Digest", "sha-256=:AAAA:")], 400),
+        (vec![("Content-Digest", "sha-256=abc")], 400),
+        (vec![("Content-Digest", "md4=:AAAA:")], 201),
+        (vec![("Content-MD5", "XUFAKrxLKna5cZ2REBfFkg==")], 201),
+        (vec![("Content-MD5", "AAAAAAAAAAAAAAAAAAAAAA==")], 400),
+    ];
+    for (headers, status) in test_cases {
+        assert_eq!(
+            server.post("/files/a.txt", &headers, b"hello").status,
+            status,
+            "{headers:?}"
+        );
+    }
+
+    let fetched = server.get("/files/a.txt", &[]);
+    assert_eq!(fetched.header("content-digest"), Some(sha256));
+    let partial = server.get("/files/a.txt", &[("Range", "bytes=0-1")]);
+    assert_eq!(partial.status, 206);
+    assert_eq!(partial.header("content-digest"), None);
+}
+
+#[test]
+fn uploads_should_resume_from_the_ranges_received() {
+    let server = TestServer::start(Config {
+        storage: StorageKind::Memory,
+        enable_dir_listing: true,
+        ..Config::default()
+    });
+    let put = |path: &str, range: &str, body: &[u8]| {
+        common::send(
+            server.addr(),
+            "PUT",
+            path,
+            &[("Content-Range", range)],
+            body,
+        )
+    };
+
+    let test_cases = vec![
+        ("bytes 6-9/10", &b"6789"[..], 308, Some("bytes=6-9")),
+        ("bytes 0-1/10", &b"01"[..], 308, Some("bytes=0-1,6-9")),
+        ("bytes 2-4/12", &b"234"[..], 409, None),
+        ("bytes 2-4/10", &b"2345"[..], 400, None),
+        ("bytes 2-5/10", &b"2345"[..], 201, None),
+        ("bytes 0-9/10", &b"abcdefghij"[..], 204, None),
+        ("bytes 0-0/1000000000001", &b"x"[..], 413, None),
+        (
+            "bytes 1000000000000-1000000000000/1000000000001",
+            &b"x"[..],
+            413,
+            None,
+        ),
+    ];
+    for (range, body, status, received) in test_cases {
+        let response = put("/files/big.txt", range, body);
+        assert_eq!(response.status, status, "{range}");
+        assert_eq!(response.header("range"), received, "{range}");
+        if status == 308 {
+            assert_eq!(server.get("/files/big.txt", &[]).status, 404, "{range}");
+        }
+    }
+    assert_eq!(server.get("/files/big.txt", &[]).body, b"abcdefghij");
+
+    // the parts collected so far stay out of sight
+    assert_eq!(put("/files/part.txt", "bytes 0-1/4", b"ab").status, 308);
+    assert_eq!(server.get("/files/.part.txt.upload", &[]).status, 404);
+    let listing = server.get("/files/", &[("Accept", "text/plain")]);
+    let listing = String::from_utf8_lossy(&listing.body);
+    assert!(listing.contains("big.txt"), "{listing}");
+    assert!(!listing.contains(".upload"), "{listing}");
+}
+
+#[test]
+fn directories_should_be_served_by_their_index() {
+    let dir = common::temp_dir("index");
+    fs::create_dir_all(dir.join("docs")).unwrap();
+    fs::create_dir_all(dir.join("bare")).unwrap();
+    fs::write(dir.join("index.html"), "<h1>home</h1>").unwrap();
+    fs::write(dir.join("docs/index.htm"), "<h1>docs</h1>").unwrap();
+
+    let server = TestServer::start(Config {
+        directory: Some(dir.clone()),
+        ..Config::default()
+    });
+
+    let test_cases = vec![
+        ("/", 200, "<h1>home</h1>"),
+        ("/files/", 200, "<h1>home</h1>"),
+        ("/files/docs", 200, "<h1>docs</h1>"),
+        ("/files/bare", 403, ""),
+    ];
+
+    for (path, status, body) in test_cases {
+        let response = server.get(path, &[]);
+        assert_eq!(response.status, status, "{path}");
+        assert_eq!(response.body, body.as_bytes(), "{path}");
+        if status == 200 {
+            assert_eq!(
+                response.header("content-type"),
+                Some("text/html; charset=utf-8"),
+                "{path}"
+            );
+        }
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn spa_mode_should_fall_back_to_the_root_index() {
+    let dir = common::temp_dir("spa");
+    fs::write(dir.join("index.html"), "<div id=app></div>").unwrap();
+    fs::write(dir.join("app.js"), "boot()").unwrap();
+
+    let server = TestServer::start(Config {
+        directory: Some(dir.clone()),
+        spa: true,
+        ..Config::default()
+    });
+
+    let test_cases = vec![
+        ("/", 200, "<div id=app></div>"),
+        ("/app.js", 200, "boot()"),
+        ("/users/7/settings", 200, "<div id=app></div>"),
+        ("/echo/abc", 200, "abc"),
+        ("/api/users", 404, ""),
+    ];
+
+    for (path, status, body) in test_cases {
+        let response = server.get(path, &[]);
+        assert_eq!(response.status, status, "{path}");
+        assert_eq!(response.body, body.as_bytes(), "{path}");
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn ranges_should_be_served_while_if_range_matches() {
+    let dir = common::temp_dir("ranges");
+    fs::write(dir.join("digits.txt"), "0123456789").unwrap();
+
+    let server = TestServer::start(Config {
+        directory: Some(dir.clone()),
+        ..Config::default()
+    });
+
+    let full = server.get("/files/digits.txt", &[]);
+    assert_eq!(full.header("accept-ranges"), Some("bytes"));
+    let etag = full.header("etag").unwrap().to_string();
+    let last_modified = full.header("last-modified").unwrap().to_string();
+
+    let test_cases = vec![
+        (
+            vec![("Range", "bytes=2-4")],
+            206,
+            "234",
+            Some("bytes 2-4/10"),
+        ),
+        (
+            vec![("Range", "bytes=-3")],
+            206,
+            "789",
+            Some("bytes 7-9/10"),
+        ),
+        (
+            vec![("Range", "bytes=2-4"), ("If-Range", etag.as_str())],
+            206,
+            "234",
+            Some("bytes 2-4/10"),
+        ),
+        (
+            vec![("Range", "bytes=2-4"), ("If-Range", last_modified.as_str())],
+            206,
+            "234",
+            Some("bytes 2-4/10"),
+        ),
+        (
+            vec![("Range", "bytes=2-4"), ("If-Range", "\"stale\"")],
+            200,
+            "0123456789",
+            None,
+        ),
+        (
+            vec![
+                ("Range", "bytes=2-4"),
+                ("If-Range", "Thu, 01 Jan 1970 00:00:00 GMT"),
+            ],
+            200,
+            "0123456789",
+            None,
+        ),
+        (vec![("Range", "bytes=20-")], 416, "", Some("bytes */10")),
+        (vec![("Range", "lines=1-2")], 200, "0123456789", None),
+    ];
+
+    for (headers, status, body, content_range) in test_cases {
+        let response = server.get("/files/digits.txt", &headers);
+        assert_eq!(response.status, status, "{headers:?}");
+        assert_eq!(response.body, body.as_bytes(), "{headers:?}");
+        assert_eq!(
+            response.header("content-range"),
+            content_range,
+            "{headers:?}"
+        );
+    }
+
+    let response = server.get("/files/digits.txt", &[("Range", "bytes=0-1, -2")]);
+    assert_eq!(response.status, 206);
+    let boundary = response
+        .header("content-type")
+        .and_then(|value| value.strip_prefix("multipart/byteranges; boundary="))
+        .unwrap();
+    let expected = format!(
+        "--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+         --{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+         --{boundary}--\r\n"
+    );
+    assert_eq!(String::from_utf8_lossy(&response.body), expected);
+    assert_eq!(response.header("content-range"), None);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn multipart_uploads_should_store_each_file() {
+    let dir = common::temp_dir("multipart");
+    let server = TestServer::start(Config {
+        directory: Some(dir.clone()),
+        ..Config::default()
+    });
+
+    let body = concat!(
+        "--b0undary\r\n",
+        "Content-Disposition: form-data; name=\"note\"\r\n\r\n",
+        "ignored\r\n",
+        "--b0undary\r\n",
+        "Content-Disposition: form-data; name=\"a\"; filename=\"a.txt\"\r\n",
+        "Content-Type: text/plain\r\n\r\n",
+        "first\r\n",
+        "--b0undary\r\n",
+        "Content-Disposition: form-data; name=\"b\"; filename=\"C:\\\\tmp\\\\b.bin\"\r\n\r\n",
+        "second\r\n",
+        "--b0undary--\r\n",
+    );
+    let content_type = [("Content-Type", "multipart/form-data; boundary=b0undary")];
+
+    let response = server.post("/files/uploads/", &content_type, body.as_bytes());
+    assert_eq!(response.status, 201);
+    assert_eq!(response.body, b"a.txt\nb.bin");
+    assert_eq!(fs::read(dir.join("uploads/a.txt")).unwrap(), b"first");
+    assert_eq!(fs::read(dir.join("uploads/b.bin")).unwrap(), b"second");
+
+    let test_cases = vec![
+        ("/files/../x/", body.to_string(), 404),
+        ("/files/", body.replace("b.bin", ".."), 400),
+        ("/files/", body.replace("b0undary--", "b0undary"), 400),
+    ];
+
+    for (path, body, status) in test_cases {
+        let response = server.post(path, &content_type, body.as_bytes());
+        assert_eq!(response.status, status, "{path} {body:?}");
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn cached_files_should_follow_every_write_to_them() {
+    let dir = common::temp_dir("cache");
+    fs::create_dir_all(dir.join("up")).unwrap();
+    fs::write(dir.join("a.txt"), b"one").unwrap();
+    fs::write(dir.join("up/b.txt"), b"old").unwrap();
+    let server = TestServer::start_with(
+        Config {
+            directory: Some(dir.clone()),
+            enable_dir_listing: true,
+            ..Config::default()
+        },
+        |server| server.with(Cache::new(1024 * 1024)),
+    );
+
+    assert_eq!(server.get("/files/a.txt", &[]).body, b"one");
+    assert_eq!(server.get("/files/./a.txt", &[]).body, b"one");
+    let response = common::send(server.addr(), "PUT", "//files/a.txt", &[], b"two");
+    assert_eq!(response.status, 204);
+    assert_eq!(server.get("/files/a.txt", &[]).body, b"two");
+    assert_eq!(server.get("/files/./a.txt", &[]).body, b"two");
+
+    assert_eq!(server.get("/files/up/b.txt", &[]).body, b"old");
+    let body = concat!(
+        "--b0undary\r\n",
+        "Content-Disposition: form-data; name=\"b\"; filename=\"b.txt\"\r\n\r\n",
+        "new\r\n",
+        "--b0undary--\r\n",
+    );
+    let content_type = [("Content-Type", "multipart/form-data; boundary=b0undary")];
+    let response = server.post("/files/up/", &content_type, body.as_bytes());
+    assert_eq!(response.status, 201);
+    assert_eq!(server.get("/files/up/b.txt", &[]).body, b"new");
+
+    let unmodified = [("If-Modified-Since", "Fri, 01 Jan 2100 00:00:00 GMT")];
+    assert_eq!(server.get("/files/a.txt", &unmodified).status, 304);
+
+    let test_cases = vec![
+        ("application/json", "application/json"),
+        ("text/html", "text/html; charset=utf-8"),
+        ("text/plain", "text/plain; charset=utf-8"),
+    ];
+    for (accept, content_type) in test_cases {
+        let response = server.get("/files/up/", &[("Accept", accept)]);
+        assert_eq!(
+            response.header("content-type"),
+            Some(content_type),
+            "{accept}"
+        );
+        assert_eq!(response.header("vary"), Some("Accept"), "{accept}");
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn precompressed_sidecars_should_be_served_to_gzip_clients() {
+    let dir = common::temp_dir("precompress");
+    let text = "precompressed ".repeat(100);
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::write(dir.join("nested/a.txt"), &text).unwrap();
+
+    let config = Config {
+        directory: Some(dir.clone()),
+        ..Config::default()
+    };
+    precompress::precompress_all(&config).unwrap();
+    let sidecar = fs::read(dir.join("nested/a.txt.gz")).unwrap();
+
+    let server = TestServer::start(config);
+
+    let test_cases = vec![
+        (vec![("Accept-Encoding", "gzip")], Some("gzip"), sidecar),
+        (vec![], None, text.clone().into_bytes()),
+    ];
+
+    for (headers, encoding, body) in test_cases {
+        let response = server.get("/files/nested/a.txt", &headers);
+        assert_eq!(response.status, 200, "{headers:?}");
+        assert_eq!(response.header("content-encoding"), encoding, "{headers:?}");
+        assert_eq!(response.header("vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            response.header("content-type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(response.body, body, "{headers:?}");
+    }
+
+    let mut decoded = String::new();
+    GzDecoder::new(&fs::read(dir.join("nested/a.txt.gz")).unwrap()[..])
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, text);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn files_should_be_served_per_virtual_host() {
+    let default_dir = common::temp_dir("vhost-default");
+    let example_dir = common::temp_dir("vhost-example");
+    fs::write(default_dir.join("x.txt"), "default").unwrap();
+    fs::write(example_dir.join("x.txt"), "example").unwrap();
+
+    let server = TestServer::start(Config {
+        directory: Some(default_dir.clone()),
+        vhosts: vec![("example.com".to_string(), example_dir.clone())],
+        ..Config::default()
+    });
+
+    let test_cases = vec![
+        ("Example.COM:4221", "example"),
+        ("example.com.", "example"),
+        ("other.example", "default"),
+    ];
+
+    for (host, body) in test_cases {
+        let response = server.get("/files/x.txt", &[("Host", host)]);
+        assert_eq!(response.body, body.as_bytes(), "{host}");
+    }
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+
+    fs::remove_dir_all(default_dir).unwrap();
+    fs::remove_dir_all(example_dir).unwrap();
+}
+
+#[test]
+fn responses_should_be_compressed_when_accepted() {
+    let server = TestServer::start(Config::default());
+
+    let message = "compress-me".repeat(30);
+    let response = server.get(&format!("/echo/{message}"), &[("Accept-Encoding", "gzip")]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), Some("gzip"));
+
+    let mut decoded = String::new();
+    GzDecoder::new(&response.body[..])
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, message);
+
+    // too small to be worth it
+    let response = server.get("/echo/compress-me", &[("Accept-Encoding", "gzip")]);
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.body, b"compress-me");
+}
+
+#[test]
+fn oversized_bodies_should_be_rejected_with_413() {
+    let dir = common::temp_dir("limits");
+    let server = TestServer::start(Config {
+        directory: Some(dir.clone()),
+        limits: RequestLimits {
+            max_body_size: 4,
+            ..RequestLimits::default()
+        },
+        ..Config::default()
+    });
+
+    let test_cases = vec![(&b"abcd"[..], 201), (&b"abcde"[..], 413)];
+
+    for (body, status) in test_cases {
+        assert_eq!(server.post("/files/a.txt", &[], body).status, status);
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn compressed_uploads_should_be_stored_decompressed() {
+    let server = TestServer::start(Config {
+        storage: StorageKind::Memory,
+        limits: RequestLimits {
+            max_body_size: 1024,
+            ..RequestLimits::default()
+        },
+        ..Config::default()
+    });
+    let gzip = |content: &[u8]| {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    };
+    let bomb = gzip(&[0; 4096]);
+    assert!(bomb.len() < 1024);
+
+    let test_cases = vec![
+        (
+            "/files/a.txt",
+            "gzip",
+            gzip(b"hello"),
+            201,
+            Some(&b"hello"[..]),
+        ),
+        ("/files/b.txt", "x-gzip", gzip(b"hi"), 201, Some(&b"hi"[..])),
+        (
+            "/files/c.txt",
+            "identity",
+            b"plain".to_vec(),
+            201,
+            Some(&b"plain"[..]),
+        ),
+        ("/files/d.txt", "gzip", bomb, 413, None),
+        ("/files/e.txt", "gzip", b"not gzip".to_vec(), 400, None),
+        ("/files/f.txt", "compress", b"x".to_vec(), 415, None),
+    ];
+
+    for (path, encoding, body, status, stored) in test_cases {
+        let response = server.post(path, &[("Content-Encoding", encoding)], &body);
+        assert_eq!(response.status, status, "{path}");
+        if status == 415 {
+            assert_eq!(
+                response.header("accept-encoding"),
+                Some("gzip, br, deflate")
+            );
+        }
+        let fetched = server.get(path, &[]);
+        match stored {
+            Some(stored) => assert_eq!(fetched.body, stored, "{path}"),
+            None => assert_eq!(fetched.status, 404, "{path}"),
+        }
+    }
+}
+
+#[test]
+fn oversized_heads_should_be_rejected() {
+    let server = TestServer::start(Config {
+        limits: RequestLimits {
+            max_header_line: 64,
+            max_header_count: 4,
+            ..RequestLimits::default()
+        },
+        ..Config::default()
+    });
+
+    let long_value = "x".repeat(64);
+    let long_path = format!("/echo/{long_value}");
+
+    let test_cases = vec![
+        ("/echo/ok", vec![("A", "1"), ("B", "2")], 200),
+        ("/echo/ok", vec![("A", long_value.as_str())], 431),
+        (
+            "/echo/ok",
+            vec![("A", "1"), ("B", "2"), ("C", "3"), ("D", "4")],
+            431,
+        ),
+        (long_path.as_str(), vec![], 414),
+    ];
+
+    for (path, headers, status) in test_cases {
+        assert_eq!(
+            server.get(path, &headers).status,
+            status,
+            "{path} {headers:?}"
+        );
+    }
+}
+
+#[test]
+fn malformed_requests_should_get_an_error_status() {
+    let server = TestServer::start(Config::default());
+
+    let test_cases = vec![
+        ("BREW", "/", vec![], 501),
+        ("GET", "/", vec![("Content-Length", "abc")], 400),
+        ("POST", "/", vec![("Content-Length", "+0")], 400),
+        ("POST", "/", vec![("Content-Length", "-0")], 400),
+        ("GET", "/", vec![("Transfer-Encoding", "gzip")], 501),
+        (
+            "POST",
+            "/",
+            vec![("Transfer-Encoding", "gzip, chunked")],
+            501,
+        ),
+        (
+            "POST",
+            "/",
+            vec![
+                ("Transfer-Encoding", "chunked"),
+                ("Transfer-Encoding", "chunked"),
+            ],
+            501,
+        ),
+        (
+            "POST",
+            "/",
+            vec![("Content-Length", "0"), ("Transfer-Encoding", "chunked")],
+            400,
+        ),
+        (
+            "POST",
+            "/",
+            vec![("Content-Length", "1"), ("Content-Length", "2")],
+            400,
+        ),
+        ("GET", "/", vec![("X-Folded", "a\r\n b")], 400),
+        ("GET", "http://other.example/", vec![], 400),
+    ];
+
+    for (method, path, headers, status) in test_cases {
+        let response = common::send(server.addr(), method, path, &headers, b"");
+        assert_eq!(response.status, status, "{method} {headers:?}");
+        assert_eq!(response.header("connection"), Some("close"));
+    }
+}
+
+#[test]
+fn error_pages_should_replace_empty_error_bodies() {
+    let dir = common::temp_dir("error-pages");
+    fs::write(dir.join("404.html"), "<h1>gone</h1>").unwrap();
+    fs::write(dir.join("400.html"), "<h1>bad</h1>").unwrap();
+    fs::write(dir.join("notes.html"), "ignored").unwrap();
+
+    let server = TestServer::start(Config {
+        error_pages: Some(dir.clone()),
+        ..Config::default()
+    });
+
+    let test_cases = vec![
+        ("GET", "/missing", vec![], 404, "<h1>gone</h1>"),
+        (
+            "GET",
+            "/",
+            vec![("Content-Length", "abc")],
+            400,
+            "<h1>bad</h1>",
+        ),
+        ("BREW", "/", vec![], 501, ""),
+        ("GET", "/echo/hi", vec![], 200, "hi"),
+    ];
+
+    for (method, path, headers, status, body) in test_cases {
+        let response = common::send(server.addr(), method, path, &headers, b"");
+        assert_eq!(response.status, status, "{method} {path}");
+        assert_eq!(response.body, body.as_bytes(), "{method} {path}");
+    }
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn request_ids_should_be_echoed_when_valid_and_generated_otherwise() {
+    let server = TestServer::start(Config {
+        problem_json: true,
+        ..Config::default()
+    });
+    let is_uuid = |id: &str| {
+        id.len() == 36
+            && id.as_bytes()[14] == b'4'
+            && id.split('-').map(str::len).eq([8, 4, 4, 4, 12])
+    };
+
+    let test_cases = vec![
+        (
+            "/",
+            vec![("X-Request-Id", "edge-42:a.b_c")],
+            Some("edge-42:a.b_c"),
+        ),
+        ("/", vec![("X-Request-Id", "two words")], None),
+        ("/", vec![("X-Request-Id", "")], None),
+        ("/", vec![], None),
+        ("/missing", vec![("X-Request-Id", "lost-1")], Some("lost-1")),
+    ];
+
+    for (path, headers, echoed) in test_cases {
+        let response = server.get(path, &headers);
+        let id = response.header("x-request-id").unwrap_or_default();
+        match echoed {
+            Some(echoed) => assert_eq!(id, echoed, "{headers:?}"),
+            None => assert!(is_uuid(id), "{id} for {headers:?}"),
+        }
+        if response.status == 404 {
+            let body = String::from_utf8_lossy(&response.body);
+            assert!(body.contains(&format!("\"request_id\":\"{id}\"")), "{body}");
+        }
+    }
+
+    let malformed = common::send(server.addr(), "GET", "/", &[("Content-Length", "abc")], b"");
+    assert_eq!(malformed.status, 400);
+    assert!(is_uuid(
+        malformed.header("x-request-id").unwrap_or_default()
+    ));
+}
+
+#[test]
+fn responses_should_carry_the_configured_server_header() {
+    let test_cases = vec![
+        (Config::default(), Some(DEFAULT_SERVER_HEADER)),
+        (
+            Config {
+                server_header: "edge".to_string(),
+                ..Config::default()
+            },
+            Some("edge"),
+        ),
+        (
+            Config {
+                server_header: String::new(),
+                ..Config::default()
+            },
+            None,
+        ),
+    ];
+
+    for (conf, expected) in test_cases {
+        let server = TestServer::start(conf);
+        for path in ["/", "/missing"] {
+            let response = server.get(path, &[]);
+            assert_eq!(response.header("server"), expected, "{path}");
+            assert!(response.header("date").is_some(), "{path}");
+        }
+    }
+}
+
+#[test]
+fn pipelined_requests_should_be_answered_in_order() {
+    for io_model in [IoModel::Threads, IoModel::Evented] {
+        let server = TestServer::start(Config {
+            io_model,
+            ..Config::default()
+        });
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .write_all(
+                concat!(
+                    "GET /echo/one HTTP/1.1\r\nHost: x\r\n\r\n",
+                    "GET /echo/two HTTP/1.1\r\nHost: x\r\nContent-Length: 22\r\n\r\n",
+                    "GET /echo/bad HTTP/1.1",
+                    "GET /echo/three HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n",
+                    "3\r\nabc\r\n0\r\n\r\n",
+                    "GET /user-agent HTTP/1.1\r\nHost: x\r\nUser-Agent: four\r\nConnection: close\r\n\r\n",
+                    "GET /echo/ignored HTTP/1.1\r\nHost: x\r\n\r\n",
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).unwrap();
+
+        let bodies: Vec<_> = common::parse_responses(&raw)
+            .into_iter()
+            .map(|response| (response.status, String::from_utf8(response.body).unwrap()))
+            .collect();
+
+        assert_eq!(
+            bodies,
+            vec![
+                (200, "one".to_string()),
+                (200, "two".to_string()),
+                (200, "three".to_string()),
+                (200, "four".to_string()),
+            ],
+            "{io_model:?}"
+        );
+    }
+}
+
+#[test]
+fn connections_should_be_recorded_as_they_went_over_the_wire() {
+    for io_model in [IoModel::Threads, IoModel::Evented] {
+        let dir = common::temp_dir("record");
+        let server = TestServer::start(Config {
+            record: Some(dir.clone()),
+            io_model,
+            ..Config::default()
+        });
+
+        let exchanges = [
+            concat!(
+                "GET /echo/abc HTTP/1.1\r\nHost: a\r\nAuthorization: Basic dTpw\r\n\r\n",
+                "GET /echo/x HTTP/1.1\r\nHost: a\r\nCookie: s=1\r\n",
+                "Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n2\r\nhi\r\n0\r\n\r\n",
+            ),
+            "GET / HTTP/9.9\r\n\r\n",
+        ];
+        for raw in exchanges {
+            let mut stream = TcpStream::connect(server.addr()).unwrap();
+            stream.write_all(raw.as_bytes()).unwrap();
+            stream.read_to_end(&mut Vec::new()).unwrap();
+        }
+
+        let mut paths: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        let recorded: Vec<String> = paths
+            .iter()
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect();
+
+        assert_eq!(recorded.len(), 4, "{io_model:?} {paths:?}");
+        let expected = exchanges[0]
+            .replace("Basic dTpw", "[redacted]")
+            .replace("s=1", "[redacted]");
+        assert_eq!(recorded[0], expected, "{io_model:?}");
+        assert_eq!(recorded[1].matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        for header in ["X-Request-Id: ", "Server: ", "Date: "] {
+            assert!(recorded[1].contains(header), "{io_model:?} {header}");
+        }
+        assert_eq!(recorded[2], exchanges[1], "{io_model:?}");
+        assert!(
+            recorded[3].starts_with("HTTP/1.1 505 "),
+            "{io_model:?} {}",
+            recorded[3]
+        );
+
+        drop(server);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn unsized_bodies_should_reach_http10_clients_as_they_stream() {
+    for io_model in [IoModel::Threads, IoModel::Evented] {
+        let server = TestServer::start(Config {
+            io_model,
+            ..Config::default()
+        });
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        stream
+            .write_all(b"GET /events HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+
+        // the event stream never ends, the first event has to arrive anyway
+        let mut raw = Vec::new();
+        let mut buf = [0; 1024];
+        while !String::from_utf8_lossy(&raw).contains("event: stats") {
+            let n = stream.read(&mut buf).expect("no event before the timeout");
+            assert!(n > 0, "{io_model:?} closed after {raw:?}");
+            raw.extend_from_slice(&buf[..n]);
+        }
+
+        let raw = String::from_utf8_lossy(&raw).to_lowercase();
+        assert!(raw.contains("connection: close\r\n"), "{io_model:?} {raw}");
+        assert!(!raw.contains("transfer-encoding"), "{io_model:?} {raw}");
+    }
+}
+
+#[test]
+fn evented_io_should_serve_requests_arriving_in_pieces() {
+    let dir = common::temp_dir("evented");
+    let server = TestServer::start(Config {
+        directory: Some(dir.clone()),
+        io_model: IoModel::Evented,
+        ..Config::default()
+    });
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    let request = concat!(
+        "POST /files/slow.txt HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n",
+        "5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        "GET /files/slow.txt HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+    );
+    for piece in request.as_bytes().chunks(7) {
+        stream.write_all(piece).unwrap();
+        thread::sleep(Duration::from_millis(2));
+    }
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+
+    let responses: Vec<_> = common::parse_responses(&raw)
+        .into_iter()
+        .map(|response| (response.status, String::from_utf8(response.body).unwrap()))
+        .collect();
+    assert_eq!(
+        responses,
+        vec![(201, String::new()), (200, "hello world".to_string())]
+    );
+
+    let large = vec![b'x'; 3 * 1024 * 1024];
+    fs::write(dir.join("large.bin"), &large).unwrap();
+    let response = server.get("/files/large.bin", &[]);
+    assert_eq!(response.status, 200);
+    assert!(response.body == large);
+
+    // more than one turn of reads arriving at once
+    let upload: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    assert_eq!(server.post("/files/upload.bin", &[], &upload).status, 201);
+    assert!(fs::read(dir.join("upload.bin")).unwrap() == upload);
+}
+
+#[cfg(all(feature = "h2", not(feature = "tokio")))]
+#[test]
+fn h2c_upgrades_should_answer_the_request_on_stream_1() {
+    for io_model in [IoModel::Threads, IoModel::Evented] {
+        let server = TestServer::start(Config {
+            io_model,
+            ..Config::default()
+        });
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .write_all(
+                concat!(
+                    "GET /echo/upgraded HTTP/1.1\r\nHost: x\r\n",
+                    "Connection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\n",
+                    "HTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n",
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101"), "{io_model:?} {head}");
+        assert!(head.contains("Upgrade: h2c\r\n"), "{io_model:?} {head}");
+
+        // the client preface, an empty SETTINGS frame included
+        stream
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+            .unwrap();
+
+        let mut body = Vec::new();
+        loop {
+            let mut frame = [0; 9];
+            stream.read_exact(&mut frame).unwrap();
+            let length = u32::from_be_bytes([0, frame[0], frame[1], frame[2]]) as usize;
+            let mut payload = vec![0; length];
+            stream.read_exact(&mut payload).unwrap();
+
+            let (kind, flags, stream_id) = (frame[3], frame[4], frame[8]);
+            if kind == 0 && stream_id == 1 {
+                body.extend_from_slice(&payload);
+                if flags & 1 != 0 {
+                    break;
+                }
+            }
+        }
+        assert_eq!(body, b"upgraded", "{io_model:?}");
+    }
+}
+
+#[test]
+fn upgraded_connections_should_count_against_the_limit() {
+    for io_model in [IoModel::Threads, IoModel::Evented] {
+        let server = TestServer::start(Config {
+            max_connections: Some(1),
+            io_model,
+            ..Config::default()
+        });
+
+        let mut upgraded = TcpStream::connect(server.addr()).unwrap();
+        upgraded
+            .write_all(
+                concat!(
+                "GET /ws HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n",
+                "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            )
+                .as_bytes(),
+            )
+            .unwrap();
+        let mut head = Vec::new();
+        let mut byte = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            upgraded.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        assert!(head.starts_with(b"HTTP/1.1 101 "), "{io_model:?}");
+
+        let mut rejected = TcpStream::connect(server.addr()).unwrap();
+        let mut response = String::new();
+        rejected.read_to_string(&mut response).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 503"),
+            "{io_model:?} {response}"
+        );
+    }
+}
+
+#[test]
+fn connections_beyond_the_limit_should_get_a_503() {
+    let server = TestServer::start(Config {
+        max_connections: Some(1),
+        ..Config::default()
+    });
+
+    let idle = TcpStream::connect(server.addr()).unwrap();
+
+    // the 503 is sent without reading, a request written first could make
+    // the close reset the connection before the client read the response
+    let mut rejected = TcpStream::connect(server.addr()).unwrap();
+    let mut response = String::new();
+    rejected.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+    assert!(response.contains("Retry-After: 1\r\n"), "{response}");
+
+    drop(idle);
+
+    // the server only notices the close on its next read, until then the
+    // request may also be reset
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        let mut response = String::new();
+        let answered = stream
+            .write_all(b"GET /echo/hi HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .and_then(|_| stream.read_to_string(&mut response))
+            .is_ok();
+        if answered && response.starts_with("HTTP/1.1 200") {
+            assert!(response.ends_with("\r\n\r\nhi"), "{response}");
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "connection slot was not released"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}